@@ -0,0 +1,640 @@
+// Music, ambience and sound effects
+
+use crate::*;
+
+// Mutes all the sounds, at any time
+pub const MUTE_KEY: KeyCode = KeyCode::M;
+
+// Music settings: the combat track goes on a little after the last boar stopped chasing,
+// and the exploration track starts over after a long fight
+pub const COMBAT_MUSIC_DELAY: f32 = 4.0;
+pub const COMBAT_MUSIC_RESTART: f32 = 10.0;
+pub const MUSIC_CROSSFADE_TIME: f32 = 1.5;
+
+// How long a sound effect is followed when its length is unknown
+pub const SFX_DEFAULT_LENGTH: f32 = 2.0;
+
+// Ambience settings, the ambience is quieter than the music
+pub const AMBIENCE_VOLUME: f32 = 0.4;
+pub const AMBIENCE_CROSSFADE_TIME: f32 = 2.0;
+
+// Positional sound settings: the boars are heard up to a bit more than the width of the view
+pub const BOAR_SOUND_VOLUME: f32 = 1.0;
+pub const BOAR_SOUND_DISTANCE: f32 = 800.0;
+
+// Bark settings: a calm boar grunts from time to time, the other noises have a cooldown
+// so that a boar going back and forth between states does not make them over and over
+pub const GRUNT_INTERVAL_MIN: f32 = 8.0;
+pub const GRUNT_INTERVAL_MAX: f32 = 20.0;
+pub const SNORT_COOLDOWN: f32 = 3.0;
+pub const SNORT_VOLUME: f32 = 1.5;
+pub const SQUEAL_COOLDOWN: f32 = 0.5;
+
+// The music played depends on what is going on in the game
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MusicTrack {
+    Exploration,
+    Combat,
+    Menu,
+}
+
+impl MusicTrack {
+    pub fn path(&self) -> &'static str {
+	match self {
+	    MusicTrack::Exploration => "sounds/acid_soup_2.ogg",
+	    MusicTrack::Combat => "sounds/combat_theme.wav",
+	    MusicTrack::Menu => "sounds/menu_theme.wav",
+	}
+    }
+}
+
+// Looping sounds where the current one fades in and the others fade out
+pub struct Crossfade<T> {
+    // Every loop started so far, paused once faded out
+    pub sinks: HashMap<T, Handle<AudioSink>>,
+    // How loud each loop is in the crossfade, from 0 (silent) to 1
+    pub fades: HashMap<T, f32>,
+    // Loops stopped instead of paused once faded out
+    pub stop_when_silent: HashSet<T>,
+    pub current: Option<T>,
+    pub crossfade_time: f32,
+}
+
+impl<T: Copy + Eq + Hash> Crossfade<T> {
+    pub fn new(crossfade_time: f32) -> Crossfade<T> {
+	Crossfade {
+	    sinks: HashMap::new(),
+	    fades: HashMap::new(),
+	    stop_when_silent: HashSet::new(),
+	    current: None,
+	    crossfade_time,
+	}
+    }
+
+    // A loop fading out comes back from its current volume, new loops start silent
+    pub fn switch_to(&mut self, wanted: Option<T>, start: impl FnOnce(T) -> Handle<AudioSink>) {
+	if let Some(wanted) = wanted {
+	    self.stop_when_silent.remove(&wanted);
+	    self.sinks.entry(wanted).or_insert_with(|| start(wanted));
+	}
+	self.current = wanted;
+    }
+
+    // Forgets a loop so that it starts over the next time it is played
+    pub fn stop(&mut self, track: T, audio_sinks: &Assets<AudioSink>) {
+	if let Some(sink) = self.sinks.get(&track).and_then(|sink| audio_sinks.get(sink)) {
+	    sink.stop();
+	    self.sinks.remove(&track);
+	    self.fades.remove(&track);
+	}
+    }
+
+    pub fn fade(&mut self, delta_seconds: f32, volume: f32, audio_sinks: &Assets<AudioSink>) {
+	let step = delta_seconds / self.crossfade_time;
+	let current = self.current;
+	let Crossfade { sinks, fades, stop_when_silent, .. } = self;
+
+	// The sinks only exist once the loops are loaded, until then only the fade goes on
+	sinks.retain(|track, sink| {
+	    let fade = fades.entry(*track).or_insert(0.0);
+	    *fade = if Some(*track) == current {
+		(*fade + step).min(1.0)
+	    } else {
+		(*fade - step).max(0.0)
+	    };
+	    let Some(sink) = audio_sinks.get(sink) else {
+		return true;
+	    };
+	    sink.set_volume(*fade * volume);
+
+	    if *fade > 0.0 {
+		if sink.is_paused() {
+		    sink.play();
+		}
+	    } else if stop_when_silent.remove(track) {
+		sink.stop();
+		fades.remove(track);
+		return false;
+	    } else if !sink.is_paused() {
+		// Paused rather than stopped, to resume where it was
+		sink.pause();
+	    }
+	    true
+	});
+    }
+}
+
+#[derive(Resource)]
+pub struct MusicController {
+    pub tracks: Crossfade<MusicTrack>,
+    // The boars currently chasing the player
+    pub chasing: HashSet<Entity>,
+    pub in_combat: bool,
+    pub combat_time: f32,
+    pub calm_time: f32,
+}
+
+impl Default for MusicController {
+    fn default() -> MusicController {
+	MusicController {
+	    tracks: Crossfade::new(MUSIC_CROSSFADE_TIME),
+	    chasing: HashSet::new(),
+	    in_combat: false,
+	    combat_time: 0.0,
+	    calm_time: 0.0,
+	}
+    }
+}
+
+impl MusicController {
+    pub fn wanted_track(&self, state: &GameState) -> MusicTrack {
+	match state {
+	    GameState::Loading | GameState::MainMenu | GameState::Paused | GameState::Stats | GameState::Achievements
+	    | GameState::Saves | GameState::Victory | GameState::GameOver | GameState::Controls => MusicTrack::Menu,
+	    GameState::Playing | GameState::QuestLog | GameState::Inventory | GameState::Chest | GameState::Dialogue | GameState::Shop if self.in_combat => MusicTrack::Combat,
+	    GameState::Playing | GameState::QuestLog | GameState::Inventory | GameState::Chest | GameState::Dialogue | GameState::Shop => MusicTrack::Exploration,
+	}
+    }
+}
+
+// Groups of sounds sharing a volume setting
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AudioChannel {
+    Music,
+    Sfx,
+}
+
+// A sound being played, with its own volume before the settings are applied
+pub struct PlayingSound {
+    pub sink: Handle<AudioSink>,
+    pub volume: f32,
+    // The sound is forgotten once it is over
+    pub remaining: Timer,
+}
+
+// The sounds being played, to change their volume when the settings change.
+// The loops (music, ambience) are not in there, their crossfade applies the settings every frame
+#[derive(Resource, Default)]
+pub struct AudioChannels {
+    pub sounds: HashMap<AudioChannel, Vec<PlayingSound>>,
+}
+
+impl AudioChannels {
+    pub fn add(&mut self, channel: AudioChannel, sink: Handle<AudioSink>, volume: f32, length: f32) {
+	self.sounds.entry(channel).or_default().push(PlayingSound {
+	    sink,
+	    volume,
+	    remaining: Timer::from_seconds(length, TimerMode::Once),
+	});
+    }
+}
+
+// Length of a sound in seconds, the wav files give it
+pub fn sound_length(sound: Option<&AudioSource>) -> f32 {
+    sound.and_then(|sound| Decoder::new(Cursor::new(sound.clone())).ok())
+	.and_then(|decoder| decoder.total_duration())
+	.map_or(SFX_DEFAULT_LENGTH, |length| length.as_secs_f32())
+}
+
+// The ambience loops. Outdoors is the daytime one, a night one will come with a day/night cycle
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Ambience {
+    Outdoors,
+    Interior,
+}
+
+impl Ambience {
+    pub fn path(&self) -> &'static str {
+	match self {
+	    Ambience::Outdoors => "sounds/ambience_outdoors.wav",
+	    Ambience::Interior => "sounds/ambience_interior.wav",
+	}
+    }
+}
+
+#[derive(Resource)]
+pub struct AmbienceController {
+    pub beds: Crossfade<Ambience>,
+}
+
+impl Default for AmbienceController {
+    fn default() -> AmbienceController {
+	AmbienceController {
+	    beds: Crossfade::new(AMBIENCE_CROSSFADE_TIME),
+	}
+    }
+}
+
+// The sound effects, gameplay code asks for them with a PlaySfx event
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Sfx {
+    Thud,
+    Grunt,
+    Hurt,
+    Blip,
+    Snort,
+    Squeal,
+    Heartbeat,
+    Click,
+    LevelUp,
+    Unlock,
+    Achievement,
+    Door,
+    // The footsteps have a few samples per terrain, played in turn
+    Step(Terrain, usize),
+}
+
+impl Sfx {
+    pub fn all() -> Vec<Sfx> {
+	let mut all = vec![Sfx::Thud, Sfx::Grunt, Sfx::Hurt, Sfx::Blip, Sfx::Snort, Sfx::Squeal, Sfx::Heartbeat, Sfx::Click, Sfx::LevelUp, Sfx::Unlock, Sfx::Achievement, Sfx::Door];
+	for terrain in [Terrain::Grass, Terrain::Dirt] {
+	    all.extend((0..FOOTSTEP_SAMPLES).map(|sample| Sfx::Step(terrain, sample)));
+	}
+	all
+    }
+
+    pub fn path(&self) -> String {
+	match self {
+	    Sfx::Thud => "sounds/thud.wav".to_string(),
+	    Sfx::Grunt => "sounds/grunt.wav".to_string(),
+	    Sfx::Hurt => "sounds/hurt.wav".to_string(),
+	    Sfx::Blip => "sounds/blip.wav".to_string(),
+	    Sfx::Snort => "sounds/snort.wav".to_string(),
+	    Sfx::Heartbeat => "sounds/heartbeat.wav".to_string(),
+	    Sfx::Click => "sounds/click.wav".to_string(),
+	    Sfx::Squeal => "sounds/squeal.wav".to_string(),
+	    Sfx::LevelUp => "sounds/level_up.wav".to_string(),
+	    Sfx::Unlock => "sounds/unlock.wav".to_string(),
+	    Sfx::Achievement => "sounds/achievement.wav".to_string(),
+	    Sfx::Door => "sounds/door.wav".to_string(),
+	    Sfx::Step(Terrain::Grass, sample) => format!("sounds/step_grass_{}.wav", sample + 1),
+	    Sfx::Step(Terrain::Dirt, sample) => format!("sounds/step_dirt_{}.wav", sample + 1),
+	}
+    }
+}
+
+pub struct PlaySfx(pub Sfx);
+
+// A sound coming from somewhere in the world: it is panned toward its source,
+// and fades with the distance to the player until it is not played at all
+#[derive(Clone, Copy, Debug)]
+pub struct SpatialSound {
+    pub source: Entity,
+    pub base_volume: f32,
+    pub max_distance: f32,
+    // Where the source was when the sound was asked for, it is heard from there when the source is despawned
+    // before the sound is played
+    pub position: Option<Vec2>,
+}
+
+impl SpatialSound {
+    pub fn at(self, position: Vec2) -> SpatialSound {
+	SpatialSound { position: Some(position), ..self }
+    }
+}
+
+pub struct PlaySpatialSfx(pub Sfx, pub SpatialSound);
+
+// A sound with a volume for each of the left and right channels
+#[derive(TypeUuid)]
+#[uuid = "6478856d-9b4c-4d92-b72c-cb35e7cbdf45"]
+pub struct PannedSound {
+    pub sound: AudioSource,
+    pub channel_volumes: [f32; 2],
+}
+
+impl Decodable for PannedSound {
+    type Decoder = ChannelVolume<Decoder<Cursor<AudioSource>>>;
+    type DecoderItem = i16;
+
+    fn decoder(&self) -> Self::Decoder {
+	let decoder = Decoder::new(Cursor::new(self.sound.clone())).unwrap();
+	ChannelVolume::new(decoder, self.channel_volumes.to_vec())
+    }
+}
+
+#[derive(Resource)]
+pub struct SfxLibrary {
+    pub sounds: HashMap<Sfx, Handle<AudioSource>>,
+}
+
+pub fn load_sfx(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let sounds = Sfx::all().into_iter()
+	.map(|sfx| (sfx, asset_server.load(sfx.path())))
+	.collect();
+    commands.insert_resource(SfxLibrary { sounds });
+}
+
+// System that plays the sound effects asked for, a sound asked several times in a frame is played once
+pub fn play_sfx(
+    audio: Res<Audio>,
+    library: Res<SfxLibrary>,
+    settings: Res<Settings>,
+    audio_sources: Res<Assets<AudioSource>>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mut channels: ResMut<AudioChannels>,
+    mut sfx_events: EventReader<PlaySfx>,
+) {
+    let mut played = HashSet::new();
+    for PlaySfx(sfx) in sfx_events.iter() {
+	if !played.insert(*sfx) {
+	    continue;
+	}
+	if let Some(sound) = library.sounds.get(sfx) {
+	    let sink = audio.play_with_settings(
+		sound.clone(),
+		PlaybackSettings::ONCE.with_volume(settings.volume(AudioChannel::Sfx)),
+	    );
+	    channels.add(AudioChannel::Sfx, audio_sinks.get_handle(sink), 1.0, sound_length(audio_sources.get(sound)));
+	}
+    }
+}
+
+// The volume and the pan of the sounds coming from the world, given with their offset from the player.
+// Like the other sound effects, a sound asked several times in a frame is played once: a herd snorting together
+// is heard as loud as its closest boar. The sources too far away are not heard
+pub fn mix_spatial_sounds(sounds: impl IntoIterator<Item = (Sfx, SpatialSound, Vec2)>) -> HashMap<Sfx, (f32, f32)> {
+    let mut mixed: HashMap<Sfx, (f32, f32)> = HashMap::new();
+    for (sfx, spatial, offset) in sounds {
+	let distance = offset.length();
+	if distance > spatial.max_distance {
+	    continue;
+	}
+	let volume = spatial.base_volume * (1.0 - distance / spatial.max_distance);
+	// -1 is fully on the left, 1 fully on the right
+	let pan = (offset.x / spatial.max_distance).clamp(-1.0, 1.0);
+	if mixed.get(&sfx).is_none_or(|(loudest, _)| volume > *loudest) {
+	    mixed.insert(sfx, (volume, pan));
+	}
+    }
+    mixed
+}
+
+// System that plays the sounds coming from the world, relative to the player
+pub fn play_spatial_sfx(
+    audio: Res<Audio<PannedSound>>,
+    library: Res<SfxLibrary>,
+    settings: Res<Settings>,
+    audio_sources: Res<Assets<AudioSource>>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mut channels: ResMut<AudioChannels>,
+    mut panned_sounds: ResMut<Assets<PannedSound>>,
+    mut sfx_events: EventReader<PlaySpatialSfx>,
+    query_player: Query<&GlobalTransform, With<Player>>,
+    query_source: Query<&GlobalTransform>,
+) {
+    let listener = query_player.single().translation().truncate();
+    let sounds = sfx_events.iter().filter_map(|PlaySpatialSfx(sfx, spatial)| {
+	// The source may have been despawned since
+	let source_position = query_source.get(spatial.source).ok()
+	    .map(|source_transform| source_transform.translation().truncate())
+	    .or(spatial.position)?;
+	Some((*sfx, *spatial, source_position - listener))
+    });
+    for (sfx, (volume, pan)) in mix_spatial_sounds(sounds) {
+	let Some(sound) = library.sounds.get(&sfx).and_then(|handle| audio_sources.get(handle)) else {
+	    continue;
+	};
+	let panned_sound = panned_sounds.add(PannedSound {
+	    sound: sound.clone(),
+	    channel_volumes: [(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)],
+	});
+	let sink = audio.play_with_settings(
+	    panned_sound,
+	    PlaybackSettings::ONCE.with_volume(settings.volume(AudioChannel::Sfx) * volume),
+	);
+	channels.add(AudioChannel::Sfx, audio_sinks.get_handle(sink), volume, sound_length(Some(sound)));
+    }
+}
+
+// System that applies the volume settings to the sounds being played as soon as they change
+pub fn apply_volumes(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    mut channels: ResMut<AudioChannels>,
+) {
+    for (channel, sounds) in channels.sounds.iter_mut() {
+	sounds.retain_mut(|sound| !sound.remaining.tick(time.delta()).finished());
+	if !settings.is_changed() {
+	    continue;
+	}
+	for sound in sounds.iter() {
+	    if let Some(sink) = audio_sinks.get(&sound.sink) {
+		sink.set_volume(sound.volume * settings.volume(*channel));
+	    }
+	}
+    }
+}
+
+// System that mutes or unmutes everything, the loops go on silently
+pub fn toggle_mute(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut query_icon: Query<&mut Visibility, With<MuteIcon>>,
+) {
+    if keyboard_input.just_pressed(MUTE_KEY) {
+	settings.muted = !settings.muted;
+	settings.save();
+	for mut visibility in &mut query_icon {
+	    visibility.is_visible = settings.muted;
+	}
+    }
+}
+
+pub fn collision_sounds(
+    mut collision_events: EventReader<CollisionEvent>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    for _ in collision_events.iter() {
+	sfx_events.send(PlaySfx(Sfx::Thud));
+    }
+}
+
+// A cry when the player is hurt, the boars have their own noises
+pub fn damage_sounds(
+    mut damage_events: EventReader<DamageEvent>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_player: Query<(), (With<Player>, Without<Invulnerable>)>,
+) {
+    for event in damage_events.iter() {
+	if query_player.contains(event.target) {
+	    sfx_events.send(PlaySfx(Sfx::Hurt));
+	}
+    }
+}
+
+// System that makes the boars grunt now and then while calm, and snort when they start chasing.
+// They squeal when hurt, from apply_damage
+pub fn boar_barks(
+    time: Res<Time>,
+    mut state_events: EventReader<BoarStateChanged>,
+    mut spatial_sfx_events: EventWriter<PlaySpatialSfx>,
+    mut query_boar: Query<(Entity, &BoarAi, &mut Barks)>,
+) {
+    for (boar, boar_ai, mut barks) in &mut query_boar {
+	barks.snort_cooldown.tick(time.delta());
+	barks.squeal_cooldown.tick(time.delta());
+	if boar_ai.state == BoarState::Chase {
+	    continue;
+	}
+	if barks.next_grunt.tick(time.delta()).finished() {
+	    barks.next_grunt = Barks::grunt_timer();
+	    spatial_sfx_events.send(PlaySpatialSfx(Sfx::Grunt, boar_sound(boar, BOAR_SOUND_VOLUME)));
+	}
+    }
+
+    for event in state_events.iter() {
+	let Ok((_, _, mut barks)) = query_boar.get_mut(event.boar) else {
+	    continue;
+	};
+	if event.to == BoarState::Chase && barks.snort_cooldown.finished() {
+	    barks.snort_cooldown.reset();
+	    spatial_sfx_events.send(PlaySpatialSfx(Sfx::Snort, boar_sound(event.boar, SNORT_VOLUME)));
+	}
+    }
+}
+
+pub fn boar_sound(boar: Entity, volume: f32) -> SpatialSound {
+    SpatialSound {
+	source: boar,
+	base_volume: volume,
+	max_distance: BOAR_SOUND_DISTANCE,
+	position: None,
+    }
+}
+
+// System that picks the music from the game state and the fights
+pub fn update_music(
+    time: Res<Time>,
+    audio: Res<Audio>,
+    asset_server: Res<AssetServer>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    state: Res<State<GameState>>,
+    mut music: ResMut<MusicController>,
+    mut boar_events: EventReader<BoarStateChanged>,
+    removed_boars: RemovedComponents<BoarAi>,
+) {
+    for event in boar_events.iter() {
+	if event.to == BoarState::Chase {
+	    music.chasing.insert(event.boar);
+	} else {
+	    music.chasing.remove(&event.boar);
+	}
+    }
+    for boar in removed_boars.iter() {
+	music.chasing.remove(&boar);
+    }
+
+    // A fight lasts from the first chase until COMBAT_MUSIC_DELAY seconds after the last one
+    if *state.current() == GameState::Playing {
+	if !music.chasing.is_empty() {
+	    if !music.in_combat {
+		music.in_combat = true;
+		music.combat_time = 0.0;
+	    }
+	    music.calm_time = 0.0;
+	} else if music.in_combat {
+	    music.calm_time += time.delta_seconds();
+	    if music.calm_time >= COMBAT_MUSIC_DELAY {
+		music.in_combat = false;
+	    }
+	}
+	if music.in_combat {
+	    music.combat_time += time.delta_seconds();
+	}
+    }
+
+    let wanted = music.wanted_track(state.current());
+    if music.tracks.current == Some(wanted) {
+	return;
+    }
+
+    // The combat track starts over at every fight, and so does the exploration track after a long one
+    if music.tracks.current == Some(MusicTrack::Combat) && wanted == MusicTrack::Exploration {
+	music.tracks.stop_when_silent.insert(MusicTrack::Combat);
+	if music.combat_time >= COMBAT_MUSIC_RESTART {
+	    music.tracks.stop(MusicTrack::Exploration, &audio_sinks);
+	}
+    }
+
+    music.tracks.switch_to(Some(wanted), |track| {
+	let sink = audio.play_with_settings(
+	    asset_server.load(track.path()),
+	    PlaybackSettings::LOOP.with_volume(0.0),
+	);
+	audio_sinks.get_handle(sink)
+    });
+}
+
+// System that fades the current track in and the others out, and applies the volume settings
+pub fn fade_music(
+    time: Res<Time>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    settings: Res<Settings>,
+    mut music: ResMut<MusicController>,
+) {
+    let volume = settings.volume(AudioChannel::Music);
+    music.tracks.fade(time.delta_seconds(), volume, &audio_sinks);
+}
+
+// System that picks the ambience from where the player is, there is none in the menus
+pub fn update_ambience(
+    audio: Res<Audio>,
+    asset_server: Res<AssetServer>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    state: Res<State<GameState>>,
+    location: Res<Location>,
+    mut ambience: ResMut<AmbienceController>,
+) {
+    let wanted = match (state.current(), *location) {
+	(GameState::Playing | GameState::QuestLog | GameState::Inventory | GameState::Chest | GameState::Dialogue | GameState::Shop, Location::Outdoors) => Some(Ambience::Outdoors),
+	(GameState::Playing | GameState::QuestLog | GameState::Inventory | GameState::Chest | GameState::Dialogue | GameState::Shop, Location::House) => Some(Ambience::Interior),
+	_ => None,
+    };
+    if ambience.beds.current == wanted {
+	return;
+    }
+    ambience.beds.switch_to(wanted, |bed| {
+	let sink = audio.play_with_settings(
+	    asset_server.load(bed.path()),
+	    PlaybackSettings::LOOP.with_volume(0.0),
+	);
+	audio_sinks.get_handle(sink)
+    });
+}
+
+// System that crossfades the ambience, the beds faded out in the menus are paused
+// and resume where they were
+pub fn fade_ambience(
+    time: Res<Time>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    settings: Res<Settings>,
+    mut ambience: ResMut<AmbienceController>,
+) {
+    let volume = settings.volume(AudioChannel::Music) * AMBIENCE_VOLUME;
+    ambience.beds.fade(time.delta_seconds(), volume, &audio_sinks);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    // The boars of a herd snorting in the same frame are heard once, from the closest one, and the far ones not at all
+    #[test]
+    fn same_sounds_of_a_frame_are_played_once() {
+	let mut world = World::new();
+	let boars: Vec<Entity> = (0..4).map(|_| world.spawn_empty().id()).collect();
+	let snort = |boar: Entity, offset: Vec2| (Sfx::Snort, boar_sound(boar, SNORT_VOLUME), offset);
+	let mixed = mix_spatial_sounds([
+	    snort(boars[0], Vec2::new(-400.0, 0.0)),
+	    snort(boars[1], Vec2::new(200.0, 0.0)),
+	    snort(boars[2], Vec2::new(-300.0, 0.0)),
+	    (Sfx::Squeal, boar_sound(boars[3], BOAR_SOUND_VOLUME), Vec2::new(0.0, BOAR_SOUND_DISTANCE + 1.0)),
+	]);
+	assert_eq!(mixed.len(), 1);
+	let (volume, pan) = mixed[&Sfx::Snort];
+	assert_eq!(volume, SNORT_VOLUME * (1.0 - 200.0 / BOAR_SOUND_DISTANCE));
+	assert_eq!(pan, 200.0 / BOAR_SOUND_DISTANCE);
+    }
+}
@@ -0,0 +1,242 @@
+// The game view: it follows the player, zooms, and keeps its shape with black bars
+
+use crate::*;
+
+// The shapes the game view can keep, black bars fill the rest of the window. The last one fills the window
+pub const ASPECT_RATIOS: [Option<f32>; 3] = [Some(16.0 / 9.0), Some(4.0 / 3.0), None];
+
+// Camera settings: the view leads the player in the way they move, by the look-ahead of the settings
+// at full speed. The offset moves toward its target at the given rate, so it recenters smoothly on a stop
+pub const CAMERA_LOOK_AHEAD: f32 = 48.0;
+pub const CAMERA_LOOK_AHEAD_RATE: f32 = 3.0;
+
+// Identifiers for cameras
+#[derive(Component)]
+pub struct MapCamera;
+
+// A black bar around the game view: 0 is on the left or at the top, 1 on the right or at the bottom
+#[derive(Component)]
+pub struct LetterboxBar(pub usize);
+
+// Places the cameras, they stay for the whole run. There are none without a window
+pub fn spawn_cameras(mut commands: Commands, map: Res<MapData>) {
+    // The camera starts on the player
+    let player_position = map.spawn_points(SpawnKind::Player, PLAYER_SPAWN)[0].position;
+    commands.spawn((
+	Camera2dBundle {
+	    projection: OrthographicProjection {
+		scale: 0.75,
+		..default()
+	    },
+	    transform: Transform::from_translation(player_position.extend(0.5)),
+	    ..default()
+	},
+	UiCameraConfig { show_ui: false },
+	MapCamera,
+	Persistent,
+    ));
+    // The UI has its own camera over the whole window, the map camera can leave black bars around the view
+    commands.spawn((
+	Camera2dBundle {
+	    camera: Camera {
+		priority: 1,
+		..default()
+	    },
+	    camera_2d: Camera2d {
+		clear_color: ClearColorConfig::None,
+	    },
+	    ..default()
+	},
+	RenderLayers::none(),
+	Persistent,
+    ));
+}
+
+// System that samples the textures with the nearest neighbor in pixel-perfect mode, smoothly otherwise.
+// It applies to the new textures, and to all of them when the setting changes
+pub fn apply_texture_sampling(
+    settings: Res<Settings>,
+    mut images: ResMut<Assets<Image>>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    // The setting the textures have
+    mut applied: Local<Option<bool>>,
+) {
+    let sampler = || if settings.pixel_perfect { ImageSampler::nearest() } else { ImageSampler::linear() };
+    if *applied != Some(settings.pixel_perfect) {
+	*applied = Some(settings.pixel_perfect);
+	let handles: Vec<_> = images.ids().collect();
+	for id in handles {
+	    if let Some(image) = images.get_mut(&Handle::weak(id)) {
+		image.sampler_descriptor = sampler();
+	    }
+	}
+	return;
+    }
+    for event in image_events.iter() {
+	if let AssetEvent::Created { handle } = event {
+	    if let Some(image) = images.get_mut(handle) {
+		image.sampler_descriptor = sampler();
+	    }
+	}
+    }
+}
+
+// System that keeps the camera on the player, a little ahead of where they go, without going past the walls
+// or, on a tilemap, without showing what is past its edges
+pub fn move_camera (
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    settings: Res<Settings>,
+    map: Res<MapData>,
+    mut look_ahead: Local<Vec2>,
+    query_player: Query<(&Transform, &Velocity, &Speed, &PlayerSize), (With<Player>, Without<MapCamera>)>,
+    mut query_camera: Query<(&mut Transform, &OrthographicProjection), With<MapCamera>>,
+) {
+    let Ok((mut camera_transform, camera_projection)) = query_camera.get_single_mut() else {
+	return;
+    };
+    let (player_transform, velocity, speed, player_size) = query_player.single();
+
+    // The offset is the look-ahead at the speed of the player (more when sprinting or dashing, up to the clamp),
+    // it eases toward its target and back to the center when the player stops
+    let target = if speed.0 > 0.0 {
+	(velocity.0 / speed.0 * settings.look_ahead).clamp_length_max(settings.look_ahead)
+    } else {
+	Vec2::ZERO
+    };
+    let ease = 1.0 - (-CAMERA_LOOK_AHEAD_RATE * simulation.dt()).exp();
+    *look_ahead = look_ahead.lerp(target, ease);
+    let focus = player_transform.translation.truncate() + *look_ahead;
+
+    // Bounds ensure that the camera never goes out of the screen, they are the ones of the player.
+    // The tilemap is seen whole, a tilemap smaller than the view is centered in it
+    let bounds = match &map.tilemap {
+	Some(tilemap) => {
+	    let area = tilemap.area();
+	    let half_view = Vec2::new(
+		camera_projection.right - camera_projection.left,
+		camera_projection.top - camera_projection.bottom,
+	    ) * camera_projection.scale / 2.0;
+	    let min = area.min + half_view;
+	    let max = area.max - half_view;
+	    Rect { min: min.min(area.center()), max: max.max(area.center()) }
+	},
+	None => play_area.bounds(player_size.0),
+    };
+
+    // Apply the translation
+    let mut x = focus.x.clamp(bounds.min.x, bounds.max.x);
+    let mut y = focus.y.clamp(bounds.min.y, bounds.max.y);
+    if settings.pixel_perfect {
+	// A screen pixel is `scale` world units wide
+	let pixel = camera_projection.scale;
+	x = snap_to_pixel(x, pixel, bounds.min.x, bounds.max.x);
+	y = snap_to_pixel(y, pixel, bounds.min.y, bounds.max.y);
+    }
+    camera_transform.translation.x = x;
+    camera_transform.translation.y = y;
+}
+
+// Rounds a coordinate to a whole number of pixels, without leaving the bounds:
+// past a bound the rounding goes the other way
+pub fn snap_to_pixel(value: f32, pixel: f32, min: f32, max: f32) -> f32 {
+    let snapped = (value / pixel).round() * pixel;
+    if snapped > max {
+	(value / pixel).floor() * pixel
+    } else if snapped < min {
+	(value / pixel).ceil() * pixel
+    } else {
+	snapped
+    }
+}
+
+// System that keeps the shape of the game view when the window or the settings change:
+// the map camera draws in the middle of the window, with black bars above and below or on the sides
+pub fn letterbox(
+    settings: Res<Settings>,
+    windows: Res<Windows>,
+    mut resized: EventReader<WindowResized>,
+    mut query_camera: Query<&mut Camera, With<MapCamera>>,
+    mut query_bar: Query<(&LetterboxBar, &mut Style)>,
+) {
+    if resized.iter().last().is_none() && !settings.is_changed() {
+	return;
+    }
+    let Some(window) = windows.get_primary() else {
+	return;
+    };
+    let window_size = Vec2::new(window.physical_width() as f32, window.physical_height() as f32);
+    if window_size.min_element() < 1.0 {
+	// Minimized
+	return;
+    }
+    let view_size = match settings.aspect_ratio {
+	Some(ratio) if window_size.x > window_size.y * ratio => Vec2::new(window_size.y * ratio, window_size.y),
+	Some(ratio) => Vec2::new(window_size.x, window_size.x / ratio),
+	None => window_size,
+    }.round().max(Vec2::ONE);
+    let bars = ((window_size - view_size) / 2.0).floor();
+
+    let Ok(mut camera) = query_camera.get_single_mut() else {
+	return;
+    };
+    camera.viewport = (view_size != window_size).then(|| Viewport {
+	physical_position: bars.as_uvec2(),
+	physical_size: view_size.as_uvec2(),
+	..default()
+    });
+
+    // The UI counts in logical pixels
+    let bar_size = bars / window.scale_factor() as f32;
+    for (bar, mut style) in &mut query_bar {
+	style.size = if bar_size.x > 0.0 {
+	    Size::new(Val::Px(bar_size.x), Val::Percent(100.0))
+	} else {
+	    Size::new(Val::Percent(100.0), Val::Px(bar_size.y))
+	};
+	style.position = if bar.0 == 0 {
+	    UiRect {
+		left: Val::Px(0.0),
+		top: Val::Px(0.0),
+		..default()
+	    }
+	} else {
+	    UiRect {
+		right: Val::Px(0.0),
+		bottom: Val::Px(0.0),
+		..default()
+	    }
+	};
+    }
+}
+
+// The cursor in the game view, which is smaller than the window when there are black bars
+pub fn cursor_in_view(window: &Window, camera: &Camera) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let Some(viewport) = &camera.viewport else {
+	return Some(cursor);
+    };
+    let scale = window.scale_factor() as f32;
+    let origin = viewport.physical_position.as_vec2() / scale;
+    let size = viewport.physical_size.as_vec2() / scale;
+    // The cursor counts from the bottom of the window, the viewport from the top
+    Some(Vec2::new(cursor.x - origin.x, cursor.y - (window.height() - origin.y - size.y)))
+}
+
+// System that checks if a zoom in/out input is made and acts accordingly
+pub fn zoom_camera(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut query_camera: Query<&mut OrthographicProjection, With<MapCamera>>,
+) {
+    let Ok(mut camera_proj) = query_camera.get_single_mut() else {
+	return;
+    };
+
+    if keyboard_input.pressed(KeyCode::Minus) {
+	camera_proj.scale *= 1.07;
+    }
+    if keyboard_input.pressed(KeyCode::Equals) {
+	camera_proj.scale *= 0.93;
+    }
+    camera_proj.scale = camera_proj.scale.clamp(0.5, 2.0);
+}
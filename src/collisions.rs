@@ -0,0 +1,262 @@
+// The solid obstacles, and how the characters slide along them
+
+use crate::*;
+
+// Movement of an entity, in units per second
+#[derive(Component, Default)]
+pub struct Velocity(pub Vec2);
+
+// Components to handle collisions
+#[derive(Component)]
+pub struct Collider;
+
+// An obstacle that breaks once its health is gone (crates, ...)
+#[derive(Component)]
+pub struct Destructible;
+
+// Sent when the player bumps into a collider (only once until the player moves away from it)
+pub struct CollisionEvent;
+
+// Size of the box used for the collisions of an entity: the sprite size, or the size of the characters
+pub fn collider_size(transform: &Transform, sprite: &Sprite) -> Vec2 {
+    sprite.custom_size.unwrap_or(CHARACTER_SIZE) * transform.scale.truncate()
+}
+
+// Cancels the part of a movement going into the side of an obstacle that was hit,
+// what remains makes the player slide along the obstacle
+pub fn slide_velocity(velocity: Vec2, collision: &Collision) -> Vec2 {
+    match collision {
+	Collision::Left if velocity.x > 0.0 => Vec2::new(0.0, velocity.y),
+	Collision::Right if velocity.x < 0.0 => Vec2::new(0.0, velocity.y),
+	Collision::Bottom if velocity.y > 0.0 => Vec2::new(velocity.x, 0.0),
+	Collision::Top if velocity.y < 0.0 => Vec2::new(velocity.x, 0.0),
+	_ => velocity,
+    }
+}
+
+// Moves a box out of an obstacle, through the side it collided with
+pub fn push_out(position: Vec2, size: Vec2, obstacle_position: Vec2, obstacle_size: Vec2, collision: &Collision) -> Vec2 {
+    let distance = (size + obstacle_size) / 2.0;
+    match collision {
+	Collision::Left => Vec2::new(obstacle_position.x - distance.x, position.y),
+	Collision::Right => Vec2::new(obstacle_position.x + distance.x, position.y),
+	Collision::Bottom => Vec2::new(position.x, obstacle_position.y - distance.y),
+	Collision::Top => Vec2::new(position.x, obstacle_position.y + distance.y),
+	Collision::Inside => position,
+    }
+}
+
+// The side of an obstacle that a box came in through during its last movement: the axis on which it was still apart
+// from the obstacle before moving. A fast box goes so deep into a thin wall in one tick that the overlap alone tells
+// the wrong side, or none when the box is wider than the wall (Inside). A box that was already overlapping keeps the
+// side of the overlap
+pub fn entry_side(center: Vec2, size: Vec2, movement: Vec2, obstacle_center: Vec2, obstacle_size: Vec2, collision: Collision) -> Collision {
+    let previous = center - movement;
+    let gap = (previous - obstacle_center).abs() - (size + obstacle_size) / 2.0;
+    let x_side = if movement.x > 0.0 { Collision::Left } else { Collision::Right };
+    let y_side = if movement.y > 0.0 { Collision::Bottom } else { Collision::Top };
+    match (gap.x >= 0.0, gap.y >= 0.0) {
+	(true, false) => x_side,
+	(false, true) => y_side,
+	// Past a corner, the axis on which the box was the farthest away was crossed last
+	(true, true) => if gap.x / movement.x.abs() >= gap.y / movement.y.abs() { x_side } else { y_side },
+	(false, false) => collision,
+    }
+}
+
+// Whether the segment between two points goes through none of the obstacles (given as center and size)
+pub fn line_of_sight(from: Vec2, to: Vec2, obstacles: &[(Vec2, Vec2)]) -> bool {
+    let delta = to - from;
+    !obstacles.iter().any(|(center, size)| {
+	let min = *center - *size / 2.0;
+	let max = *center + *size / 2.0;
+	// The parts of the segment between the sides of the box, on each axis, must overlap
+	let (mut enter, mut exit) = (0.0_f32, 1.0_f32);
+	for axis in 0..2 {
+	    if delta[axis] == 0.0 {
+		if from[axis] < min[axis] || from[axis] > max[axis] {
+		    return false;
+		}
+		continue;
+	    }
+	    let to_min = (min[axis] - from[axis]) / delta[axis];
+	    let to_max = (max[axis] - from[axis]) / delta[axis];
+	    enter = enter.max(to_min.min(to_max));
+	    exit = exit.min(to_min.max(to_max));
+	}
+	enter <= exit
+    })
+}
+
+// Moves a box out of the obstacles (given as center and size), one after the other, back through the side it came
+// from with the movement of the tick. Returns the new center and velocity, and adds the indices of the obstacles hit
+// to touched
+pub fn resolve_collisions(
+    mut center: Vec2,
+    size: Vec2,
+    mut velocity: Vec2,
+    movement: Vec2,
+    obstacles: &[(Vec2, Vec2)],
+    touched: &mut Vec<usize>,
+) -> (Vec2, Vec2) {
+    for (index, &(obstacle_center, obstacle_size)) in obstacles.iter().enumerate() {
+	let Some(collision) = collide(center.extend(0.0), size, obstacle_center.extend(0.0), obstacle_size) else {
+	    continue;
+	};
+	let collision = entry_side(center, size, movement, obstacle_center, obstacle_size, collision);
+	touched.push(index);
+	center = push_out(center, size, obstacle_center, obstacle_size, &collision);
+	velocity = slide_velocity(velocity, &collision);
+    }
+    (center, velocity)
+}
+
+// System that keeps the player out of the colliders: only the blocked direction of the
+// movement is cancelled, so that moving diagonally into a wall slides along it
+pub fn check_for_collisions(
+    simulation: Res<Simulation>,
+    mut collision_events: EventWriter<CollisionEvent>,
+    mut query_player: Query<(&mut Transform, &mut Velocity, &PlayerSize), With<Player>>,
+    query_collider: Query<(Entity, &Transform, &Sprite), (With<Collider>, Without<Player>)>,
+    // The colliders touched at the previous tick
+    mut touching: Local<HashSet<Entity>>,
+) {
+    let (mut player_transform, mut velocity, player_size) = query_player.single_mut();
+    let (entities, obstacles): (Vec<_>, Vec<_>) = query_collider
+	.iter()
+	.map(|(obstacle, transform, sprite)| (obstacle, (transform.translation.truncate(), collider_size(transform, sprite))))
+	.unzip();
+
+    // The box of the player is moved out
+    let position = player_transform.translation.truncate();
+    let mut touched_indices = Vec::new();
+    let movement = velocity.0 * simulation.dt();
+    let (new_position, new_velocity) = resolve_collisions(position, player_size.0, velocity.0, movement,
+							  &obstacles, &mut touched_indices);
+    player_transform.translation.x = new_position.x;
+    player_transform.translation.y = new_position.y;
+    velocity.0 = new_velocity;
+
+    let touched: HashSet<Entity> = touched_indices.into_iter().map(|index| entities[index]).collect();
+    for obstacle in &touched {
+	if !touching.contains(obstacle) {
+	    collision_events.send(CollisionEvent);
+	}
+    }
+    *touching = touched;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    // Collision benchmark settings, the counts can be changed with the variables of the same name
+    const BENCH_COLLIDERS: usize = 1000;
+    const BENCH_ITERATIONS: u32 = 1000;
+    const BENCH_AREA: f32 = 2000.0;
+
+    // The count given by the environment variable, or the default one when it is not set
+    fn bench_count<T: std::str::FromStr>(name: &str, default: T) -> T {
+	match env::var(name) {
+	    Ok(value) => value.parse().unwrap_or_else(|_| panic!("{name} should be a count, not {value:?}")),
+	    Err(_) => default,
+	}
+    }
+
+    // Dashing or sprinting into a wall of the editor stops at its side, even though one tick goes deeper than the wall
+    // is thick
+    #[test]
+    fn fast_moves_do_not_go_through_thin_walls() {
+	for (key, ability) in [(DASH_KEY, Ability::Dash), (SPRINT_KEY, Ability::Sprint)] {
+	    let mut test = TestApp::new();
+	    test.app.world.resource_mut::<Abilities>().unlocked.insert(ability);
+	    test.add_fixed_systems(SystemSet::new()
+				   .with_system(update_sprint)
+				   .with_system(move_player.after(update_sprint))
+				   .with_system(check_for_collisions.after(move_player)));
+	    let segment = WallSegment { position: Vec2::ZERO, size: Vec2::new(WALL_THICKNESS, EDITOR_GRID) };
+	    test.app.world.spawn((WallBundle::new(WallLocation::Inside(segment), None, &PlayArea::default()), InsideWall));
+	    let side = segment.position.x - (PLAYER_SIZE.x + segment.size.x) / 2.0;
+	    let player = test.spawn_player(Vec2::new(side, segment.size.y / 2.0));
+
+	    test.press(KeyCode::D);
+	    test.press(key);
+	    test.step_fixed(test.ticks(1.0));
+	    assert_eq!(test.position(player).x, side, "{}", ability.name());
+	}
+    }
+
+    // Walking into the house sends one collision event, and another one only after leaving it
+    #[test]
+    fn bumping_into_the_house_sends_a_collision_event() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new()
+			       .with_system(move_player)
+			       .with_system(check_for_collisions.after(move_player)));
+	let player = test.spawn_player(Vec2::ZERO);
+	let house_position = Vec2::new(200.0, 0.0);
+	test.app.world.spawn((
+	    SpriteBundle {
+		transform: Transform::from_translation(house_position.extend(0.0)),
+		..default()
+	    },
+	    House,
+	    Collider,
+	));
+	let mut reader = test.app.world.resource::<Events<CollisionEvent>>().get_reader();
+	let mut collisions = |test: &mut TestApp, ticks: u32| {
+	    (0..ticks).map(|_| {
+		test.step_fixed(1);
+		reader.iter(test.app.world.resource::<Events<CollisionEvent>>()).count()
+	    }).sum::<usize>()
+	};
+	// Long enough to reach the house and keep pushing against it
+	let walk = test.ticks(house_position.x / PLAYER_SPEED) * 2;
+
+	test.press(KeyCode::D);
+	assert_eq!(collisions(&mut test, walk), 1);
+	// Stopped against the side of the house
+	assert!(test.position(player).x <= house_position.x - (PLAYER_SIZE.x + CHARACTER_SIZE.x) / 2.0);
+
+	test.release(KeyCode::D);
+	test.press(KeyCode::A);
+	assert_eq!(collisions(&mut test, walk), 0);
+
+	// Back from farther away
+	test.release(KeyCode::A);
+	test.press(KeyCode::D);
+	assert_eq!(collisions(&mut test, 2 * walk), 1);
+    }
+
+    // Times the collisions of the player against many colliders scattered around it, without the game.
+    // It gives a baseline before optimizing the collisions, and shows when they get slower.
+    // Run with: cargo test --release bench_collisions -- --ignored --nocapture
+    // or for another size: BENCH_COLLIDERS=5000 BENCH_ITERATIONS=200 cargo test --release bench_collisions -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_collisions() {
+	let colliders = bench_count("BENCH_COLLIDERS", BENCH_COLLIDERS);
+	let iterations = bench_count("BENCH_ITERATIONS", BENCH_ITERATIONS);
+	let mut rng = rand::thread_rng();
+	let obstacles: Vec<(Vec2, Vec2)> = (0..colliders)
+	    .map(|_| {
+		let center = Vec2::new(rng.gen_range(-BENCH_AREA..BENCH_AREA), rng.gen_range(-BENCH_AREA..BENCH_AREA));
+		(center, Vec2::splat(EDITOR_GRID))
+	    })
+	    .collect();
+	let mut touched = Vec::new();
+	let start = Instant::now();
+	for iteration in 0..iterations {
+	    // The player walks across the area so that it hits different colliders
+	    let progress = iteration as f32 / iterations as f32;
+	    let position = Vec2::splat(-BENCH_AREA + 2.0 * BENCH_AREA * progress);
+	    touched.clear();
+	    let velocity = Vec2::ONE * PLAYER_SPEED;
+	    std::hint::black_box(resolve_collisions(position, PLAYER_SIZE, velocity, velocity / DEFAULT_TICK_RATE,
+						    &obstacles, &mut touched));
+	}
+	let elapsed = start.elapsed();
+	println!("{colliders} colliders, {iterations} iterations: {:?} per iteration", elapsed / iterations);
+    }
+}
@@ -0,0 +1,717 @@
+// Attacks, health and damage, for the player and the NPCs
+
+use crate::*;
+
+// A dead boar fades away where it fell
+pub const BOAR_DEATH_FADE_TIME: f32 = 0.6;
+// The damage dealt floats up from the character hit, and fades
+pub const DAMAGE_TEXT_TIME: f32 = 0.8;
+pub const DAMAGE_TEXT_RISE: f32 = 30.0;
+pub const DAMAGE_TEXT_SIZE: f32 = 20.0;
+pub const DAMAGE_TEXT_COLOR: Color = Color::rgb(1.0, 0.95, 0.8);
+pub const DAMAGE_TEXT_Z: f32 = 0.45;
+
+// Melee settings: the attack hurts the boars in a box in front of the player, and pushes them away
+pub const ATTACK_KEY: KeyCode = KeyCode::Space;
+pub const ATTACK_DAMAGE: f32 = 10.0;
+pub const ATTACK_COOLDOWN: f32 = 0.5;
+pub const ATTACK_REACH: f32 = 48.0;
+pub const ATTACK_SIZE: Vec2 = Vec2::new(48.0, 48.0);
+pub const ATTACK_TIME: f32 = 0.15;
+// A press a bit before the end of the cooldown is kept, the attack comes as soon as it is over (three ticks)
+pub const ATTACK_BUFFER_TIME: f32 = 0.25;
+// With aim assist, the attack turns to the boars this close
+pub const AIM_ASSIST_RANGE: f32 = 96.0;
+// The slash drawn over the hitbox grows during the attack, and changes color when it hits
+pub const SLASH_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.8);
+pub const SLASH_HIT_COLOR: Color = Color::rgba(1.0, 0.55, 0.25, 0.9);
+pub const SLASH_START_SCALE: f32 = 0.6;
+pub const KNOCKBACK_SPEED_PER_DAMAGE: f32 = 30.0;
+pub const KNOCKBACK_TIME: f32 = 0.2;
+// The game freezes for a moment on a killing blow and when the boss is hit, not with reduced motion
+pub const HIT_STOP_KILL_TIME: f32 = 0.08;
+pub const HIT_STOP_BOSS_TIME: f32 = 0.05;
+
+// Health settings. The health the player starts with is in the loadout file (in the assets folder), this one is the default
+pub const PLAYER_HEALTH: f32 = 100.0;
+pub const LOADOUT_FILE: &str = "config/loadout.ron";
+
+// Grab settings: a weakened boar next to the player can be carried, then thrown at the other boars
+pub const GRAB_KEY: KeyCode = KeyCode::E;
+pub const GRAB_RANGE: f32 = 80.0;
+pub const GRAB_HEALTH_RATIO: f32 = 0.25;
+pub const CARRY_OFFSET: Vec3 = Vec3::new(0.0, 40.0, 0.01);
+pub const THROW_SPEED: f32 = 500.0;
+pub const THROW_TIME: f32 = 0.6;
+pub const THROW_DAMAGE: f32 = 20.0;
+
+// Hurts the player on touch, then waits for the cooldown before hurting again
+#[derive(Component)]
+pub struct ContactDamage {
+    pub amount: f32,
+    pub cooldown: Timer,
+}
+
+impl ContactDamage {
+    // The first touch hurts right away
+    pub fn new(amount: f32) -> ContactDamage {
+	let mut cooldown = Timer::from_seconds(CONTACT_DAMAGE_COOLDOWN, TimerMode::Once);
+	cooldown.tick(cooldown.duration());
+	ContactDamage { amount, cooldown }
+    }
+}
+
+// Health of the characters, they die when it reaches zero
+#[derive(Component)]
+pub struct HealthPoints {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl HealthPoints {
+    pub fn new(max: f32) -> HealthPoints {
+	HealthPoints { current: max, max }
+    }
+
+    pub fn ratio(&self) -> f32 {
+	self.current / self.max
+    }
+
+    pub fn heal(&mut self, amount: f32) {
+	self.current = (self.current + amount).min(self.max);
+    }
+}
+
+// Melee attack of the player, it goes the way the player is facing
+#[derive(Component)]
+pub struct Attack {
+    pub cooldown: Timer,
+    // Running while a press waits for the cooldown
+    pub buffer: Timer,
+}
+
+impl Attack {
+    pub fn new() -> Attack {
+	let mut cooldown = Timer::from_seconds(ATTACK_COOLDOWN, TimerMode::Once);
+	// The player can attack from the start
+	cooldown.tick(Duration::from_secs_f32(ATTACK_COOLDOWN));
+	let mut attack = Attack {
+	    cooldown,
+	    buffer: Timer::from_seconds(ATTACK_BUFFER_TIME, TimerMode::Once),
+	};
+	attack.consume_buffer();
+	attack
+    }
+
+    pub fn buffered(&self) -> bool {
+	!self.buffer.finished()
+    }
+
+    pub fn consume_buffer(&mut self) {
+	let duration = self.buffer.duration();
+	self.buffer.tick(duration);
+    }
+}
+
+// While running, the movements, the AI, the animations and the tweens wait. It counts down in real time
+#[derive(Resource)]
+pub struct HitStop(pub Timer);
+
+impl Default for HitStop {
+    fn default() -> HitStop {
+	let mut timer = Timer::from_seconds(HIT_STOP_KILL_TIME, TimerMode::Once);
+	timer.tick(Duration::from_secs_f32(HIT_STOP_KILL_TIME));
+	HitStop(timer)
+    }
+}
+
+impl HitStop {
+    pub fn active(&self) -> bool {
+	!self.0.finished()
+    }
+
+    // A longer hit-stop is not cut short
+    pub fn start(&mut self, duration: f32) {
+	let remaining = self.0.duration().as_secs_f32() - self.0.elapsed_secs();
+	if !self.active() || duration > remaining {
+	    self.0 = Timer::from_seconds(duration, TimerMode::Once);
+	}
+    }
+}
+
+// Area hurting the boars for a short time, spawned as a child of the player by an attack
+#[derive(Component)]
+pub struct AttackHitbox {
+    pub damage: f32,
+    // A boar is only hit once by each attack
+    pub hit: HashSet<Entity>,
+}
+
+// The visible arc of an attack, a child of the hitbox
+#[derive(Component)]
+pub struct Slash;
+
+// A boar pushed away by a hit, it does not act until the timer finishes
+#[derive(Component)]
+pub struct Knockback(pub Timer);
+
+// Box used for the collisions of a character, it can be smaller than the sprite or off its center.
+// The offset is for a sprite facing right, it is mirrored when the sprite is flipped
+#[derive(Component, Deserialize, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hitbox {
+    pub offset: Vec2,
+    pub size: Vec2,
+}
+
+impl Default for Hitbox {
+    fn default() -> Hitbox {
+	Hitbox {
+	    offset: Vec2::ZERO,
+	    size: CHARACTER_SIZE,
+	}
+    }
+}
+
+impl Hitbox {
+    pub fn center(&self, position: Vec2, flip_x: bool) -> Vec2 {
+	if flip_x {
+	    position + Vec2::new(-self.offset.x, self.offset.y)
+	} else {
+	    position + self.offset
+	}
+    }
+}
+
+// A boar held above the head of the player (it is a child of the player meanwhile)
+#[derive(Component)]
+pub struct Carried;
+
+// A boar flying after being thrown, it lands when the timer finishes or when it hits something
+#[derive(Component)]
+pub struct Thrown(pub Timer);
+
+// Sent when something hurts an entity that has HealthPoints
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    // Where the hit came from
+    pub source: Vec2,
+}
+
+// Sent when an NPC dies, the entity is despawned at the end of the frame
+pub struct DeathEvent(pub Entity);
+
+// System that hurts the player touched by the NPCs that bite or sting
+pub fn contact_damage(
+    simulation: Res<Simulation>,
+    mut damage_events: EventWriter<DamageEvent>,
+    query_player: Query<(Entity, &Transform, &PlayerSize), With<Player>>,
+    mut query_npc: Query<(&Transform, &Sprite, &Hitbox, &mut ContactDamage), (Without<Player>, Without<Carried>, Without<Thrown>, Without<SpawningIn>)>,
+) {
+    let (player, player_transform, player_size) = query_player.single();
+    for (npc_transform, npc_sprite, npc_hitbox, mut contact) in &mut query_npc {
+	if !contact.cooldown.tick(Duration::from_secs_f32(simulation.dt())).finished() {
+	    continue;
+	}
+	let npc_center = npc_hitbox.center(npc_transform.translation.truncate(), npc_sprite.flip_x);
+	if collide(player_transform.translation, player_size.0, npc_center.extend(0.0), npc_hitbox.size).is_some() {
+	    damage_events.send(DamageEvent { target: player, amount: contact.amount, source: npc_center });
+	    contact.cooldown.reset();
+	}
+    }
+}
+
+// System that picks up a weakened boar next to the player, or throws the boar being carried
+pub fn grab_boar(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    query_player: Query<(Entity, &Transform, &Facing), With<Player>>,
+    query_boar: Query<(Entity, &Transform, &HealthPoints, Option<&Carried>), (With<BoarAi>, Without<Player>, Without<Thrown>, Without<SpawningIn>, Without<Boss>)>,
+) {
+    let (player, player_transform, facing) = query_player.single();
+    if !bindings.just_pressed(&keyboard_input, Action::Grab) {
+	return;
+    }
+
+    // Throw the boar if one is carried
+    if let Some((boar, _, _, _)) = query_boar.iter().find(|(_, _, _, carried)| carried.is_some()) {
+	commands.entity(player).remove_children(&[boar]);
+	commands.entity(boar).remove::<Carried>().insert((
+	    Transform::from_translation(player_transform.translation + CARRY_OFFSET),
+	    // The boar is thrown the way the player is facing
+	    Velocity(facing.direction() * THROW_SPEED),
+	    Thrown(Timer::from_seconds(THROW_TIME, TimerMode::Once)),
+	));
+	return;
+    }
+
+    // Otherwise pick up the closest weakened boar in range, not behind the player
+    let closest = query_boar.iter()
+	.filter(|(_, boar_transform, health, _)| {
+	    let offset = (boar_transform.translation - player_transform.translation).truncate();
+	    health.ratio() <= GRAB_HEALTH_RATIO
+		&& offset.length() <= GRAB_RANGE
+		&& offset.dot(facing.direction()) >= 0.0
+	})
+	.min_by(|(_, a, _, _), (_, b, _, _)| {
+	    let distance_a = a.translation.distance(player_transform.translation);
+	    let distance_b = b.translation.distance(player_transform.translation);
+	    distance_a.total_cmp(&distance_b)
+	});
+    if let Some((boar, _, _, _)) = closest {
+	commands.entity(player).add_child(boar);
+	// While carried, the translation is relative to the player
+	commands.entity(boar).insert((Carried, Transform::from_translation(CARRY_OFFSET)));
+    }
+}
+
+// System that moves the thrown boars, which hurt the boars they hit and land on walls
+pub fn move_thrown_boars(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    mut commands: Commands,
+    mut particles: ResMut<Particles>,
+    hit_stop: Res<HitStop>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut query_thrown: Query<(Entity, &mut Transform, &mut Velocity, &mut Thrown)>,
+    query_boar: Query<(Entity, &Transform), (With<BoarAi>, Without<Thrown>, Without<Carried>, Without<SpawningIn>)>,
+    query_destructible: Query<(Entity, &Transform, &Sprite), (With<Destructible>, Without<Thrown>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    for (thrown_boar, mut boar_transform, mut velocity, mut thrown) in &mut query_thrown {
+	thrown.0.tick(Duration::from_secs_f32(simulation.dt()));
+	let new_position = boar_transform.translation.truncate() + velocity.0 * simulation.dt();
+
+	// Bounds are the same as for the other characters
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+	let clamped_position = Vec2::new(
+	    new_position.x.clamp(bounds.min.x, bounds.max.x),
+	    new_position.y.clamp(bounds.min.y, bounds.max.y),
+	);
+	boar_transform.translation.x = clamped_position.x;
+	boar_transform.translation.y = clamped_position.y;
+	let mut landed = thrown.0.finished() || clamped_position != new_position;
+
+	for (other_boar, other_transform) in &query_boar {
+	    if collide(boar_transform.translation, CHARACTER_SIZE,
+		       other_transform.translation, CHARACTER_SIZE).is_some() {
+		damage_events.send(DamageEvent { target: other_boar, amount: THROW_DAMAGE, source: clamped_position });
+		landed = true;
+		break;
+	    }
+	}
+	// The crates stop the thrown boars, and break
+	for (obstacle, obstacle_transform, obstacle_sprite) in &query_destructible {
+	    if !landed && collide(boar_transform.translation, CHARACTER_SIZE, obstacle_transform.translation,
+				  collider_size(obstacle_transform, obstacle_sprite)).is_some() {
+		damage_events.send(DamageEvent { target: obstacle, amount: THROW_DAMAGE, source: clamped_position });
+		landed = true;
+	    }
+	}
+
+	if landed {
+	    velocity.0 = Vec2::ZERO;
+	    commands.entity(thrown_boar).remove::<Thrown>();
+	    let feet = boar_transform.translation + Vec3::new(0.0, SHADOW_OFFSET, 0.01);
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Dust, feet, DUST_LANDING_COUNT);
+	}
+    }
+}
+
+// System that starts an attack when the attack key is held and the previous one is over
+pub fn player_attack(
+    simulation: Res<Simulation>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    accessibility: Res<Accessibility>,
+    loadout: Res<PlayerLoadout>,
+    mut query_player: Query<(Entity, &Transform, &Facing, &mut Attack, Option<&Eating>), With<Player>>,
+    query_npc: Query<&Transform, (With<Hitbox>, Without<Player>, Without<Carried>, Without<SpawningIn>)>,
+) {
+    let (player, player_transform, facing, mut attack, eating) = query_player.single_mut();
+    attack.cooldown.tick(Duration::from_secs_f32(simulation.dt()));
+    attack.buffer.tick(Duration::from_secs_f32(simulation.dt()));
+    let wanted = bindings.pressed(&keyboard_input, Action::Attack) || attack.buffered();
+    if !wanted || !attack.cooldown.finished() || eating.is_some() || !loadout.melee {
+	return;
+    }
+    attack.cooldown.reset();
+    attack.consume_buffer();
+
+    // With aim assist, the attack goes towards the closest NPC in range whatever the facing
+    let position = player_transform.translation.truncate();
+    let target = accessibility.aim_assist.then(|| {
+	query_npc.iter()
+	    .map(|npc_transform| npc_transform.translation.truncate() - position)
+	    .filter(|offset| offset.length() <= AIM_ASSIST_RANGE)
+	    .min_by(|a, b| a.length().total_cmp(&b.length()))
+    }).flatten();
+    let direction = target.map_or(facing.direction(), |offset| offset.normalize_or_zero());
+
+    // The hitbox follows the player until it disappears, and the slash with it.
+    // The slash is drawn just over the player, it points to where the attack goes
+    let rotation = Quat::from_rotation_z(direction.y.atan2(direction.x));
+    let hitbox = commands.spawn((
+	SpatialBundle::from_transform(Transform::from_translation((direction * ATTACK_REACH).extend(0.0))),
+	AttackHitbox {
+	    damage: ATTACK_DAMAGE,
+	    hit: HashSet::new(),
+	},
+	Lifetime::new(ATTACK_TIME),
+    )).with_children(|parent| {
+	parent.spawn((
+	    SpriteBundle {
+		texture: asset_server.load("sprites/slash.png"),
+		sprite: Sprite {
+		    color: SLASH_COLOR,
+		    ..default()
+		},
+		transform: Transform::from_xyz(0.0, 0.0, 0.01).with_rotation(rotation),
+		..default()
+	    },
+	    Tween::scale(Vec3::splat(SLASH_START_SCALE), Vec3::ONE).over(ATTACK_TIME).ease(Easing::EaseOut),
+	    Slash,
+	));
+    }).id();
+    commands.entity(player).add_child(hitbox);
+}
+
+// System that keeps the presses of the attack key for player_attack. It runs every frame: a quick press
+// between two ticks would be missed, and one made during the cooldown fires once it is over
+pub fn buffer_attack(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut query_player: Query<&mut Attack, With<Player>>,
+) {
+    if bindings.just_pressed(&keyboard_input, Action::Attack) {
+	query_player.single_mut().buffer.reset();
+    }
+}
+
+// System that colors the slash of an attack once it hits something
+pub fn tint_slashes(
+    mut damage_events: EventReader<DamageEvent>,
+    query_hitbox: Query<(&AttackHitbox, &Children)>,
+    mut query_slash: Query<&mut Sprite, With<Slash>>,
+) {
+    for event in damage_events.iter() {
+	for (hitbox, children) in &query_hitbox {
+	    if !hitbox.hit.contains(&event.target) {
+		continue;
+	    }
+	    let mut slashes = query_slash.iter_many_mut(children);
+	    while let Some(mut sprite) = slashes.fetch_next() {
+		sprite.color = SLASH_HIT_COLOR;
+	    }
+	}
+    }
+}
+
+// System that hurts the NPCs touched by an attack and knocks them back, harder for stronger attacks.
+// A boar hit by the player starts chasing
+pub fn attack_hits(
+    mut commands: Commands,
+    mut particles: ResMut<Particles>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut state_events: EventWriter<BoarStateChanged>,
+    query_player: Query<&Transform, With<Player>>,
+    mut query_hitbox: Query<(&GlobalTransform, &mut AttackHitbox)>,
+    mut query_npc: Query<(Entity, &Transform, &Sprite, &Hitbox, &mut Velocity, Option<&mut BoarAi>, Option<&Tamed>), (Without<Player>, Without<Carried>, Without<Thrown>, Without<SpawningIn>)>,
+    query_destructible: Query<(Entity, &Transform, &Sprite), With<Destructible>>,
+) {
+    let player_position = query_player.single().translation.truncate();
+
+    for (hitbox_transform, mut hitbox) in &mut query_hitbox {
+	for (boar, boar_transform, boar_sprite, boar_hitbox, mut velocity, boar_ai, tamed) in &mut query_npc {
+	    let boar_center = boar_hitbox.center(boar_transform.translation.truncate(), boar_sprite.flip_x);
+	    if hitbox.hit.contains(&boar)
+		|| collide(hitbox_transform.translation(), ATTACK_SIZE,
+			   boar_center.extend(0.0), boar_hitbox.size).is_none() {
+		continue;
+	    }
+	    hitbox.hit.insert(boar);
+	    damage_events.send(DamageEvent { target: boar, amount: hitbox.damage, source: player_position });
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Impact, boar_center.extend(IMPACT_Z), IMPACT_COUNT);
+
+	    let away = (boar_transform.translation.truncate() - player_position).normalize_or_zero();
+	    velocity.0 = away * hitbox.damage * KNOCKBACK_SPEED_PER_DAMAGE;
+	    commands.entity(boar).insert(Knockback(Timer::from_seconds(KNOCKBACK_TIME, TimerMode::Once)));
+	    if let Some(mut boar_ai) = boar_ai.filter(|boar_ai| boar_ai.state != BoarState::Chase && tamed.is_none()) {
+		boar_ai.switch_to(BoarState::Chase);
+		state_events.send(BoarStateChanged { boar, to: BoarState::Chase });
+	    }
+	}
+
+	for (obstacle, obstacle_transform, obstacle_sprite) in &query_destructible {
+	    if hitbox.hit.contains(&obstacle)
+		|| collide(hitbox_transform.translation(), ATTACK_SIZE,
+			   obstacle_transform.translation, collider_size(obstacle_transform, obstacle_sprite)).is_none() {
+		continue;
+	    }
+	    hitbox.hit.insert(obstacle);
+	    damage_events.send(DamageEvent { target: obstacle, amount: hitbox.damage, source: player_position });
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Impact,
+			obstacle_transform.translation.truncate().extend(IMPACT_Z), IMPACT_COUNT);
+	}
+    }
+}
+
+// System that slides the knocked back boars, they stop at the walls like when walking
+pub fn move_knocked_back(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    mut commands: Commands,
+    hit_stop: Res<HitStop>,
+    mut query_boar: Query<(Entity, &mut Transform, &mut Velocity, &mut Knockback), Without<Carried>>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    for (boar, mut boar_transform, mut velocity, mut knockback) in &mut query_boar {
+	let new_position = boar_transform.translation.truncate() + velocity.0 * simulation.dt();
+
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+	boar_transform.translation.x = new_position.x.clamp(bounds.min.x, bounds.max.x);
+	boar_transform.translation.y = new_position.y.clamp(bounds.min.y, bounds.max.y);
+
+	knockback.0.tick(Duration::from_secs_f32(simulation.dt()));
+	if knockback.0.finished() {
+	    velocity.0 = Vec2::ZERO;
+	    commands.entity(boar).remove::<Knockback>();
+	}
+    }
+}
+
+// System that removes health from the damaged entities, the NPCs are removed when they die
+pub fn apply_damage(
+    mut commands: Commands,
+    loot: Res<LootTables>,
+    mut game_rng: ResMut<GameRng>,
+    mut particles: ResMut<Particles>,
+    accessibility: Res<Accessibility>,
+    mut stats: ResMut<Stats>,
+    mut run_stats: ResMut<RunStats>,
+    mut hit_stop: ResMut<HitStop>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut spatial_sfx_events: EventWriter<PlaySpatialSfx>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut deaths: EventWriter<DeathEvent>,
+    mut experience_events: EventWriter<ExperienceGained>,
+    mut query_health: Query<(&mut HealthPoints, Option<&Player>, Option<&Destructible>, Option<&Boss>, Option<&Boar>, Option<&Loot>, Option<&mut Barks>, &GlobalTransform, &Sprite, &Handle<Image>), Without<Invulnerable>>,
+) {
+    let rng = &mut game_rng.0;
+    for event in damage_events.iter() {
+	let Ok((mut health, player, destructible, boss, boar, loot_table, barks, transform, sprite, texture)) = query_health.get_mut(event.target) else {
+	    continue;
+	};
+	if health.current == 0.0 {
+	    // Already dead, from another hit of the same tick
+	    continue;
+	}
+	if player.is_some() {
+	    run_stats.damage_taken += event.amount.min(health.current);
+	}
+	health.current = (health.current - event.amount).max(0.0);
+	// The squeal is heard from where the boar was hit, a killed boar is despawned before it is played,
+	// and always squeals
+	if let Some(mut barks) = barks {
+	    if barks.squeal_cooldown.finished() || health.current == 0.0 {
+		barks.squeal_cooldown.reset();
+		let sound = boar_sound(event.target, BOAR_SOUND_VOLUME).at(transform.translation().truncate());
+		spatial_sfx_events.send(PlaySpatialSfx(Sfx::Squeal, sound));
+	    }
+	}
+	if !accessibility.reduced_motion && player.is_none() && destructible.is_none() {
+	    if health.current == 0.0 {
+		hit_stop.start(HIT_STOP_KILL_TIME);
+	    } else if boss.is_some() {
+		hit_stop.start(HIT_STOP_BOSS_TIME);
+	    }
+	}
+	if health.current == 0.0 && destructible.is_some() {
+	    // A broken obstacle flies to pieces
+	    commands.entity(event.target).despawn_recursive();
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Splinter, transform.translation(), CRATE_SPLINTER_COUNT);
+	    sfx_events.send(PlaySfx(Sfx::Thud));
+	    let drops = loot.roll(CRATE_LOOT, rng);
+	    spawn_loot(&mut commands, rng, &drops, transform.translation().truncate());
+	} else if health.current == 0.0 && player.is_none() {
+	    commands.entity(event.target).despawn_recursive();
+	    // A copy stays behind and fades, it does not interact with anything
+	    commands.spawn((
+		SpriteBundle {
+		    texture: texture.clone(),
+		    sprite: sprite.clone(),
+		    transform: transform.compute_transform(),
+		    ..default()
+		},
+		Tween::alpha(sprite.color.a(), 0.0).over(BOAR_DEATH_FADE_TIME).ease(Easing::EaseInOut).despawn_on_complete(),
+	    ));
+	    if let Some(Loot(table)) = loot_table {
+		let drops = loot.roll(table, rng);
+		spawn_loot(&mut commands, rng, &drops, transform.translation().truncate());
+		experience_events.send(ExperienceGained(loot.xp(table)));
+	    }
+	    deaths.send(DeathEvent(event.target));
+	    if boar.is_some() {
+		stats.boars_killed += 1;
+		stats.save();
+	    }
+	} else if health.current == 0.0 {
+	    stats.deaths += 1;
+	    stats.alive_time = 0.0;
+	    stats.save();
+	}
+    }
+}
+
+pub fn tick_hit_stop(time: Res<Time>, mut hit_stop: ResMut<HitStop>) {
+    hit_stop.0.tick(time.delta());
+}
+
+// System that shows the damage dealt above the characters hit
+pub fn damage_numbers(
+    mut commands: Commands,
+    mut damage_events: EventReader<DamageEvent>,
+    query_target: Query<&GlobalTransform, (With<HealthPoints>, Without<Invulnerable>)>,
+) {
+    for event in damage_events.iter() {
+	let Ok(target_transform) = query_target.get(event.target) else {
+	    continue;
+	};
+	let start = target_transform.translation().truncate().extend(DAMAGE_TEXT_Z) + Vec3::Y * CHARACTER_SIZE.y / 2.0;
+	commands.spawn_floating_damage(event.amount, start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    // A boar touching the player bites right away, then waits for the cooldown before biting again
+    #[test]
+    fn boar_hurts_the_player_on_contact() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new()
+			       .with_system(contact_damage)
+			       .with_system(apply_damage.after(contact_damage)));
+	let player = test.spawn_player(Vec2::ZERO);
+	let bite = 10.0;
+	test.app.world.spawn((
+	    SpriteBundle {
+		transform: Transform::from_translation(Vec3::new(PLAYER_SIZE.x / 2.0, 0.0, 0.1)),
+		..default()
+	    },
+	    Boar,
+	    Hostile,
+	    Hitbox { offset: Vec2::ZERO, size: CHARACTER_SIZE },
+	    ContactDamage::new(bite),
+	));
+	let health = |test: &TestApp| test.app.world.get::<HealthPoints>(player).unwrap().current;
+
+	test.step_fixed(1);
+	assert_eq!(health(&test), PLAYER_HEALTH - bite);
+
+	test.step_fixed(test.ticks(CONTACT_DAMAGE_COOLDOWN) - 1);
+	assert_eq!(health(&test), PLAYER_HEALTH - bite);
+
+	test.step_fixed(1);
+	assert_eq!(health(&test), PLAYER_HEALTH - 2.0 * bite);
+    }
+
+    // The offset of a hitbox is mirrored horizontally when the sprite is flipped
+    #[test]
+    fn hitbox_center_follows_the_flip() {
+	let hitbox = Hitbox { offset: Vec2::new(10.0, -4.0), size: CHARACTER_SIZE };
+	let position = Vec2::new(100.0, 50.0);
+	assert_eq!(hitbox.center(position, false), Vec2::new(110.0, 46.0));
+	assert_eq!(hitbox.center(position, true), Vec2::new(90.0, 46.0));
+	assert_eq!(Hitbox::default().center(position, true), position);
+    }
+
+    // The loot of the boars killed, in the order of the pickups spawned
+    fn loot_of_boars(seed: u64) -> Vec<(ItemKind, u32, Vec2)> {
+	let mut test = TestApp::new();
+	test.app.insert_resource(GameRng::new(seed));
+	let table = LootTable {
+	    entries: vec![
+		LootEntry { item: ItemKind::Meat, chance: 0.5, count: (1, 3) },
+		LootEntry { item: ItemKind::Tusk, chance: 0.3, count: (1, 1) },
+		LootEntry { item: ItemKind::Coin, chance: 0.8, count: (1, 10) },
+	    ],
+	    xp: 0,
+	};
+	test.app.world.resource_mut::<LootTables>().tables.insert("boar".to_string(), table);
+	test.add_fixed_systems(SystemSet::new().with_system(apply_damage));
+	// The global transforms are not propagated, the boars all die at the origin
+	for index in 0..10 {
+	    let boar = test.app.world.spawn((
+		SpriteBundle {
+		    transform: Transform::from_translation(Vec3::new(100.0 * index as f32, 0.0, 0.1)),
+		    ..default()
+		},
+		Boar,
+		HealthPoints::new(1.0),
+		Loot("boar".to_string()),
+	    )).id();
+	    test.app.world.send_event(DamageEvent { target: boar, amount: 1.0, source: Vec2::ZERO });
+	}
+
+	test.step_fixed(1);
+	let mut pickups = test.app.world.query::<(Entity, &Pickup, &Transform)>();
+	let mut loot: Vec<(Entity, ItemKind, u32, Vec2)> = pickups.iter(&test.app.world)
+	    .map(|(entity, pickup, transform)| (entity, pickup.item, pickup.amount, transform.translation.truncate()))
+	    .collect();
+	loot.sort_by_key(|(entity, ..)| *entity);
+	loot.into_iter().map(|(_, item, amount, position)| (item, amount, position)).collect()
+    }
+
+    // The same seed drops the same items, in the same numbers and at the same places
+    #[test]
+    fn same_seed_same_loot() {
+	let loot = loot_of_boars(7);
+	assert!(!loot.is_empty());
+	assert_eq!(loot, loot_of_boars(7));
+	assert_ne!(loot, loot_of_boars(8));
+    }
+
+    // A boar squeals where it was hit, even when the hit kills it, and not again during the cooldown
+    #[test]
+    fn hit_boars_squeal_from_where_they_were() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(apply_damage));
+	let spawn_boar = |test: &mut TestApp, health: f32, position: Vec2| {
+	    test.app.world.spawn((
+		SpriteBundle {
+		    global_transform: GlobalTransform::from_translation(position.extend(0.0)),
+		    ..default()
+		},
+		Boar,
+		HealthPoints::new(health),
+		Barks::new(),
+	    )).id()
+	};
+	let killed = spawn_boar(&mut test, 1.0, Vec2::new(100.0, 0.0));
+	let hurt = spawn_boar(&mut test, 10.0, Vec2::new(-100.0, 0.0));
+	let mut reader = test.app.world.resource::<Events<PlaySpatialSfx>>().get_reader();
+	let mut hit = |test: &mut TestApp, target: Entity| {
+	    test.app.world.send_event(DamageEvent { target, amount: 1.0, source: Vec2::ZERO });
+	    test.step_fixed(1);
+	    reader.iter(test.app.world.resource::<Events<PlaySpatialSfx>>())
+		.map(|PlaySpatialSfx(sfx, sound)| (*sfx, sound.source, sound.position))
+		.collect::<Vec<_>>()
+	};
+
+	assert_eq!(hit(&mut test, killed), [(Sfx::Squeal, killed, Some(Vec2::new(100.0, 0.0)))]);
+	assert!(test.app.world.get_entity(killed).is_none());
+	assert_eq!(hit(&mut test, hurt), [(Sfx::Squeal, hurt, Some(Vec2::new(-100.0, 0.0)))]);
+	assert_eq!(hit(&mut test, hurt), []);
+    }
+}
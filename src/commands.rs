@@ -0,0 +1,178 @@
+// Shortcuts to spawn the common things of the gameplay: world text, pickups, damage numbers and NPCs
+
+use crate::*;
+
+// Spawns the things that gameplay code makes all over, the same way everywhere.
+// The assets are loaded when the commands are applied, so no asset server is needed to call them
+pub trait GameCommands {
+    // Text in the world, centered on its position
+    fn spawn_world_text(&mut self, value: String, position: Vec3, font_size: f32, color: Color) -> Entity;
+    // An item on the ground, bobbing up and down until picked up
+    fn spawn_pickup(&mut self, item: ItemKind, amount: u32, position: Vec2) -> Entity;
+    // The damage dealt, rising and fading above what was hit
+    fn spawn_floating_damage(&mut self, amount: f32, position: Vec3) -> Entity;
+    // A character from its prefab, with the behavior of its kind (the AI of a boar, which grows from nothing first)
+    fn spawn_npc(&mut self, prefab: &str, position: Vec2) -> Entity;
+}
+
+impl GameCommands for Commands<'_, '_> {
+    fn spawn_world_text(&mut self, value: String, position: Vec3, font_size: f32, color: Color) -> Entity {
+	let entity = self.spawn_empty().id();
+	self.add(move |world: &mut World| {
+	    let font = world.resource::<AssetServer>().load(FONT);
+	    world.entity_mut(entity).insert(Text2dBundle {
+		text: Text::from_section(value, TextStyle { font, font_size, color }).with_alignment(TextAlignment::CENTER),
+		transform: Transform::from_translation(position),
+		..default()
+	    });
+	});
+	entity
+    }
+
+    fn spawn_pickup(&mut self, item: ItemKind, amount: u32, position: Vec2) -> Entity {
+	let start = position.extend(PICKUP_Z);
+	let entity = self.spawn((
+	    Pickup { item, amount },
+	    Tween::translation(start, start + Vec3::Y * PICKUP_BOB_HEIGHT)
+		.over(PICKUP_BOB_TIME)
+		.ease(Easing::EaseInOut)
+		.ping_pong(),
+	)).id();
+	self.add(move |world: &mut World| {
+	    let texture = world.resource::<AssetServer>().load(item.sprite());
+	    world.entity_mut(entity).insert(SpriteBundle {
+		texture,
+		sprite: Sprite {
+		    custom_size: Some(PICKUP_SIZE),
+		    ..default()
+		},
+		transform: Transform::from_translation(start),
+		..default()
+	    });
+	});
+	entity
+    }
+
+    fn spawn_floating_damage(&mut self, amount: f32, position: Vec3) -> Entity {
+	let mut faded = DAMAGE_TEXT_COLOR;
+	faded.set_a(0.0);
+	let text = self.spawn_world_text(format!("{}", amount.round()), Vec3::ZERO, DAMAGE_TEXT_SIZE, DAMAGE_TEXT_COLOR);
+	self.entity(text).insert(Tween::color(DAMAGE_TEXT_COLOR, faded).over(DAMAGE_TEXT_TIME).ease(Easing::EaseIn));
+	// The parent rises while the text fades
+	self.spawn((
+	    SpatialBundle::from_transform(Transform::from_translation(position)),
+	    Tween::translation(position, position + Vec3::Y * DAMAGE_TEXT_RISE)
+		.over(DAMAGE_TEXT_TIME)
+		.ease(Easing::EaseOut),
+	    Lifetime::new(DAMAGE_TEXT_TIME),
+	)).add_child(text).id()
+    }
+
+    fn spawn_npc(&mut self, prefab: &str, position: Vec2) -> Entity {
+	let entity = self.spawn_prefab(prefab, position.extend(0.1)).id();
+	self.add(move |world: &mut World| {
+	    // Rolled here, in the order of the commands, so that a seed gives the same boars
+	    let variation = world.entity(entity).contains::<Boar>().then(|| BoarVariation::roll(&mut world.resource_mut::<GameRng>().0));
+	    let mut entity = world.entity_mut(entity);
+	    if entity.contains::<Boar>() || entity.contains::<Wolf>() {
+		entity.insert((
+		    Transform::from_translation(position.extend(0.1)).with_scale(Vec3::ZERO),
+		    SpawningIn(Timer::from_seconds(SPAWN_IN_TIME, TimerMode::Once)),
+		    Tween::scale(Vec3::ZERO, Vec3::ONE).over(SPAWN_IN_TIME).ease(Easing::Back),
+		    Velocity::default(),
+		    Facing::Right,
+		));
+	    }
+	    if let Some(variation) = variation {
+		if let Some(mut health) = entity.get_mut::<HealthPoints>() {
+		    *health = HealthPoints::new(health.max * variation.health);
+		}
+		if let Some(mut speed) = entity.get_mut::<Speed>() {
+		    speed.0 *= variation.speed;
+		}
+		if let Some(mut sprite) = entity.get_mut::<Sprite>() {
+		    sprite.color = variation.tint;
+		    sprite.custom_size = Some(CHARACTER_SIZE * variation.size);
+		}
+		entity.insert((BoarAi::new(), Barks::new()));
+	    }
+	    if entity.contains::<Wolf>() {
+		entity.insert(WolfAi::new());
+	    }
+	    if entity.contains::<Villager>() {
+		entity.insert((VillagerAi::new(), Velocity::default(), Facing::Right));
+	    }
+	});
+	entity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+    use crate::*;
+    use crate::testing::TestApp;
+
+    // The test app with the assets and the prefabs of the game, the commands are applied right away
+    fn spawn_with(spawn: impl FnOnce(&mut Commands) -> Entity) -> (TestApp, Entity) {
+	let mut test = TestApp::new();
+	test.app.add_asset::<TextureAtlas>()
+	    .add_startup_system(load_prefabs);
+	test.app.update();
+	let mut queue = CommandQueue::default();
+	let entity = spawn(&mut Commands::new(&mut queue, &test.app.world));
+	queue.apply(&mut test.app.world);
+	(test, entity)
+    }
+
+    #[test]
+    fn world_text_is_centered_on_its_position() {
+	let position = Vec3::new(10.0, 20.0, 0.5);
+	let (test, entity) = spawn_with(|commands| commands.spawn_world_text("Boar".to_string(), position, 12.0, Color::RED));
+	let text = test.app.world.get::<Text>(entity).unwrap();
+	assert_eq!(text.sections[0].value, "Boar");
+	assert_eq!(text.sections[0].style.font_size, 12.0);
+	assert_eq!(test.app.world.get::<Transform>(entity).unwrap().translation, position);
+    }
+
+    #[test]
+    fn pickup_bobs_on_the_ground() {
+	let (test, entity) = spawn_with(|commands| commands.spawn_pickup(ItemKind::Apple, 3, Vec2::new(5.0, 6.0)));
+	let pickup = test.app.world.get::<Pickup>(entity).unwrap();
+	assert_eq!((pickup.item, pickup.amount), (ItemKind::Apple, 3));
+	assert_eq!(test.app.world.get::<Sprite>(entity).unwrap().custom_size, Some(PICKUP_SIZE));
+	assert_eq!(test.app.world.get::<Transform>(entity).unwrap().translation, Vec3::new(5.0, 6.0, PICKUP_Z));
+	assert!(test.app.world.get::<Tween>(entity).is_some());
+    }
+
+    // The rounded amount is on a text under a parent that rises and goes away
+    #[test]
+    fn floating_damage_is_rounded_and_expires() {
+	let (test, entity) = spawn_with(|commands| commands.spawn_floating_damage(12.4, Vec3::new(0.0, 0.0, DAMAGE_TEXT_Z)));
+	assert!(test.app.world.get::<Lifetime>(entity).is_some());
+	let children = test.app.world.get::<Children>(entity).unwrap();
+	assert_eq!(children.len(), 1);
+	assert_eq!(test.app.world.get::<Text>(children[0]).unwrap().sections[0].value, "12");
+    }
+
+    // A boar grows from nothing, with its AI and a variation rolled from the seed of the test app
+    #[test]
+    fn npc_gets_the_behavior_of_its_kind() {
+	let spawn = || {
+	    let (test, entity) = spawn_with(|commands| commands.spawn_npc("boar", Vec2::new(30.0, 40.0)));
+	    let world = &test.app.world;
+	    assert!(world.get::<Boar>(entity).is_some());
+	    assert!(world.get::<BoarAi>(entity).is_some());
+	    assert!(world.get::<SpawningIn>(entity).is_some());
+	    let transform = world.get::<Transform>(entity).unwrap();
+	    assert_eq!(transform.translation.truncate(), Vec2::new(30.0, 40.0));
+	    assert_eq!(transform.scale, Vec3::ZERO);
+	    (world.get::<HealthPoints>(entity).unwrap().max, world.get::<Speed>(entity).unwrap().0)
+	};
+	assert_eq!(spawn(), spawn());
+
+	let (test, entity) = spawn_with(|commands| commands.spawn_npc("villager", Vec2::ZERO));
+	assert!(test.app.world.get::<VillagerAi>(entity).is_some());
+	assert!(test.app.world.get::<SpawningIn>(entity).is_none());
+    }
+}
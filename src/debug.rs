@@ -0,0 +1,214 @@
+// Debugging tools, enabled with --debug and a few keys
+
+use crate::*;
+
+// With --debug, the radii are drawn around the boars in the color of their state
+pub const DEBUG_IDLE_COLOR: Color = Color::rgba(0.8, 0.8, 0.8, 0.6);
+pub const DEBUG_WANDER_COLOR: Color = Color::rgba(0.3, 0.9, 0.3, 0.6);
+pub const DEBUG_CHASE_COLOR: Color = Color::rgba(1.0, 0.2, 0.2, 0.6);
+pub const DEBUG_BAIT_COLOR: Color = Color::rgba(1.0, 0.8, 0.2, 0.6);
+// The leash is drawn fainter than the detection
+pub const DEBUG_LEASH_ALPHA: f32 = 0.25;
+// F3 shows the position of the characters and the crates above them, to look into the movement and the collisions
+pub const COORDINATE_LABELS_KEY: KeyCode = KeyCode::F3;
+pub const COORDINATE_LABEL_SIZE: f32 = 12.0;
+pub const COORDINATE_LABEL_COLOR: Color = Color::rgb(0.6, 1.0, 0.6);
+pub const COORDINATE_LABEL_OFFSET: f32 = 12.0;
+// With --debug, F4 grants all the abilities
+pub const GRANT_ABILITIES_KEY: KeyCode = KeyCode::F4;
+// With --debug, F6 freezes the gameplay and F7 then runs it one tick at a time, to follow the collisions and the AI
+pub const STEP_MODE_KEY: KeyCode = KeyCode::F6;
+pub const STEP_KEY: KeyCode = KeyCode::F7;
+
+// The debug drawings are only there when enabled with --debug
+#[derive(Resource)]
+pub struct DebugView;
+
+// A circle drawn around a boar in debug, of the given radius in the world
+#[derive(Component)]
+pub struct DebugRing {
+    pub radius: f32,
+}
+
+// Whether the positions are shown above the characters and the crates
+#[derive(Resource, Default)]
+pub struct CoordinateLabels {
+    pub shown: bool,
+}
+
+// The position of an entity, drawn above it in the world
+#[derive(Component)]
+pub struct CoordinateLabel {
+    pub target: Entity,
+}
+
+// Whether the gameplay is frozen, and the ticks it may still run. The menus, the camera and the drawings go on
+#[derive(Resource, Default)]
+pub struct StepControl {
+    pub stepping: bool,
+    pub pending: u32,
+}
+
+impl StepControl {
+    // Whether the gameplay may run a tick, a tick run in step mode uses up a pending one
+    pub fn take_tick(&mut self) -> bool {
+	if !self.stepping {
+	    return true;
+	}
+	if self.pending == 0 {
+	    return false;
+	}
+	self.pending -= 1;
+	true
+    }
+}
+
+pub fn enable_debug_view(mut commands: Commands) {
+    if env::args().any(|arg| arg == "--debug") {
+	info!("Debug view enabled: the detection and leash radii of the boars are drawn, F4 grants all the abilities, F6 enters step mode");
+	commands.insert_resource(DebugView);
+    }
+}
+
+// Debug system that draws the detection and leash radii around the boars, in the color of their state
+pub fn draw_boar_radii(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    debug: Option<Res<DebugView>>,
+    query_boar: Query<(Entity, &BoarAi, &Transform, Option<&Children>)>,
+    mut query_ring: Query<(&DebugRing, &mut Sprite, &mut Transform, &mut Visibility), Without<BoarAi>>,
+) {
+    if debug.is_none() {
+	return;
+    }
+    for (boar, boar_ai, boar_transform, children) in &query_boar {
+	let rings = children.map_or(0, |children| query_ring.iter_many(children).count());
+	if rings == 0 {
+	    commands.entity(boar).with_children(|parent| {
+		for radius in [DETECTION_RADIUS, LEASH_RADIUS] {
+		    parent.spawn((
+			SpriteBundle {
+			    texture: asset_server.load("sprites/ring.png"),
+			    sprite: Sprite {
+				custom_size: Some(Vec2::splat(2.0 * radius)),
+				..default()
+			    },
+			    transform: Transform::from_xyz(0.0, 0.0, -0.05),
+			    ..default()
+			},
+			DebugRing { radius },
+		    ));
+		}
+	    });
+	    continue;
+	}
+
+	let color = match boar_ai.state {
+	    BoarState::Idle => DEBUG_IDLE_COLOR,
+	    BoarState::Wander => DEBUG_WANDER_COLOR,
+	    BoarState::Chase => DEBUG_CHASE_COLOR,
+	    BoarState::Attracted | BoarState::Eating => DEBUG_BAIT_COLOR,
+	};
+	let mut iter = query_ring.iter_many_mut(children.into_iter().flatten());
+	while let Some((ring, mut sprite, mut transform, mut visibility)) = iter.fetch_next() {
+	    sprite.color = color;
+	    if ring.radius == LEASH_RADIUS {
+		sprite.color.set_a(DEBUG_LEASH_ALPHA);
+	    }
+	    // The radii are in the world, whatever the size of the boar
+	    visibility.is_visible = boar_transform.scale.x > 0.0;
+	    if visibility.is_visible {
+		transform.scale = (1.0 / boar_transform.scale.truncate()).extend(1.0);
+	    }
+	}
+    }
+}
+
+// Debug system that shows or hides the positions above the characters and the crates
+pub fn toggle_coordinate_labels(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut labels: ResMut<CoordinateLabels>,
+    query_label: Query<Entity, With<CoordinateLabel>>,
+) {
+    if !keyboard_input.just_pressed(COORDINATE_LABELS_KEY) {
+	return;
+    }
+    labels.shown = !labels.shown;
+    if !labels.shown {
+	for label in &query_label {
+	    commands.entity(label).despawn_recursive();
+	}
+    }
+}
+
+// Debug system that enters and leaves step mode, and asks for the next tick while in it
+pub fn control_stepping(
+    keyboard_input: Res<Input<KeyCode>>,
+    debug: Option<Res<DebugView>>,
+    mut step: ResMut<StepControl>,
+) {
+    if debug.is_none() {
+	return;
+    }
+    if keyboard_input.just_pressed(STEP_MODE_KEY) {
+	step.stepping = !step.stepping;
+	step.pending = 0;
+	info!("Step mode {}", if step.stepping { "on, F7 runs the next tick" } else { "off" });
+    }
+    if step.stepping && keyboard_input.just_pressed(STEP_KEY) {
+	step.pending += 1;
+    }
+}
+
+// Debug system that writes the position of the characters and the crates above them, at each tick.
+// The labels are not children so that they keep their size whatever the scale of what they follow
+pub fn update_coordinate_labels(
+    mut commands: Commands,
+    labels: Res<CoordinateLabels>,
+    query_target: Query<(Entity, &Transform), (Or<(With<Player>, With<House>, With<Boar>, With<Destructible>)>, Without<CoordinateLabel>)>,
+    mut query_label: Query<(Entity, &CoordinateLabel, &mut Transform, &mut Text)>,
+) {
+    if !labels.shown {
+	return;
+    }
+    let anchor = |position: Vec2| (position + Vec2::Y * (CHARACTER_SIZE.y / 2.0 + COORDINATE_LABEL_OFFSET)).extend(DAMAGE_TEXT_Z);
+    let describe = |position: Vec2| format!("({:.0}, {:.0})", position.x, position.y);
+    let mut labelled = HashSet::new();
+    for (label, CoordinateLabel { target }, mut transform, mut text) in &mut query_label {
+	let Ok((_, target_transform)) = query_target.get(*target) else {
+	    commands.entity(label).despawn_recursive();
+	    continue;
+	};
+	let position = target_transform.translation.truncate();
+	transform.translation = anchor(position);
+	text.sections[0].value = describe(position);
+	labelled.insert(*target);
+    }
+    for (target, target_transform) in &query_target {
+	if labelled.contains(&target) {
+	    continue;
+	}
+	let position = target_transform.translation.truncate();
+	let label = commands.spawn_world_text(describe(position), anchor(position), COORDINATE_LABEL_SIZE, COORDINATE_LABEL_COLOR);
+	commands.entity(label).insert(CoordinateLabel { target });
+    }
+}
+
+// Debug system that warns, once each, about the entities that outlived their parent. Despawning a parent with `despawn`
+// instead of `despawn_recursive` leaves its children (shadows, debug rings, ...) floating in the world
+pub fn check_orphans(
+    debug: Option<Res<DebugView>>,
+    query_child: Query<(Entity, &Parent)>,
+    query_entity: Query<()>,
+    mut warned: Local<HashSet<Entity>>,
+) {
+    if debug.is_none() {
+	return;
+    }
+    for (child, parent) in &query_child {
+	if !query_entity.contains(parent.get()) && warned.insert(child) {
+	    warn!("{child:?} outlived its parent {:?}", parent.get());
+	}
+    }
+}
@@ -0,0 +1,493 @@
+// Small cosmetic animations, particles, and the adaptive quality of the effects
+
+use crate::*;
+
+// Tween settings, for the small cosmetic animations
+pub const TWEEN_DEFAULT_TIME: f32 = 0.5;
+pub const TWEEN_BACK_OVERSHOOT: f32 = 1.7;
+
+// Particle settings: small square sprites that fly, fade and shrink. Beyond the maximum,
+// the oldest particles make way for the new ones
+pub const MAX_PARTICLES: usize = 2000;
+// The particles that fly this far past the walls are gone
+pub const PARTICLE_BOUNDS_MARGIN: f32 = 32.0;
+pub const DUST_RATE: f32 = 20.0;
+pub const DUST_LIFETIME: f32 = 0.5;
+pub const DUST_SIZE: f32 = 6.0;
+pub const DUST_COLOR: Color = Color::rgba(0.6, 0.5, 0.35, 0.6);
+pub const DUST_LANDING_COUNT: usize = 12;
+pub const IMPACT_LIFETIME: f32 = 0.25;
+pub const IMPACT_SIZE: f32 = 4.0;
+pub const IMPACT_COLOR: Color = Color::rgb(1.0, 0.9, 0.6);
+pub const IMPACT_COUNT: usize = 10;
+// The sparks of the hits are drawn over the characters
+pub const IMPACT_Z: f32 = 0.3;
+pub const SPLINTER_LIFETIME: f32 = 0.6;
+pub const SPLINTER_SIZE: f32 = 5.0;
+pub const SPLINTER_COLOR: Color = Color::rgb(0.55, 0.37, 0.18);
+pub const SPARKLE_LIFETIME: f32 = 1.0;
+pub const SPARKLE_SIZE: f32 = 4.0;
+pub const SPARKLE_COLOR: Color = Color::rgb(1.0, 0.85, 0.3);
+pub const HEART_LIFETIME: f32 = 1.2;
+pub const HEART_SIZE: f32 = 6.0;
+pub const HEART_COLOR: Color = Color::rgb(1.0, 0.4, 0.6);
+
+// Adaptive quality settings: effects are reduced when the average framerate
+// drops below LOW_FPS with many entities around, and restored above HIGH_FPS
+pub const FPS_SAMPLES: usize = 120;
+pub const LOW_FPS: f64 = 40.0;
+pub const HIGH_FPS: f64 = 55.0;
+pub const MANY_ENTITIES: f64 = 2000.0;
+
+// How the progress of a tween speeds up and slows down
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    // Goes a bit past the end, and comes back
+    Back,
+}
+
+impl Easing {
+    // Eased progress, from 0 to 1 for a linear progress from 0 to 1
+    pub fn apply(&self, t: f32) -> f32 {
+	match self {
+	    Easing::Linear => t,
+	    Easing::EaseIn => t * t,
+	    Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+	    Easing::EaseInOut => if t < 0.5 { 2.0 * t * t } else { 1.0 - 2.0 * (1.0 - t) * (1.0 - t) },
+	    Easing::Back => {
+		let t = t - 1.0;
+		1.0 + (TWEEN_BACK_OVERSHOOT + 1.0) * t * t * t + TWEEN_BACK_OVERSHOOT * t * t
+	    },
+	}
+    }
+}
+
+// The property animated by a tween, with its start and end values
+#[derive(Clone, Copy, Debug)]
+pub enum TweenTarget {
+    Translation(Vec3, Vec3),
+    Scale(Vec3, Vec3),
+    Color(Color, Color),
+    Alpha(f32, f32),
+}
+
+impl TweenTarget {
+    // The same property, from the end value back to the start value
+    pub fn reversed(&self) -> TweenTarget {
+	match *self {
+	    TweenTarget::Translation(start, end) => TweenTarget::Translation(end, start),
+	    TweenTarget::Scale(start, end) => TweenTarget::Scale(end, start),
+	    TweenTarget::Color(start, end) => TweenTarget::Color(end, start),
+	    TweenTarget::Alpha(start, end) => TweenTarget::Alpha(end, start),
+	}
+    }
+}
+
+// The effects that stop their own tween, a tween they find without their tag was put over theirs by another effect
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TweenTag {
+    Eating,
+    IdleBob,
+}
+
+// Animates a property of the transform or the sprite of its entity, for cosmetic effects
+// (afterimages, dead boars, ...). The tween is removed once done, or the entity with it,
+// unless it goes back and forth
+#[derive(Component)]
+pub struct Tween {
+    pub target: TweenTarget,
+    pub timer: Timer,
+    pub easing: Easing,
+    pub despawn_on_complete: bool,
+    pub ping_pong: bool,
+    pub tag: Option<TweenTag>,
+}
+
+impl Tween {
+    pub fn new(target: TweenTarget) -> Tween {
+	Tween {
+	    target,
+	    timer: Timer::from_seconds(TWEEN_DEFAULT_TIME, TimerMode::Once),
+	    easing: Easing::Linear,
+	    despawn_on_complete: false,
+	    ping_pong: false,
+	    tag: None,
+	}
+    }
+
+    pub fn translation(start: Vec3, end: Vec3) -> Tween {
+	Tween::new(TweenTarget::Translation(start, end))
+    }
+
+    pub fn scale(start: Vec3, end: Vec3) -> Tween {
+	Tween::new(TweenTarget::Scale(start, end))
+    }
+
+    pub fn color(start: Color, end: Color) -> Tween {
+	Tween::new(TweenTarget::Color(start, end))
+    }
+
+    pub fn alpha(start: f32, end: f32) -> Tween {
+	Tween::new(TweenTarget::Alpha(start, end))
+    }
+
+    // Duration in seconds
+    pub fn over(mut self, seconds: f32) -> Tween {
+	self.timer = Timer::from_seconds(seconds, TimerMode::Once);
+	self
+    }
+
+    pub fn ease(mut self, easing: Easing) -> Tween {
+	self.easing = easing;
+	self
+    }
+
+    pub fn despawn_on_complete(mut self) -> Tween {
+	self.despawn_on_complete = true;
+	self
+    }
+
+    // Goes back to the start once at the end, and so on until removed
+    pub fn ping_pong(mut self) -> Tween {
+	self.ping_pong = true;
+	self
+    }
+
+    pub fn tagged(mut self, tag: TweenTag) -> Tween {
+	self.tag = Some(tag);
+	self
+    }
+}
+
+// Removes the tween of an effect from an entity, not one that another effect put over it
+pub fn remove_tagged_tween(commands: &mut Commands, entity: Entity, tween: Option<&Tween>, tag: TweenTag) -> bool {
+    if tween.and_then(|tween| tween.tag) != Some(tag) {
+	return false;
+    }
+    commands.entity(entity).remove::<Tween>();
+    true
+}
+
+// The different looks of the particles
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParticleKind {
+    // Kicked up from the ground
+    Dust,
+    // Sparks of a hit
+    Impact,
+    // Wood chips of a broken crate
+    Splinter,
+    // Around the player gaining a level
+    Sparkle,
+    // Around a boar tamed
+    Heart,
+}
+
+impl ParticleKind {
+    pub fn lifetime(&self) -> f32 {
+	match self {
+	    ParticleKind::Dust => DUST_LIFETIME,
+	    ParticleKind::Impact => IMPACT_LIFETIME,
+	    ParticleKind::Splinter => SPLINTER_LIFETIME,
+	    ParticleKind::Sparkle => SPARKLE_LIFETIME,
+	    ParticleKind::Heart => HEART_LIFETIME,
+	}
+    }
+
+    // Box the velocities are picked in
+    pub fn velocity_range(&self) -> Rect {
+	match self {
+	    ParticleKind::Dust => Rect::new(-20.0, 5.0, 20.0, 30.0),
+	    ParticleKind::Impact => Rect::new(-200.0, -200.0, 200.0, 200.0),
+	    ParticleKind::Splinter => Rect::new(-120.0, -60.0, 120.0, 120.0),
+	    ParticleKind::Sparkle => Rect::new(-80.0, -20.0, 80.0, 120.0),
+	    ParticleKind::Heart => Rect::new(-40.0, 20.0, 40.0, 80.0),
+	}
+    }
+
+    pub fn sprite(&self) -> Sprite {
+	let (color, size) = match self {
+	    ParticleKind::Dust => (DUST_COLOR, DUST_SIZE),
+	    ParticleKind::Impact => (IMPACT_COLOR, IMPACT_SIZE),
+	    ParticleKind::Splinter => (SPLINTER_COLOR, SPLINTER_SIZE),
+	    ParticleKind::Sparkle => (SPARKLE_COLOR, SPARKLE_SIZE),
+	    ParticleKind::Heart => (HEART_COLOR, HEART_SIZE),
+	};
+	Sprite {
+	    color,
+	    custom_size: Some(Vec2::splat(size)),
+	    ..default()
+	}
+    }
+}
+
+// Continuously spawns particles around its entity while active
+#[derive(Component)]
+pub struct ParticleEmitter {
+    pub kind: ParticleKind,
+    // Particles per second
+    pub rate: f32,
+    pub lifetime: f32,
+    pub velocity_range: Rect,
+    // Relative to the entity
+    pub offset: Vec3,
+    pub active: bool,
+    // Fraction of a particle carried over to the next frame
+    pub pending: f32,
+}
+
+impl ParticleEmitter {
+    pub fn new(kind: ParticleKind, rate: f32, offset: Vec3) -> ParticleEmitter {
+	ParticleEmitter {
+	    kind,
+	    rate,
+	    lifetime: kind.lifetime(),
+	    velocity_range: kind.velocity_range(),
+	    offset,
+	    active: false,
+	    pending: 0.0,
+	}
+    }
+}
+
+#[derive(Component)]
+pub struct Particle {
+    pub velocity: Vec2,
+    pub start_alpha: f32,
+}
+
+// The particles from the oldest spawned to the newest, with the serial number of their spawn.
+// A particle spawned again is pushed again with a new serial, its older entries are skipped and dropped later.
+// The ones released to the pool stay in there until they are spawned again
+#[derive(Resource, Default)]
+pub struct Particles {
+    pub live: VecDeque<(Entity, u64)>,
+    // The serial of the last spawn of every particle of the pool
+    pub serials: HashMap<Entity, u64>,
+    pub next_serial: u64,
+    pub pool: Pool<Particle>,
+}
+
+impl Particles {
+    pub fn spawn(&mut self, commands: &mut Commands, kind: ParticleKind, position: Vec3, velocity_range: Rect, lifetime: f32) {
+	// Past the limit, the oldest particle is taken over when none is free
+	if self.serials.len() >= MAX_PARTICLES && self.pool.free.is_empty() {
+	    while let Some((oldest, serial)) = self.live.pop_front() {
+		if self.serials.get(&oldest) == Some(&serial) {
+		    self.pool.release(commands, oldest);
+		    break;
+		}
+	    }
+	}
+	let mut rng = rand::thread_rng();
+	let velocity = Vec2::new(
+	    rng.gen_range(velocity_range.min.x..=velocity_range.max.x),
+	    rng.gen_range(velocity_range.min.y..=velocity_range.max.y),
+	);
+	let sprite = kind.sprite();
+	let start_alpha = sprite.color.a();
+	let particle = self.pool.acquire(commands, (
+	    SpriteBundle {
+		sprite,
+		transform: Transform::from_translation(position),
+		..default()
+	    },
+	    Particle {
+		velocity,
+		start_alpha,
+	    },
+	    Lifetime::new(lifetime),
+	    DespawnOutOfBounds { margin: PARTICLE_BOUNDS_MARGIN },
+	));
+	self.serials.insert(particle, self.next_serial);
+	self.live.push_back((particle, self.next_serial));
+	self.next_serial += 1;
+	// Drops the skipped entries once they are as many as the particles, each spawn pays for one of them
+	if self.live.len() > 2 * self.serials.len() {
+	    let serials = &self.serials;
+	    self.live.retain(|(live, serial)| serials.get(live) == Some(serial));
+	}
+    }
+}
+
+// Spawns a few particles at once, with the defaults of their kind
+pub fn spawn_burst(commands: &mut Commands, particles: &mut Particles, kind: ParticleKind, position: Vec3, count: usize) {
+    for _ in 0..count {
+	particles.spawn(commands, kind, position, kind.velocity_range(), kind.lifetime());
+    }
+}
+
+// Level of detail of the purely cosmetic effects
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum EffectsQuality {
+    #[default]
+    High,
+    Low,
+}
+
+// Keeps track of the recent framerate to adapt the effects quality
+#[derive(Resource, Default)]
+pub struct PerformanceMonitor {
+    pub fps_samples: VecDeque<f64>,
+    pub quality: EffectsQuality,
+}
+
+impl PerformanceMonitor {
+    pub fn average_fps(&self) -> Option<f64> {
+	if self.fps_samples.is_empty() {
+	    return None;
+	}
+	Some(self.fps_samples.iter().sum::<f64>() / self.fps_samples.len() as f64)
+    }
+}
+
+// System that kicks up dust while the player sprints
+pub fn sprint_dust(mut query_player: Query<(&Sprinting, &PlayerState, &Velocity, &mut ParticleEmitter), With<Player>>) {
+    let (sprinting, player_state, velocity, mut emitter) = query_player.single_mut();
+    emitter.active = sprinting.0 && *player_state == PlayerState::Walking && velocity.0 != Vec2::ZERO;
+}
+
+// System that spawns the particles of the active emitters, they are only decoration
+// and are skipped when effects are reduced
+pub fn emit_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    monitor: Res<PerformanceMonitor>,
+    mut particles: ResMut<Particles>,
+    mut query_emitter: Query<(&GlobalTransform, &mut ParticleEmitter)>,
+) {
+    for (transform, mut emitter) in &mut query_emitter {
+	if !emitter.active || monitor.quality == EffectsQuality::Low {
+	    emitter.pending = 0.0;
+	    continue;
+	}
+	emitter.pending += emitter.rate * time.delta_seconds();
+	let position = transform.translation() + emitter.offset;
+	while emitter.pending >= 1.0 {
+	    emitter.pending -= 1.0;
+	    particles.spawn(&mut commands, emitter.kind, position, emitter.velocity_range, emitter.lifetime);
+	}
+    }
+}
+
+// System that moves, fades and shrinks the particles over their lifetime.
+// They go back to the pool at the end of it, or once too far past the walls
+pub fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    play_area: Res<PlayArea>,
+    mut particles: ResMut<Particles>,
+    mut query_particle: Query<(Entity, &mut Transform, &mut Sprite, &Particle, &mut Lifetime, &DespawnOutOfBounds)>,
+) {
+    for (entity, mut transform, mut sprite, particle, mut lifetime, out_of_bounds) in &mut query_particle {
+	if lifetime.0.tick(time.delta()).finished() || out_of_bounds.is_out(&play_area, transform.translation.truncate()) {
+	    particles.pool.release(&mut commands, entity);
+	    continue;
+	}
+	transform.translation += (particle.velocity * time.delta_seconds()).extend(0.0);
+	let left = lifetime.0.percent_left();
+	transform.scale = Vec3::splat(left);
+	sprite.color.set_a(particle.start_alpha * left);
+    }
+}
+
+// Changes the color of a sprite, of all the sections of a text, or of the background of a UI node
+pub fn set_color(
+    sprite: Option<Mut<Sprite>>,
+    text: Option<Mut<Text>>,
+    background: Option<Mut<BackgroundColor>>,
+    change: impl Fn(Color) -> Color,
+) {
+    if let Some(mut sprite) = sprite {
+	sprite.color = change(sprite.color);
+    }
+    if let Some(mut text) = text {
+	for section in &mut text.sections {
+	    section.style.color = change(section.style.color);
+	}
+    }
+    if let Some(mut background) = background {
+	background.0 = change(background.0);
+    }
+}
+
+// System that runs the tweens, a tween on an entity without the property does nothing
+pub fn run_tweens(
+    mut commands: Commands,
+    time: Res<Time>,
+    hit_stop: Res<HitStop>,
+    mut query_tween: Query<(Entity, &mut Tween, Option<&mut Transform>, Option<&mut Sprite>, Option<&mut Text>, Option<&mut BackgroundColor>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    for (entity, mut tween, transform, sprite, text, background) in &mut query_tween {
+	tween.timer.tick(time.delta());
+	let progress = tween.easing.apply(tween.timer.percent());
+	match tween.target {
+	    TweenTarget::Translation(start, end) => if let Some(mut transform) = transform {
+		transform.translation = start.lerp(end, progress);
+	    },
+	    TweenTarget::Scale(start, end) => if let Some(mut transform) = transform {
+		transform.scale = start.lerp(end, progress);
+	    },
+	    TweenTarget::Color(start, end) => {
+		let color = Vec4::from(start.as_rgba_f32()).lerp(Vec4::from(end.as_rgba_f32()), progress);
+		set_color(sprite, text, background, |_| Color::rgba(color.x, color.y, color.z, color.w));
+	    },
+	    TweenTarget::Alpha(start, end) => {
+		let alpha = start + (end - start) * progress;
+		set_color(sprite, text, background, |mut color| *color.set_a(alpha));
+	    },
+	}
+
+	if tween.timer.finished() {
+	    if tween.ping_pong {
+		tween.target = tween.target.reversed();
+		tween.timer.reset();
+	    } else if tween.despawn_on_complete {
+		commands.entity(entity).despawn_recursive();
+	    } else {
+		commands.entity(entity).remove::<Tween>();
+	    }
+	}
+    }
+}
+
+// System that lowers the effects quality when the framerate drops because of too many entities
+pub fn monitor_performance(
+    diagnostics: Res<Diagnostics>,
+    mut monitor: ResMut<PerformanceMonitor>,
+) {
+    let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS).and_then(|fps| fps.value()) else {
+	return;
+    };
+    let entity_count = diagnostics.get(EntityCountDiagnosticsPlugin::ENTITY_COUNT)
+	.and_then(|count| count.value())
+	.unwrap_or(0.0);
+
+    monitor.fps_samples.push_back(fps);
+    if monitor.fps_samples.len() > FPS_SAMPLES {
+	monitor.fps_samples.pop_front();
+    }
+    let Some(average_fps) = monitor.average_fps() else {
+	return;
+    };
+
+    // The two thresholds are apart so that the quality does not flicker
+    match monitor.quality {
+	EffectsQuality::High if average_fps < LOW_FPS && entity_count > MANY_ENTITIES => {
+	    info!("Average framerate is {average_fps:.0} with {entity_count} entities, reducing effects");
+	    monitor.quality = EffectsQuality::Low;
+	},
+	EffectsQuality::Low if average_fps > HIGH_FPS => {
+	    info!("Average framerate is back to {average_fps:.0}, restoring effects");
+	    monitor.quality = EffectsQuality::High;
+	},
+	_ => (),
+    }
+}
@@ -0,0 +1,462 @@
+// The screens of the game, the loading, and the level built for every new game
+
+use crate::*;
+
+// The gameplay is updated this many times per second, unless the simulation file (in the assets folder) says otherwise.
+// The speeds are in units per second, they do not depend on it
+pub const SIMULATION_FILE: &str = "config/simulation.ron";
+pub const DEFAULT_TICK_RATE: f32 = 12.0;
+
+// The screen fades to black and back when going from a screen to another,
+// the switch happens halfway, behind the black
+pub const FADE_TIME: f32 = 0.6;
+// Leaves the game for the main menu, a new game starts from the save
+pub const MAIN_MENU_KEY: KeyCode = KeyCode::Q;
+
+// The different screens of the game
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GameState {
+    Loading,
+    MainMenu,
+    Playing,
+    Paused,
+    // The quest log screen is open, the game waits behind it
+    QuestLog,
+    // The inventory screen is open, the game waits behind it
+    Inventory,
+    // The lifetime stats, from the main menu
+    Stats,
+    // The achievements, from the main menu
+    Achievements,
+    // The save slots, from the main menu
+    Saves,
+    // The ending, with the stats of the game. It is pushed on top of the game, which goes on in free play after it
+    Victory,
+    // Items are moved between the inventory and a chest, the game waits behind
+    Chest,
+    // Someone is talking to the player, the game waits behind
+    Dialogue,
+    // Items are bought and sold at the house, the game waits behind
+    Shop,
+    // The player has no life left, it is pushed on top of the game, which is left for the main menu
+    GameOver,
+    // The keys of the actions, from the pause menu. It replaces the pause menu, the game waits behind
+    Controls,
+}
+
+#[derive(StageLabel)]
+pub struct FixedUpdateStage;
+
+// The player moves and attacks first in a tick, the rest of the world reacts after it. Bevy runs the systems
+// without an order between them in any order, a run would not play out the same way twice
+#[derive(SystemLabel)]
+pub struct PlayerTurn;
+
+// Run criteria of the fixed stage: the gameplay while playing, only a tick at a time in step mode
+pub fn gameplay_tick(state: Res<State<GameState>>, mut step: ResMut<StepControl>) -> ShouldRun {
+    if *state.current() == GameState::Playing && step.take_tick() {
+	ShouldRun::Yes
+    } else {
+	ShouldRun::No
+    }
+}
+
+// Run criteria of the gameplay outside of the update stage. The ones of the states, like SystemSet::on_update, only stop
+// checking again in the stage that drives the state, anywhere else they would run the systems over and over
+pub fn while_playing(state: Res<State<GameState>>) -> ShouldRun {
+    if *state.current() == GameState::Playing {
+	ShouldRun::Yes
+    } else {
+	ShouldRun::No
+    }
+}
+
+// How often the gameplay is updated
+#[derive(Resource, Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct Simulation {
+    // Ticks per second
+    pub tick_rate: f32,
+}
+
+impl Default for Simulation {
+    fn default() -> Simulation {
+	Simulation {
+	    tick_rate: DEFAULT_TICK_RATE,
+	}
+    }
+}
+
+// The randomness of the gameplay: the wandering, the spawns, the loot, the boars rolled.
+// A run with the same seed and the same inputs plays out the same way. What is only seen or heard
+// (the particles, the grunts) uses thread_rng instead, it changes nothing
+#[derive(Resource)]
+pub struct GameRng(pub ChaCha8Rng);
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+	GameRng(ChaCha8Rng::seed_from_u64(seed))
+    }
+}
+
+impl Simulation {
+    // Reads the simulation settings from the assets folder, the default ones are used if they cannot be read
+    pub fn load() -> Simulation {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(SIMULATION_FILE);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the simulation settings {}: {error}", full_path.display());
+		return Simulation::default();
+	    },
+	};
+	match ron::from_str::<Simulation>(&contents) {
+	    Ok(simulation) if simulation.tick_rate > 0.0 => simulation,
+	    Ok(simulation) => {
+		warn!("The tick rate must be above 0, not {}", simulation.tick_rate);
+		Simulation::default()
+	    },
+	    Err(error) => {
+		warn!("Could not parse the simulation settings {}: {error}", full_path.display());
+		Simulation::default()
+	    },
+	}
+    }
+
+    // Seconds between two ticks, every system of the fixed stage moves and counts by it
+    pub fn dt(&self) -> f32 {
+	1.0 / self.tick_rate
+    }
+}
+
+// Marker for the UI of the menus, it is removed when leaving them
+#[derive(Component)]
+pub struct MenuUi;
+
+// Marker for what stays when the game is left for the main menu: the cameras, and the overlays of the window.
+// Everything else is despawned, and spawned again for the next game
+#[derive(Component)]
+pub struct Persistent;
+
+// Handles to the assets shared by several systems, loaded before anything is spawned
+#[derive(Resource)]
+pub struct GameAssets {
+    pub wall_horizontal: Handle<Image>,
+    pub wall_vertical: Handle<Image>,
+}
+
+pub fn load_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(GameAssets {
+	wall_horizontal: asset_server.load("sprites/wall_horizontal.png"),
+	wall_vertical: asset_server.load("sprites/wall_vertical.png"),
+    });
+}
+
+// System that opens the main menu once the sounds and the background are loaded
+pub fn finish_loading(
+    asset_server: Res<AssetServer>,
+    library: Res<SfxLibrary>,
+    mut state: ResMut<State<GameState>>,
+    query_background: Query<&Handle<Image>, With<Background>>,
+) {
+    match asset_server.get_group_load_state(library.sounds.values().map(|sound| sound.id())) {
+	LoadState::Loaded => (),
+	LoadState::Failed => warn!("Some sound effects could not be loaded"),
+	_ => return,
+    }
+    // The walls are moved to the edges of the background once it is loaded, the game starts with them there
+    let background_loaded = query_background.iter()
+	.all(|handle| matches!(asset_server.get_load_state(handle), LoadState::Loaded | LoadState::Failed));
+    if !background_loaded {
+	return;
+    }
+    let _ = state.set(GameState::MainMenu);
+}
+
+// setup function that places what stays for the whole run: the overlays of the window
+pub fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    // Black bars around the game view, under the rest of the UI. They are sized with the window
+    for index in 0..2 {
+	commands.spawn((
+	    NodeBundle {
+		style: Style {
+		    position_type: PositionType::Absolute,
+		    ..default()
+		},
+		background_color: Color::BLACK.into(),
+		z_index: ZIndex::Global(-1),
+		..default()
+	    },
+	    LetterboxBar(index),
+	    Persistent,
+	));
+    }
+
+    // Black screen of the transitions, over everything else
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		position_type: PositionType::Absolute,
+		..default()
+	    },
+	    background_color: Color::NONE.into(),
+	    visibility: Visibility { is_visible: false },
+	    z_index: ZIndex::Global(i32::MAX),
+	    ..default()
+	},
+	FadeOverlay,
+	Persistent,
+    ));
+
+    // HUD, in the top right corner
+    commands.spawn((
+	ImageBundle {
+	    style: Style {
+		size: Size::new(Val::Px(HUD_ICON_SIZE), Val::Px(HUD_ICON_SIZE)),
+		position_type: PositionType::Absolute,
+		position: UiRect {
+		    top: Val::Px(8.0),
+		    right: Val::Px(8.0),
+		    ..default()
+		},
+		..default()
+	    },
+	    image: asset_server.load("sprites/muted.png").into(),
+	    visibility: Visibility { is_visible: settings.muted },
+	    ..default()
+	},
+	MuteIcon,
+	Persistent,
+    ));
+}
+
+// Spawns a menu screen: a title and a line of instructions over a dark background
+pub fn spawn_menu(commands: &mut Commands, asset_server: &AssetServer, title: &str, instructions: &str) {
+    let font = asset_server.load(FONT);
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(TextBundle::from_section(title, TextStyle {
+	    font: font.clone(),
+	    font_size: 60.0,
+	    color: Color::WHITE,
+	}));
+	parent.spawn(TextBundle::from_section(instructions, TextStyle {
+	    font,
+	    font_size: 24.0,
+	    color: Color::WHITE,
+	}));
+    });
+}
+
+pub fn spawn_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(&mut commands, &asset_server, "Boar Game",
+	       "Press Enter to play\nPress L to choose the save\nPress S to see the stats\nPress A to see the achievements");
+}
+
+pub fn spawn_pause_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_menu(&mut commands, &asset_server, "Paused",
+	       "Press P to resume\nPress X to toggle pixel-perfect scaling\
+		\nPress R to toggle reduced motion\nPress T to switch between holding and toggling the sprint\
+		\nPress C to toggle the colorblind palette\
+		\nPress I to invert the horizontal movement\nPress O to invert the vertical movement\
+		\nPress K to toggle the aim assist\nPress U to toggle pausing when the window loses the focus\
+		\nPress V to change the shape of the view\nPress N to change the color of the player\
+		\nPress 1 and 2 to change the volume, 3 and 4 the music, 5 and 6 the sounds\
+		\nPress B to change the controls\nPress Q to return to the main menu");
+}
+
+pub fn despawn_menu(mut commands: Commands, query_menu: Query<Entity, With<MenuUi>>) {
+    for menu in &query_menu {
+	commands.entity(menu).despawn_recursive();
+    }
+}
+
+pub fn start_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut transition: ResMut<Transition>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Return) && transition.target.is_none() {
+	transition.start(GameState::Playing);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// A change of screen in progress, with a fade to black
+#[derive(Resource, Default)]
+pub struct Transition {
+    pub target: Option<GameState>,
+    pub timer: Timer,
+    // Whether the state was switched already, at the middle of the fade
+    pub switched: bool,
+}
+
+impl Transition {
+    // Fades to another state, unless a transition is already going on
+    pub fn start(&mut self, target: GameState) {
+	if self.target.is_some() {
+	    return;
+	}
+	self.target = Some(target);
+	self.timer = Timer::from_seconds(FADE_TIME, TimerMode::Once);
+	self.switched = false;
+    }
+}
+
+#[derive(Component)]
+pub struct FadeOverlay;
+
+// System that runs the transitions: the screen goes black, the state changes, and the screen comes back
+pub fn run_transition(
+    time: Res<Time>,
+    mut transition: ResMut<Transition>,
+    mut state: ResMut<State<GameState>>,
+    mut query_overlay: Query<(&mut BackgroundColor, &mut Visibility), With<FadeOverlay>>,
+) {
+    let Some(target) = transition.target else {
+	return;
+    };
+    let (mut color, mut visibility) = query_overlay.single_mut();
+    transition.timer.tick(time.delta());
+    let progress = transition.timer.percent();
+    if progress >= 0.5 && !transition.switched {
+	// The whole stack is replaced, the main menu also leaves the game under the pause menu
+	let _ = state.replace(target);
+	transition.switched = true;
+    }
+    if transition.timer.finished() {
+	transition.target = None;
+	visibility.is_visible = false;
+	return;
+    }
+    visibility.is_visible = true;
+    *color = Color::rgba(0.0, 0.0, 0.0, 1.0 - (2.0 * progress - 1.0).abs()).into();
+}
+
+// The pause screen is pushed on top of the game, so that nothing is respawned when resuming
+pub fn pause_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(KeyCode::P) {
+	let _ = state.push(GameState::Paused);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+pub fn resume_game(
+    keyboard_input: Res<Input<KeyCode>>,
+    transition: Res<Transition>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(KeyCode::P) && transition.target.is_none() {
+	let _ = state.pop();
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// From the pause menu, the ending and the game over screen. The game under them is left too, the level is torn down when Playing exits
+pub fn return_to_main_menu(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut transition: ResMut<Transition>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(MAIN_MENU_KEY) && transition.target.is_none() {
+	transition.start(GameState::MainMenu);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// Despawns the level of the game that was left: the scenery, the characters, the HUD, and whatever the game
+// spawned since (projectiles, particles, loot, chunks). The children go with their parents
+pub fn despawn_level(
+    mut commands: Commands,
+    query_level: Query<Entity, (Without<Parent>, Without<Persistent>, Without<MenuUi>)>,
+) {
+    for entity in &query_level {
+	commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Forgets what the game that was left kept in the resources, like a new run of the game.
+// What carries over from a game to the next comes back from the save
+pub fn reset_level(
+    mut chunks: ResMut<ChunkIndex>,
+    (mut particles, mut projectiles): (ResMut<Particles>, ResMut<Pool<Projectile>>),
+    mut inventory: ResMut<Inventory>,
+    mut currency: ResMut<Currency>,
+    mut upgrades: ResMut<Upgrades>,
+    mut tracker: ResMut<ObjectiveTracker>,
+    mut quests: ResMut<QuestLog>,
+    mut location: ResMut<Location>,
+    mut dialogue: ResMut<Dialogue>,
+    mut inventory_screen: ResMut<InventoryScreen>,
+    mut chest_screen: ResMut<ChestScreen>,
+    mut shop_screen: ResMut<ShopScreen>,
+    mut quest_log_screen: ResMut<QuestLogScreen>,
+    mut clock: ResMut<GameClock>,
+    (mut run_stats, mut lives): (ResMut<RunStats>, ResMut<Lives>),
+    mut tutorial: ResMut<Tutorial>,
+) {
+    // The chunks were despawned with the rest, the index of the map stays
+    chunks.loaded.clear();
+    *particles = Particles::default();
+    *projectiles = Pool::default();
+    *inventory = Inventory::default();
+    *currency = Currency::default();
+    *upgrades = Upgrades::default();
+    *tracker = ObjectiveTracker::default();
+    *quests = QuestLog::default();
+    *location = Location::default();
+    *dialogue = Dialogue::default();
+    *inventory_screen = InventoryScreen::default();
+    *chest_screen = ChestScreen::default();
+    *shop_screen = ShopScreen::default();
+    *quest_log_screen = QuestLogScreen::default();
+    *clock = GameClock::default();
+    *run_stats = RunStats::default();
+    *lives = Lives::default();
+    *tutorial = Tutorial::default();
+}
+
+// The game pauses when the player goes to another window, and only resumes from the pause menu
+pub fn pause_on_focus_loss(
+    settings: Res<Settings>,
+    mut focus_events: EventReader<WindowFocused>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if focus_events.iter().any(|event| !event.focused) && settings.pause_on_focus_loss {
+	let _ = state.push(GameState::Paused);
+    }
+}
+
+// Whether a button was just pressed on one of the gamepads. The press is used up,
+// like the keys that open or close a screen
+pub fn consume_gamepad_press(gamepads: &Gamepads, gamepad_input: &mut Input<GamepadButton>, button_type: GamepadButtonType) -> bool {
+    let pressed = gamepads.iter()
+	.map(|gamepad| GamepadButton::new(gamepad, button_type))
+	.find(|button| gamepad_input.just_pressed(*button));
+    if let Some(button) = pressed {
+	gamepad_input.reset(button);
+    }
+    pressed.is_some()
+}
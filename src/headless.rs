@@ -0,0 +1,259 @@
+// Running the game without a window, to let the AI play for a long time or to replay scripted inputs.
+// It stops after a number of ticks and prints a summary of the run as JSON
+
+use crate::*;
+
+// How many ticks are run when --ticks is not given, a minute at the default tick rate
+pub const HEADLESS_TICKS: u64 = 720;
+
+// Keys held for a number of ticks, the steps of a script follow each other
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScriptStep {
+    pub ticks: u64,
+    pub keys: Vec<KeyCode>,
+}
+
+// The keys held at each tick, from a file. Nothing is held past its end
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(transparent)]
+pub struct InputScript(pub Vec<ScriptStep>);
+
+impl InputScript {
+    pub fn load(path: &str) -> Result<InputScript, String> {
+	let contents = fs::read_to_string(path).map_err(|error| format!("Could not read the script {path}: {error}"))?;
+	ron::from_str(&contents).map_err(|error| format!("Could not parse the script {path}: {error}"))
+    }
+
+    pub fn keys_at(&self, tick: u64) -> &[KeyCode] {
+	let mut start = 0;
+	for step in &self.0 {
+	    if tick < start + step.ticks {
+		return &step.keys;
+	    }
+	    start += step.ticks;
+	}
+	&[]
+    }
+}
+
+// The game without a window, rendering or audio, ready to be run or updated a tick at a time.
+// The settings and the saves are the default ones, a run does not depend on the previous ones
+pub fn headless_app(ticks: u64, script: InputScript, seed: Option<u64>) -> App {
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let mut app = App::new();
+    app.insert_resource(WgpuSettings {
+	backends: None,
+	..default()
+    })
+	.add_plugins(DefaultPlugins
+		     .set(WindowPlugin {
+			 add_primary_window: false,
+			 exit_on_all_closed: false,
+			 ..default()
+		     })
+		     .disable::<LogPlugin>()
+		     .disable::<WinitPlugin>()
+		     .disable::<AudioPlugin>())
+	.add_plugin(BoarGamePlugin { headless: true, fresh: true, seed: Some(seed) })
+	.add_plugin(HeadlessPlugin { ticks, script, seed });
+    app
+}
+
+// A digest of the world: where the characters and the obstacles are, their health, and the inventory
+pub fn world_hash<'a>(healths: impl Iterator<Item = (&'a Transform, &'a HealthPoints)>, inventory: &Inventory) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (transform, health) in healths {
+	for value in [transform.translation.x, transform.translation.y, health.current] {
+	    value.to_bits().hash(&mut hasher);
+	}
+    }
+    for stack in &inventory.slots {
+	stack.map(|stack| (stack.item, stack.count)).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// Adds the script and the end of the run to a game without a window
+pub struct HeadlessPlugin {
+    pub ticks: u64,
+    pub script: InputScript,
+    pub seed: u64,
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+	// The game sees the same time from the start, the frames are exactly a tick apart
+	let start = Instant::now();
+	app.insert_resource(HeadlessRun {
+	    ticks: self.ticks,
+	    done: 0,
+	    seed: self.seed,
+	    script: self.script.clone(),
+	    clock: start,
+	    events: EventCounts::default(),
+	})
+	    .insert_resource(TimeUpdateStrategy::ManualInstant(start))
+	    .add_system_to_stage(CoreStage::PreUpdate, apply_script.after(InputSystem))
+	    .add_system_set(SystemSet::on_update(GameState::Loading).with_system(start_headless_run))
+	    .add_system_set_to_stage(FixedUpdateStage, SystemSet::new().with_run_criteria(while_playing).with_system(count_ticks))
+	    .add_system_to_stage(CoreStage::Last, count_events)
+	    .add_system_to_stage(CoreStage::Last, finish_headless_run.after(count_events));
+    }
+}
+
+// How many of the gameplay events were sent during the run
+#[derive(Serialize, Default, Debug)]
+pub struct EventCounts {
+    pub damage: u64,
+    pub deaths: u64,
+    pub collisions: u64,
+    pub boar_state_changes: u64,
+    pub experience_gained: u64,
+}
+
+#[derive(Resource)]
+pub struct HeadlessRun {
+    pub ticks: u64,
+    pub done: u64,
+    pub seed: u64,
+    pub script: InputScript,
+    // The time the game sees, it moves by one tick at each frame
+    pub clock: Instant,
+    pub events: EventCounts,
+}
+
+// Where a character is at the end of the run, and its health
+#[derive(Serialize, Debug)]
+pub struct CharacterSummary {
+    pub name: String,
+    pub position: Vec2,
+    pub health: f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunSummary {
+    pub ticks: u64,
+    pub seed: u64,
+    // The digest of the world at the end, the same for the runs with the same seed and the same script
+    pub hash: u64,
+    pub state: String,
+    pub lives: u32,
+    pub player: Option<CharacterSummary>,
+    pub npcs: Vec<CharacterSummary>,
+    pub events: EventCounts,
+}
+
+// System that holds the keys of the script for the tick about to run, after the keyboard is read
+pub fn apply_script(run: Res<HeadlessRun>, mut keyboard_input: ResMut<Input<KeyCode>>) {
+    let keys = run.script.keys_at(run.done);
+    let released: Vec<KeyCode> = keyboard_input.get_pressed().filter(|key| !keys.contains(key)).copied().collect();
+    for key in released {
+	keyboard_input.release(key);
+    }
+    for key in keys {
+	if !keyboard_input.pressed(*key) {
+	    keyboard_input.press(*key);
+	}
+    }
+}
+
+// System that starts the run once the background is loaded. The walls are moved to its edges then,
+// a run that started before would depend on how fast it loads
+pub fn start_headless_run(
+    asset_server: Res<AssetServer>,
+    mut state: ResMut<State<GameState>>,
+    query_background: Query<&Handle<Image>, With<Background>>,
+) {
+    let loaded = query_background.iter()
+	.all(|handle| matches!(asset_server.get_load_state(handle), LoadState::Loaded | LoadState::Failed));
+    if loaded {
+	let _ = state.set(GameState::Playing);
+    }
+}
+
+pub fn count_ticks(mut run: ResMut<HeadlessRun>) {
+    run.done += 1;
+}
+
+pub fn count_events(
+    mut run: ResMut<HeadlessRun>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut deaths: EventReader<DeathEvent>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut state_events: EventReader<BoarStateChanged>,
+    mut experience_events: EventReader<ExperienceGained>,
+) {
+    let events = &mut run.events;
+    events.damage += damage_events.iter().count() as u64;
+    events.deaths += deaths.iter().count() as u64;
+    events.collisions += collision_events.iter().count() as u64;
+    events.boar_state_changes += state_events.iter().count() as u64;
+    events.experience_gained += experience_events.iter().count() as u64;
+}
+
+// System that moves the time of the game by a tick for the next frame, and ends the run once all the ticks are done
+pub fn finish_headless_run(
+    simulation: Res<Simulation>,
+    lives: Res<Lives>,
+    state: Res<State<GameState>>,
+    mut run: ResMut<HeadlessRun>,
+    mut time_update: ResMut<TimeUpdateStrategy>,
+    mut exit: EventWriter<AppExit>,
+    query_player: Query<(&Transform, &HealthPoints), With<Player>>,
+    query_npc: Query<(&Transform, &HealthPoints, Option<&DisplayName>), (With<Hostile>, Without<Player>)>,
+    query_health: Query<(&Transform, &HealthPoints)>,
+    inventory: Res<Inventory>,
+) {
+    run.clock += Duration::from_secs_f32(simulation.dt());
+    *time_update = TimeUpdateStrategy::ManualInstant(run.clock);
+    if run.done < run.ticks {
+	return;
+    }
+    let summary = RunSummary {
+	ticks: run.done,
+	seed: run.seed,
+	hash: world_hash(query_health.iter(), &inventory),
+	state: format!("{:?}", state.current()),
+	lives: lives.0,
+	player: query_player.get_single().ok().map(|(transform, health)| CharacterSummary {
+	    name: "Player".to_string(),
+	    position: transform.translation.truncate(),
+	    health: health.current,
+	}),
+	npcs: query_npc.iter().map(|(transform, health, name)| CharacterSummary {
+	    name: name.map_or("NPC".to_string(), |name| name.0.clone()),
+	    position: transform.translation.truncate(),
+	    health: health.current,
+	}).collect(),
+	events: std::mem::take(&mut run.events),
+    };
+    match serde_json::to_string(&summary) {
+	Ok(json) => println!("{json}"),
+	Err(error) => error!("Could not serialize the summary of the run: {error}"),
+    }
+    exit.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The whole game for the given ticks, then the digest of its world
+    fn run(seed: u64, ticks: u64) -> u64 {
+	// Walks among the boars while attacking, they wander, chase, get hit and drop their loot
+	let script = ron::from_str("[(ticks: 300, keys: [A, Space]), (ticks: 300, keys: [S, Space]), (ticks: 400, keys: [D, W, Space])]").unwrap();
+	let mut app = headless_app(ticks, script, Some(seed));
+	while app.world.resource::<HeadlessRun>().done < ticks {
+	    app.update();
+	}
+	let mut query_health = app.world.query::<(&Transform, &HealthPoints)>();
+	world_hash(query_health.iter(&app.world), app.world.resource::<Inventory>())
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+	let first = run(7, 1000);
+	assert_eq!(first, run(7, 1000));
+	assert_ne!(first, run(8, 1000));
+    }
+}
@@ -0,0 +1,1003 @@
+// The items: pickups, inventory, loot, projectiles, chests and the shop stock
+
+use crate::*;
+
+// Pickup settings: the items on the ground bob up and down until the player walks over them.
+// Items dropped together are scattered around the spot so that they do not stack
+pub const PICKUP_SIZE: Vec2 = Vec2::new(16.0, 16.0);
+pub const PICKUP_Z: f32 = 0.08;
+pub const PICKUP_BOB_HEIGHT: f32 = 3.0;
+pub const PICKUP_BOB_TIME: f32 = 0.6;
+pub const PICKUP_SCATTER: f32 = 24.0;
+// The loot of the dead boars and the broken crates vanishes when left too long, it blinks before
+pub const LOOT_LIFETIME: f32 = 30.0;
+pub const LOOT_BLINK_TIME: f32 = 5.0;
+pub const LOOT_BLINK_PERIOD: f32 = 0.2;
+// Item settings: F uses the item of the selected slot, so does the key of the slot when
+// pressed again. Rocks can also be thrown directly
+pub const USE_ITEM_KEY: KeyCode = KeyCode::F;
+pub const THROW_KEY: KeyCode = KeyCode::G;
+pub const APPLE_HEAL: f32 = 20.0;
+pub const EATING_TIME: f32 = 0.5;
+pub const EATING_SQUASH: Vec3 = Vec3::new(1.1, 0.9, 1.0);
+pub const PROJECTILE_SPEED: f32 = 400.0;
+pub const PROJECTILE_RANGE_TIME: f32 = 0.8;
+pub const PROJECTILE_DAMAGE: f32 = 15.0;
+pub const PROJECTILE_SIZE: Vec2 = Vec2::new(12.0, 12.0);
+pub const PROJECTILE_Z: f32 = 0.25;
+pub const MEAT_HEAL: f32 = 40.0;
+pub const POTION_HEAL: f32 = 60.0;
+// What the boss and the broken crates leave behind, and what the chests hold at first,
+// from the tables of the loot file (in the assets folder). The other NPCs name their table in their prefab
+pub const LOOT_FILE: &str = "config/loot.ron";
+pub const BOSS_LOOT: &str = "boss";
+pub const CRATE_LOOT: &str = "crate";
+pub const CHEST_LOOT: &str = "chest";
+
+// Inventory settings, the first slots are shown in the hotbar at the bottom of the screen
+pub const INVENTORY_SLOTS: usize = 12;
+pub const MAX_STACK: u32 = 99;
+pub const HOTBAR_KEYS: [KeyCode; 4] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+pub const HOTBAR_SLOT_SIZE: f32 = 48.0;
+pub const HOTBAR_SLOT_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+pub const HOTBAR_SELECTED_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.4);
+// The shop of the house, with its prices and stock from the shop file (in the assets folder)
+pub const SHOP_FILE: &str = "config/shop.ron";
+pub const SHOP_ICON_SIZE: f32 = 32.0;
+
+// Chest settings
+pub const CHEST_SIZE: Vec2 = Vec2::new(24.0, 20.0);
+
+// The things the player can carry in the inventory
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ItemKind {
+    Apple,
+    Rock,
+    Bait,
+    Meat,
+    Tusk,
+    Potion,
+    // Never in the inventory, it goes to the purse
+    Coin,
+}
+
+impl ItemKind {
+    pub fn name(&self) -> &'static str {
+	match self {
+	    ItemKind::Apple => "Apple",
+	    ItemKind::Rock => "Rock",
+	    ItemKind::Bait => "Bait",
+	    ItemKind::Meat => "Meat",
+	    ItemKind::Tusk => "Tusk",
+	    ItemKind::Potion => "Potion",
+	    ItemKind::Coin => "Coin",
+	}
+    }
+
+    // Shown in the tooltip of the inventory screen
+    pub fn description(&self) -> &'static str {
+	match self {
+	    ItemKind::Apple => "Eat it to get some health back, or throw it to a boar",
+	    ItemKind::Rock => "Throw it at the boars",
+	    ItemKind::Bait => "Throw it to lure the boars away",
+	    ItemKind::Meat => "Eat it to get a lot of health back",
+	    ItemKind::Tusk => "A trophy from a boar",
+	    ItemKind::Potion => "Drink it to get most of your health back",
+	    ItemKind::Coin => "Spend it at the house",
+	}
+    }
+
+    pub fn sprite(&self) -> &'static str {
+	match self {
+	    ItemKind::Apple => "sprites/apple.png",
+	    ItemKind::Rock => "sprites/rock.png",
+	    ItemKind::Bait => "sprites/bait.png",
+	    ItemKind::Meat => "sprites/meat.png",
+	    ItemKind::Tusk => "sprites/tusk.png",
+	    ItemKind::Potion => "sprites/potion.png",
+	    ItemKind::Coin => "sprites/coin.png",
+	}
+    }
+
+    pub fn use_effect(&self) -> ItemEffect {
+	match self {
+	    ItemKind::Apple => ItemEffect::Heal(APPLE_HEAL),
+	    ItemKind::Rock => ItemEffect::Throw,
+	    ItemKind::Bait => ItemEffect::Lure,
+	    ItemKind::Meat => ItemEffect::Heal(MEAT_HEAL),
+	    ItemKind::Tusk => ItemEffect::Keep,
+	    ItemKind::Potion => ItemEffect::Heal(POTION_HEAL),
+	    ItemKind::Coin => ItemEffect::Keep,
+	}
+    }
+}
+
+// What using an item does
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ItemEffect {
+    // Eaten, unless the player has all its health
+    Heal(f32),
+    // Thrown where the player faces
+    Throw,
+    // Thrown where the player faces, over the boars, and left on the ground for them
+    Lure,
+    // Nothing, the item is only kept
+    Keep,
+}
+
+// A thrown item flying, it hurts the first boar or obstacle it meets.
+// A lure flies over them, and lands as a bait scent. A treat is given to the first boar it meets,
+// and lands as an item otherwise
+#[derive(Component)]
+pub struct Projectile {
+    pub range: Timer,
+    pub damage: f32,
+    pub lure: bool,
+    pub treat: bool,
+}
+
+// Items lying on the ground, the player picks them up by walking over them
+#[derive(Component)]
+pub struct Pickup {
+    pub item: ItemKind,
+    pub amount: u32,
+}
+
+// A pickup the player dropped, it is picked up again only once the player has walked off it
+#[derive(Component)]
+pub struct Dropped;
+
+// Despawns its entity, with the children, when the timer finishes. The time only goes on while playing
+#[derive(Component)]
+pub struct Lifetime(pub Timer);
+
+impl Lifetime {
+    pub fn new(seconds: f32) -> Lifetime {
+	Lifetime(Timer::from_seconds(seconds, TimerMode::Once))
+    }
+}
+
+// Despawns its entity, with the children, once it is farther than the margin past the walls around the map
+#[derive(Component)]
+pub struct DespawnOutOfBounds {
+    pub margin: f32,
+}
+
+impl DespawnOutOfBounds {
+    pub fn is_out(&self, play_area: &PlayArea, position: Vec2) -> bool {
+	!(position.cmpge(play_area.walls.min - self.margin).all() && position.cmple(play_area.walls.max + self.margin).all())
+    }
+}
+
+// An entity kept by a pool once released, the despawn systems leave it alone
+#[derive(Component)]
+pub struct Pooled;
+
+// The entities with a T that were released, hidden and kept to be acquired again instead of spawning new ones.
+// Releasing removes the T, so the systems of the T no longer see the entity until it is acquired
+#[derive(Resource)]
+pub struct Pool<T: Component> {
+    pub free: Vec<Entity>,
+    pub released: HashSet<Entity>,
+    pub kind: PhantomData<T>,
+}
+
+impl<T: Component> Default for Pool<T> {
+    fn default() -> Self {
+	Pool {
+	    free: Vec::new(),
+	    released: HashSet::new(),
+	    kind: PhantomData,
+	}
+    }
+}
+
+impl<T: Component> Pool<T> {
+    // Resets a released entity with the bundle, or spawns a new one when there is none left
+    pub fn acquire(&mut self, commands: &mut Commands, bundle: impl Bundle) -> Entity {
+	match self.free.pop() {
+	    Some(entity) => {
+		self.released.remove(&entity);
+		commands.entity(entity).insert(bundle);
+		entity
+	    },
+	    None => commands.spawn((bundle, Pooled)).id(),
+	}
+    }
+
+    // Hides the entity for later, releasing it again does nothing
+    pub fn release(&mut self, commands: &mut Commands, entity: Entity) {
+	if self.released.insert(entity) {
+	    commands.entity(entity).remove::<T>().insert(Visibility { is_visible: false });
+	    self.free.push(entity);
+	}
+    }
+}
+
+// The coins of the player, picked up from the loot and spent at the house
+#[derive(Resource, Default)]
+pub struct Currency {
+    pub coins: u32,
+}
+
+// A chest, its items are in its own inventory. The id finds them in the save
+#[derive(Component)]
+pub struct Chest {
+    pub id: String,
+}
+
+// A pile of items of the same kind, in a slot of the inventory
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct ItemStack {
+    pub item: ItemKind,
+    pub count: u32,
+}
+
+// The items carried by the player, in a fixed number of slots.
+// The first slots are in the hotbar, one of them is selected.
+// Chests have their own, as a component
+#[derive(Resource, Component, Default)]
+pub struct Inventory {
+    pub slots: [Option<ItemStack>; INVENTORY_SLOTS],
+    pub selected: usize,
+}
+
+// There is not enough room in the inventory for the items, nothing was added
+#[derive(Debug)]
+pub struct InventoryFull;
+
+impl Inventory {
+    // Fills the stacks of the same kind first, then the empty slots
+    pub fn add(&mut self, item: ItemKind, amount: u32) -> Result<(), InventoryFull> {
+	if self.room(item) < amount {
+	    return Err(InventoryFull);
+	}
+
+	let mut left = amount;
+	for stack in self.slots.iter_mut().flatten().filter(|stack| stack.item == item) {
+	    let added = left.min(MAX_STACK - stack.count);
+	    stack.count += added;
+	    left -= added;
+	}
+	for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+	    if left == 0 {
+		break;
+	    }
+	    let added = left.min(MAX_STACK);
+	    *slot = Some(ItemStack { item, count: added });
+	    left -= added;
+	}
+	Ok(())
+    }
+
+    // Takes items out, from the last stacks first. Nothing is removed if there are not enough
+    pub fn remove(&mut self, item: ItemKind, amount: u32) -> bool {
+	if self.count(item) < amount {
+	    return false;
+	}
+	let mut left = amount;
+	for slot in self.slots.iter_mut().rev() {
+	    let Some(stack) = slot else {
+		continue;
+	    };
+	    if stack.item != item {
+		continue;
+	    }
+	    let removed = left.min(stack.count);
+	    stack.count -= removed;
+	    left -= removed;
+	    if stack.count == 0 {
+		*slot = None;
+	    }
+	}
+	true
+    }
+
+    // How many more items of a kind fit
+    pub fn room(&self, item: ItemKind) -> u32 {
+	self.slots.iter()
+	    .map(|slot| match slot {
+		None => MAX_STACK,
+		Some(stack) if stack.item == item => MAX_STACK - stack.count,
+		Some(_) => 0,
+	    })
+	    .sum()
+    }
+
+    // Takes items out of a slot, at most what it holds
+    pub fn take(&mut self, slot: usize, amount: u32) -> Option<ItemStack> {
+	let stack = self.slots[slot].as_mut()?;
+	let taken = amount.min(stack.count);
+	stack.count -= taken;
+	let item = stack.item;
+	if stack.count == 0 {
+	    self.slots[slot] = None;
+	}
+	Some(ItemStack { item, count: taken })
+    }
+
+    // Moves items from a slot to another inventory, as many as fit. Returns how many were moved
+    pub fn transfer(&mut self, slot: usize, amount: u32, other: &mut Inventory) -> u32 {
+	let Some(stack) = self.slots[slot] else {
+	    return 0;
+	};
+	let moved = amount.min(stack.count).min(other.room(stack.item));
+	if moved == 0 || other.add(stack.item, moved).is_err() {
+	    return 0;
+	}
+	self.take(slot, moved);
+	moved
+    }
+
+    // Moves a stack to another slot: onto a stack of the same kind as much as fits, otherwise the slots are swapped
+    pub fn move_stack(&mut self, from: usize, to: usize) {
+	if from != to {
+	    (self.slots[from], self.slots[to]) = stack_onto(self.slots[from], self.slots[to]);
+	}
+    }
+
+    pub fn count(&self, item: ItemKind) -> u32 {
+	self.slots.iter().flatten().filter(|stack| stack.item == item).map(|stack| stack.count).sum()
+    }
+}
+
+// Puts a stack onto a slot: onto a stack of the same kind as much as fits, otherwise they are swapped.
+// Returns what ends up in the slot moved from, and in the slot moved to
+pub fn stack_onto(moved: Option<ItemStack>, target: Option<ItemStack>) -> (Option<ItemStack>, Option<ItemStack>) {
+    match (moved, target) {
+	(Some(moved), Some(mut target)) if moved.item == target.item => {
+	    let added = moved.count.min(MAX_STACK - target.count);
+	    target.count += added;
+	    ((moved.count > added).then_some(ItemStack { count: moved.count - added, ..moved }), Some(target))
+	}
+	_ => (target, moved),
+    }
+}
+
+// The two sides of the chest screen
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Container {
+    Player,
+    Chest,
+}
+
+impl Container {
+    // The inventory of this side, then the one of the other side
+    pub fn sides<'a>(self, player: &'a mut Inventory, chest: &'a mut Inventory) -> (&'a mut Inventory, &'a mut Inventory) {
+	match self {
+	    Container::Player => (player, chest),
+	    Container::Chest => (chest, player),
+	}
+    }
+
+    // Said when the other side has no room left
+    pub fn full_message(self) -> &'static str {
+	match self {
+	    Container::Player => "The chest is full",
+	    Container::Chest => "Inventory full",
+	}
+    }
+}
+
+// Spawns items on the ground, spread around the position when there are several
+pub fn spawn_pickups(commands: &mut Commands, rng: &mut impl Rng, items: &[(ItemKind, u32)], position: Vec2) -> Vec<Entity> {
+    let mut pickups = Vec::new();
+    for &(item, amount) in items {
+	let offset = if items.len() > 1 {
+	    Vec2::new(rng.gen_range(-PICKUP_SCATTER..PICKUP_SCATTER), rng.gen_range(-PICKUP_SCATTER..PICKUP_SCATTER))
+	} else {
+	    Vec2::ZERO
+	};
+	pickups.push(commands.spawn_pickup(item, amount, position + offset));
+    }
+    pickups
+}
+
+// An item that may be dropped: with a chance from 0 to 1, in a number between the two counts
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct LootEntry {
+    pub item: ItemKind,
+    pub chance: f64,
+    pub count: (u32, u32),
+}
+
+// The items that may be dropped together
+#[derive(Deserialize, Serialize, Clone, Default, Debug)]
+pub struct LootTable {
+    pub entries: Vec<LootEntry>,
+    // Experience for defeating what drops it
+    #[serde(default)]
+    pub xp: u32,
+}
+
+impl LootTable {
+    // Each entry is rolled on its own
+    pub fn roll(&self, rng: &mut impl Rng) -> Vec<(ItemKind, u32)> {
+	let mut drops = Vec::new();
+	for entry in &self.entries {
+	    if !rng.gen_bool(entry.chance.clamp(0.0, 1.0)) {
+		continue;
+	    }
+	    let count = rng.gen_range(entry.count.0..=entry.count.1.max(entry.count.0));
+	    if count > 0 {
+		drops.push((entry.item, count));
+	    }
+	}
+	drops
+    }
+}
+
+// All the loot tables, by name
+#[derive(Resource, Deserialize, Serialize, Default, Debug)]
+pub struct LootTables {
+    pub tables: HashMap<String, LootTable>,
+}
+
+impl LootTables {
+    // Reads the tables from the assets folder, nothing is dropped if they cannot be read
+    pub fn load() -> LootTables {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(LOOT_FILE);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the loot tables {}: {error}", full_path.display());
+		return LootTables::default();
+	    },
+	};
+	match ron::from_str(&contents) {
+	    Ok(tables) => tables,
+	    Err(error) => {
+		warn!("Could not parse the loot tables {}: {error}", full_path.display());
+		LootTables::default()
+	    },
+	}
+    }
+
+    pub fn roll(&self, table: &str, rng: &mut impl Rng) -> Vec<(ItemKind, u32)> {
+	match self.tables.get(table) {
+	    Some(table) => table.roll(rng),
+	    None => {
+		warn!("There is no loot table {table}");
+		Vec::new()
+	    },
+	}
+    }
+
+    pub fn xp(&self, table: &str) -> u32 {
+	self.tables.get(table).map_or(0, |table| table.xp)
+    }
+}
+
+// An item of the shop, with its prices in coins
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ShopOffer {
+    pub item: ItemKind,
+    // Not for sale without it
+    #[serde(default)]
+    pub buy: Option<u32>,
+    // Not bought from the player without it
+    #[serde(default)]
+    pub sell: Option<u32>,
+    // How many are left to buy, no limit without it
+    #[serde(default)]
+    pub stock: Option<u32>,
+}
+
+// An upgrade of the shop, with its price in coins
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct UpgradeOffer {
+    pub upgrade: Upgrade,
+    pub price: u32,
+    // How many are left to buy, no limit without it
+    #[serde(default)]
+    pub stock: Option<u32>,
+}
+
+// Whether the player buys an item, sells one, or buys an upgrade
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trade {
+    Buy,
+    Sell,
+    Upgrade,
+}
+
+// The offers of the house, read from the assets folder
+#[derive(Resource, Deserialize, Serialize, Default, Debug)]
+pub struct Shop {
+    pub offers: Vec<ShopOffer>,
+    #[serde(default)]
+    pub upgrades: Vec<UpgradeOffer>,
+}
+
+impl Shop {
+    // Reads the offers from the assets folder, the shop is empty if they cannot be read
+    pub fn load() -> Shop {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(SHOP_FILE);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the shop {}: {error}", full_path.display());
+		return Shop::default();
+	    },
+	};
+	match ron::from_str(&contents) {
+	    Ok(shop) => shop,
+	    Err(error) => {
+		warn!("Could not parse the shop {}: {error}", full_path.display());
+		Shop::default()
+	    },
+	}
+    }
+
+    // The lines of the shop screen: what can be bought, what can be sold, then the upgrades
+    pub fn rows(&self) -> Vec<ShopRow> {
+	let buy = self.offers.iter().enumerate()
+	    .filter(|(_, offer)| offer.buy.is_some())
+	    .map(|(offer, _)| ShopRow { trade: Trade::Buy, offer });
+	let sell = self.offers.iter().enumerate()
+	    .filter(|(_, offer)| offer.sell.is_some())
+	    .map(|(offer, _)| ShopRow { trade: Trade::Sell, offer });
+	let upgrades = (0..self.upgrades.len()).map(|offer| ShopRow { trade: Trade::Upgrade, offer });
+	buy.chain(sell).chain(upgrades).collect()
+    }
+
+    pub fn sprite(&self, row: ShopRow) -> &'static str {
+	match row.trade {
+	    Trade::Buy | Trade::Sell => self.offers[row.offer].item.sprite(),
+	    Trade::Upgrade => self.upgrades[row.offer].upgrade.sprite(),
+	}
+    }
+
+    // Buys or sells one item, or buys an upgrade. Everything is checked before anything changes,
+    // so a trade that cannot be done leaves the coins, the inventory and the stock as they were
+    pub fn trade(&mut self, row: ShopRow, currency: &mut Currency, inventory: &mut Inventory, upgrades: &mut Upgrades) -> Result<(), &'static str> {
+	if row.trade == Trade::Upgrade {
+	    let offer = &mut self.upgrades[row.offer];
+	    if offer.stock == Some(0) {
+		return Err("Sold out");
+	    }
+	    if currency.coins < offer.price {
+		return Err("Not enough coins");
+	    }
+	    currency.coins -= offer.price;
+	    if let Some(stock) = &mut offer.stock {
+		*stock -= 1;
+	    }
+	    upgrades.add(offer.upgrade);
+	    return Ok(());
+	}
+	let offer = &mut self.offers[row.offer];
+	match (row.trade, offer.buy, offer.sell) {
+	    (Trade::Buy, Some(price), _) => {
+		if offer.stock == Some(0) {
+		    return Err("Sold out");
+		}
+		if currency.coins < price {
+		    return Err("Not enough coins");
+		}
+		if inventory.add(offer.item, 1).is_err() {
+		    return Err("Inventory full");
+		}
+		currency.coins -= price;
+		if let Some(stock) = &mut offer.stock {
+		    *stock -= 1;
+		}
+	    },
+	    (Trade::Sell, _, Some(price)) => {
+		if !inventory.remove(offer.item, 1) {
+		    return Err("You have none to sell");
+		}
+		currency.coins += price;
+	    },
+	    _ => return Err("The house does not trade that"),
+	}
+	Ok(())
+    }
+
+    // The text of a line of the shop screen
+    pub fn describe(&self, row: ShopRow, inventory: &Inventory) -> String {
+	let left = |stock: Option<u32>| match stock {
+	    Some(stock) => format!(", {stock} left"),
+	    None => String::new(),
+	};
+	match row.trade {
+	    Trade::Buy => {
+		let offer = &self.offers[row.offer];
+		format!("{}: {} coins{}", offer.item.name(), offer.buy.unwrap_or_default(), left(offer.stock))
+	    },
+	    Trade::Sell => {
+		let offer = &self.offers[row.offer];
+		format!("{}: {} coins, you have {}", offer.item.name(), offer.sell.unwrap_or_default(), inventory.count(offer.item))
+	    },
+	    Trade::Upgrade => {
+		let offer = &self.upgrades[row.offer];
+		format!("{}: {} coins{}", offer.upgrade.name(), offer.price, left(offer.stock))
+	    },
+	}
+    }
+}
+
+// Spawns the pickups left by a death or a broken obstacle, they expire when not picked up
+pub fn spawn_loot(commands: &mut Commands, rng: &mut impl Rng, items: &[(ItemKind, u32)], position: Vec2) {
+    for pickup in spawn_pickups(commands, rng, items, position) {
+	commands.entity(pickup).insert(Lifetime::new(LOOT_LIFETIME));
+    }
+}
+
+// System that blinks the loot about to expire, and despawns it once expired
+pub fn blink_loot(mut query: Query<(&Lifetime, &mut Visibility), With<Pickup>>) {
+    for (lifetime, mut visibility) in &mut query {
+	let remaining = lifetime.0.duration().as_secs_f32() - lifetime.0.elapsed_secs();
+	visibility.is_visible = remaining > LOOT_BLINK_TIME || (remaining / LOOT_BLINK_PERIOD).fract() < 0.5;
+    }
+}
+
+// System that puts the items the player walks over in the inventory, and the coins in the purse
+pub fn collect_pickups(
+    mut commands: Commands,
+    mut inventory: ResMut<Inventory>,
+    mut currency: ResMut<Currency>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut toasts: EventWriter<Toast>,
+    query_player: Query<(&Transform, &PlayerSize), With<Player>>,
+    query_pickup: Query<(Entity, &Transform, &Pickup, Option<&Dropped>)>,
+    // The pickups left on the ground because the inventory is full, while the player stands on them
+    mut refused: Local<HashSet<Entity>>,
+) {
+    let (player_transform, player_size) = query_player.single();
+    let mut still_refused = HashSet::new();
+    for (entity, pickup_transform, pickup, dropped) in &query_pickup {
+	let touching = collide(player_transform.translation.truncate().extend(0.0), player_size.0,
+			       pickup_transform.translation, PICKUP_SIZE).is_some();
+	if dropped.is_some() {
+	    if !touching {
+		commands.entity(entity).remove::<Dropped>();
+	    }
+	    continue;
+	}
+	if !touching {
+	    continue;
+	}
+	if pickup.item == ItemKind::Coin {
+	    currency.coins += pickup.amount;
+	    commands.entity(entity).despawn_recursive();
+	    sfx_events.send(PlaySfx(Sfx::Blip));
+	    toasts.send(Toast(format!("Picked up {} coins ({})", pickup.amount, currency.coins)));
+	    continue;
+	}
+	if inventory.add(pickup.item, pickup.amount).is_err() {
+	    // Said once, not at every frame spent on the pickup
+	    if !refused.contains(&entity) {
+		toasts.send(Toast("Inventory full".to_string()));
+	    }
+	    still_refused.insert(entity);
+	    continue;
+	}
+	commands.entity(entity).despawn_recursive();
+	sfx_events.send(PlaySfx(Sfx::Blip));
+	toasts.send(Toast(format!("Picked up {} ({})", pickup.item.name(), inventory.count(pickup.item))));
+    }
+    *refused = still_refused;
+}
+
+// System that uses items: the selected one, or a rock thrown directly
+pub fn use_items(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    loadout: Res<PlayerLoadout>,
+    mut inventory: ResMut<Inventory>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut projectiles: ResMut<Pool<Projectile>>,
+    mut query_player: Query<(Entity, &Transform, &Facing, &mut HealthPoints, Option<&Eating>), With<Player>>,
+) {
+    let (player, player_transform, facing, mut health, eating) = query_player.single_mut();
+    let thrown = bindings.just_pressed(&keyboard_input, Action::Throw) && loadout.ranged;
+    let item = if thrown {
+	// The apples are thrown to the boars when selected, the rocks otherwise
+	match inventory.slots[inventory.selected] {
+	    Some(stack) if stack.item == ItemKind::Apple => ItemKind::Apple,
+	    _ => ItemKind::Rock,
+	}
+    } else if bindings.just_pressed(&keyboard_input, Action::UseItem) || keyboard_input.just_pressed(HOTBAR_KEYS[inventory.selected]) {
+	let Some(stack) = inventory.slots[inventory.selected] else {
+	    return;
+	};
+	stack.item
+    } else {
+	return;
+    };
+    if eating.is_some() {
+	return;
+    }
+
+    match item.use_effect() {
+	ItemEffect::Heal(amount) if !thrown => {
+	    if health.current >= health.max || !inventory.remove(item, 1) {
+		return;
+	    }
+	    health.heal(amount);
+	    commands.entity(player).insert((
+		Eating(Timer::from_seconds(EATING_TIME, TimerMode::Once)),
+		Tween::scale(Vec3::ONE, EATING_SQUASH).over(EATING_TIME / 4.0).ease(Easing::EaseInOut).ping_pong().tagged(TweenTag::Eating),
+	    ));
+	    sfx_events.send(PlaySfx(Sfx::Blip));
+	},
+	ItemEffect::Heal(_) | ItemEffect::Throw | ItemEffect::Lure => {
+	    if !inventory.remove(item, 1) {
+		sfx_events.send(PlaySfx(Sfx::Click));
+		return;
+	    }
+	    projectiles.acquire(&mut commands, (
+		SpriteBundle {
+		    texture: asset_server.load(item.sprite()),
+		    sprite: Sprite {
+			custom_size: Some(PROJECTILE_SIZE),
+			..default()
+		    },
+		    transform: Transform::from_translation(player_transform.translation.truncate().extend(PROJECTILE_Z)),
+		    ..default()
+		},
+		Velocity(facing.direction() * PROJECTILE_SPEED),
+		Projectile {
+		    range: Timer::from_seconds(PROJECTILE_RANGE_TIME, TimerMode::Once),
+		    damage: PROJECTILE_DAMAGE,
+		    lure: item.use_effect() == ItemEffect::Lure,
+		    treat: item == ItemKind::Apple,
+		},
+	    ));
+	    sfx_events.send(PlaySfx(Sfx::Thud));
+	},
+	ItemEffect::Keep => sfx_events.send(PlaySfx(Sfx::Click)),
+    }
+}
+
+// System that moves the thrown items, they stop at the first NPC or obstacle hit,
+// at the walls, or once out of range
+pub fn move_projectiles(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut particles: ResMut<Particles>,
+    mut projectiles: ResMut<Pool<Projectile>>,
+    mut game_rng: ResMut<GameRng>,
+    hit_stop: Res<HitStop>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut treats: EventWriter<TreatGiven>,
+    mut query_projectile: Query<(Entity, &mut Transform, &Velocity, &mut Projectile)>,
+    query_npc: Query<(Entity, &Transform, &Sprite, &Hitbox, Option<&Boar>), (Without<Projectile>, Without<Carried>, Without<SpawningIn>)>,
+    query_destructible: Query<(Entity, &Transform, &Sprite), (With<Destructible>, Without<Projectile>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    for (projectile, mut transform, velocity, mut stats) in &mut query_projectile {
+	let new_position = transform.translation.truncate() + velocity.0 * simulation.dt();
+	transform.translation.x = new_position.x;
+	transform.translation.y = new_position.y;
+
+	// The treats fly over what is not a boar
+	let hit_npc = query_npc.iter().filter(|(.., boar)| boar.is_some() || !stats.treat).find(|(_, npc_transform, npc_sprite, npc_hitbox, _)| {
+	    let npc_center = npc_hitbox.center(npc_transform.translation.truncate(), npc_sprite.flip_x);
+	    collide(transform.translation, PROJECTILE_SIZE, npc_center.extend(0.0), npc_hitbox.size).is_some()
+	}).map(|(npc, ..)| npc);
+	let hit_obstacle = query_destructible.iter().find(|(_, obstacle_transform, obstacle_sprite)| {
+	    collide(transform.translation, PROJECTILE_SIZE, obstacle_transform.translation,
+		    collider_size(obstacle_transform, obstacle_sprite)).is_some()
+	}).map(|(obstacle, ..)| obstacle);
+	if let Some(boar) = hit_npc.filter(|_| stats.treat) {
+	    treats.send(TreatGiven { boar, by_hand: false });
+	    projectiles.release(&mut commands, projectile);
+	    continue;
+	}
+	if let Some(target) = hit_npc.or(hit_obstacle).filter(|_| !stats.lure && !stats.treat) {
+	    damage_events.send(DamageEvent { target, amount: stats.damage, source: transform.translation.truncate() });
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Impact, transform.translation, IMPACT_COUNT);
+	    projectiles.release(&mut commands, projectile);
+	    continue;
+	}
+
+	let bounds = play_area.bounds(Vec2::ZERO);
+	let out = !bounds.contains(new_position);
+	if !stats.range.tick(Duration::from_secs_f32(simulation.dt())).finished() && !out {
+	    continue;
+	}
+	spawn_burst(&mut commands, &mut particles, ParticleKind::Dust, transform.translation, DUST_LANDING_COUNT);
+	projectiles.release(&mut commands, projectile);
+	let landing = Vec2::new(new_position.x.clamp(bounds.min.x, bounds.max.x), new_position.y.clamp(bounds.min.y, bounds.max.y));
+	if stats.treat {
+	    spawn_pickups(&mut commands, &mut game_rng.0, &[(ItemKind::Apple, 1)], landing);
+	}
+	if stats.lure {
+	    commands.spawn((
+		SpriteBundle {
+		    texture: asset_server.load(ItemKind::Bait.sprite()),
+		    sprite: Sprite {
+			custom_size: Some(PICKUP_SIZE),
+			..default()
+		    },
+		    transform: Transform::from_translation(landing.extend(PICKUP_Z)),
+		    ..default()
+		},
+		BaitScent::default(),
+	    ));
+	}
+    }
+}
+
+// System that selects a slot of the hotbar with the number keys
+pub fn select_hotbar_slot(keyboard_input: Res<Input<KeyCode>>, mut inventory: ResMut<Inventory>) {
+    if let Some(slot) = HOTBAR_KEYS.iter().position(|key| keyboard_input.just_pressed(*key)) {
+	inventory.selected = slot;
+    }
+}
+
+// System that despawns what came to the end of its lifetime
+pub fn despawn_expired(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Lifetime), Without<Pooled>>,
+) {
+    for (entity, mut lifetime) in &mut query {
+	if lifetime.0.tick(time.delta()).finished() {
+	    commands.entity(entity).despawn_recursive();
+	}
+    }
+}
+
+// System that despawns what went too far past the walls around the map
+pub fn despawn_out_of_bounds(
+    mut commands: Commands,
+    play_area: Res<PlayArea>,
+    query: Query<(Entity, &GlobalTransform, &DespawnOutOfBounds), Without<Pooled>>,
+) {
+    for (entity, transform, out_of_bounds) in &query {
+	if out_of_bounds.is_out(&play_area, transform.translation().truncate()) {
+	    commands.entity(entity).despawn_recursive();
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::ecs::system::CommandQueue;
+    use bevy::time::TimeUpdateStrategy;
+    use crate::*;
+    use crate::testing::TestApp;
+
+    fn counts(inventory: &Inventory) -> Vec<Option<u32>> {
+	inventory.slots.iter().map(|slot| slot.map(|stack| stack.count)).collect()
+    }
+
+    // Adding fills the stack already there before starting a new one
+    #[test]
+    fn add_fills_the_stacks_first() {
+	let mut inventory = Inventory::default();
+	inventory.add(ItemKind::Apple, MAX_STACK - 1).unwrap();
+	inventory.add(ItemKind::Rock, 1).unwrap();
+	inventory.add(ItemKind::Apple, 3).unwrap();
+
+	let counts = counts(&inventory);
+	assert_eq!(&counts[..3], &[Some(MAX_STACK), Some(1), Some(2)]);
+	assert_eq!(inventory.count(ItemKind::Apple), MAX_STACK + 2);
+    }
+
+    // Nothing is added when there is not enough room for all the items
+    #[test]
+    fn add_does_not_overflow() {
+	let mut inventory = Inventory::default();
+	inventory.add(ItemKind::Rock, 1).unwrap();
+	let room = inventory.room(ItemKind::Apple);
+	assert_eq!(room, (INVENTORY_SLOTS as u32 - 1) * MAX_STACK);
+
+	assert!(inventory.add(ItemKind::Apple, room + 1).is_err());
+	assert_eq!(inventory.count(ItemKind::Apple), 0);
+
+	inventory.add(ItemKind::Apple, room).unwrap();
+	assert_eq!(inventory.room(ItemKind::Apple), 0);
+	assert!(inventory.add(ItemKind::Apple, 1).is_err());
+	assert_eq!(inventory.count(ItemKind::Apple), room);
+    }
+
+    // Removing takes from the last stacks first and empties their slots, or takes nothing at all
+    #[test]
+    fn remove_takes_from_the_last_stacks() {
+	let mut inventory = Inventory::default();
+	inventory.add(ItemKind::Apple, MAX_STACK + 5).unwrap();
+
+	assert!(!inventory.remove(ItemKind::Apple, MAX_STACK + 6));
+	assert_eq!(inventory.count(ItemKind::Apple), MAX_STACK + 5);
+
+	assert!(inventory.remove(ItemKind::Apple, 7));
+	let counts = counts(&inventory);
+	assert_eq!(&counts[..2], &[Some(MAX_STACK - 2), None]);
+	assert!(!inventory.remove(ItemKind::Rock, 1));
+    }
+
+    #[derive(Component)]
+    struct Pellet;
+
+    // Steps the app with the given time between the frames, the lifetimes tick with the time.
+    // The first frame of the app has no delta, it only starts the clock
+    fn step_time(test: &mut TestApp, seconds: f32, frames: u32) {
+	let mut now = match *test.app.world.resource::<TimeUpdateStrategy>() {
+	    TimeUpdateStrategy::ManualInstant(now) => now,
+	    _ => {
+		let startup = test.app.world.resource::<Time>().startup();
+		test.app.insert_resource(TimeUpdateStrategy::ManualInstant(startup));
+		test.app.update();
+		startup
+	    },
+	};
+	for _ in 0..frames {
+	    now += Duration::from_secs_f32(seconds);
+	    test.app.insert_resource(TimeUpdateStrategy::ManualInstant(now));
+	    test.app.update();
+	}
+    }
+
+    // The entities go away when their lifetime is over, the pooled ones are left alone
+    // and released entities are acquired again instead of spawning new ones
+    #[test]
+    fn lifetimes_despawn_and_pools_reuse() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(despawn_expired));
+	let lifetime = |index: usize| 0.5 * (index % 4 + 1) as f32;
+	let entities: Vec<Entity> = (0..100).map(|index| test.app.world.spawn(Lifetime::new(lifetime(index))).id()).collect();
+
+	let mut pool = Pool::<Pellet>::default();
+	let mut queue = CommandQueue::default();
+	let mut commands = Commands::new(&mut queue, &test.app.world);
+	let pooled: Vec<Entity> = (0..10).map(|_| pool.acquire(&mut commands, (Pellet, Lifetime::new(0.5)))).collect();
+	queue.apply(&mut test.app.world);
+
+	// Half a second at a time, the shortest lifetimes first
+	for step in 1..=4 {
+	    step_time(&mut test, 0.25, 2);
+	    for (index, entity) in entities.iter().enumerate() {
+		assert_eq!(test.app.world.get_entity(*entity).is_some(), lifetime(index) > 0.5 * step as f32);
+	    }
+	}
+	assert!(pooled.iter().all(|entity| test.app.world.get_entity(*entity).is_some()));
+
+	let mut commands = Commands::new(&mut queue, &test.app.world);
+	for entity in &pooled[..5] {
+	    pool.release(&mut commands, *entity);
+	    pool.release(&mut commands, *entity);
+	}
+	let acquired: Vec<Entity> = (0..7).map(|_| pool.acquire(&mut commands, Pellet)).collect();
+	queue.apply(&mut test.app.world);
+
+	assert_eq!(pool.free.len(), 0);
+	assert!(pooled[..5].iter().all(|entity| acquired.contains(entity)));
+	assert_eq!(test.app.world.query_filtered::<(), With<Pooled>>().iter(&test.app.world).count(), 12);
+	assert_eq!(test.app.world.query_filtered::<(), With<Pellet>>().iter(&test.app.world).count(), 12);
+    }
+
+    // Far more particles than the limit: the pool stops growing at the limit, the oldest ones are taken over
+    // and the queue of the particles stays bounded
+    #[test]
+    fn particles_stay_under_the_limit() {
+	let mut test = TestApp::new();
+	let mut queue = CommandQueue::default();
+	for _ in 0..10 {
+	    let mut particles = test.app.world.remove_resource::<Particles>().unwrap();
+	    let mut commands = Commands::new(&mut queue, &test.app.world);
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Dust, Vec3::ZERO, 500);
+	    queue.apply(&mut test.app.world);
+	    test.app.insert_resource(particles);
+	}
+
+	let particles = test.app.world.resource::<Particles>();
+	assert_eq!(particles.next_serial, 5000);
+	assert_eq!(particles.serials.len(), MAX_PARTICLES);
+	assert!(particles.live.len() <= 2 * MAX_PARTICLES);
+	let newest: Vec<Entity> = particles.live.iter().rev().take(100).map(|(entity, _)| *entity).collect();
+	assert_eq!(test.app.world.query_filtered::<(), With<Pooled>>().iter(&test.app.world).count(), MAX_PARTICLES);
+	assert_eq!(test.app.world.query_filtered::<(), With<Particle>>().iter(&test.app.world).count(), MAX_PARTICLES);
+	assert!(newest.iter().all(|entity| test.app.world.get::<Particle>(*entity).is_some()));
+    }
+}
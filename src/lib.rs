@@ -0,0 +1,424 @@
+// The game as a plugin, the executable adds it to an app with a window. The code is split by domain,
+// and everything is public so that the tests can reach it
+
+// Bevy queries with filters are long by nature, and systems take many parameters.
+// The components that start in a given state are made with new(), they have no use for a Default
+#![allow(clippy::type_complexity, clippy::too_many_arguments, clippy::new_without_default)]
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    env,
+    f32::consts::PI,
+    fs,
+    hash::{Hash, Hasher},
+    io::{Cursor, ErrorKind},
+    marker::PhantomData,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    prelude::*,
+    ecs::{event::ManualEventReader, schedule::ShouldRun, system::{Command, EntityCommands}},
+    asset::{FileAssetIo, LoadState},
+    audio::{play_queued_audio_system, AudioOutput, AudioPlugin, AudioSink},
+    log::LogPlugin,
+    winit::WinitPlugin,
+    reflect::TypeUuid,
+    core_pipeline::clear_color::ClearColorConfig,
+    render::{camera::Viewport, settings::WgpuSettings, texture::ImageSampler, view::RenderLayers},
+    sprite::collide_aabb::{collide, Collision},
+    time::{FixedTimestep, TimeUpdateStrategy},
+    app::AppExit,
+    ui::FocusPolicy,
+    window::{WindowFocused, WindowResized},
+    input::{
+	gamepad::GamepadEventRaw,
+	keyboard::KeyboardInput,
+	mouse::{MouseButtonInput, MouseScrollUnit, MouseWheel},
+	ButtonState,
+	InputSystem,
+    },
+    // For debugging
+    diagnostic::{Diagnostics, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
+};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rodio::{source::ChannelVolume, Decoder, Source};
+use serde::{Deserialize, Serialize};
+
+mod game;
+mod save;
+mod player;
+mod combat;
+mod npc;
+mod collisions;
+mod walls;
+mod world;
+mod commands;
+mod items;
+mod quests;
+mod effects;
+mod audio;
+mod camera;
+mod ui;
+mod debug;
+mod headless;
+mod replay;
+#[cfg(test)]
+mod testing;
+
+pub use game::*;
+pub use save::*;
+pub use player::*;
+pub use combat::*;
+pub use npc::*;
+pub use collisions::*;
+pub use walls::*;
+pub use world::*;
+pub use commands::*;
+pub use items::*;
+pub use quests::*;
+pub use effects::*;
+pub use audio::*;
+pub use camera::*;
+pub use ui::*;
+pub use debug::*;
+pub use headless::*;
+pub use replay::*;
+
+// Everything the game adds to the app: its states, resources, events and systems
+#[derive(Default)]
+pub struct BoarGamePlugin {
+    // Without a window: no cameras, no sounds and no menus, and the ticks follow each other as fast as they can.
+    pub headless: bool,
+    // The settings and the saves are the default ones, and nothing is written, so that the game does not depend
+    // on the previous ones: for a headless run, a recording or a replay
+    pub fresh: bool,
+    // The seed of the randomness of the gameplay, a random one when not given
+    pub seed: Option<u64>,
+}
+
+impl Plugin for BoarGamePlugin {
+    fn build(&self, app: &mut App) {
+	let simulation = Simulation::load();
+	let (settings, save, accessibility, stats) = if self.fresh {
+	    (Settings { in_memory: true, ..default() }, SaveGame { in_memory: true, ..default() }, Accessibility { in_memory: true, ..default() }, Stats { in_memory: true, ..default() })
+	} else {
+	    let settings = Settings::load();
+	    let save = SaveGame::load(settings.save_slot);
+	    (settings, save, Accessibility::load(), Stats::load())
+	};
+	let seed = self.seed.unwrap_or_else(|| rand::thread_rng().gen());
+	info!("Seed of the game: {seed}");
+	let mut fixed_update = SystemStage::parallel();
+	if !self.headless {
+	    fixed_update = fixed_update.with_run_criteria(FixedTimestep::step(f64::from(simulation.dt())));
+	}
+	app.add_state(GameState::Loading)
+	    .insert_resource(settings.key_bindings.clone())
+	    .insert_resource(settings)
+	    .insert_resource(simulation)
+	    .insert_resource(accessibility)
+	    .insert_resource(stats)
+	    .insert_resource(GameRng::new(seed))
+	    .insert_resource(save)
+	    .insert_resource(LootTables::load())
+	    .insert_resource(Progression::load())
+	    .insert_resource(PlayerLoadout::load())
+	    .insert_resource(FreePlay::load())
+	    .insert_resource(Shop::load())
+	    .init_resource::<PerformanceMonitor>()
+	    .init_resource::<MusicController>()
+	    .init_resource::<AmbienceController>()
+	    .init_resource::<AudioChannels>()
+	    .init_resource::<ObjectiveTracker>()
+	    .init_resource::<QuestLog>()
+	    .init_resource::<QuestLogScreen>()
+	    .init_resource::<Location>()
+	    .init_resource::<Particles>()
+	    .init_resource::<Pool<Projectile>>()
+	    .init_resource::<Inventory>()
+	    .init_resource::<Currency>()
+	    .init_resource::<InventoryScreen>()
+	    .init_resource::<ChestScreen>()
+	    .init_resource::<Dialogue>()
+	    .init_resource::<ShopScreen>()
+	    .init_resource::<SavesScreen>()
+	    .init_resource::<ControlsScreen>()
+	    .init_resource::<Upgrades>()
+	    .init_resource::<Abilities>()
+	    .init_resource::<Transition>()
+	    .init_resource::<CoordinateLabels>()
+	    .init_resource::<StepControl>()
+	    .init_resource::<GameClock>()
+	    .init_resource::<RunStats>()
+	    .init_resource::<Lives>()
+	    .init_resource::<HitStop>()
+	    .init_resource::<Tutorial>()
+	    .init_resource::<PlayArea>()
+	    .add_event::<DamageEvent>()
+	    .add_event::<Toast>()
+	    .add_event::<Interacted>()
+	    .add_event::<DialogueAction>()
+	    .add_event::<DeathEvent>()
+	    .add_event::<ExperienceGained>()
+	    .add_event::<TreatGiven>()
+	    .add_event::<BoarTamed>()
+	    .add_event::<CollisionEvent>()
+	    .add_event::<PlaySfx>()
+	    .add_event::<PlaySpatialSfx>()
+	    // What has a lifetime or must stay near the map is cleaned up once the frame is done with it
+	    .add_system_set_to_stage(CoreStage::PostUpdate, SystemSet::new().with_run_criteria(while_playing)
+				     .with_system(despawn_expired)
+				     .with_system(despawn_out_of_bounds))
+	    .add_event::<BoarStateChanged>()
+	    .add_event::<PlayAreaChanged>()
+	    .add_startup_system_to_stage(StartupStage::PreStartup, load_map)
+	    .add_startup_system_to_stage(StartupStage::PreStartup, enable_debug_view)
+	    .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+	    .add_startup_system_to_stage(StartupStage::PreStartup, load_prefabs)
+	    .add_startup_system(setup)
+	    .add_startup_system(spawn_level)
+	    .add_startup_system(spawn_world)
+	    .add_startup_system(index_chunks)
+	    .add_startup_system(restore_quests)
+	    .add_startup_system(restore_abilities)
+	    // The gameplay runs at a fixed timestep, and only while playing
+	    .add_stage_after(CoreStage::Update, FixedUpdateStage, fixed_update)
+	    .add_system_set_to_stage(FixedUpdateStage, SystemSet::new().with_run_criteria(gameplay_tick)
+			    .with_system(move_player.label(PlayerTurn))
+			    .with_system(check_for_collisions.label(PlayerTurn).after(move_player))
+			    .with_system(spawn_afterimages.after(check_for_collisions))
+			    .with_system(footsteps.after(check_for_collisions))
+			    .with_system(update_facing.after(boar_wander).after(boar_chase).after(run_wolves).after(run_villagers))
+			    .with_system(update_player_facing.label(PlayerTurn).after(check_for_collisions))
+			    .with_system(flip_npcs.after(update_facing))
+			    .with_system(boar_idle_bob.after(spawn_in_boars))
+			    .with_system(animate_player.after(update_player_facing))
+			    .with_system(animate_npcs.after(update_facing))
+			    .with_system(player_attack.label(PlayerTurn).after(update_player_facing))
+			    .with_system(attack_hits.label(PlayerTurn).after(player_attack))
+			    .with_system(tint_slashes.after(attack_hits))
+			    .with_system(move_knocked_back.after(PlayerTurn))
+			    .with_system(regenerate_in_safe_zones.after(PlayerTurn))
+			    .with_system(keep_out_of_safe_zones.after(move_knocked_back).after(boar_wander).after(boar_chase).after(run_wolves))
+			    .with_system(spawn_in_boars)
+			    .with_system(boar_detect.after(PlayerTurn))
+			    .with_system(boss_attacks.after(boar_detect).after(attack_hits))
+			    .with_system(boar_bait.after(boar_detect).after(move_projectiles))
+			    .with_system(boar_wander.after(boar_bait))
+			    .with_system(follow_player.after(boar_detect))
+			    .with_system(call_for_help.after(boar_detect).after(attack_hits).after(move_thrown_boars))
+			    .with_system(herd_aggro.after(boar_detect).after(attack_hits).after(move_thrown_boars).after(move_projectiles))
+			    .with_system(boar_flocking.after(call_for_help).after(herd_aggro))
+			    .with_system(boar_chase.after(boar_flocking))
+			    .with_system(run_wolves.after(boar_wander))
+			    .with_system(contact_damage.after(run_wolves).after(boar_chase))
+			    .with_system(run_villagers.after(boar_detect).after(call_for_help).after(herd_aggro))
+			    .with_system(move_thrown_boars.after(PlayerTurn))
+			    .with_system(move_projectiles.after(PlayerTurn))
+			    .with_system(finish_eating.after(PlayerTurn))
+			    .with_system(blink_loot)
+			    .with_system(update_coordinate_labels.after(check_for_collisions))
+			    .with_system(move_camera.after(check_for_collisions).after(zoom_camera))
+			    .with_system(zoom_camera))
+	    .add_system_set(SystemSet::on_update(GameState::Playing)
+			    .with_system(edit_walls)
+			    .with_system(save_walls)
+			    .with_system(reload_walls)
+			    .with_system(grab_boar)
+			    .with_system(buffer_attack)
+			    .with_system(update_sprint)
+			    .with_system(apply_damage)
+			    .with_system(lose_lives.after(apply_damage))
+			    .with_system(blink_invulnerable)
+			    .with_system(damage_numbers)
+			    .with_system(show_damage_direction)
+			    .with_system(collect_pickups)
+			    .with_system(pause_game)
+			    .with_system(pause_on_focus_loss))
+	    // Menus
+	    .add_system(run_transition)
+	    .add_system_set(SystemSet::on_enter(GameState::MainMenu).with_system(spawn_main_menu))
+	    .add_system_set(SystemSet::on_update(GameState::MainMenu)
+			    .with_system(start_game)
+			    .with_system(show_stats)
+			    .with_system(show_achievements)
+			    .with_system(show_saves))
+	    .add_system_set(SystemSet::on_enter(GameState::Stats).with_system(spawn_stats_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Stats).with_system(hide_stats))
+	    .add_system_set(SystemSet::on_exit(GameState::Stats).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_enter(GameState::Achievements).with_system(spawn_achievements_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Achievements).with_system(hide_achievements))
+	    .add_system_set(SystemSet::on_exit(GameState::Achievements).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_enter(GameState::Saves).with_system(spawn_saves_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Saves)
+			    .with_system(choose_save_slot)
+			    .with_system(update_saves_screen.after(choose_save_slot)))
+	    // The level is built again for the save chosen
+	    .add_system_set(SystemSet::on_exit(GameState::Saves)
+			    .with_system(despawn_menu)
+			    .with_system(despawn_level)
+			    .with_system(reset_level)
+			    .with_system(restore_quests.after(reset_level))
+			    .with_system(restore_abilities)
+			    .with_system(spawn_level)
+			    .with_system(spawn_world.after(reset_level)))
+	    .add_system_set(SystemSet::on_update(GameState::Playing)
+			    .with_system(count_play_time)
+			    .with_system(count_distance_walked)
+			    .with_system(tick_game_clock)
+			    .with_system(reach_victory.after(talk_at_house)))
+	    .add_system_set(SystemSet::on_enter(GameState::Victory).with_system(spawn_victory_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Victory)
+			    .with_system(continue_free_play)
+			    .with_system(return_to_main_menu))
+	    .add_system_set(SystemSet::on_exit(GameState::Victory).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(spawn_game_over_screen))
+	    .add_system_set(SystemSet::on_update(GameState::GameOver).with_system(return_to_main_menu))
+	    .add_system_set(SystemSet::on_exit(GameState::GameOver).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_pause(GameState::Playing).with_system(save_stats))
+	    // Playing is only left for the main menu, the next game gets a new level
+	    .add_system_set(SystemSet::on_exit(GameState::Playing)
+			    .with_system(save_on_exit)
+			    .with_system(despawn_level)
+			    .with_system(reset_level)
+			    .with_system(restore_quests.after(reset_level))
+			    .with_system(restore_abilities)
+			    .with_system(spawn_level)
+			    .with_system(spawn_world.after(reset_level)))
+	    .add_system_set(SystemSet::on_exit(GameState::MainMenu).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_enter(GameState::Paused).with_system(spawn_pause_menu))
+	    .add_system_set(SystemSet::on_update(GameState::Paused)
+			    .with_system(resume_game)
+			    .with_system(return_to_main_menu)
+			    .with_system(toggle_pixel_perfect)
+			    .with_system(toggle_focus_pause)
+			    .with_system(cycle_aspect_ratio)
+			    .with_system(cycle_player_tint)
+			    .with_system(change_volumes)
+			    .with_system(toggle_accessibility)
+			    .with_system(show_controls))
+	    .add_system_set(SystemSet::on_exit(GameState::Paused).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_enter(GameState::Controls).with_system(spawn_controls_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Controls)
+			    .with_system(rebind_keys.before(bevy::window::close_on_esc))
+			    .with_system(update_controls_screen.after(rebind_keys)))
+	    .add_system_set(SystemSet::on_exit(GameState::Controls).with_system(despawn_menu))
+	    .add_system(collision_sounds)
+	    .add_system(damage_sounds)
+	    .add_system(apply_player_tint)
+	    .add_system(update_life_icons)
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(flash_hurt_player))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(boar_barks))
+	    .add_system(toggle_mute)
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_location))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_objectives.after(update_location)))
+	    .add_system_set(SystemSet::on_update(GameState::Playing)
+			    .with_system(spawn_boss.after(update_objectives))
+			    .with_system(spawn_daily_boars.after(apply_damage))
+			    .with_system(run_spawners.after(spawn_daily_boars))
+			    .with_system(update_boss_health_bar))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(open_quest_log))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(low_health_warning))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_dash_indicator))
+	    .add_system(show_toasts.after(collect_pickups))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(run_tutorial.after(update_location)))
+	    .add_system_set(SystemSet::on_update(GameState::Playing)
+			    .with_system(use_items.before(select_hotbar_slot))
+			    .with_system(select_hotbar_slot))
+	    .add_system(update_hotbar.after(select_hotbar_slot).after(collect_pickups))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(open_inventory))
+	    .add_system_set(SystemSet::on_enter(GameState::Inventory).with_system(spawn_inventory_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Inventory)
+			    .with_system(close_inventory.before(bevy::window::close_on_esc))
+			    .with_system(drag_inventory_items)
+			    .with_system(navigate_inventory)
+			    .with_system(update_inventory_screen.after(drag_inventory_items).after(navigate_inventory)))
+	    .add_system_set(SystemSet::on_exit(GameState::Inventory).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_update(GameState::Playing)
+			    .with_system(interact)
+			    .with_system(open_chest.after(interact))
+			    .with_system(talk_at_house.after(interact).after(advance_quests))
+			    .with_system(talk_to_villagers.after(interact)))
+	    .add_system_set(SystemSet::on_enter(GameState::Chest).with_system(spawn_chest_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Chest)
+			    .with_system(close_chest.before(bevy::window::close_on_esc))
+			    .with_system(move_chest_items)
+			    .with_system(update_chest_screen.after(move_chest_items)))
+	    .add_system_set(SystemSet::on_exit(GameState::Chest)
+			    .with_system(despawn_menu)
+			    .with_system(save_chest))
+	    .add_system_set(SystemSet::on_enter(GameState::Dialogue).with_system(spawn_dialogue_box))
+	    .add_system_set(SystemSet::on_update(GameState::Dialogue)
+			    .with_system(choose_dialogue.before(bevy::window::close_on_esc))
+			    .with_system(update_dialogue_box.after(choose_dialogue)))
+	    .add_system_set(SystemSet::on_exit(GameState::Dialogue).with_system(despawn_menu))
+	    .add_system_set(SystemSet::on_enter(GameState::Shop).with_system(spawn_shop_screen))
+	    .add_system_set(SystemSet::on_update(GameState::Shop)
+			    .with_system(close_shop.before(bevy::window::close_on_esc))
+			    .with_system(trade_in_shop)
+			    .with_system(update_shop_screen.after(trade_in_shop)))
+	    .add_system_set(SystemSet::on_exit(GameState::Shop).with_system(despawn_menu))
+	    .add_system(update_coin_counter.after(collect_pickups))
+	    .add_system(update_boar_counter)
+	    .add_system(apply_health_upgrades)
+	    .add_system(gain_experience)
+	    .add_system(update_experience_bar.after(gain_experience))
+	    .add_system(grant_abilities.after(gain_experience).after(choose_dialogue))
+	    .add_system(show_ability_indicators.after(grant_abilities))
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_shadows))
+	    .add_system_set(SystemSet::on_update(GameState::Playing)
+			    .with_system(sprint_dust.after(update_sprint))
+			    .with_system(emit_particles.after(sprint_dust))
+			    .with_system(update_particles))
+	    .add_system_set(SystemSet::on_enter(GameState::QuestLog).with_system(spawn_quest_log_screen))
+	    .add_system_set(SystemSet::on_update(GameState::QuestLog)
+			    .with_system(close_quest_log.before(bevy::window::close_on_esc))
+			    .with_system(navigate_quest_log)
+			    .with_system(update_quest_log_screen.after(navigate_quest_log)))
+	    .add_system_set(SystemSet::on_exit(GameState::QuestLog).with_system(despawn_menu))
+	    .add_system(tame_boars.after(interact))
+	    .add_system(advance_quests.after(interact).after(apply_damage).after(tame_boars))
+	    .add_system(unlock_achievements.after(advance_quests).after(count_play_time).after(count_distance_walked))
+	    .add_system(update_quest_log.after(advance_quests))
+	    // The tiles of a wall refitted in the same frame are despawned with it
+	    .add_system(tile_wall_textures.before(fit_walls_to_map))
+	    .add_system(apply_texture_sampling)
+	    .add_system(stream_chunks)
+	    .add_system(letterbox)
+	    .add_system(monitor_performance)
+	    .add_system_to_stage(CoreStage::Last, check_orphans)
+	    .add_system(draw_boar_radii)
+	    .add_system(toggle_coordinate_labels)
+	    .add_system(control_stepping)
+	    .add_system(fade_overlays)
+	    .add_system(tick_hit_stop.after(apply_damage))
+	    .add_system(fit_walls_to_map)
+	    .add_system(keep_inside_play_area.after(fit_walls_to_map).after(reload_walls))
+	    // The tweens wait during the pauses, like the rest of the game
+	    .add_system_set(SystemSet::on_update(GameState::Playing).with_system(run_tweens))
+	    .add_system(bevy::window::close_on_esc);
+
+	// What is seen and heard, the game runs the same without it
+	if !self.headless {
+	    app.add_startup_system(spawn_cameras)
+		.add_startup_system_to_stage(StartupStage::PreStartup, load_sfx)
+		// The loading screen waits for the sounds and the background, a headless run only for the background
+		.add_system_set(SystemSet::on_update(GameState::Loading).with_system(finish_loading))
+		// Bevy cannot pan its sounds, the positional ones have their own audio output
+		.init_non_send_resource::<AudioOutput<PannedSound>>()
+		.add_asset::<PannedSound>()
+		.init_resource::<Audio<PannedSound>>()
+		.add_system_to_stage(CoreStage::PostUpdate, play_queued_audio_system::<PannedSound>)
+		.add_system(play_sfx.after(collision_sounds).after(damage_sounds))
+		.add_system(play_spatial_sfx.after(boar_barks).after(apply_damage))
+		.add_system(apply_volumes.after(toggle_mute).after(play_sfx).after(play_spatial_sfx))
+		.add_system(update_music)
+		.add_system(fade_music.after(update_music))
+		.add_system(update_ambience.after(update_location))
+		.add_system(fade_ambience.after(update_ambience));
+	}
+    }
+}
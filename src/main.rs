@@ -1,295 +1,86 @@
+use std::{env, process, time::Duration};
+
 use bevy::{
     prelude::*,
-    // This will be needed when we incorporate collisions (for combat).  The necessary components are already implemented
-//    sprite::collide_aabb::{collide, Collision},
-    time::{FixedTimestep},
+    app::{ScheduleRunnerPlugin, ScheduleRunnerSettings},
+    log::Level,
     // For debugging
-    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    diagnostic::{EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
 };
+use boar_game::{
+    headless_app, BoarGamePlugin, InputScript, RecordPlugin, Recording, ReplayPlugin, HEADLESS_TICKS,
+};
+use rand::Rng;
 
-// Set to 60 frames per second
-const TIMESTEP: f32 = 5.0 / 60.0;
-
-const PLAYER_SPEED: f32 = 100.0;
-
-
-// Walls settings
-const WALL_THICKNESS: f32 = 10.0;
-const TOP_WALL: f32 = 540.0;
-const LEFT_WALL: f32 = -960.0;
-const BOTTOM_WALL: f32 = -540.0;
-const RIGHT_WALL: f32 = 960.0;
-const WALL_COLOR: Color = Color::rgb(0.0, 0.0, 0.0);
-
-
+fn main() {
+    if env::args().any(|arg| arg == "--headless") {
+	run_headless();
+	return;
+    }
 
-// Main loop
-fn main() {    
-    App::new()
-	.add_plugins(DefaultPlugins.set(WindowPlugin {
-	    window: WindowDescriptor {
-		title: "Boar Game".into(),
-		width: 1024.0,
-		height: 762.0,
-		..default()
-	    },
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+	window: WindowDescriptor {
+	    title: "Boar Game".into(),
+	    width: 1024.0,
+	    height: 762.0,
 	    ..default()
-	}))
+	},
+	..default()
+    }))
 	// Show framerate in console
 	.add_plugin(LogDiagnosticsPlugin::default())
-	.add_plugin(FrameTimeDiagnosticsPlugin::default())
-	.add_startup_system(setup)
-    .add_startup_system(audio_setup)
-	.add_system_set(SystemSet::new()
-			.with_run_criteria(FixedTimestep::step(f64::from(TIMESTEP)))
-			.with_system(move_player)
-			.with_system(move_camera)
-			.with_system(zoom_camera))
-	.add_system(bevy::window::close_on_esc)
-	.run();
-}
-
-
-// Components for the characters
-#[derive(Component)]
-struct Player;
-
-#[derive(Component)]
-struct Npc;
-
-#[derive(Component)]
-struct Name;
-
-
-// Identifiers for cameras
-#[derive(Component)]
-struct MapCamera;
-
-// Components to handle collisions
-#[derive(Component)]
-struct Collider;
-
-#[derive(Default)]
-struct CollisionEvent;
-
-
-// Walls are a bundle consisting of a sprite and a collider
-#[derive(Bundle)]
-struct WallBundle {
-    sprite_bundle: SpriteBundle,
-    collider: Collider,
-}
-
-// To better manipulate the walls, we will consider the four separately
-enum WallLocation {
-    Top,
-    Left,
-    Bottom,
-    Right,
-}
-
-impl WallLocation {
-    // Outputs the location of the (middle) of a wall
-    fn position(&self) -> Vec2 {
-	match self {
-	    | WallLocation::Top => Vec2::new(0., TOP_WALL),
-	    | WallLocation::Left => Vec2::new(LEFT_WALL, 0.),
-	    | WallLocation::Bottom => Vec2::new(0., BOTTOM_WALL),
-	    | WallLocation::Right => Vec2::new(RIGHT_WALL, 0.),
-	}
-    }
-
-    // Outputs the length of the wall
-    fn size(&self) -> Vec2 {
-	match self {
-	    WallLocation::Left | WallLocation::Right => Vec2::new(WALL_THICKNESS, TOP_WALL - BOTTOM_WALL - WALL_THICKNESS),
-	    WallLocation::Top | WallLocation::Bottom => Vec2::new(RIGHT_WALL - LEFT_WALL - WALL_THICKNESS, WALL_THICKNESS),
-	}
-    }
-}
-
-impl WallBundle {
-    fn new(location: WallLocation) -> WallBundle {
-	WallBundle {
-	    sprite_bundle: SpriteBundle{
-		transform: Transform{
-		    // Not sure why we need to transform into Vec3 ??
-		    translation: location.position().extend(0.0),
-		    scale: location.size().extend(1.0),
-		    ..default()
-		},
-		sprite: Sprite {
-		    color: WALL_COLOR,
-		    ..default()
-		},
-		..default()
+	.add_plugin(FrameTimeDiagnosticsPlugin)
+	.add_plugin(EntityCountDiagnosticsPlugin);
+
+    // --record <file> writes the input of the game, --replay <file> plays it again
+    if let Some(path) = arg_value("--record") {
+	let seed = seed().unwrap_or_else(|| rand::thread_rng().gen());
+	app.add_plugin(BoarGamePlugin { fresh: true, seed: Some(seed), ..default() })
+	    .add_plugin(RecordPlugin { path, seed });
+    } else if let Some(path) = arg_value("--replay") {
+	let recording = match Recording::load(&path) {
+	    Ok(recording) => recording,
+	    Err(error) => {
+		eprintln!("{error}");
+		process::exit(1);
 	    },
-
-	    collider: Collider,
-	}
+	};
+	app.add_plugin(BoarGamePlugin { fresh: true, seed: Some(recording.seed), ..default() })
+	    .add_plugin(ReplayPlugin { recording });
+    } else {
+	app.add_plugin(BoarGamePlugin { seed: seed(), ..default() });
     }
+    app.run();
 }
 
-
-
-
-// setup function that places everything in the World before the game starts
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-) {
-    // Utilities
-    commands.spawn((
-	Camera2dBundle {
-	    projection: OrthographicProjection {
-		scale: 0.75,
-		..default()
-	    },
-	    transform: Transform::from_xyz(350.0, 350.0, 0.5),
-	    ..default()
-	},
-	MapCamera,
-    ));
-
-    // Background
-    commands.spawn(SpriteBundle {
-	texture: asset_server.load("sprites/background.png"),
-	..default()
-	});
-
-	
-    // Player character
-    commands.spawn((
-	SpriteBundle {
-	    texture: asset_server.load("sprites/triangulus.png"),
-	    transform: Transform::from_xyz(350., 350., 0.2),
-	    ..default()
-	},
-	Player,
-	Collider,
-    ));
-
-    
-    // House
-    commands.spawn((
-	SpriteBundle {
-	    texture: asset_server.load("sprites/maison.png").into(),
-	    transform: Transform::from_xyz(150.0, -200.0, 0.1),
-	    ..default()
-	},
-	Npc,
-    ));
-
-
-    // Boar (currently with frank sprite)
-    commands.spawn((
-	SpriteBundle {
-	    texture: asset_server.load("sprites/frank.png"),
-	    transform: Transform::from_xyz(-360.0, 270.0, 0.1),
-	    ..default()
-	},
-	Npc,
-    ));
-    
-    // Spawn the walls
-    commands.spawn(WallBundle::new(WallLocation::Top));
-    commands.spawn(WallBundle::new(WallLocation::Left));
-    commands.spawn(WallBundle::new(WallLocation::Bottom));
-    commands.spawn(WallBundle::new(WallLocation::Right));  
-}
-
-fn audio_setup(asset_server: Res<AssetServer>, audio: Res<Audio>) {
-    let music = asset_server.load("sounds/acid_soup_2.ogg");
-    audio.play(music);
-}
-
-fn move_player(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query_player: Query<&mut Transform, With<Player>>,
-) {
-    let mut player_transform = query_player.single_mut();
-    let mut x_direction = 0.0;
-    let mut y_direction = 0.0;
-    
-    if keyboard_input.pressed(KeyCode::A){
-	x_direction -= 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::D){
-	x_direction += 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::W){
-	y_direction += 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::S){
-	y_direction -= 1.0;
-    }
-
-    // Compute the new coordinates of Player
-    let new_transform_x = player_transform.translation.x + x_direction * PLAYER_SPEED * TIMESTEP;
-    let new_transform_y = player_transform.translation.y + y_direction * PLAYER_SPEED * TIMESTEP;
-
-    // Bounds ensure that the sprite never goes out of the screen
-    let left_bound = LEFT_WALL + WALL_THICKNESS / 2.0 + 16.0;
-    let right_bound = RIGHT_WALL - WALL_THICKNESS / 2.0 - 16.0;
-    let top_bound = TOP_WALL - WALL_THICKNESS / 2.0 - 24.0;
-    let bottom_bound = BOTTOM_WALL + WALL_THICKNESS / 2.0 + 16.0;
-
-    // Apply the translation
-    player_transform.translation.x = new_transform_x.clamp(left_bound, right_bound);
-    player_transform.translation.y = new_transform_y.clamp(bottom_bound, top_bound);
+// The value given after an argument
+fn arg_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
 }
 
-
-
-fn move_camera (
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query_camera: Query<&mut Transform, With<MapCamera>>,
-) {
-    let mut camera_transform = query_camera.single_mut();
-    let mut x_direction = 0.0;
-    let mut y_direction = 0.0;
-    
-    if keyboard_input.pressed(KeyCode::A){
-	x_direction -= 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::D){
-	x_direction += 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::W){
-	y_direction += 1.0;
-    }
-    if keyboard_input.pressed(KeyCode::S){
-	y_direction -= 1.0;
-    }
-
-    // Compute the new coordinates of Player
-    let new_transform_x = camera_transform.translation.x + x_direction * PLAYER_SPEED * TIMESTEP;
-    let new_transform_y = camera_transform.translation.y + y_direction * PLAYER_SPEED * TIMESTEP;
-
-    // Bounds ensure that the sprite never goes out of the screen
-    let left_bound = LEFT_WALL + WALL_THICKNESS / 2.0 + 16.0;
-    let right_bound = RIGHT_WALL - WALL_THICKNESS / 2.0 - 16.0;
-    let top_bound = TOP_WALL - WALL_THICKNESS / 2.0 - 24.0;
-    let bottom_bound = BOTTOM_WALL + WALL_THICKNESS / 2.0 + 16.0;
-
-    // Apply the translation
-    camera_transform.translation.x = new_transform_x.clamp(left_bound, right_bound);
-    camera_transform.translation.y = new_transform_y.clamp(bottom_bound, top_bound);
+// The seed of the randomness of the gameplay, --seed <number> plays a game again
+fn seed() -> Option<u64> {
+    arg_value("--seed").and_then(|arg| arg.parse().ok())
 }
 
-
-// System that checks if a zoom in/out input is made and acts accordingly
-fn zoom_camera(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query_camera: Query<&mut OrthographicProjection, With<MapCamera>>,
-) {
-    let mut camera_proj = query_camera.single_mut();
-
-    if keyboard_input.pressed(KeyCode::Minus) {
-	camera_proj.scale *= 1.07;
-    }
-    if keyboard_input.pressed(KeyCode::Equals) {
-	camera_proj.scale *= 0.93;
-    }
-    camera_proj.scale = camera_proj.scale.clamp(0.5, 2.0);
+// Runs the game without a window, rendering or audio: --headless [--ticks <count>] [--script <file>] [--seed <number>].
+// The script holds the keys of each tick, the summary of the run is printed as JSON at the end
+fn run_headless() {
+    let ticks = arg_value("--ticks").and_then(|arg| arg.parse().ok()).unwrap_or(HEADLESS_TICKS);
+    let script = match arg_value("--script").map(|path| InputScript::load(&path)) {
+	Some(Ok(script)) => script,
+	Some(Err(error)) => {
+	    eprintln!("{error}");
+	    process::exit(1);
+	},
+	None => InputScript::default(),
+    };
+    // The logs go to the error output, the standard output only has the summary
+    tracing_subscriber::fmt().with_writer(std::io::stderr).with_max_level(Level::WARN).init();
+
+    headless_app(ticks, script, seed())
+	.insert_resource(ScheduleRunnerSettings::run_loop(Duration::ZERO))
+	.add_plugin(ScheduleRunnerPlugin)
+	.run();
 }
@@ -0,0 +1,1405 @@
+// The boars and the other characters of the world, and how they behave
+
+use crate::*;
+
+// Boar animation settings: the sheet (in the prefab) has an idle row and a trot row, the trot is at the
+// given framerate at the wandering speed. Without the sheet the boars are still sprites
+pub const BOAR_IDLE_FRAMES: usize = 2;
+pub const BOAR_TROT_FRAMES: usize = 4;
+pub const BOAR_IDLE_FPS: f32 = 2.0;
+pub const BOAR_TROT_FPS: f32 = 8.0;
+// An idle boar stretches up and down a little, once per second
+pub const BOAR_BOB_AMOUNT: f32 = 0.02;
+pub const BOAR_BOB_FREQUENCY: f32 = 1.0;
+
+// Spawn settings: new boars grow from nothing, a little past their size, then settle
+pub const SPAWN_IN_TIME: f32 = 0.4;
+
+// Boss settings: a huge boar comes once all the others are defeated. Its attack changes
+// as it gets weaker: it charges, then calls other boars, then slams the ground
+pub const BOSS_SPAWN: Vec2 = Vec2::new(0.0, 0.0);
+pub const BOSS_SCALE: f32 = 2.0;
+pub const BOSS_HEALTH: f32 = 300.0;
+pub const BOSS_SUMMON_RATIO: f32 = 0.66;
+pub const BOSS_SLAM_RATIO: f32 = 0.33;
+pub const BOSS_CHARGE_INTERVAL: f32 = 3.0;
+pub const BOSS_CHARGE_SPEED: f32 = 400.0;
+pub const BOSS_CHARGE_TIME: f32 = 0.6;
+pub const BOSS_CHARGE_DAMAGE: f32 = 15.0;
+pub const BOSS_SUMMON_INTERVAL: f32 = 6.0;
+pub const BOSS_SUMMON_COUNT: usize = 2;
+pub const BOSS_SUMMON_DISTANCE: f32 = 100.0;
+pub const BOSS_SLAM_INTERVAL: f32 = 2.5;
+pub const BOSS_SLAM_RADIUS: f32 = 150.0;
+pub const BOSS_SLAM_DAMAGE: f32 = 20.0;
+pub const BOSS_SLAM_DUST_COUNT: usize = 30;
+pub const BOSS_BAR_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+pub const BOSS_BAR_COLOR: Color = Color::rgb(0.7, 0.1, 0.1);
+pub const BOSS_BAR_COLORBLIND_COLOR: Color = Color::rgb(0.1, 0.3, 0.8);
+
+// Bait settings: a thrown bait lands as a scent that draws the boars around, the first boar there eats it
+// and stays calm for a while, it does not notice the player
+pub const BAIT_ATTRACT_RADIUS: f32 = 500.0;
+pub const BAIT_REACH: f32 = 16.0;
+pub const BAIT_EAT_TIME: f32 = 3.0;
+pub const BAIT_CALM_TIME: f32 = 10.0;
+// Taming settings: a boar that is not chasing the player eats the apples thrown at it or given by hand,
+// and follows the player once it has eaten enough of them. It keeps away from the player and the other boars
+pub const TAME_APPLES: u32 = 3;
+pub const TAMED_FOLLOW_DISTANCE: f32 = 90.0;
+pub const TAMED_SEPARATION_RADIUS: f32 = 60.0;
+pub const TAMED_HEART_COUNT: usize = 20;
+
+// Wandering settings: the boar alternates between walking and standing still
+pub const BOAR_SPEED: f32 = 60.0;
+pub const WANDER_WALK_TIME: f32 = 3.0;
+pub const WANDER_IDLE_TIME: f32 = 2.0;
+
+// Variety settings: each boar rolls how far its health, speed and size are from the prefab, as a fraction,
+// and how dark its coat is. The ranges stay small so that the balance holds
+pub const BOAR_HEALTH_VARIATION: f32 = 0.15;
+pub const BOAR_SPEED_VARIATION: f32 = 0.1;
+pub const BOAR_SIZE_VARIATION: f32 = 0.1;
+pub const BOAR_SHADE_MIN: f32 = 0.8;
+
+// Chase settings: the boar runs after the player once close enough, and gives up when too far
+pub const DETECTION_RADIUS: f32 = 250.0;
+pub const LEASH_RADIUS: f32 = 400.0;
+pub const BOAR_CHASE_SPEED: f32 = 90.0;
+// A boar starting to chase, or hurt, brings the boars around it along
+pub const HELP_RADIUS: f32 = 300.0;
+// The boars of a spawner are a herd instead: a member hurt brings along the others around it that can see it,
+// and a member giving up the chase past the leash makes the others give up too
+pub const HERD_AGGRO_RADIUS: f32 = 350.0;
+
+// Wolf settings: a wolf notices the player from farther than a boar, circles the player for a while,
+// darts in to bite, then runs off before circling again. It gives up once the player is far away
+pub const WOLF_DETECTION_RADIUS: f32 = 350.0;
+pub const WOLF_LEASH_RADIUS: f32 = 600.0;
+pub const WOLF_CIRCLE_RADIUS: f32 = 200.0;
+// How far ahead around the circle the wolf heads, in radians
+pub const WOLF_CIRCLE_LEAD: f32 = 0.5;
+pub const WOLF_CIRCLE_TIME: f32 = 3.0;
+pub const WOLF_DART_TIME: f32 = 1.5;
+pub const WOLF_RETREAT_TIME: f32 = 1.0;
+// The damage of a touch is in the prefab, a character touching the player hurts it at most this often
+pub const CONTACT_DAMAGE_COOLDOWN: f32 = 1.0;
+
+// Villager settings: a villager walks its path and waits a little at each end, steps aside when the player
+// stands in the way, and runs to the door (the first point of its path) when a boar chases around
+pub const VILLAGER_PAUSE_TIME: f32 = 2.0;
+// How far ahead the villager looks for the player in its way
+pub const VILLAGER_AVOID_DISTANCE: f32 = 80.0;
+pub const VILLAGER_FLEE_RADIUS: f32 = 300.0;
+pub const VILLAGER_FLEE_SPEED_FACTOR: f32 = 2.0;
+// The villager comes out once no boar has chased around for this long
+pub const VILLAGER_HIDE_TIME: f32 = 5.0;
+
+// Pack settings: the chasing boars keep apart (separation), go the same way (alignment)
+// and close in on the player (cohesion)
+pub const FLOCK_NEIGHBOR_RADIUS: f32 = 150.0;
+pub const FLOCK_SEPARATION_RADIUS: f32 = 80.0;
+pub const FLOCK_SEPARATION_WEIGHT: f32 = 1.5;
+pub const FLOCK_ALIGNMENT_WEIGHT: f32 = 0.3;
+pub const FLOCK_COHESION_WEIGHT: f32 = 1.0;
+
+// Spawner settings: a spawner waits while the player is this close, so nothing appears on screen,
+// and gives up on a spawn after this many points blocked by the obstacles (it tries again the next frame)
+pub const SPAWNER_PAUSE_DISTANCE: f32 = 200.0;
+pub const SPAWNER_TRIES: usize = 8;
+
+// What a prefab is, only to read it: the entities get the markers and the components of their kind
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum NpcKind {
+    House,
+    Boar,
+    Wolf,
+    Villager,
+}
+
+// Marker for the houses, they are talked to
+#[derive(Component)]
+pub struct House;
+
+// Marker for the boars, tamed or not
+#[derive(Component)]
+pub struct Boar;
+
+// Marker for the wolves
+#[derive(Component)]
+pub struct Wolf;
+
+// Marker for the villagers, they are talked to
+#[derive(Component)]
+pub struct Villager;
+
+// Marker for what fights the player: the wolves, and the boars until they are tamed
+#[derive(Component)]
+pub struct Hostile;
+
+// The table of the loot file rolled when the NPC dies, with the experience it is worth
+#[derive(Component)]
+pub struct Loot(pub String);
+
+// The attacks of the boss, they change as its health goes down
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BossPhase {
+    Charge,
+    Summon,
+    Slam,
+}
+
+impl BossPhase {
+    pub fn from_health(ratio: f32) -> BossPhase {
+	if ratio > BOSS_SUMMON_RATIO {
+	    BossPhase::Charge
+	} else if ratio > BOSS_SLAM_RATIO {
+	    BossPhase::Summon
+	} else {
+	    BossPhase::Slam
+	}
+    }
+
+    pub fn attack_interval(&self) -> f32 {
+	match self {
+	    BossPhase::Charge => BOSS_CHARGE_INTERVAL,
+	    BossPhase::Summon => BOSS_SUMMON_INTERVAL,
+	    BossPhase::Slam => BOSS_SLAM_INTERVAL,
+	}
+    }
+}
+
+// The huge boar of the last wave, it also has the components of the other boars
+#[derive(Component)]
+pub struct Boss {
+    pub phase: BossPhase,
+    pub attack: Timer,
+    // A charge is a knockback towards the player, it hurts the player once
+    pub charging: bool,
+    pub charge_hit: bool,
+}
+
+impl Boss {
+    pub fn new() -> Boss {
+	Boss {
+	    phase: BossPhase::Charge,
+	    attack: Timer::from_seconds(BossPhase::Charge.attack_interval(), TimerMode::Repeating),
+	    charging: false,
+	    charge_hit: false,
+	}
+    }
+}
+
+// A boar breathing while idle, with a tween on its scale
+#[derive(Component)]
+pub struct IdleBob;
+
+// A boar appearing, it does not act and cannot be hit until the timer finishes
+#[derive(Component)]
+pub struct SpawningIn(pub Timer);
+
+// The boar the house wants gone
+#[derive(Component)]
+pub struct Frank;
+
+// A boar won over with apples: it follows the player, and never chases it again
+#[derive(Component)]
+pub struct Tamed;
+
+// Makes more of a prefab every `interval` seconds around itself, up to `max_alive` of them at once.
+// The timer is saved by spawn id, the spawned entities are not
+#[derive(Component, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Spawner {
+    pub prefab: String,
+    pub interval: f32,
+    pub max_alive: u32,
+    pub radius: f32,
+    #[serde(skip)]
+    pub timer: Timer,
+    // What it spawned, until they are gone
+    #[serde(skip)]
+    pub spawned: Vec<Entity>,
+}
+
+// The boars made by the same spawner, they fight and calm down together
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Herd(pub u32);
+
+// An apple given to a boar, thrown at it or by hand
+pub struct TreatGiven {
+    pub boar: Entity,
+    pub by_hand: bool,
+}
+
+// Sent when a boar is tamed
+pub struct BoarTamed(pub Entity);
+
+// What the boar is currently doing, and for how long
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BoarState {
+    Idle,
+    Wander,
+    Chase,
+    // Going to a bait
+    Attracted,
+    // Eating the bait reached
+    Eating,
+}
+
+// Sent every time a boar switches to another state
+pub struct BoarStateChanged {
+    pub boar: Entity,
+    pub to: BoarState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WolfState {
+    // Waiting for the player to come close
+    Prowl,
+    Circle,
+    Dart,
+    Retreat,
+}
+
+#[derive(Component)]
+pub struct WolfAi {
+    pub state: WolfState,
+    pub timer: Timer,
+    // Which way the wolf goes around the player, 1 or -1
+    pub turn: f32,
+}
+
+impl WolfAi {
+    pub fn new() -> WolfAi {
+	WolfAi {
+	    state: WolfState::Prowl,
+	    timer: Timer::default(),
+	    turn: 1.0,
+	}
+    }
+
+    // Changes the state and restarts the timer with the duration of the new state
+    pub fn switch_to(&mut self, state: WolfState) {
+	self.state = state;
+	self.timer = match state {
+	    WolfState::Prowl => Timer::default(),
+	    WolfState::Circle => Timer::from_seconds(WOLF_CIRCLE_TIME, TimerMode::Once),
+	    WolfState::Dart => Timer::from_seconds(WOLF_DART_TIME, TimerMode::Once),
+	    WolfState::Retreat => Timer::from_seconds(WOLF_RETREAT_TIME, TimerMode::Once),
+	};
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VillagerState {
+    Walk,
+    // Waiting at a point of the path
+    Pause,
+    // Running to the door
+    Flee,
+    // Hidden behind the door
+    Indoors,
+}
+
+#[derive(Component)]
+pub struct VillagerAi {
+    pub state: VillagerState,
+    pub timer: Timer,
+}
+
+impl VillagerAi {
+    pub fn new() -> VillagerAi {
+	VillagerAi {
+	    state: VillagerState::Walk,
+	    timer: Timer::default(),
+	}
+    }
+
+    // Changes the state and restarts the timer with the duration of the new state
+    pub fn switch_to(&mut self, state: VillagerState) {
+	self.state = state;
+	self.timer = match state {
+	    VillagerState::Walk | VillagerState::Flee => Timer::default(),
+	    VillagerState::Pause => Timer::from_seconds(VILLAGER_PAUSE_TIME, TimerMode::Once),
+	    VillagerState::Indoors => Timer::from_seconds(VILLAGER_HIDE_TIME, TimerMode::Once),
+	};
+    }
+}
+
+#[derive(Component)]
+pub struct BoarAi {
+    pub state: BoarState,
+    pub timer: Timer,
+    // Direction of the current random walk
+    pub direction: Vec2,
+    // The bait gone to or eaten
+    pub bait: Option<Entity>,
+    // After eating a bait, the boar does not notice the player until this runs out
+    pub calm: Timer,
+    // Apples eaten, the boar is tamed once it has eaten enough
+    pub apples: u32,
+}
+
+impl BoarAi {
+    pub fn new() -> BoarAi {
+	BoarAi {
+	    state: BoarState::Idle,
+	    timer: Timer::from_seconds(WANDER_IDLE_TIME, TimerMode::Once),
+	    direction: Vec2::ZERO,
+	    bait: None,
+	    calm: Timer::default(),
+	    apples: 0,
+	}
+    }
+
+    // Changes the state and restarts the timer with the duration of the new state
+    pub fn switch_to(&mut self, state: BoarState) {
+	self.state = state;
+	self.timer = match state {
+	    BoarState::Idle => Timer::from_seconds(WANDER_IDLE_TIME, TimerMode::Once),
+	    BoarState::Wander => Timer::from_seconds(WANDER_WALK_TIME, TimerMode::Once),
+	    // Chasing lasts as long as the player is in range, and going to a bait until it is reached
+	    BoarState::Chase | BoarState::Attracted => Timer::default(),
+	    BoarState::Eating => Timer::from_seconds(BAIT_EAT_TIME, TimerMode::Once),
+	};
+	if !matches!(state, BoarState::Attracted | BoarState::Eating) {
+	    self.bait = None;
+	}
+    }
+
+    pub fn is_calm(&self) -> bool {
+	!self.calm.remaining().is_zero()
+    }
+}
+
+// The noises of a boar
+#[derive(Component)]
+pub struct Barks {
+    pub next_grunt: Timer,
+    pub snort_cooldown: Timer,
+    pub squeal_cooldown: Timer,
+}
+
+impl Barks {
+    pub fn new() -> Barks {
+	// Snorts and squeals are available from the start
+	let mut snort_cooldown = Timer::from_seconds(SNORT_COOLDOWN, TimerMode::Once);
+	snort_cooldown.tick(Duration::from_secs_f32(SNORT_COOLDOWN));
+	let mut squeal_cooldown = Timer::from_seconds(SQUEAL_COOLDOWN, TimerMode::Once);
+	squeal_cooldown.tick(Duration::from_secs_f32(SQUEAL_COOLDOWN));
+	Barks {
+	    next_grunt: Barks::grunt_timer(),
+	    snort_cooldown,
+	    squeal_cooldown,
+	}
+    }
+
+    pub fn grunt_timer() -> Timer {
+	let interval = rand::thread_rng().gen_range(GRUNT_INTERVAL_MIN..GRUNT_INTERVAL_MAX);
+	Timer::from_seconds(interval, TimerMode::Once)
+    }
+}
+
+// The player is eating, and cannot attack or use another item meanwhile
+#[derive(Component)]
+pub struct Eating(pub Timer);
+
+// A bait on the ground, the boars around come to eat it. It is taken by the first one there
+#[derive(Component, Default)]
+pub struct BaitScent {
+    pub eater: Option<Entity>,
+}
+
+// System that runs the spawners: their timer goes on while they are not full and the player is not close,
+// then they spawn at a random point around them that is not in an obstacle. The timers are kept in the save
+pub fn run_spawners(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut save: ResMut<SaveGame>,
+    mut query_spawner: Query<(Entity, &mut Spawner, &Transform, Option<&SpawnId>)>,
+    query_player: Query<&Transform, (With<Player>, Without<Spawner>)>,
+    query_collider: Query<(&Transform, &Sprite), (With<Collider>, Without<Spawner>)>,
+    query_alive: Query<()>,
+    mut game_rng: ResMut<GameRng>,
+) {
+    let player_position = query_player.single().translation.truncate();
+    let rng = &mut game_rng.0;
+    for (spawner_entity, mut spawner, transform, spawn_id) in &mut query_spawner {
+	// The timer starts where the save left it
+	if spawner.timer.duration().is_zero() {
+	    spawner.timer = Timer::from_seconds(spawner.interval, TimerMode::Once);
+	    if let Some(elapsed) = spawn_id.and_then(|id| save.spawners.get(&id.0)) {
+		spawner.timer.set_elapsed(Duration::from_secs_f32(*elapsed));
+	    }
+	}
+	spawner.spawned.retain(|entity| query_alive.contains(*entity));
+
+	let position = transform.translation.truncate();
+	if spawner.spawned.len() as u32 >= spawner.max_alive || position.distance(player_position) < SPAWNER_PAUSE_DISTANCE {
+	    continue;
+	}
+	spawner.timer.tick(time.delta());
+	if let Some(id) = spawn_id {
+	    save.spawners.insert(id.0.clone(), spawner.timer.elapsed_secs());
+	}
+	if !spawner.timer.finished() {
+	    continue;
+	}
+
+	let point = (0..SPAWNER_TRIES)
+	    .map(|_| {
+		let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+		position + Vec2::from_angle(angle) * spawner.radius * rng.gen::<f32>().sqrt()
+	    })
+	    .find(|point| !query_collider.iter().any(|(transform, sprite)| {
+		collide(point.extend(0.0), CHARACTER_SIZE, transform.translation, collider_size(transform, sprite)).is_some()
+	    }));
+	let Some(point) = point else {
+	    continue;
+	};
+	let entity = commands.spawn_npc(&spawner.prefab, point);
+	commands.entity(entity).insert(Herd(spawner_entity.index()));
+	spawner.spawned.push(entity);
+	spawner.timer.reset();
+	if let Some(id) = spawn_id {
+	    save.spawners.insert(id.0.clone(), 0.0);
+	    save.save();
+	}
+    }
+}
+
+// How a boar differs from the prefab, rolled when it spawns
+pub struct BoarVariation {
+    pub health: f32,
+    pub speed: f32,
+    pub size: f32,
+    pub tint: Color,
+}
+
+impl BoarVariation {
+    pub fn roll(rng: &mut impl Rng) -> BoarVariation {
+	let shade = rng.gen_range(BOAR_SHADE_MIN..=1.0);
+	BoarVariation {
+	    health: 1.0 + rng.gen_range(-BOAR_HEALTH_VARIATION..=BOAR_HEALTH_VARIATION),
+	    speed: 1.0 + rng.gen_range(-BOAR_SPEED_VARIATION..=BOAR_SPEED_VARIATION),
+	    size: 1.0 + rng.gen_range(-BOAR_SIZE_VARIATION..=BOAR_SIZE_VARIATION),
+	    // Darker and a bit redder
+	    tint: Color::rgb(1.0, shade, shade * shade),
+	}
+    }
+}
+
+// Spawns a boar, it grows from nothing before acting. Its look, health and speed are in its prefab,
+// with a small variation for each boar
+pub fn spawn_boar(commands: &mut Commands, position: Vec2) -> Entity {
+    commands.spawn_npc("boar", position)
+}
+
+// System that ends the eating of the player
+pub fn finish_eating(
+    simulation: Res<Simulation>,
+    mut commands: Commands,
+    mut query_player: Query<(Entity, &mut Transform, &mut Eating, Option<&Tween>), With<Player>>,
+) {
+    let Ok((player, mut player_transform, mut eating, tween)) = query_player.get_single_mut() else {
+	return;
+    };
+    if eating.0.tick(Duration::from_secs_f32(simulation.dt())).finished() {
+	commands.entity(player).remove::<Eating>();
+	if remove_tagged_tween(&mut commands, player, tween, TweenTag::Eating) {
+	    player_transform.scale = Vec3::ONE;
+	}
+    }
+}
+
+// System that makes the idle boars breathe, by stretching them up and down a little.
+// The breathing stops as soon as the boar does something else, or is hit
+pub fn boar_idle_bob(
+    mut commands: Commands,
+    mut query_boar: Query<(Entity, &BoarAi, &mut Transform, Option<&IdleBob>, Option<&Tween>, Option<&Knockback>, Option<&Carried>, Option<&Thrown>), (Without<SpawningIn>, Without<Boss>)>,
+) {
+    for (boar, boar_ai, mut boar_transform, bob, tween, knockback, carried, thrown) in &mut query_boar {
+	let idle = boar_ai.state == BoarState::Idle && knockback.is_none() && carried.is_none() && thrown.is_none();
+	// Another tween, like the end of the spawn animation, goes first
+	if idle && tween.is_none() {
+	    let stretched = Vec3::new(1.0, 1.0 + BOAR_BOB_AMOUNT, 1.0);
+	    commands.entity(boar).insert((
+		IdleBob,
+		Tween::scale(Vec3::ONE, stretched).over(0.5 / BOAR_BOB_FREQUENCY).ease(Easing::EaseInOut).ping_pong().tagged(TweenTag::IdleBob),
+	    ));
+	} else if !idle && bob.is_some() {
+	    commands.entity(boar).remove::<IdleBob>();
+	    if remove_tagged_tween(&mut commands, boar, tween, TweenTag::IdleBob) {
+		boar_transform.scale = Vec3::ONE;
+	    }
+	}
+    }
+}
+
+// System that mirrors the NPCs looking left, they keep their side when going up or down
+pub fn flip_npcs(mut query_npc: Query<(&Facing, &mut Sprite), (Without<Player>, Changed<Facing>)>) {
+    for (facing, mut sprite) in &mut query_npc {
+	match facing {
+	    Facing::Left => sprite.flip_x = true,
+	    Facing::Right => sprite.flip_x = false,
+	    Facing::Up | Facing::Down => {},
+	}
+    }
+}
+
+// System that shows the NPCs standing or trotting, the trot is faster when the NPC goes faster.
+// An NPC changing rows starts the new one from its first frame
+pub fn animate_npcs(
+    simulation: Res<Simulation>,
+    atlases: Res<Assets<TextureAtlas>>,
+    hit_stop: Res<HitStop>,
+    mut query_npc: Query<(&Velocity, &SpriteSheet, &mut AnimationState, &mut Sprite, Option<&BoarAi>), Without<Player>>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    for (velocity, sheet, mut animation, mut sprite, boar_ai) in &mut query_npc {
+	let Some(atlas) = atlases.get(&sheet.atlas) else {
+	    continue;
+	};
+	let speed = velocity.0.length();
+	let idle = boar_ai.is_some_and(|boar_ai| boar_ai.state == BoarState::Idle);
+	let (row, frames, fps) = if idle || speed == 0.0 {
+	    (0, BOAR_IDLE_FRAMES, BOAR_IDLE_FPS)
+	} else {
+	    (1, BOAR_TROT_FRAMES, BOAR_TROT_FPS * speed / BOAR_SPEED)
+	};
+	if animation.row != row {
+	    animation.row = row;
+	    animation.frame = 0;
+	    animation.timer.reset();
+	}
+
+	// The timer is at ANIMATION_FPS, it goes faster or slower to match the framerate
+	animation.timer.tick(Duration::from_secs_f32(simulation.dt() * fps / ANIMATION_FPS));
+	for _ in 0..animation.timer.times_finished_this_tick() {
+	    animation.frame = (animation.frame + 1) % frames;
+	}
+	sprite.rect = Some(atlas.textures[animation.row * sheet.columns + animation.frame]);
+    }
+}
+
+// System that makes the boars walk around, following their patrol path if they have one
+pub fn boar_wander(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    hit_stop: Res<HitStop>,
+    mut game_rng: ResMut<GameRng>,
+    mut state_events: EventWriter<BoarStateChanged>,
+    mut query_boar: Query<(Entity, &mut Transform, &mut Velocity, &mut BoarAi, &Speed, Option<&mut PatrolPath>), (Without<Tamed>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    let rng = &mut game_rng.0;
+
+    for (boar, mut boar_transform, mut velocity, mut boar_ai, speed, patrol_path) in &mut query_boar {
+	if !matches!(boar_ai.state, BoarState::Idle | BoarState::Wander) {
+	    continue;
+	}
+
+	// Switch between walking and standing still
+	boar_ai.timer.tick(Duration::from_secs_f32(simulation.dt()));
+	if boar_ai.timer.finished() {
+	    let new_state = if boar_ai.state == BoarState::Idle {
+		let angle = rng.gen_range(-PI..PI);
+		boar_ai.direction = Vec2::new(angle.cos(), angle.sin());
+		BoarState::Wander
+	    } else {
+		BoarState::Idle
+	    };
+	    boar_ai.switch_to(new_state);
+	    state_events.send(BoarStateChanged { boar, to: new_state });
+	}
+	if boar_ai.state != BoarState::Wander {
+	    velocity.0 = Vec2::ZERO;
+	    continue;
+	}
+
+	let step = speed.0 * simulation.dt();
+	let position = boar_transform.translation.truncate();
+	let new_position = match patrol_path {
+	    Some(mut path) if !path.points.is_empty() => {
+		// Walk to the next point of the path, and aim for the one after once reached
+		let target = path.points[path.next];
+		if position.distance(target) <= step {
+		    path.next = (path.next + 1) % path.points.len();
+		    target
+		} else {
+		    position + (target - position).normalize() * step
+		}
+	    },
+	    _ => position + boar_ai.direction * step,
+	};
+
+	// Boars stay inside the walls, like the player
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+
+	boar_transform.translation.x = new_position.x.clamp(bounds.min.x, bounds.max.x);
+	boar_transform.translation.y = new_position.y.clamp(bounds.min.y, bounds.max.y);
+	velocity.0 = (boar_transform.translation.truncate() - position) / simulation.dt();
+    }
+}
+
+// System that feeds the apples to the boars, the ones thrown and the ones given by hand.
+// A boar chasing the player snorts instead, a hand-fed apple is then kept.
+// The boar is tamed once it has eaten enough, which is saved
+pub fn tame_boars(
+    mut commands: Commands,
+    mut inventory: ResMut<Inventory>,
+    mut particles: ResMut<Particles>,
+    mut save: ResMut<SaveGame>,
+    mut toasts: EventWriter<Toast>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut spatial_sfx_events: EventWriter<PlaySpatialSfx>,
+    mut state_events: EventWriter<BoarStateChanged>,
+    mut tamings: EventWriter<BoarTamed>,
+    mut interactions: EventReader<Interacted>,
+    mut treats: EventReader<TreatGiven>,
+    mut query_boar: Query<(&Transform, &mut BoarAi, Option<&Frank>, Option<&SpawnId>), Without<Tamed>>,
+) {
+    let by_hand: Vec<TreatGiven> = interactions.iter().map(|Interacted(entity)| TreatGiven { boar: *entity, by_hand: true }).collect();
+    for treat in treats.iter().chain(&by_hand) {
+	let Ok((transform, mut boar_ai, frank, spawn_id)) = query_boar.get_mut(treat.boar) else {
+	    continue;
+	};
+	if boar_ai.state == BoarState::Chase {
+	    spatial_sfx_events.send(PlaySpatialSfx(Sfx::Snort, boar_sound(treat.boar, SNORT_VOLUME)));
+	    continue;
+	}
+	if treat.by_hand && !inventory.remove(ItemKind::Apple, 1) {
+	    toasts.send(Toast("You have no apples to give".to_string()));
+	    continue;
+	}
+	boar_ai.apples += 1;
+	spatial_sfx_events.send(PlaySpatialSfx(Sfx::Grunt, boar_sound(treat.boar, BOAR_SOUND_VOLUME)));
+	let name = if frank.is_some() { "Frank" } else { "The boar" };
+	if boar_ai.apples < TAME_APPLES {
+	    toasts.send(Toast(format!("{name} eats the apple ({}/{TAME_APPLES})", boar_ai.apples)));
+	    continue;
+	}
+
+	// A tamed boar is not fed anymore, it does not get in the way of the other interactions
+	commands.entity(treat.boar).insert(Tamed).remove::<(Interactable, Hostile)>();
+	boar_ai.switch_to(BoarState::Idle);
+	state_events.send(BoarStateChanged { boar: treat.boar, to: BoarState::Idle });
+	tamings.send(BoarTamed(treat.boar));
+	spawn_burst(&mut commands, &mut particles, ParticleKind::Heart,
+		    transform.translation.truncate().extend(IMPACT_Z), TAMED_HEART_COUNT);
+	sfx_events.send(PlaySfx(Sfx::Unlock));
+	toasts.send(Toast(format!("{name} is tamed, and follows you")));
+	if let Some(SpawnId(id)) = spawn_id {
+	    save.tamed.insert(id.clone());
+	    save.save();
+	}
+    }
+}
+
+// System that keeps the tamed boars behind the player, without crowding it or each other
+pub fn follow_player(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    query_player: Query<(Entity, &Transform), With<Player>>,
+    mut query_boar: Query<(Entity, &mut Transform, &mut Velocity, &Speed, Option<&Tamed>), (With<BoarAi>, Without<Player>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    let (player, player_transform) = query_player.single();
+    let player_position = player_transform.translation.truncate();
+    // The boars keep away from the player too
+    let neighbors: Vec<(Entity, Vec2)> = query_boar.iter()
+	.map(|(boar, boar_transform, ..)| (boar, boar_transform.translation.truncate()))
+	.chain([(player, player_position)])
+	.collect();
+
+    for (boar, mut boar_transform, mut velocity, speed, tamed) in &mut query_boar {
+	if tamed.is_none() {
+	    continue;
+	}
+	let position = boar_transform.translation.truncate();
+	let to_player = player_position - position;
+	let mut steering = if to_player.length() > TAMED_FOLLOW_DISTANCE {
+	    to_player.normalize() * speed.0
+	} else {
+	    Vec2::ZERO
+	};
+	for (other, other_position) in &neighbors {
+	    let away = position - *other_position;
+	    if *other != boar && away.length() < TAMED_SEPARATION_RADIUS {
+		steering += away.normalize_or_zero() * (1.0 - away.length() / TAMED_SEPARATION_RADIUS) * speed.0;
+	    }
+	}
+	velocity.0 = steering.clamp_length_max(BOAR_CHASE_SPEED * speed.0 / BOAR_SPEED);
+
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+	let new_position = position + velocity.0 * simulation.dt();
+	boar_transform.translation.x = new_position.x.clamp(bounds.min.x, bounds.max.x);
+	boar_transform.translation.y = new_position.y.clamp(bounds.min.y, bounds.max.y);
+    }
+}
+
+// System that grows the new boars, which start acting once at their full size
+pub fn spawn_in_boars(
+    simulation: Res<Simulation>,
+    mut commands: Commands,
+    mut query_spawning: Query<(Entity, &mut SpawningIn)>,
+) {
+    for (boar, mut spawning) in &mut query_spawning {
+	spawning.0.tick(Duration::from_secs_f32(simulation.dt()));
+	if spawning.0.finished() {
+	    commands.entity(boar).remove::<SpawningIn>();
+	}
+    }
+}
+
+// System that makes the boars chase the player when close, and give up when far away
+pub fn boar_detect(
+    mut state_events: EventWriter<BoarStateChanged>,
+    query_player: Query<&Transform, With<Player>>,
+    query_zone: Query<(&Transform, &SafeZone)>,
+    mut query_boar: Query<(Entity, &Transform, &mut BoarAi), (Without<Player>, Without<Tamed>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    let player_position = query_player.single().translation.truncate();
+    // The player is out of reach in a safe zone
+    let safe = query_zone.iter().any(|(zone_transform, zone)| zone.contains(zone_transform.translation.truncate(), player_position));
+
+    for (boar, boar_transform, mut boar_ai) in &mut query_boar {
+	let distance = boar_transform.translation.truncate().distance(player_position);
+	let new_state = match boar_ai.state {
+	    BoarState::Idle | BoarState::Wander if distance <= DETECTION_RADIUS && !boar_ai.is_calm() && !safe => BoarState::Chase,
+	    BoarState::Chase if distance > LEASH_RADIUS || safe => BoarState::Idle,
+	    _ => continue,
+	};
+	boar_ai.switch_to(new_state);
+	state_events.send(BoarStateChanged { boar, to: new_state });
+    }
+}
+
+// System that draws the boars to the baits around, the first one there eats the bait while the others go back
+// to wandering. A boar that has eaten stays calm for a while
+pub fn boar_bait(
+    simulation: Res<Simulation>,
+    mut commands: Commands,
+    mut game_rng: ResMut<GameRng>,
+    mut state_events: EventWriter<BoarStateChanged>,
+    mut query_bait: Query<(Entity, &Transform, &mut BaitScent)>,
+    mut query_boar: Query<(Entity, &mut Transform, &mut Velocity, &mut BoarAi, &Speed), (Without<BaitScent>, Without<Boss>, Without<Tamed>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    // A bait whose eater was interrupted (hit, carried away, ...) is free again
+    for (bait, _, mut scent) in &mut query_bait {
+	let still_eating = scent.eater
+	    .and_then(|eater| query_boar.get(eater).ok())
+	    .is_some_and(|(_, _, _, boar_ai, _)| boar_ai.state == BoarState::Eating && boar_ai.bait == Some(bait));
+	if !still_eating {
+	    scent.eater = None;
+	}
+    }
+
+    let rng = &mut game_rng.0;
+    for (boar, mut boar_transform, mut velocity, mut boar_ai, speed) in &mut query_boar {
+	boar_ai.calm.tick(Duration::from_secs_f32(simulation.dt()));
+	let position = boar_transform.translation.truncate();
+	let new_state = match boar_ai.state {
+	    BoarState::Idle | BoarState::Wander | BoarState::Chase if !boar_ai.is_calm() => {
+		let closest = query_bait.iter()
+		    .filter(|(_, _, scent)| scent.eater.is_none())
+		    .map(|(bait, bait_transform, _)| (bait, bait_transform.translation.truncate().distance(position)))
+		    .filter(|(_, distance)| *distance <= BAIT_ATTRACT_RADIUS)
+		    .min_by(|(_, a), (_, b)| a.total_cmp(b));
+		let Some((bait, _)) = closest else {
+		    continue;
+		};
+		boar_ai.switch_to(BoarState::Attracted);
+		boar_ai.bait = Some(bait);
+		BoarState::Attracted
+	    },
+	    BoarState::Attracted => {
+		let target = boar_ai.bait
+		    .and_then(|bait| query_bait.get(bait).ok())
+		    .filter(|(_, _, scent)| scent.eater.is_none())
+		    .map(|(bait, bait_transform, _)| (bait, bait_transform.translation.truncate()));
+		let Some((bait, bait_position)) = target else {
+		    // Eaten by another boar
+		    velocity.0 = Vec2::ZERO;
+		    boar_ai.switch_to(BoarState::Idle);
+		    state_events.send(BoarStateChanged { boar, to: BoarState::Idle });
+		    continue;
+		};
+		let offset = bait_position - position;
+		if offset.length() > BAIT_REACH {
+		    let step = offset.clamp_length_max(speed.0 * simulation.dt());
+		    boar_transform.translation += step.extend(0.0);
+		    velocity.0 = step / simulation.dt();
+		    continue;
+		}
+		if let Ok((_, _, mut scent)) = query_bait.get_mut(bait) {
+		    scent.eater = Some(boar);
+		}
+		velocity.0 = Vec2::ZERO;
+		boar_ai.switch_to(BoarState::Eating);
+		boar_ai.bait = Some(bait);
+		BoarState::Eating
+	    },
+	    BoarState::Eating => {
+		if !boar_ai.timer.tick(Duration::from_secs_f32(simulation.dt())).finished() {
+		    continue;
+		}
+		if let Some(bait) = boar_ai.bait {
+		    commands.entity(bait).despawn_recursive();
+		}
+		let angle = rng.gen_range(-PI..PI);
+		boar_ai.direction = Vec2::new(angle.cos(), angle.sin());
+		boar_ai.calm = Timer::from_seconds(BAIT_CALM_TIME, TimerMode::Once);
+		boar_ai.switch_to(BoarState::Wander);
+		BoarState::Wander
+	    },
+	    _ => continue,
+	};
+	state_events.send(BoarStateChanged { boar, to: new_state });
+    }
+}
+
+// System that makes the boars near a boar that started chasing or got hurt chase as well.
+// The boars past the leash are not called, they would give up right away. The herds only answer to their own
+pub fn call_for_help(
+    mut state_events: ResMut<Events<BoarStateChanged>>,
+    mut state_reader: Local<ManualEventReader<BoarStateChanged>>,
+    mut damage_events: EventReader<DamageEvent>,
+    query_player: Query<&Transform, With<Player>>,
+    mut query_boar: Query<(Entity, &Transform, &mut BoarAi), (Without<Player>, Without<Herd>, Without<Tamed>, Without<Carried>, Without<Thrown>, Without<SpawningIn>)>,
+) {
+    let caller_positions: Vec<Vec2> = state_reader.iter(&state_events)
+	.filter(|event| event.to == BoarState::Chase)
+	.map(|event| event.boar)
+	.chain(damage_events.iter().map(|event| event.target))
+	.filter_map(|caller| query_boar.get(caller).ok())
+	.map(|(_, boar_transform, _)| boar_transform.translation.truncate())
+	.collect();
+    if caller_positions.is_empty() {
+	return;
+    }
+
+    let player_position = query_player.single().translation.truncate();
+    for (boar, boar_transform, mut boar_ai) in &mut query_boar {
+	let position = boar_transform.translation.truncate();
+	if boar_ai.state == BoarState::Chase || boar_ai.is_calm() || position.distance(player_position) > LEASH_RADIUS {
+	    continue;
+	}
+	if caller_positions.iter().any(|caller| position.distance(*caller) <= HELP_RADIUS) {
+	    boar_ai.switch_to(BoarState::Chase);
+	    state_events.send(BoarStateChanged { boar, to: BoarState::Chase });
+	}
+    }
+    // The boars called here do not call others
+    state_reader.iter(&state_events).for_each(drop);
+}
+
+// System that pushes the hostile animals back to the edge of the safe zones they walked into
+pub fn keep_out_of_safe_zones(
+    query_zone: Query<(&Transform, &SafeZone), Without<Hostile>>,
+    mut query_hostile: Query<&mut Transform, (With<Hostile>, Without<Carried>, Without<Thrown>)>,
+) {
+    for (zone_transform, zone) in &query_zone {
+	let center = zone_transform.translation.truncate();
+	for mut transform in &mut query_hostile {
+	    let position = transform.translation.truncate();
+	    if !zone.contains(center, position) {
+		continue;
+	    }
+	    let away = (position - center).try_normalize().unwrap_or(Vec2::X);
+	    let edge = center + away * zone.radius;
+	    transform.translation.x = edge.x;
+	    transform.translation.y = edge.y;
+	}
+    }
+}
+
+// System that makes the herd of a hurt boar chase as well, the members around it that can see it.
+// A member that gave up the chase past the leash makes the whole herd give up
+pub fn herd_aggro(
+    mut state_events: ResMut<Events<BoarStateChanged>>,
+    mut state_reader: Local<ManualEventReader<BoarStateChanged>>,
+    mut damage_events: EventReader<DamageEvent>,
+    query_player: Query<&Transform, With<Player>>,
+    query_collider: Query<(&Transform, &Sprite), With<Collider>>,
+    mut query_boar: Query<(Entity, &Transform, &Herd, &mut BoarAi), (Without<Player>, Without<Tamed>, Without<Carried>, Without<Thrown>, Without<SpawningIn>)>,
+) {
+    let player_position = query_player.single().translation.truncate();
+    let given_up: Vec<Herd> = state_reader.iter(&state_events)
+	.filter(|event| event.to == BoarState::Idle)
+	.filter_map(|event| query_boar.get(event.boar).ok())
+	.filter(|(_, boar_transform, ..)| boar_transform.translation.truncate().distance(player_position) > LEASH_RADIUS)
+	.map(|(_, _, herd, _)| *herd)
+	.collect();
+    let callers: Vec<(Herd, Vec2)> = damage_events.iter()
+	.filter_map(|event| query_boar.get(event.target).ok())
+	.map(|(_, boar_transform, herd, _)| (*herd, boar_transform.translation.truncate()))
+	.collect();
+    if given_up.is_empty() && callers.is_empty() {
+	return;
+    }
+
+    let obstacles: Vec<(Vec2, Vec2)> = query_collider.iter()
+	.map(|(transform, sprite)| (transform.translation.truncate(), collider_size(transform, sprite)))
+	.collect();
+    for (boar, boar_transform, herd, mut boar_ai) in &mut query_boar {
+	if boar_ai.state == BoarState::Chase && given_up.contains(herd) {
+	    boar_ai.switch_to(BoarState::Idle);
+	    state_events.send(BoarStateChanged { boar, to: BoarState::Idle });
+	    continue;
+	}
+	if boar_ai.state == BoarState::Chase || boar_ai.is_calm() {
+	    continue;
+	}
+	let position = boar_transform.translation.truncate();
+	let called = callers.iter().any(|(caller_herd, caller)| {
+	    caller_herd == herd && position.distance(*caller) <= HERD_AGGRO_RADIUS && line_of_sight(position, *caller, &obstacles)
+	});
+	if called {
+	    boar_ai.switch_to(BoarState::Chase);
+	    state_events.send(BoarStateChanged { boar, to: BoarState::Chase });
+	}
+    }
+    // The boars changed here do not change others
+    state_reader.iter(&state_events).for_each(drop);
+}
+
+// System that steers the chasing boars as a pack, toward the player
+pub fn boar_flocking(
+    simulation: Res<Simulation>,
+    query_player: Query<&Transform, With<Player>>,
+    mut query_boar: Query<(Entity, &Transform, &mut Velocity, &BoarAi, &Speed), (Without<Player>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    let player_position = query_player.single().translation.truncate();
+    let pack: Vec<(Entity, Vec2, Vec2)> = query_boar.iter()
+	.filter(|(_, _, _, boar_ai, _)| boar_ai.state == BoarState::Chase)
+	.map(|(boar, boar_transform, velocity, _, _)| (boar, boar_transform.translation.truncate(), velocity.0))
+	.collect();
+
+    for (boar, boar_transform, mut velocity, boar_ai, speed) in &mut query_boar {
+	if boar_ai.state != BoarState::Chase {
+	    continue;
+	}
+	let position = boar_transform.translation.truncate();
+	// The faster boars of free play chase faster too
+	let chase_speed = BOAR_CHASE_SPEED * speed.0 / BOAR_SPEED;
+
+	// Straight at the player, slowing down to stop next to the player
+	let offset = player_position - position;
+	let approach_speed = ((offset.length() - CHARACTER_SIZE.x) / simulation.dt()).clamp(0.0, chase_speed);
+	let cohesion = offset.normalize_or_zero() * approach_speed;
+
+	// Away from the close boars, the closer the stronger, and along the others
+	let mut separation = Vec2::ZERO;
+	let mut heading = Vec2::ZERO;
+	let mut neighbors = 0;
+	for (other, other_position, other_velocity) in &pack {
+	    let distance = position.distance(*other_position);
+	    if *other == boar || distance > FLOCK_NEIGHBOR_RADIUS {
+		continue;
+	    }
+	    if distance < FLOCK_SEPARATION_RADIUS {
+		separation += (position - *other_position).normalize_or_zero() * (1.0 - distance / FLOCK_SEPARATION_RADIUS);
+	    }
+	    heading += *other_velocity;
+	    neighbors += 1;
+	}
+	let alignment = if neighbors > 0 { heading / neighbors as f32 } else { Vec2::ZERO };
+
+	velocity.0 = (cohesion * FLOCK_COHESION_WEIGHT
+		      + separation * chase_speed * FLOCK_SEPARATION_WEIGHT
+		      + alignment * FLOCK_ALIGNMENT_WEIGHT)
+	    .clamp_length_max(chase_speed);
+    }
+}
+
+// System that moves the chasing boars as steered by the pack
+pub fn boar_chase(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    hit_stop: Res<HitStop>,
+    mut query_boar: Query<(&mut Transform, &Velocity, &BoarAi), (Without<Player>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    for (mut boar_transform, velocity, boar_ai) in &mut query_boar {
+	if boar_ai.state != BoarState::Chase {
+	    continue;
+	}
+	let new_position = boar_transform.translation.truncate() + velocity.0 * simulation.dt();
+
+	// Bounds are the same as for the other characters
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+
+	boar_transform.translation.x = new_position.x.clamp(bounds.min.x, bounds.max.x);
+	boar_transform.translation.y = new_position.y.clamp(bounds.min.y, bounds.max.y);
+    }
+}
+
+// System that makes the wolves circle the player once close, then dart in and run off, over and over
+pub fn run_wolves(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    hit_stop: Res<HitStop>,
+    mut game_rng: ResMut<GameRng>,
+    query_player: Query<&Transform, With<Player>>,
+    mut query_wolf: Query<(&mut Transform, &mut Velocity, &mut WolfAi, &Speed), (Without<Player>, Without<Carried>, Without<Thrown>, Without<Knockback>, Without<SpawningIn>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    let player_position = query_player.single().translation.truncate();
+    for (mut wolf_transform, mut velocity, mut wolf_ai, speed) in &mut query_wolf {
+	let position = wolf_transform.translation.truncate();
+	// From the player to the wolf
+	let offset = position - player_position;
+	let distance = offset.length();
+	let done = wolf_ai.timer.tick(Duration::from_secs_f32(simulation.dt())).finished();
+	match wolf_ai.state {
+	    WolfState::Prowl if distance <= WOLF_DETECTION_RADIUS => wolf_ai.switch_to(WolfState::Circle),
+	    WolfState::Prowl => (),
+	    _ if distance > WOLF_LEASH_RADIUS => wolf_ai.switch_to(WolfState::Prowl),
+	    WolfState::Circle if done => wolf_ai.switch_to(WolfState::Dart),
+	    WolfState::Dart if done || distance <= CHARACTER_SIZE.x => wolf_ai.switch_to(WolfState::Retreat),
+	    WolfState::Retreat if done => {
+		wolf_ai.turn = if game_rng.0.gen_bool(0.5) { 1.0 } else { -1.0 };
+		wolf_ai.switch_to(WolfState::Circle);
+	    },
+	    WolfState::Circle | WolfState::Dart | WolfState::Retreat => (),
+	}
+
+	velocity.0 = match wolf_ai.state {
+	    WolfState::Prowl => Vec2::ZERO,
+	    // Toward a point a little further around the circle, which brings the wolf onto the circle too
+	    WolfState::Circle => {
+		let angle = offset.y.atan2(offset.x) + wolf_ai.turn * WOLF_CIRCLE_LEAD;
+		let target = player_position + Vec2::from_angle(angle) * WOLF_CIRCLE_RADIUS;
+		(target - position).normalize_or_zero() * speed.0
+	    },
+	    WolfState::Dart => -offset.normalize_or_zero() * speed.0,
+	    WolfState::Retreat => offset.normalize_or_zero() * speed.0,
+	};
+	let new_position = position + velocity.0 * simulation.dt();
+
+	// Bounds are the same as for the other characters
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+	wolf_transform.translation.x = new_position.x.clamp(bounds.min.x, bounds.max.x);
+	wolf_transform.translation.y = new_position.y.clamp(bounds.min.y, bounds.max.y);
+    }
+}
+
+// System that walks the villagers along their path, around the player standing in the way.
+// A villager runs home when a boar chases nearby, and hides there until the boars are gone for a while
+pub fn run_villagers(
+    mut commands: Commands,
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    hit_stop: Res<HitStop>,
+    query_player: Query<&Transform, With<Player>>,
+    query_boar: Query<(&Transform, &BoarAi), (Without<Player>, Without<Villager>, Without<Tamed>)>,
+    mut query_villager: Query<(Entity, &mut Transform, &mut Velocity, &mut Visibility, &mut VillagerAi, &mut PatrolPath, &Speed), (With<Villager>, Without<Player>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    let player_position = query_player.single().translation.truncate();
+    for (villager, mut villager_transform, mut velocity, mut visibility, mut villager_ai, mut path, speed) in &mut query_villager {
+	if path.points.is_empty() {
+	    continue;
+	}
+	let position = villager_transform.translation.truncate();
+	let door = path.points[0];
+	let danger = query_boar.iter().any(|(boar_transform, boar_ai)| {
+	    boar_ai.state == BoarState::Chase
+		&& boar_transform.translation.truncate().distance(position) <= VILLAGER_FLEE_RADIUS
+	});
+	let done = villager_ai.timer.tick(Duration::from_secs_f32(simulation.dt())).finished();
+	match villager_ai.state {
+	    VillagerState::Walk | VillagerState::Pause if danger => villager_ai.switch_to(VillagerState::Flee),
+	    // The time indoors starts over while the boars chase
+	    VillagerState::Indoors if danger => villager_ai.timer.reset(),
+	    VillagerState::Indoors if done => {
+		villager_ai.switch_to(VillagerState::Walk);
+		visibility.is_visible = true;
+		commands.entity(villager).insert(Interactable);
+	    },
+	    VillagerState::Pause if done => villager_ai.switch_to(VillagerState::Walk),
+	    _ => (),
+	}
+
+	let step = speed.0 * simulation.dt();
+	let new_position = match villager_ai.state {
+	    VillagerState::Pause | VillagerState::Indoors => position,
+	    VillagerState::Flee if position.distance(door) <= step * VILLAGER_FLEE_SPEED_FACTOR => {
+		// Gone in: out of sight, and not talked to through the door
+		villager_ai.switch_to(VillagerState::Indoors);
+		visibility.is_visible = false;
+		commands.entity(villager).remove::<Interactable>();
+		path.next = 1 % path.points.len();
+		door
+	    },
+	    VillagerState::Flee => position + (door - position).normalize() * step * VILLAGER_FLEE_SPEED_FACTOR,
+	    VillagerState::Walk => {
+		let target = path.points[path.next];
+		// The player standing on the point is as good as reaching it
+		let blocked = target.distance(player_position) < CHARACTER_SIZE.x
+		    && position.distance(target) <= VILLAGER_AVOID_DISTANCE;
+		if position.distance(target) <= step || blocked {
+		    path.next = (path.next + 1) % path.points.len();
+		    villager_ai.switch_to(VillagerState::Pause);
+		    if blocked { position } else { target }
+		} else {
+		    let mut direction = (target - position).normalize();
+		    // The player is in the way when close ahead, near the line walked: step to the other side
+		    let to_player = player_position - position;
+		    let ahead = to_player.dot(direction);
+		    let aside = direction.perp_dot(to_player);
+		    if ahead > 0.0 && ahead <= VILLAGER_AVOID_DISTANCE && aside.abs() < CHARACTER_SIZE.x {
+			direction = (direction - direction.perp() * aside.signum() * 2.0).normalize();
+		    }
+		    position + direction * step
+		}
+	    },
+	};
+
+	// Bounds are the same as for the other characters
+	let bounds = play_area.bounds(CHARACTER_SIZE);
+	villager_transform.translation.x = new_position.x.clamp(bounds.min.x, bounds.max.x);
+	villager_transform.translation.y = new_position.y.clamp(bounds.min.y, bounds.max.y);
+	velocity.0 = (villager_transform.translation.truncate() - position) / simulation.dt();
+    }
+}
+
+// System that brings the boss once all the boars are defeated
+pub fn spawn_boss(
+    mut commands: Commands,
+    map: Res<MapData>,
+    mut tracker: ResMut<ObjectiveTracker>,
+    mut toasts: EventWriter<Toast>,
+) {
+    if !tracker.completed.contains(&Objective::DefeatBoars)
+	|| tracker.active.contains(&Objective::DefeatBoss)
+	|| tracker.completed.contains(&Objective::DefeatBoss) {
+	return;
+    }
+    let position = map.spawn_points(SpawnKind::Boss, BOSS_SPAWN)[0].position;
+    let boss = spawn_boar(&mut commands, position);
+    // The boss is bigger, and after the player right away
+    let mut boar_ai = BoarAi::new();
+    boar_ai.switch_to(BoarState::Chase);
+    commands.entity(boss).insert((
+	Boss::new(),
+	boar_ai,
+	Loot(BOSS_LOOT.to_string()),
+	HealthPoints::new(BOSS_HEALTH),
+	Hitbox {
+	    offset: Vec2::ZERO,
+	    size: CHARACTER_SIZE * BOSS_SCALE,
+	},
+	Tween::scale(Vec3::ZERO, Vec3::splat(BOSS_SCALE)).over(SPAWN_IN_TIME).ease(Easing::Back),
+    ));
+    tracker.active.push(Objective::DefeatBoss);
+    toasts.send(Toast("A huge boar appears!".to_string()));
+}
+
+// System that runs the attacks of the boss, the attack depends on the health left
+pub fn boss_attacks(
+    simulation: Res<Simulation>,
+    mut commands: Commands,
+    mut particles: ResMut<Particles>,
+    hit_stop: Res<HitStop>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut toasts: EventWriter<Toast>,
+    query_player: Query<(Entity, &Transform, &PlayerSize), With<Player>>,
+    mut query_boss: Query<(Entity, &Transform, &Hitbox, &HealthPoints, &BoarAi, &mut Velocity, &mut Boss, Option<&Knockback>), (Without<Player>, Without<SpawningIn>)>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    let Ok((boss_entity, boss_transform, boss_hitbox, health, boar_ai, mut velocity, mut boss, knockback)) = query_boss.get_single_mut() else {
+	return;
+    };
+    let (player, player_transform, player_size) = query_player.single();
+    let boss_position = boss_transform.translation.truncate();
+    let player_center = player_transform.translation.truncate();
+
+    let phase = BossPhase::from_health(health.ratio());
+    if phase != boss.phase {
+	boss.phase = phase;
+	boss.attack = Timer::from_seconds(phase.attack_interval(), TimerMode::Repeating);
+	toasts.send(Toast("The boar boss is getting angry!".to_string()));
+    }
+
+    // During a charge, running into the player hurts it
+    if boss.charging {
+	if knockback.is_none() {
+	    boss.charging = false;
+	} else if !boss.charge_hit
+	    && collide(boss_position.extend(0.0), boss_hitbox.size, player_center.extend(0.0), player_size.0).is_some() {
+	    boss.charge_hit = true;
+	    damage_events.send(DamageEvent { target: player, amount: BOSS_CHARGE_DAMAGE, source: boss_position });
+	}
+	return;
+    }
+
+    // The boss only attacks when after the player, and not while knocked back
+    if boar_ai.state != BoarState::Chase || knockback.is_some() {
+	return;
+    }
+    if !boss.attack.tick(Duration::from_secs_f32(simulation.dt())).just_finished() {
+	return;
+    }
+    match phase {
+	BossPhase::Charge => {
+	    velocity.0 = (player_center - boss_position).normalize_or_zero() * BOSS_CHARGE_SPEED;
+	    commands.entity(boss_entity).insert(Knockback(Timer::from_seconds(BOSS_CHARGE_TIME, TimerMode::Once)));
+	    boss.charging = true;
+	    boss.charge_hit = false;
+	    sfx_events.send(PlaySfx(Sfx::Snort));
+	},
+	BossPhase::Summon => {
+	    for index in 0..BOSS_SUMMON_COUNT {
+		let angle = 2.0 * PI * index as f32 / BOSS_SUMMON_COUNT as f32;
+		let position = boss_position + Vec2::from_angle(angle) * BOSS_SUMMON_DISTANCE;
+		spawn_boar(&mut commands, position);
+	    }
+	    sfx_events.send(PlaySfx(Sfx::Squeal));
+	},
+	BossPhase::Slam => {
+	    if boss_position.distance(player_center) <= BOSS_SLAM_RADIUS {
+		damage_events.send(DamageEvent { target: player, amount: BOSS_SLAM_DAMAGE, source: boss_position });
+	    }
+	    let feet = boss_position + Vec2::Y * SHADOW_OFFSET * BOSS_SCALE;
+	    spawn_burst(&mut commands, &mut particles, ParticleKind::Dust, feet.extend(IMPACT_Z), BOSS_SLAM_DUST_COUNT);
+	    sfx_events.send(PlaySfx(Sfx::Thud));
+	},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    fn spawn_boar(test: &mut TestApp, position: Vec2, herd: u32, state: BoarState, velocity: Vec2) -> Entity {
+	let mut boar_ai = BoarAi::new();
+	boar_ai.switch_to(state);
+	test.app.world.spawn((
+	    Transform::from_translation(position.extend(0.1)),
+	    Boar,
+	    Hostile,
+	    Herd(herd),
+	    boar_ai,
+	    Velocity(velocity),
+	    Speed(BOAR_SPEED),
+	)).id()
+    }
+
+    fn state(test: &TestApp, boar: Entity) -> BoarState {
+	test.app.world.get::<BoarAi>(boar).unwrap().state
+    }
+
+    fn velocity(test: &TestApp, boar: Entity) -> Vec2 {
+	test.app.world.get::<Velocity>(boar).unwrap().0
+    }
+
+    // A hurt boar makes the members of its herd around it chase, not the ones too far or of another herd
+    #[test]
+    fn hurt_boar_calls_its_herd() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(herd_aggro));
+	test.spawn_player(Vec2::new(0.0, -1000.0));
+	let hurt = spawn_boar(&mut test, Vec2::ZERO, 1, BoarState::Idle, Vec2::ZERO);
+	let near = spawn_boar(&mut test, Vec2::new(HERD_AGGRO_RADIUS - 50.0, 0.0), 1, BoarState::Wander, Vec2::ZERO);
+	let far = spawn_boar(&mut test, Vec2::new(HERD_AGGRO_RADIUS + 50.0, 0.0), 1, BoarState::Idle, Vec2::ZERO);
+	let stranger = spawn_boar(&mut test, Vec2::new(-50.0, 0.0), 2, BoarState::Idle, Vec2::ZERO);
+	test.app.world.send_event(DamageEvent { target: hurt, amount: 1.0, source: Vec2::ZERO });
+
+	test.step_fixed(1);
+	assert_eq!(state(&test, near), BoarState::Chase);
+	assert_eq!(state(&test, far), BoarState::Idle);
+	assert_eq!(state(&test, stranger), BoarState::Idle);
+    }
+
+    // Chasing boars closer than the separation radius steer away from each other, while closing in on the player
+    #[test]
+    fn close_boars_keep_apart() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(boar_flocking));
+	test.spawn_player(Vec2::new(0.0, 1000.0));
+	let left = spawn_boar(&mut test, Vec2::new(-FLOCK_SEPARATION_RADIUS / 4.0, 0.0), 1, BoarState::Chase, Vec2::ZERO);
+	let right = spawn_boar(&mut test, Vec2::new(FLOCK_SEPARATION_RADIUS / 4.0, 0.0), 1, BoarState::Chase, Vec2::ZERO);
+
+	test.step_fixed(1);
+	assert!(velocity(&test, left).x < 0.0 && velocity(&test, left).y > 0.0);
+	assert!(velocity(&test, right).x > 0.0 && velocity(&test, right).y > 0.0);
+    }
+
+    // Past the separation radius the chasing boars no longer push apart, the neighbors pull them the same way
+    #[test]
+    fn neighbor_boars_go_the_same_way() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(boar_flocking));
+	test.spawn_player(Vec2::new(0.0, 1000.0));
+	let distance = (FLOCK_SEPARATION_RADIUS + FLOCK_NEIGHBOR_RADIUS) / 2.0;
+	let boar = spawn_boar(&mut test, Vec2::ZERO, 1, BoarState::Chase, Vec2::ZERO);
+	let neighbor = spawn_boar(&mut test, Vec2::new(-distance, 0.0), 1, BoarState::Chase, Vec2::ZERO);
+	let alone = spawn_boar(&mut test, Vec2::new(-2.0 * FLOCK_NEIGHBOR_RADIUS, 0.0), 1, BoarState::Chase, Vec2::ZERO);
+
+	test.step_fixed(1);
+	assert_eq!(velocity(&test, boar).x, 0.0);
+	assert!(velocity(&test, boar).y > 0.0);
+
+	// The neighbor runs to the right, the boar out of the radius to the left
+	test.app.world.get_mut::<Velocity>(neighbor).unwrap().0 = Vec2::new(BOAR_CHASE_SPEED, 0.0);
+	test.app.world.get_mut::<Velocity>(alone).unwrap().0 = Vec2::new(-BOAR_CHASE_SPEED, 0.0);
+	test.step_fixed(1);
+	assert!(velocity(&test, boar).x > 0.0);
+    }
+
+    // A boar that stops idling stops breathing, but a tween put over the breathing by another effect stays
+    #[test]
+    fn idle_bob_leaves_other_tweens_alone() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(boar_idle_bob));
+	let boar = spawn_boar(&mut test, Vec2::ZERO, 1, BoarState::Idle, Vec2::ZERO);
+	let tag = |test: &TestApp| test.app.world.get::<Tween>(boar).map(|tween| tween.tag);
+
+	test.step_fixed(1);
+	assert_eq!(tag(&test), Some(Some(TweenTag::IdleBob)));
+	test.app.world.get_mut::<BoarAi>(boar).unwrap().switch_to(BoarState::Wander);
+	test.step_fixed(1);
+	assert_eq!(tag(&test), None);
+	assert!(test.app.world.get::<IdleBob>(boar).is_none());
+
+	test.app.world.get_mut::<BoarAi>(boar).unwrap().switch_to(BoarState::Idle);
+	test.step_fixed(1);
+	test.app.world.entity_mut(boar).insert(Tween::color(Color::RED, Color::WHITE));
+	test.app.world.get_mut::<BoarAi>(boar).unwrap().switch_to(BoarState::Wander);
+	test.step_fixed(1);
+	assert_eq!(tag(&test), Some(None));
+	assert!(test.app.world.get::<IdleBob>(boar).is_none());
+    }
+
+    // The end of the eating takes its squash away, not a tween put over it by another effect
+    #[test]
+    fn finished_eating_leaves_other_tweens_alone() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(finish_eating));
+	let player = test.spawn_player(Vec2::ZERO);
+	test.app.world.entity_mut(player).insert((
+	    Eating(Timer::from_seconds(EATING_TIME, TimerMode::Once)),
+	    Tween::color(Color::RED, Color::WHITE),
+	));
+
+	test.step_fixed(test.ticks(EATING_TIME));
+	assert!(test.app.world.get::<Eating>(player).is_none());
+	assert!(test.app.world.get::<Tween>(player).is_some());
+    }
+}
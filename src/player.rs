@@ -0,0 +1,941 @@
+// The player: movement, dash, sprint, animation, experience, abilities and lives
+
+use crate::*;
+
+pub const PLAYER_SPEED: f32 = 100.0;
+// Box of the player for the collisions and the walls, centered on the player
+pub const PLAYER_SIZE: Vec2 = Vec2::new(64.0, 64.0);
+// The colors the player sprite can be tinted, picked in the pause menu. The first one leaves it as drawn
+pub const PLAYER_TINTS: [(&str, Color); 5] = [
+    ("none", Color::WHITE),
+    ("red", Color::rgb(1.0, 0.6, 0.6)),
+    ("green", Color::rgb(0.6, 1.0, 0.6)),
+    ("blue", Color::rgb(0.6, 0.7, 1.0)),
+    ("gold", Color::rgb(1.0, 0.85, 0.4)),
+];
+// The player flashes this color when hurt, and goes back to the tint
+pub const PLAYER_HURT_COLOR: Color = Color::rgb(1.0, 0.2, 0.2);
+pub const PLAYER_HURT_FLASH_TIME: f32 = 0.3;
+// The game is over once the player died this many times. Otherwise the player comes back in front of the house,
+// and cannot be hurt for a little while, blinking
+pub const PLAYER_LIVES: u32 = 3;
+pub const RESPAWN_OFFSET: Vec2 = Vec2::new(0.0, -64.0);
+pub const RESPAWN_INVULNERABLE_TIME: f32 = 2.0;
+pub const INVULNERABLE_BLINK_PERIOD: f32 = 0.15;
+pub const LIFE_ICON_SIZE: f32 = 24.0;
+
+// Sprint settings
+pub const SPRINT_KEY: KeyCode = KeyCode::LShift;
+pub const SPRINT_MULTIPLIER: f32 = 1.6;
+
+// Footstep settings: the interval is at walking speed, it gets shorter when going faster
+pub const FOOTSTEP_INTERVAL: f32 = 0.35;
+pub const FOOTSTEP_SAMPLES: usize = 3;
+
+// Player animation settings: the sheet (in the prefab) has a row per direction (down, left, right, up),
+// the first frame of a row is standing still and the others are the walk cycle.
+// Without the sheet the player is a still sprite
+// At walking speed, faster movements animate faster
+pub const ANIMATION_FPS: f32 = 8.0;
+
+// Dash settings
+pub const DASH_KEY: KeyCode = KeyCode::Q;
+pub const DASH_SPEED: f32 = 450.0;
+pub const DASH_TIME: f32 = 0.25;
+pub const DASH_COOLDOWN: f32 = 1.0;
+pub const AFTERIMAGE_ALPHA: f32 = 0.4;
+pub const AFTERIMAGE_TIME: f32 = 0.3;
+
+// Added by each upgrade bought at the house, the speed as a part of the normal one
+pub const HEALTH_UPGRADE: f32 = 20.0;
+pub const SPEED_UPGRADE: f32 = 0.1;
+
+// Experience settings: what each boar is worth is in the loot file, the levels are in the progression file
+// (in the assets folder). The experience bar is right above the hotbar
+pub const PROGRESSION_FILE: &str = "config/progression.ron";
+pub const XP_BAR_SIZE: Vec2 = Vec2::new(200.0, 6.0);
+pub const XP_BAR_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+pub const XP_BAR_COLOR: Color = Color::rgb(0.4, 0.8, 1.0);
+pub const LEVEL_UP_SPARKLE_COUNT: usize = 40;
+
+// The lives left in the current game, the one being played included
+#[derive(Resource)]
+pub struct Lives(pub u32);
+
+impl Default for Lives {
+    fn default() -> Lives {
+	Lives(PLAYER_LIVES)
+    }
+}
+
+// Components for the characters
+#[derive(Component)]
+pub struct Player;
+
+// Whether the sprint is on, from the sprint key (held or toggled)
+#[derive(Component, Default)]
+pub struct Sprinting(pub bool);
+
+// The player either walks normally or is in the middle of a dash
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerState {
+    Walking,
+    Dashing,
+}
+
+#[derive(Component)]
+pub struct Dash {
+    pub direction: Vec2,
+    pub timer: Timer,
+    pub cooldown: Timer,
+}
+
+// Frame of the sprite sheet shown, the sheet is split by the texture atlas
+#[derive(Component)]
+pub struct AnimationState {
+    pub row: usize,
+    pub frame: usize,
+    pub timer: Timer,
+}
+
+impl AnimationState {
+    pub fn new() -> AnimationState {
+	AnimationState {
+	    row: 0,
+	    frame: 0,
+	    timer: Timer::from_seconds(1.0 / ANIMATION_FPS, TimerMode::Repeating),
+	}
+    }
+}
+
+// The frames of an animated sprite, as many frames in a row as there are columns
+#[derive(Component, Clone)]
+pub struct SpriteSheet {
+    pub atlas: Handle<TextureAtlas>,
+    pub columns: usize,
+}
+
+// Counts the time until the next footstep, only while the player really moves
+#[derive(Component)]
+pub struct Footsteps {
+    pub timer: Timer,
+    pub last_position: Vec2,
+    pub next_sample: usize,
+}
+
+impl Footsteps {
+    pub fn new(position: Vec2) -> Footsteps {
+	Footsteps {
+	    timer: Timer::from_seconds(FOOTSTEP_INTERVAL, TimerMode::Repeating),
+	    last_position: position,
+	    next_sample: 0,
+	}
+    }
+}
+
+impl Dash {
+    pub fn new() -> Dash {
+	let mut cooldown = Timer::from_seconds(DASH_COOLDOWN, TimerMode::Once);
+	// The dash is available from the start
+	cooldown.tick(Duration::from_secs_f32(DASH_COOLDOWN));
+	Dash {
+	    direction: Vec2::ZERO,
+	    timer: Timer::from_seconds(DASH_TIME, TimerMode::Once),
+	    cooldown,
+	}
+    }
+}
+
+// How fast a character walks, before the upgrades and the sprint
+#[derive(Component)]
+pub struct Speed(pub f32);
+
+// The experience of the player, from the defeated boars. It starts over at each level
+#[derive(Component, Clone, Copy, Serialize, Deserialize, Debug)]
+pub struct Experience {
+    pub current: u32,
+    pub level: u32,
+}
+
+impl Default for Experience {
+    fn default() -> Experience {
+	Experience { current: 0, level: 1 }
+    }
+}
+
+impl Experience {
+    // Adds the experience, and returns the number of levels it gains. Each threshold crossed is a level
+    pub fn gain(&mut self, amount: u32, progression: &Progression) -> u32 {
+	self.current += amount;
+	let mut levels = 0;
+	while self.current >= progression.needed(self.level) {
+	    self.current -= progression.needed(self.level);
+	    self.level += 1;
+	    levels += 1;
+	}
+	levels
+    }
+}
+
+// Experience given to the player, for a defeated boar
+pub struct ExperienceGained(pub u32);
+
+// Where a character looks: the main direction of its movement, diagonals count as horizontal.
+// It stays the same when the character stops
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Facing {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Facing {
+    pub fn from_movement(movement: Vec2) -> Option<Facing> {
+	if movement == Vec2::ZERO {
+	    None
+	} else if movement.x.abs() >= movement.y.abs() {
+	    Some(if movement.x < 0.0 { Facing::Left } else { Facing::Right })
+	} else {
+	    Some(if movement.y < 0.0 { Facing::Down } else { Facing::Up })
+	}
+    }
+
+    pub fn direction(&self) -> Vec2 {
+	match self {
+	    Facing::Up => Vec2::Y,
+	    Facing::Down => Vec2::NEG_Y,
+	    Facing::Left => Vec2::NEG_X,
+	    Facing::Right => Vec2::X,
+	}
+    }
+}
+
+// The player is not hurt until the timer runs out, after coming back from a death
+#[derive(Component)]
+pub struct Invulnerable(pub Timer);
+
+// Size of the box of the player, for the collisions with the obstacles and the boars, and the walls around the map
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlayerSize(pub Vec2);
+
+// The color the player sprite is tinted, the hurt flash goes back to it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlayerTint(pub Color);
+
+// The player was just hurt, the sprite fades from the hurt color to the tint
+#[derive(Component)]
+pub struct HurtFlash(pub Timer);
+
+// How the player grows with the levels
+#[derive(Resource, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct Progression {
+    // Level n needs n times this experience to reach the next one
+    pub xp_per_level: u32,
+    // Added to the maximum health and the walking speed at each level
+    pub health_per_level: f32,
+    pub speed_per_level: f32,
+    // The sprint is unlocked once at this level
+    pub sprint_level: u32,
+}
+
+impl Default for Progression {
+    fn default() -> Progression {
+	Progression {
+	    xp_per_level: 50,
+	    health_per_level: 5.0,
+	    speed_per_level: 5.0,
+	    sprint_level: 2,
+	}
+    }
+}
+
+impl Progression {
+    // Reads the progression from the assets folder, the default one is used if it cannot be read
+    pub fn load() -> Progression {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(PROGRESSION_FILE);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the progression {}: {error}", full_path.display());
+		return Progression::default();
+	    },
+	};
+	match ron::from_str(&contents) {
+	    Ok(progression) => progression,
+	    Err(error) => {
+		warn!("Could not parse the progression {}: {error}", full_path.display());
+		Progression::default()
+	    },
+	}
+    }
+
+    // Experience needed to go from the level to the next
+    pub fn needed(&self, level: u32) -> u32 {
+	self.xp_per_level.max(1) * level
+    }
+
+    // What the levels above the first add to the player
+    pub fn health_bonus(&self, level: u32) -> f32 {
+	level.saturating_sub(1) as f32 * self.health_per_level
+    }
+
+    pub fn speed_bonus(&self, level: u32) -> f32 {
+	level.saturating_sub(1) as f32 * self.speed_per_level
+    }
+}
+
+// What the player starts a game with, the scenarios can change it
+#[derive(Resource, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct PlayerLoadout {
+    // The maximum health at the first level, the player starts with it full
+    pub health: f32,
+    pub items: Vec<(ItemKind, u32)>,
+    // Whether the player can attack, and throw things
+    pub melee: bool,
+    pub ranged: bool,
+}
+
+impl Default for PlayerLoadout {
+    fn default() -> PlayerLoadout {
+	PlayerLoadout {
+	    health: PLAYER_HEALTH,
+	    items: Vec::new(),
+	    melee: true,
+	    ranged: true,
+	}
+    }
+}
+
+impl PlayerLoadout {
+    // Reads the loadout from the assets folder, the default one is used if it cannot be read.
+    // The values that make no sense are replaced by the default ones
+    pub fn load() -> PlayerLoadout {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(LOADOUT_FILE);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the loadout {}: {error}", full_path.display());
+		return PlayerLoadout::default();
+	    },
+	};
+	match ron::from_str::<PlayerLoadout>(&contents) {
+	    Ok(loadout) => loadout.validated(),
+	    Err(error) => {
+		warn!("Could not parse the loadout {}: {error}", full_path.display());
+		PlayerLoadout::default()
+	    },
+	}
+    }
+
+    pub fn validated(mut self) -> PlayerLoadout {
+	if !self.health.is_finite() || self.health <= 0.0 {
+	    warn!("The health of the loadout must be above 0, not {}", self.health);
+	    self.health = PLAYER_HEALTH;
+	}
+	self.items.retain(|(item, amount)| {
+	    if *amount == 0 {
+		warn!("The loadout has no {} in its items", item.name());
+	    }
+	    *amount > 0
+	});
+	self
+    }
+}
+
+// The improvements of the player sold at the house
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Upgrade {
+    MaxHealth,
+    Speed,
+}
+
+impl Upgrade {
+    pub fn name(&self) -> &'static str {
+	match self {
+	    Upgrade::MaxHealth => "More health",
+	    Upgrade::Speed => "Faster walk",
+	}
+    }
+
+    pub fn sprite(&self) -> &'static str {
+	match self {
+	    Upgrade::MaxHealth => "sprites/heart.png",
+	    Upgrade::Speed => "sprites/bolt.png",
+	}
+    }
+}
+
+// The upgrades bought by the player
+#[derive(Resource, Default)]
+pub struct Upgrades {
+    pub max_health: u32,
+    pub speed: u32,
+}
+
+impl Upgrades {
+    pub fn add(&mut self, upgrade: Upgrade) {
+	match upgrade {
+	    Upgrade::MaxHealth => self.max_health += 1,
+	    Upgrade::Speed => self.speed += 1,
+	}
+    }
+
+    pub fn speed_multiplier(&self) -> f32 {
+	1.0 + self.speed as f32 * SPEED_UPGRADE
+    }
+}
+
+// What the player learns along the way: the sprint comes with a level, the dash from the house
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Ability {
+    Sprint,
+    Dash,
+}
+
+impl Ability {
+    pub fn all() -> [Ability; 2] {
+	[Ability::Sprint, Ability::Dash]
+    }
+
+    pub fn name(&self) -> &'static str {
+	match self {
+	    Ability::Sprint => "Sprint",
+	    Ability::Dash => "Dash",
+	}
+    }
+}
+
+// The abilities unlocked by the player
+#[derive(Resource, Default)]
+pub struct Abilities {
+    pub unlocked: HashSet<Ability>,
+}
+
+impl Abilities {
+    pub fn has(&self, ability: Ability) -> bool {
+	self.unlocked.contains(&ability)
+    }
+}
+
+// System that gives the player the health bought at the house, the added health comes full.
+// The levels add to the maximum too, only the upgrades bought since the last time are added
+pub fn apply_health_upgrades(
+    upgrades: Res<Upgrades>,
+    mut applied: Local<u32>,
+    mut query_player: Query<&mut HealthPoints, With<Player>>,
+) {
+    if !upgrades.is_changed() {
+	return;
+    }
+    let Ok(mut health) = query_player.get_single_mut() else {
+	return;
+    };
+    let gained = upgrades.max_health.saturating_sub(*applied) as f32 * HEALTH_UPGRADE;
+    *applied = upgrades.max_health;
+    if gained > 0.0 {
+	health.max += gained;
+	health.heal(gained);
+    }
+}
+
+// System that gives the experience of the defeated boars to the player, it is saved right away.
+// Each level gained heals the player fully, and makes them sturdier and faster
+pub fn gain_experience(
+    mut commands: Commands,
+    progression: Res<Progression>,
+    mut particles: ResMut<Particles>,
+    mut save: ResMut<SaveGame>,
+    mut toasts: EventWriter<Toast>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut experience_events: EventReader<ExperienceGained>,
+    mut query_player: Query<(&mut Experience, &mut HealthPoints, &mut Speed, &Transform), With<Player>>,
+) {
+    let amount: u32 = experience_events.iter().map(|ExperienceGained(amount)| amount).sum();
+    if amount == 0 {
+	return;
+    }
+    let Ok((mut experience, mut health, mut speed, transform)) = query_player.get_single_mut() else {
+	return;
+    };
+    let levels = experience.gain(amount, &progression);
+    if levels > 0 {
+	health.max += levels as f32 * progression.health_per_level;
+	health.current = health.max;
+	speed.0 += levels as f32 * progression.speed_per_level;
+	spawn_burst(&mut commands, &mut particles, ParticleKind::Sparkle,
+		    transform.translation.truncate().extend(IMPACT_Z), LEVEL_UP_SPARKLE_COUNT);
+	sfx_events.send(PlaySfx(Sfx::LevelUp));
+	toasts.send(Toast(format!("Level up! Level {}", experience.level)));
+    }
+    save.experience = *experience;
+    save.save();
+}
+
+pub fn restore_abilities(save: Res<SaveGame>, mut abilities: ResMut<Abilities>) {
+    abilities.unlocked = save.abilities.clone();
+}
+
+// System that unlocks the abilities: the sprint at its level, the others from the dialogues.
+// They are saved right away
+pub fn grant_abilities(
+    keyboard_input: Res<Input<KeyCode>>,
+    debug: Option<Res<DebugView>>,
+    progression: Res<Progression>,
+    mut abilities: ResMut<Abilities>,
+    mut save: ResMut<SaveGame>,
+    mut toasts: EventWriter<Toast>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut dialogue_actions: EventReader<DialogueAction>,
+    query_player: Query<&Experience, (With<Player>, Changed<Experience>)>,
+) {
+    let mut granted = Vec::new();
+    for action in dialogue_actions.iter() {
+	if let DialogueAction::GrantAbility(ability) = action {
+	    granted.push(*ability);
+	}
+    }
+    if let Ok(experience) = query_player.get_single() {
+	if experience.level >= progression.sprint_level {
+	    granted.push(Ability::Sprint);
+	}
+    }
+    if debug.is_some() && keyboard_input.just_pressed(GRANT_ABILITIES_KEY) {
+	granted.extend(Ability::all());
+    }
+
+    let mut unlocked = false;
+    for ability in granted {
+	if abilities.unlocked.insert(ability) {
+	    toasts.send(Toast(format!("New ability: {}", ability.name())));
+	    unlocked = true;
+	}
+    }
+    if unlocked {
+	sfx_events.send(PlaySfx(Sfx::Unlock));
+	save.abilities = abilities.unlocked.clone();
+	save.save();
+    }
+}
+
+// System that gives the player sprite its tint, when spawned or changed. A hurt flash ends on it by itself
+pub fn apply_player_tint(mut query_player: Query<(&PlayerTint, &mut Sprite), (Changed<PlayerTint>, Without<HurtFlash>)>) {
+    for (tint, mut sprite) in &mut query_player {
+	sprite.color = tint.0;
+    }
+}
+
+// System that flashes the player when hurt, the sprite fades back to the tint
+pub fn flash_hurt_player(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut query_player: Query<(Entity, &PlayerTint, &mut Sprite, Option<&mut HurtFlash>)>,
+    query_invulnerable: Query<(), With<Invulnerable>>,
+) {
+    for event in damage_events.iter() {
+	if query_player.contains(event.target) && !query_invulnerable.contains(event.target) {
+	    commands.entity(event.target).insert(HurtFlash(Timer::from_seconds(PLAYER_HURT_FLASH_TIME, TimerMode::Once)));
+	}
+    }
+    for (player, tint, mut sprite, flash) in &mut query_player {
+	let Some(mut flash) = flash else {
+	    continue;
+	};
+	let progress = flash.0.tick(time.delta()).percent();
+	let color = Vec4::from(PLAYER_HURT_COLOR.as_rgba_f32()).lerp(Vec4::from(tint.0.as_rgba_f32()), progress);
+	sprite.color = Color::rgba(color.x, color.y, color.z, color.w);
+	if flash.0.finished() {
+	    commands.entity(player).remove::<HurtFlash>();
+	}
+    }
+}
+
+// System that reads the sprint key every frame, a quick press could fall between two fixed updates
+pub fn update_sprint(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    accessibility: Res<Accessibility>,
+    abilities: Res<Abilities>,
+    mut query_player: Query<&mut Sprinting, With<Player>>,
+) {
+    let mut sprinting = query_player.single_mut();
+    if !abilities.has(Ability::Sprint) {
+	sprinting.0 = false;
+    } else if !accessibility.toggle_sprint {
+	sprinting.0 = bindings.pressed(&keyboard_input, Action::Sprint);
+    } else if bindings.just_pressed(&keyboard_input, Action::Sprint) {
+	sprinting.0 = !sprinting.0;
+    }
+}
+
+pub fn move_player(
+    simulation: Res<Simulation>,
+    play_area: Res<PlayArea>,
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    accessibility: Res<Accessibility>,
+    upgrades: Res<Upgrades>,
+    abilities: Res<Abilities>,
+    hit_stop: Res<HitStop>,
+    gamepads: Res<Gamepads>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut query_player: Query<(&mut Transform, &mut Velocity, &mut PlayerState, &mut Dash, &Sprinting, &Speed, &PlayerSize), With<Player>>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    let (mut player_transform, mut velocity, mut player_state, mut dash, sprinting, speed, player_size) = query_player.single_mut();
+    let mut x_direction = 0.0;
+    let mut y_direction = 0.0;
+    
+    if bindings.pressed(&keyboard_input, Action::MoveLeft) {
+	x_direction -= 1.0;
+    }
+    if bindings.pressed(&keyboard_input, Action::MoveRight) {
+	x_direction += 1.0;
+    }
+    if bindings.pressed(&keyboard_input, Action::MoveUp) {
+	y_direction += 1.0;
+    }
+    if bindings.pressed(&keyboard_input, Action::MoveDown) {
+	y_direction -= 1.0;
+    }
+    // The left stick of a gamepad walks too, slower when only pushed a little
+    for gamepad in gamepads.iter() {
+	x_direction += gamepad_axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.0);
+	y_direction += gamepad_axes.get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.0);
+    }
+    if accessibility.invert_x {
+	x_direction = -x_direction;
+    }
+    if accessibility.invert_y {
+	y_direction = -y_direction;
+    }
+    // Walking diagonally is not faster: two keys, or a stick and a key, are brought back to the length of one
+    let direction = Vec2::new(x_direction, y_direction).clamp_length_max(1.0);
+
+    // A dash goes straight in the direction the player was moving when it started
+    let tick = Duration::from_secs_f32(simulation.dt());
+    dash.cooldown.tick(tick);
+    if *player_state == PlayerState::Walking
+	&& bindings.pressed(&keyboard_input, Action::Dash)
+	&& abilities.has(Ability::Dash)
+	&& dash.cooldown.finished()
+	&& direction != Vec2::ZERO {
+	*player_state = PlayerState::Dashing;
+	dash.direction = direction.normalize();
+	dash.timer.reset();
+	dash.cooldown.reset();
+    }
+
+    velocity.0 = match *player_state {
+	PlayerState::Walking if sprinting.0 => direction * speed.0 * upgrades.speed_multiplier() * SPRINT_MULTIPLIER,
+	PlayerState::Walking => direction * speed.0 * upgrades.speed_multiplier(),
+	PlayerState::Dashing => {
+	    dash.timer.tick(tick);
+	    if dash.timer.finished() {
+		*player_state = PlayerState::Walking;
+	    }
+	    dash.direction * DASH_SPEED
+	},
+    };
+
+    // Compute the new coordinates of Player
+    let new_transform_x = player_transform.translation.x + velocity.0.x * simulation.dt();
+    let new_transform_y = player_transform.translation.y + velocity.0.y * simulation.dt();
+
+    // Bounds ensure that the box of the player never goes into the walls around the map
+    let bounds = play_area.bounds(player_size.0);
+
+    // Apply the translation
+    player_transform.translation.x = new_transform_x.clamp(bounds.min.x, bounds.max.x);
+    player_transform.translation.y = new_transform_y.clamp(bounds.min.y, bounds.max.y);
+}
+
+// System that leaves faint copies of the player behind while dashing
+pub fn spawn_afterimages(
+    mut commands: Commands,
+    monitor: Res<PerformanceMonitor>,
+    query_player: Query<(&Transform, &Handle<Image>, &Sprite, &PlayerState), With<Player>>,
+) {
+    let (player_transform, player_texture, player_sprite, player_state) = query_player.single();
+    // Afterimages are only decoration, they are skipped when effects are reduced
+    if *player_state != PlayerState::Dashing || monitor.quality == EffectsQuality::Low {
+	return;
+    }
+
+    // No collider: the copies do not interact with anything
+    commands.spawn((
+	SpriteBundle {
+	    texture: player_texture.clone(),
+	    sprite: Sprite {
+		color: Color::rgba(1.0, 1.0, 1.0, AFTERIMAGE_ALPHA),
+		// The frame the player is at, with a sprite sheet
+		rect: player_sprite.rect,
+		..default()
+	    },
+	    // Just below the player
+	    transform: player_transform.with_translation(player_transform.translation - Vec3::Z * 0.01),
+	    ..default()
+	},
+	Tween::alpha(AFTERIMAGE_ALPHA, 0.0).over(AFTERIMAGE_TIME).despawn_on_complete(),
+    ));
+}
+
+// System that plays a footstep at regular intervals while the player moves,
+// the first step of a movement is played right away
+pub fn footsteps(
+    simulation: Res<Simulation>,
+    chunks: Res<ChunkIndex>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut query_player: Query<(&Transform, &mut Footsteps), With<Player>>,
+) {
+    let (player_transform, mut footsteps) = query_player.single_mut();
+    let position = player_transform.translation.truncate();
+    let distance = position.distance(footsteps.last_position);
+    footsteps.last_position = position;
+
+    // Standing still, or walking into a wall
+    if distance == 0.0 {
+	let interval = footsteps.timer.duration();
+	footsteps.timer.set_elapsed(interval);
+	return;
+    }
+
+    if footsteps.timer.elapsed() >= footsteps.timer.duration() {
+	footsteps.timer.reset();
+    } else {
+	// Faster than walking (sprint, dash) means shorter steps
+	let speed_ratio = (distance / simulation.dt() / PLAYER_SPEED).max(1.0);
+	footsteps.timer.tick(Duration::from_secs_f32(simulation.dt() * speed_ratio));
+	if !footsteps.timer.just_finished() {
+	    return;
+	}
+    }
+
+    let terrain = chunks.terrain_at(position);
+    sfx_events.send(PlaySfx(Sfx::Step(terrain, footsteps.next_sample)));
+    footsteps.next_sample = (footsteps.next_sample + 1) % FOOTSTEP_SAMPLES;
+}
+
+// System that turns the NPCs toward where they go, the ones being pushed around keep their facing
+pub fn update_facing(
+    mut query_character: Query<(&Velocity, &mut Facing), (Without<Player>, Without<Knockback>, Without<Thrown>, Without<Carried>)>,
+) {
+    for (velocity, mut facing) in &mut query_character {
+	turn_toward_movement(velocity, &mut facing);
+    }
+}
+
+// System that turns the player toward where it goes. It is apart from the NPCs so that the attacks
+// can use the new facing without waiting for the NPCs, which themselves react to the attacks
+pub fn update_player_facing(
+    mut query_player: Query<(&Velocity, &mut Facing), (With<Player>, Without<Knockback>)>,
+) {
+    for (velocity, mut facing) in &mut query_player {
+	turn_toward_movement(velocity, &mut facing);
+    }
+}
+
+fn turn_toward_movement(velocity: &Velocity, facing: &mut Mut<Facing>) {
+    if let Some(new_facing) = Facing::from_movement(velocity.0) {
+	if **facing != new_facing {
+	    **facing = new_facing;
+	}
+    }
+}
+
+// System that plays the walk cycle of the player in the direction it faces,
+// and shows the standing frame when the player stops
+pub fn animate_player(
+    simulation: Res<Simulation>,
+    atlases: Res<Assets<TextureAtlas>>,
+    hit_stop: Res<HitStop>,
+    mut query_player: Query<(&Velocity, &Facing, &SpriteSheet, &mut AnimationState, &mut Sprite), With<Player>>,
+) {
+    if hit_stop.active() {
+	return;
+    }
+    // A player without a sprite sheet is not animated
+    let Ok((velocity, facing, sheet, mut animation, mut sprite)) = query_player.get_single_mut() else {
+	return;
+    };
+    let Some(atlas) = atlases.get(&sheet.atlas) else {
+	return;
+    };
+
+    animation.row = match facing {
+	Facing::Down => 0,
+	Facing::Left => 1,
+	Facing::Right => 2,
+	Facing::Up => 3,
+    };
+    if velocity.0 == Vec2::ZERO {
+	animation.frame = 0;
+	animation.timer.reset();
+    } else {
+	// The walk starts right away
+	if animation.frame == 0 {
+	    animation.frame = 1;
+	}
+	let speed_ratio = velocity.0.length() / PLAYER_SPEED;
+	animation.timer.tick(Duration::from_secs_f32(simulation.dt() * speed_ratio));
+	for _ in 0..animation.timer.times_finished_this_tick() {
+	    animation.frame = animation.frame % (sheet.columns - 1) + 1;
+	}
+    }
+    sprite.rect = Some(atlas.textures[animation.row * sheet.columns + animation.frame]);
+}
+
+// System that takes a life from the player once dead. The player comes back in front of the house with full health,
+// unless it was the last life: the game is over
+pub fn lose_lives(
+    mut commands: Commands,
+    map: Res<MapData>,
+    mut lives: ResMut<Lives>,
+    mut state: ResMut<State<GameState>>,
+    mut query_player: Query<(Entity, &mut Transform, &mut Velocity, &mut HealthPoints, &mut Dash, &mut PlayerState), With<Player>>,
+) {
+    let (player, mut player_transform, mut velocity, mut health, mut dash, mut player_state) = query_player.single_mut();
+    if health.current > 0.0 {
+	return;
+    }
+    lives.0 = lives.0.saturating_sub(1);
+    if lives.0 == 0 {
+	// Tried again in the next frame if another state is on its way
+	let _ = state.push(GameState::GameOver);
+	return;
+    }
+    let house = map.spawn_points(SpawnKind::House, HOUSE_SPAWN)[0].position;
+    let position = house + RESPAWN_OFFSET;
+    player_transform.translation.x = position.x;
+    player_transform.translation.y = position.y;
+    velocity.0 = Vec2::ZERO;
+    health.current = health.max;
+    *dash = Dash::new();
+    *player_state = PlayerState::Walking;
+    commands.entity(player)
+	.remove::<Knockback>()
+	.remove::<HurtFlash>()
+	.insert(Invulnerable(Timer::from_seconds(RESPAWN_INVULNERABLE_TIME, TimerMode::Once)));
+}
+
+// System that blinks the player while it cannot be hurt
+pub fn blink_invulnerable(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query_player: Query<(Entity, &mut Invulnerable, &mut Visibility)>,
+) {
+    for (player, mut invulnerable, mut visibility) in &mut query_player {
+	if invulnerable.0.tick(time.delta()).finished() {
+	    visibility.is_visible = true;
+	    commands.entity(player).remove::<Invulnerable>();
+	} else {
+	    visibility.is_visible = (invulnerable.0.elapsed_secs() / INVULNERABLE_BLINK_PERIOD).fract() < 0.5;
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    // The player walking into the corners of the map stops at the walls
+    #[test]
+    fn player_is_clamped_at_the_walls() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(move_player));
+	let player = test.spawn_player(Vec2::ZERO);
+	let bounds = PlayArea::default().bounds(PLAYER_SIZE);
+	// Diagonally, each axis only gets its part of the speed
+	let across = test.ticks((RIGHT_WALL - LEFT_WALL) / PLAYER_SPEED * std::f32::consts::SQRT_2);
+
+	test.press(KeyCode::D);
+	test.press(KeyCode::W);
+	test.step_fixed(across);
+	assert_eq!(test.position(player), bounds.max);
+
+	test.release(KeyCode::D);
+	test.release(KeyCode::W);
+	test.press(KeyCode::A);
+	test.press(KeyCode::S);
+	test.step_fixed(across);
+	assert_eq!(test.position(player), bounds.min);
+    }
+
+    // With the horizontal axis inverted, D walks to the left and A to the right. The vertical axis is left alone
+    #[test]
+    fn invert_x_swaps_left_and_right() {
+	let mut test = TestApp::new();
+	test.app.world.resource_mut::<Accessibility>().invert_x = true;
+	test.add_fixed_systems(SystemSet::new().with_system(move_player));
+	let player = test.spawn_player(Vec2::ZERO);
+
+	test.press(KeyCode::D);
+	test.press(KeyCode::W);
+	test.step_fixed(test.ticks(0.5));
+	let position = test.position(player);
+	assert!(position.x < 0.0);
+	assert!(position.y > 0.0);
+
+	test.release(KeyCode::D);
+	test.release(KeyCode::W);
+	test.press(KeyCode::A);
+	test.step_fixed(test.ticks(1.0));
+	assert!(test.position(player).x > position.x);
+    }
+
+    // The left stick of a gamepad is inverted like the keys
+    #[test]
+    fn invert_y_flips_the_left_stick() {
+	let mut test = TestApp::new();
+	test.app.world.resource_mut::<Accessibility>().invert_y = true;
+	test.add_fixed_systems(SystemSet::new().with_system(move_player));
+	let player = test.spawn_player(Vec2::ZERO);
+
+	test.tilt_left_stick(Vec2::new(0.0, 1.0));
+	test.step_fixed(test.ticks(1.0));
+	let position = test.position(player);
+	assert_eq!(position.x, 0.0);
+	assert!((position.y + PLAYER_SPEED).abs() < 0.01);
+    }
+
+    // Walking diagonally, inverted or not, is as fast as walking straight
+    #[test]
+    fn diagonal_walking_is_not_faster() {
+	for invert in [false, true] {
+	    let mut test = TestApp::new();
+	    *test.app.world.resource_mut::<Accessibility>() = Accessibility { in_memory: true, invert_x: invert, invert_y: invert, ..default() };
+	    test.add_fixed_systems(SystemSet::new().with_system(move_player));
+	    let player = test.spawn_player(Vec2::ZERO);
+
+	    test.press(KeyCode::D);
+	    test.press(KeyCode::W);
+	    test.step_fixed(test.ticks(1.0));
+	    let position = test.position(player);
+	    assert!((position.length() - PLAYER_SPEED).abs() < 0.01, "inverted: {}", invert);
+	    assert!((position.x - position.y).abs() < 0.01, "inverted: {}", invert);
+	}
+    }
+
+    // The player walks as far in a second whatever the tick rate, the speeds are per second
+    #[test]
+    fn walking_speed_does_not_depend_on_the_tick_rate() {
+	let distance = |tick_rate| {
+	    let mut test = TestApp::new();
+	    test.app.insert_resource(Simulation { tick_rate });
+	    test.add_fixed_systems(SystemSet::new().with_system(move_player));
+	    let player = test.spawn_player(Vec2::ZERO);
+	    test.press(KeyCode::D);
+	    test.step_fixed(test.ticks(1.0));
+	    test.position(player).x
+	};
+	for tick_rate in [20.0, 30.0, 60.0] {
+	    assert!((distance(tick_rate) - PLAYER_SPEED).abs() < 0.01, "{} ticks per second", tick_rate);
+	}
+    }
+}
@@ -0,0 +1,518 @@
+// Objectives, quests, and the dialogues that lead to them
+
+use crate::*;
+
+// Interactions with the things next to the player (chests, ...)
+pub const INTERACT_KEY: KeyCode = KeyCode::Return;
+pub const INTERACT_RANGE: f32 = 40.0;
+// The quest of the house: Frank is driven off once his health is below the ratio
+pub const FRANK_DRIVEN_OFF_RATIO: f32 = 0.5;
+pub const FRANK_QUEST_REWARD: u32 = 25;
+// The spawn id of Frank in the map, it finds him in the tamed boars of the save
+pub const FRANK_ID: &str = "frank";
+
+// Something the player uses by standing next to it and pressing the interact key
+#[derive(Component)]
+pub struct Interactable;
+
+// Sent when the player uses an interactable
+pub struct Interacted(pub Entity);
+
+// What happens when an answer of a dialogue is picked, it is sent as an event too
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DialogueAction {
+    OpenShop,
+    AcceptQuest(QuestId),
+    GrantAbility(Ability),
+    Leave,
+}
+
+// The dialogue being shown: who talks, what is said, and the answers of the player
+#[derive(Resource, Default)]
+pub struct Dialogue {
+    pub speaker: String,
+    pub text: String,
+    pub choices: Vec<(String, DialogueAction)>,
+    pub cursor: usize,
+}
+
+// What the player has to do
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Objective {
+    VisitHouse,
+    DefeatBoars,
+    // Added once the boss shows up
+    DefeatBoss,
+}
+
+impl Objective {
+    pub fn description(&self) -> &'static str {
+	match self {
+	    Objective::VisitHouse => "Visit the house",
+	    Objective::DefeatBoars => "Defeat all the boars",
+	    Objective::DefeatBoss => "Defeat the boar boss",
+	}
+    }
+}
+
+#[derive(Resource)]
+pub struct ObjectiveTracker {
+    pub active: Vec<Objective>,
+    pub completed: Vec<Objective>,
+}
+
+impl Default for ObjectiveTracker {
+    fn default() -> ObjectiveTracker {
+	ObjectiveTracker {
+	    active: vec![Objective::VisitHouse, Objective::DefeatBoars],
+	    completed: Vec::new(),
+	}
+    }
+}
+
+impl ObjectiveTracker {
+    pub fn complete(&mut self, objective: Objective) {
+	if let Some(index) = self.active.iter().position(|active| *active == objective) {
+	    self.active.remove(index);
+	    self.completed.push(objective);
+	}
+    }
+}
+
+// The quests of the game
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum QuestId {
+    DealWithFrank,
+}
+
+// What completes a stage of a quest
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StageGoal {
+    // An answer picked in a dialogue
+    Dialogue(DialogueAction),
+    // Using the house
+    TalkToHouse,
+    // Hurting Frank until his health is below the ratio, killing him, or taming him
+    DriveOffFrank(f32),
+}
+
+impl StageGoal {
+    // Whether the goal can be met before the stages ahead of it are done, it stays met then
+    pub fn can_be_met_early(&self) -> bool {
+	matches!(self, StageGoal::DriveOffFrank(_))
+    }
+
+    pub fn is_met_by(&self, event: QuestEvent) -> bool {
+	match (*self, event) {
+	    (StageGoal::Dialogue(wanted), QuestEvent::Dialogue(action)) => wanted == action,
+	    (StageGoal::TalkToHouse, QuestEvent::TalkedToHouse) => true,
+	    (StageGoal::DriveOffFrank(ratio), QuestEvent::FrankHurt(health)) => health < ratio,
+	    (StageGoal::DriveOffFrank(_), QuestEvent::FrankTamed) => true,
+	    _ => false,
+	}
+    }
+}
+
+// What happened in the game that can complete a stage
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum QuestEvent {
+    Dialogue(DialogueAction),
+    TalkedToHouse,
+    // With the ratio of health Frank has left
+    FrankHurt(f32),
+    FrankTamed,
+}
+
+#[derive(Clone, Debug)]
+pub struct Stage {
+    pub description: &'static str,
+    pub goal: StageGoal,
+    pub done: bool,
+}
+
+// A quest, its stages are done in order. Some can be met early, they are skipped when their turn comes
+#[derive(Clone, Debug)]
+pub struct Quest {
+    pub id: QuestId,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub stages: Vec<Stage>,
+    // The first stage not done, the quest is complete past the last one
+    pub current: usize,
+    // Coins given when the quest is complete
+    pub reward: u32,
+}
+
+impl Quest {
+    pub fn new(id: QuestId) -> Quest {
+	let stage = |description, goal| Stage { description, goal, done: false };
+	match id {
+	    QuestId::DealWithFrank => Quest {
+		id,
+		title: "Deal with Frank",
+		description: "Frank, a boar of the meadow, keeps digging up the garden of the house. \
+			      The house asked you to drive him off, by force if need be: \
+			      a good scare should be enough for him to leave the vegetables alone. \
+			      He might also be won over with a few apples. \
+			      Go back to the house once it is done, there are coins in it for you.",
+		stages: vec![
+		    stage("Talk to the house", StageGoal::Dialogue(DialogueAction::AcceptQuest(id))),
+		    stage("Drive off or tame Frank the boar", StageGoal::DriveOffFrank(FRANK_DRIVEN_OFF_RATIO)),
+		    stage("Go back to the house", StageGoal::TalkToHouse),
+		],
+		current: 0,
+		reward: FRANK_QUEST_REWARD,
+	    },
+	}
+    }
+
+    pub fn is_complete(&self) -> bool {
+	self.current >= self.stages.len()
+    }
+
+    // Marks the stages met by the event as done: the current one, and the ones ahead that can be met early.
+    // Returns whether a stage was done
+    pub fn meet(&mut self, event: QuestEvent) -> bool {
+	let mut met = false;
+	for (index, stage) in self.stages.iter_mut().enumerate().skip(self.current) {
+	    if !stage.done && stage.goal.is_met_by(event) && (index == self.current || stage.goal.can_be_met_early()) {
+		stage.done = true;
+		met = true;
+	    }
+	}
+	self.update_current();
+	met
+    }
+
+    pub fn set_done(&mut self, done: &[bool]) {
+	for (stage, done) in self.stages.iter_mut().zip(done) {
+	    stage.done = *done;
+	}
+	self.update_current();
+    }
+
+    pub fn update_current(&mut self) {
+	self.current = self.stages.iter().position(|stage| !stage.done).unwrap_or(self.stages.len());
+    }
+}
+
+// All the quests, with how far the player is in each
+#[derive(Resource)]
+pub struct QuestLog {
+    pub quests: Vec<Quest>,
+}
+
+impl Default for QuestLog {
+    fn default() -> QuestLog {
+	QuestLog {
+	    quests: vec![Quest::new(QuestId::DealWithFrank)],
+	}
+    }
+}
+
+impl QuestLog {
+    pub fn get(&self, id: QuestId) -> &Quest {
+	self.quests.iter().find(|quest| quest.id == id).expect("every quest is in the log")
+    }
+
+    // The quests as listed on the quest log screen: the active ones, then the completed ones
+    pub fn listed(&self) -> Vec<&Quest> {
+	let active = self.quests.iter().filter(|quest| !quest.is_complete());
+	let completed = self.quests.iter().filter(|quest| quest.is_complete());
+	active.chain(completed).collect()
+    }
+}
+
+// System that checks off the objectives.
+// The tracker is only touched when an active objective is done, so that the quest log is not rebuilt every frame
+pub fn update_objectives(
+    location: Res<Location>,
+    mut tracker: ResMut<ObjectiveTracker>,
+    // The tamed boars are dealt with
+    query_boar: Query<(), (With<Boar>, With<Hostile>)>,
+    query_boss: Query<(), With<Boss>>,
+) {
+    let done = [
+	(Objective::VisitHouse, *location == Location::House),
+	(Objective::DefeatBoars, query_boar.is_empty()),
+	(Objective::DefeatBoss, query_boss.is_empty()),
+    ];
+    for (objective, done) in done {
+	if done && tracker.active.contains(&objective) {
+	    tracker.complete(objective);
+	}
+    }
+}
+
+// Startup system that brings back how far the quests went, from the save
+pub fn restore_quests(save: Res<SaveGame>, mut quests: ResMut<QuestLog>) {
+    for quest in &mut quests.quests {
+	if let Some(done) = save.quests.get(&quest.id) {
+	    quest.set_done(done);
+	}
+    }
+}
+
+// System that checks off the stages of the quests on what happens in the game.
+// The progress is saved, and the reward is given once a quest is complete
+pub fn advance_quests(
+    mut quests: ResMut<QuestLog>,
+    mut currency: ResMut<Currency>,
+    mut save: ResMut<SaveGame>,
+    mut toasts: EventWriter<Toast>,
+    mut dialogue_actions: EventReader<DialogueAction>,
+    mut interactions: EventReader<Interacted>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut deaths: EventReader<DeathEvent>,
+    mut tamings: EventReader<BoarTamed>,
+    query_house: Query<(), With<House>>,
+    query_frank: Query<&HealthPoints, With<Frank>>,
+) {
+    let mut events: Vec<QuestEvent> = dialogue_actions.iter().map(|action| QuestEvent::Dialogue(*action)).collect();
+    for Interacted(entity) in interactions.iter() {
+	if query_house.contains(*entity) {
+	    events.push(QuestEvent::TalkedToHouse);
+	}
+    }
+    for event in damage_events.iter() {
+	if let Ok(health) = query_frank.get(event.target) {
+	    events.push(QuestEvent::FrankHurt(health.current / health.max));
+	}
+    }
+    for DeathEvent(entity) in deaths.iter() {
+	if query_frank.contains(*entity) {
+	    events.push(QuestEvent::FrankHurt(0.0));
+	}
+    }
+    for BoarTamed(entity) in tamings.iter() {
+	if query_frank.contains(*entity) {
+	    events.push(QuestEvent::FrankTamed);
+	}
+    }
+    if events.is_empty() {
+	return;
+    }
+
+    for quest in &mut quests.quests {
+	if quest.is_complete() {
+	    continue;
+	}
+	let mut met = false;
+	for event in &events {
+	    met |= quest.meet(*event);
+	}
+	if !met {
+	    continue;
+	}
+	if quest.is_complete() {
+	    currency.coins += quest.reward;
+	    toasts.send(Toast(format!("Quest complete: {} (+{} coins)", quest.title, quest.reward)));
+	} else {
+	    toasts.send(Toast(format!("{}: {}", quest.title, quest.stages[quest.current].description)));
+	}
+	save.quests.insert(quest.id, quest.stages.iter().map(|stage| stage.done).collect());
+	save.save();
+    }
+}
+
+// System that rewrites the lines of the quest log when the objectives or the quests change,
+// the completed ones are checked and dimmed. The quests show their current stage
+pub fn update_quest_log(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    tracker: Res<ObjectiveTracker>,
+    quests: Res<QuestLog>,
+    query_list: Query<Entity, With<QuestLogList>>,
+) {
+    if !tracker.is_changed() && !quests.is_changed() {
+	return;
+    }
+    let list = query_list.single();
+    commands.entity(list).despawn_descendants();
+
+    let font = asset_server.load(FONT);
+    let active_quests = quests.quests.iter()
+	.filter(|quest| !quest.is_complete())
+	.map(|quest| (format!("{}: {}", quest.title, quest.stages[quest.current].description), '☐', Color::WHITE));
+    let active = tracker.active.iter().map(|objective| (objective.description().to_string(), '☐', Color::WHITE));
+    let completed_quests = quests.quests.iter()
+	.filter(|quest| quest.is_complete())
+	.map(|quest| (quest.title.to_string(), '✓', QUEST_LOG_DONE_COLOR));
+    let completed = tracker.completed.iter().map(|objective| (objective.description().to_string(), '✓', QUEST_LOG_DONE_COLOR));
+    commands.entity(list).with_children(|parent| {
+	for (line, mark, color) in active_quests.chain(active).chain(completed_quests).chain(completed) {
+	    parent.spawn(TextBundle::from_section(format!("{} {}", mark, line), TextStyle {
+		font: font.clone(),
+		font_size: 20.0,
+		color,
+	    }));
+	}
+    });
+}
+
+// System that uses the closest interactable next to the player
+pub fn interact(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    gamepads: Res<Gamepads>,
+    mut gamepad_input: ResMut<Input<GamepadButton>>,
+    mut interactions: EventWriter<Interacted>,
+    query_player: Query<&Transform, With<Player>>,
+    query_interactable: Query<(Entity, &Transform), With<Interactable>>,
+) {
+    let key = bindings.key(Action::Interact);
+    if !keyboard_input.just_pressed(key) && !consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::South) {
+	return;
+    }
+    let player_position = query_player.single().translation.truncate();
+    let closest = query_interactable.iter()
+	.map(|(entity, transform)| (entity, transform.translation.truncate().distance(player_position)))
+	.filter(|(_, distance)| *distance <= INTERACT_RANGE)
+	.min_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((entity, _)) = closest {
+	interactions.send(Interacted(entity));
+	// A screen opened by the interaction is updated again in this frame, the key must not close it right away
+	keyboard_input.reset(key);
+    }
+}
+
+// System that starts the dialogue of the house, its answers lead to the shop and to the quest of Frank
+pub fn talk_at_house(
+    mut interactions: EventReader<Interacted>,
+    quests: Res<QuestLog>,
+    abilities: Res<Abilities>,
+    save: Res<SaveGame>,
+    mut dialogue: ResMut<Dialogue>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_house: Query<Option<&DisplayName>, With<House>>,
+) {
+    for Interacted(entity) in interactions.iter() {
+	let Ok(name) = query_house.get(*entity) else {
+	    continue;
+	};
+	if state.push(GameState::Dialogue).is_err() {
+	    continue;
+	}
+	let quest = quests.get(QuestId::DealWithFrank);
+	let mut choices = Vec::new();
+	let ending = if save.tamed.contains(FRANK_ID) {
+	    "Frank follows you around now, and he leaves my garden alone!"
+	} else {
+	    "Frank is gone, my garden is safe at last!"
+	};
+	let text = if quest.is_complete() && !save.won {
+	    // The last words before the ending, it comes once the dialogue is over
+	    if !abilities.has(Ability::Dash) {
+		choices.push(("Show me".to_string(), DialogueAction::GrantAbility(Ability::Dash)));
+	    }
+	    format!("{ending} Thank you, neighbor. Let me teach you a trick: when a boar charges, dash out of its way.")
+	} else if quest.is_complete() && !abilities.has(Ability::Dash) {
+	    choices.push(("Show me".to_string(), DialogueAction::GrantAbility(Ability::Dash)));
+	    "Thank you for dealing with Frank! Let me teach you a trick: when a boar charges, dash out of its way.".to_string()
+	} else if quest.is_complete() {
+	    "Thanks again for dealing with Frank! Got any coins, or something from the boars?".to_string()
+	} else if !quest.stages[0].done {
+	    choices.push(("I will deal with Frank".to_string(), DialogueAction::AcceptQuest(quest.id)));
+	    "Welcome! Frank, that boar over there, keeps digging up my garden. Could you drive him off? Though he does love apples...".to_string()
+	} else {
+	    "Frank is still around. Give him a good scare, and come back to me!".to_string()
+	};
+	choices.push(("Let me see what you have".to_string(), DialogueAction::OpenShop));
+	choices.push(("Goodbye".to_string(), DialogueAction::Leave));
+	*dialogue = Dialogue {
+	    speaker: name.map_or("House", |name| name.0.as_str()).to_string(),
+	    text,
+	    choices,
+	    cursor: 0,
+	};
+	sfx_events.send(PlaySfx(Sfx::Click));
+    }
+}
+
+// System that starts the small talk of the villagers
+pub fn talk_to_villagers(
+    mut interactions: EventReader<Interacted>,
+    mut dialogue: ResMut<Dialogue>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_villager: Query<(Option<&DisplayName>, &VillagerAi), With<Villager>>,
+) {
+    for Interacted(entity) in interactions.iter() {
+	let Ok((name, villager_ai)) = query_villager.get(*entity) else {
+	    continue;
+	};
+	if state.push(GameState::Dialogue).is_err() {
+	    continue;
+	}
+	let text = if villager_ai.state == VillagerState::Flee {
+	    "A boar! Run, neighbor, the boars never come close to the houses!"
+	} else {
+	    "Nothing beats the water of the well. Mind the boars out there, though."
+	};
+	*dialogue = Dialogue {
+	    speaker: name.map_or("Villager", |name| name.0.as_str()).to_string(),
+	    text: text.to_string(),
+	    choices: vec![("Goodbye".to_string(), DialogueAction::Leave)],
+	    cursor: 0,
+	};
+	sfx_events.send(PlaySfx(Sfx::Click));
+    }
+}
+
+// System that shows the ending once the quest of the house is complete and its last dialogue is over.
+// It comes once: the save remembers it, whichever way Frank was dealt with
+pub fn reach_victory(
+    quests: Res<QuestLog>,
+    mut save: ResMut<SaveGame>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    // The push fails in the frame the last dialogue opens, the ending waits for it to close
+    if save.won || !quests.get(QuestId::DealWithFrank).is_complete() || state.push(GameState::Victory).is_err() {
+	return;
+    }
+    save.won = true;
+    save.save();
+    sfx_events.send(PlaySfx(Sfx::Achievement));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    #[derive(Resource, Default)]
+    struct TrackerChanges(u32);
+
+    fn count_tracker_changes(tracker: Res<ObjectiveTracker>, mut changes: ResMut<TrackerChanges>) {
+	if tracker.is_changed() {
+	    changes.0 += 1;
+	}
+    }
+
+    // The tracker only changes when an objective is completed, not every frame after that
+    #[test]
+    fn objectives_are_completed_once() {
+	let mut test = TestApp::new();
+	test.app.init_resource::<Location>()
+	    .init_resource::<ObjectiveTracker>()
+	    .init_resource::<TrackerChanges>();
+	test.add_fixed_systems(SystemSet::new()
+			       .with_system(update_objectives)
+			       .with_system(count_tracker_changes.after(update_objectives)));
+
+	// There are no boars left
+	test.step_fixed(1);
+	assert_eq!(test.app.world.resource::<ObjectiveTracker>().completed, vec![Objective::DefeatBoars]);
+	let changes = test.app.world.resource::<TrackerChanges>().0;
+
+	test.step_fixed(10);
+	assert_eq!(test.app.world.resource::<TrackerChanges>().0, changes);
+
+	*test.app.world.resource_mut::<Location>() = Location::House;
+	test.step_fixed(10);
+	assert_eq!(test.app.world.resource::<TrackerChanges>().0, changes + 1);
+	assert!(test.app.world.resource::<ObjectiveTracker>().active.is_empty());
+    }
+}
@@ -0,0 +1,374 @@
+// Recording the input of a game to play it again, to reproduce a bug: --record <file> writes it,
+// --replay <file> plays it back instead of the keyboard and the mouse. The game only plays the same way again
+// from the same seed, so it is written along with the input
+
+use crate::*;
+
+// The position of the player is written every 600 ticks, a replay that finds the player elsewhere has diverged
+pub const REPLAY_CHECKPOINT_TICKS: u64 = 600;
+pub const REPLAY_BADGE_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+
+// What happened on the keyboard, the mouse and the window during a frame, and how long the frame lasted.
+// Most frames only have their duration
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct RecordedFrame {
+    // In microseconds
+    pub dt: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<(KeyCode, ButtonState)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<(MouseButton, ButtonState)>,
+    // Where the cursor moved in the window, in physical pixels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<Vec2>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focused: Option<bool>,
+}
+
+// Where the player was at the start of a tick
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Checkpoint {
+    pub tick: u64,
+    pub position: Vec2,
+}
+
+// The frames of a game from the end of the loading, the gamepads are not recorded
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Recording {
+    pub seed: u64,
+    pub frames: Vec<RecordedFrame>,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+impl Recording {
+    pub fn load(path: &str) -> Result<Recording, String> {
+	let contents = fs::read_to_string(path).map_err(|error| format!("Could not read the recording {path}: {error}"))?;
+	ron::from_str(&contents).map_err(|error| format!("Could not parse the recording {path}: {error}"))
+    }
+
+    pub fn save(&self, path: &str) {
+	let contents = match ron::to_string(self) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not serialize the recording: {error}");
+		return;
+	    },
+	};
+	if let Err(error) = fs::write(path, contents) {
+	    warn!("Could not write the recording {path}: {error}");
+	}
+    }
+}
+
+// The time the game sees while it is recorded or replayed. It stands still during the loading, which does not
+// last the same, and then moves by the duration of each frame
+pub struct ReplayClock {
+    pub time: Instant,
+    pub last_frame: Instant,
+}
+
+impl ReplayClock {
+    pub fn new() -> ReplayClock {
+	let now = Instant::now();
+	ReplayClock { time: now, last_frame: now }
+    }
+
+    // How long the last frame really lasted, to the microsecond like in the recording
+    pub fn real_dt(&mut self) -> Duration {
+	let now = Instant::now();
+	let dt = Duration::from_micros((now - self.last_frame).as_micros() as u64);
+	self.last_frame = now;
+	dt
+    }
+}
+
+// Writes the input of the game to a file
+pub struct RecordPlugin {
+    pub path: String,
+    pub seed: u64,
+}
+
+impl Plugin for RecordPlugin {
+    fn build(&self, app: &mut App) {
+	info!("Recording the game to {}", self.path);
+	let clock = ReplayClock::new();
+	app.insert_resource(TimeUpdateStrategy::ManualInstant(clock.time))
+	    .insert_resource(Recorder {
+		path: self.path.clone(),
+		recording: Recording { seed: self.seed, ..default() },
+		clock,
+		pending_dt: None,
+		cursor: None,
+		ticks: 0,
+	    })
+	    .add_system_to_stage(CoreStage::PreUpdate, record_input)
+	    .add_system_set_to_stage(FixedUpdateStage, SystemSet::new()
+				     .with_run_criteria(while_playing)
+				     .with_system(record_checkpoint.before(PlayerTurn)))
+	    .add_system_to_stage(CoreStage::Last, advance_recording_clock)
+	    .add_system_to_stage(CoreStage::Last, save_recording_on_exit);
+    }
+}
+
+#[derive(Resource)]
+pub struct Recorder {
+    pub path: String,
+    pub recording: Recording,
+    pub clock: ReplayClock,
+    // The duration of the frame about to start, it is recorded with its input
+    pub pending_dt: Option<Duration>,
+    // The last position of the cursor written
+    pub cursor: Option<Vec2>,
+    pub ticks: u64,
+}
+
+// Plays a recording instead of the keyboard and the mouse, until its end
+pub struct ReplayPlugin {
+    pub recording: Recording,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+	info!("Replaying {} frames", self.recording.frames.len());
+	let clock = ReplayClock::new();
+	app.insert_resource(TimeUpdateStrategy::ManualInstant(clock.time))
+	    .insert_resource(Replay {
+		recording: self.recording.clone(),
+		clock,
+		frame: 0,
+		pending_frame: None,
+		cursor: None,
+		ticks: 0,
+		checkpoint: 0,
+	    })
+	    .add_startup_system(spawn_replay_badge)
+	    .add_system_to_stage(CoreStage::PreUpdate, replay_input.before(InputSystem))
+	    .add_system_set_to_stage(FixedUpdateStage, SystemSet::new()
+				     .with_run_criteria(while_playing)
+				     .with_system(check_replay_checkpoint.before(PlayerTurn)))
+	    .add_system_to_stage(CoreStage::Last, advance_replay_clock);
+    }
+}
+
+#[derive(Resource)]
+pub struct Replay {
+    pub recording: Recording,
+    pub clock: ReplayClock,
+    // The next frame of the recording
+    pub frame: usize,
+    // The frame about to start, its input is played once the devices are read
+    pub pending_frame: Option<usize>,
+    // Where the recording has left the cursor, it is put back there at each frame
+    pub cursor: Option<Vec2>,
+    pub ticks: u64,
+    // The next checkpoint of the recording
+    pub checkpoint: usize,
+}
+
+impl Replay {
+    pub fn is_over(&self) -> bool {
+	self.frame >= self.recording.frames.len()
+    }
+}
+
+// The badge in the corner of the screen while the game is replayed
+#[derive(Component)]
+pub struct ReplayBadge;
+
+pub fn spawn_replay_badge(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+	TextBundle {
+	    text: Text::from_section("REPLAY", TextStyle {
+		font: asset_server.load(FONT),
+		font_size: 24.0,
+		color: REPLAY_BADGE_COLOR,
+	    }),
+	    style: Style {
+		position_type: PositionType::Absolute,
+		position: UiRect {
+		    top: Val::Px(10.0),
+		    right: Val::Px(10.0),
+		    ..default()
+		},
+		..default()
+	    },
+	    z_index: ZIndex::Global(10),
+	    ..default()
+	},
+	ReplayBadge,
+    ));
+}
+
+// System that writes what happened on the devices during the frame, once the recording has started
+pub fn record_input(
+    windows: Res<Windows>,
+    mut recorder: ResMut<Recorder>,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut mouse_events: EventReader<MouseButtonInput>,
+    mut focus_events: EventReader<WindowFocused>,
+) {
+    let keys = keyboard_events.iter().filter_map(|event| event.key_code.map(|key| (key, event.state))).collect();
+    let buttons = mouse_events.iter().map(|event| (event.button, event.state)).collect();
+    let focused = focus_events.iter().last().map(|event| event.focused);
+    let Some(dt) = recorder.pending_dt.take() else {
+	return;
+    };
+    // The cursor leaving the window is not written, it stays where it was last seen
+    let cursor = windows.get_primary()
+	.and_then(|window| window.physical_cursor_position())
+	.map(|position| position.as_vec2())
+	.filter(|position| recorder.cursor != Some(*position));
+    if cursor.is_some() {
+	recorder.cursor = cursor;
+    }
+    recorder.recording.frames.push(RecordedFrame { dt: dt.as_micros() as u64, keys, buttons, cursor, focused });
+}
+
+// System that moves the time of the game by the duration of the frame that ended, once loaded
+pub fn advance_recording_clock(
+    state: Res<State<GameState>>,
+    mut recorder: ResMut<Recorder>,
+    mut time_update: ResMut<TimeUpdateStrategy>,
+) {
+    let dt = recorder.clock.real_dt();
+    if *state.current() == GameState::Loading {
+	return;
+    }
+    recorder.clock.time += dt;
+    recorder.pending_dt = Some(dt);
+    *time_update = TimeUpdateStrategy::ManualInstant(recorder.clock.time);
+}
+
+// System that writes where the player is every few ticks. The recording is saved then, a crash loses little of it
+pub fn record_checkpoint(mut recorder: ResMut<Recorder>, query_player: Query<&Transform, With<Player>>) {
+    let tick = recorder.ticks;
+    recorder.ticks += 1;
+    if tick == 0 || !tick.is_multiple_of(REPLAY_CHECKPOINT_TICKS) {
+	return;
+    }
+    let Ok(player_transform) = query_player.get_single() else {
+	return;
+    };
+    recorder.recording.checkpoints.push(Checkpoint { tick, position: player_transform.translation.truncate() });
+    recorder.recording.save(&recorder.path);
+}
+
+pub fn save_recording_on_exit(recorder: Res<Recorder>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.iter().next().is_some() {
+	recorder.recording.save(&recorder.path);
+	info!("Recorded {} frames to {}", recorder.recording.frames.len(), recorder.path);
+    }
+}
+
+// System that replaces what happened on the devices with the frame of the recording, before the input is read
+pub fn replay_input(
+    mut replay: ResMut<Replay>,
+    mut windows: ResMut<Windows>,
+    mut keyboard_events: ResMut<Events<KeyboardInput>>,
+    mut mouse_events: ResMut<Events<MouseButtonInput>>,
+    mut focus_events: ResMut<Events<WindowFocused>>,
+    mut gamepad_events: ResMut<Events<GamepadEventRaw>>,
+) {
+    if replay.is_over() && replay.pending_frame.is_none() {
+	return;
+    }
+    keyboard_events.clear();
+    mouse_events.clear();
+    focus_events.clear();
+    gamepad_events.clear();
+    if let Some(frame) = replay.pending_frame.take() {
+	let frame = &replay.recording.frames[frame];
+	keyboard_events.extend(frame.keys.iter().map(|(key, state)| KeyboardInput {
+	    scan_code: 0,
+	    key_code: Some(*key),
+	    state: *state,
+	}));
+	mouse_events.extend(frame.buttons.iter().map(|(button, state)| MouseButtonInput { button: *button, state: *state }));
+	if let (Some(focused), Some(window)) = (frame.focused, windows.get_primary()) {
+	    focus_events.send(WindowFocused { id: window.id(), focused });
+	}
+	if frame.cursor.is_some() {
+	    replay.cursor = frame.cursor;
+	}
+    }
+    if let (Some(cursor), Some(window)) = (replay.cursor, windows.get_primary_mut()) {
+	window.update_cursor_physical_position_from_backend(Some(cursor.as_dvec2()));
+    }
+}
+
+// System that moves the time of the game by the duration of the next frame of the recording, once loaded.
+// At the end of the recording, the player takes over
+pub fn advance_replay_clock(
+    mut commands: Commands,
+    state: Res<State<GameState>>,
+    mut replay: ResMut<Replay>,
+    mut time_update: ResMut<TimeUpdateStrategy>,
+    query_badge: Query<Entity, With<ReplayBadge>>,
+) {
+    let real_dt = replay.clock.real_dt();
+    if *state.current() == GameState::Loading {
+	return;
+    }
+    let dt = match replay.recording.frames.get(replay.frame).map(|frame| frame.dt) {
+	Some(dt) => {
+	    replay.pending_frame = Some(replay.frame);
+	    Duration::from_micros(dt)
+	},
+	None => real_dt,
+    };
+    if replay.frame == replay.recording.frames.len() {
+	info!("The replay is over, the keyboard and the mouse are back");
+	for badge in &query_badge {
+	    commands.entity(badge).despawn_recursive();
+	}
+    }
+    replay.frame += 1;
+    replay.clock.time += dt;
+    *time_update = TimeUpdateStrategy::ManualInstant(replay.clock.time);
+}
+
+// System that warns when the player is not where the recording had them, the replay has diverged from there
+pub fn check_replay_checkpoint(mut replay: ResMut<Replay>, query_player: Query<&Transform, With<Player>>) {
+    let tick = replay.ticks;
+    replay.ticks += 1;
+    let Some(checkpoint) = replay.recording.checkpoints.get(replay.checkpoint).copied() else {
+	return;
+    };
+    if checkpoint.tick != tick {
+	return;
+    }
+    replay.checkpoint += 1;
+    let Ok(player_transform) = query_player.get_single() else {
+	return;
+    };
+    let position = player_transform.translation.truncate();
+    if position != checkpoint.position {
+	warn!("The replay diverged by tick {tick}: the player is at {position} instead of {}", checkpoint.position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    // A frame without input only has its duration, and reads back the same
+    #[test]
+    fn recording_is_compact() {
+	let recording = Recording {
+	    seed: 7,
+	    frames: vec![
+		RecordedFrame { dt: 16667, ..default() },
+		RecordedFrame { dt: 16667, keys: vec![(KeyCode::D, ButtonState::Pressed)], ..default() },
+	    ],
+	    checkpoints: vec![Checkpoint { tick: 600, position: Vec2::new(12.0, -4.5) }],
+	};
+	let contents = ron::to_string(&recording).unwrap();
+	assert!(contents.contains("(dt:16667),"));
+
+	let read: Recording = ron::from_str(&contents).unwrap();
+	assert_eq!(read.seed, 7);
+	assert_eq!(read.frames[1].keys, recording.frames[1].keys);
+	assert_eq!(read.frames[0].cursor, None);
+	assert_eq!(read.checkpoints[0].position, Vec2::new(12.0, -4.5));
+    }
+}
@@ -0,0 +1,602 @@
+// What is kept next to the executable: the settings, the options, the lifetime stats and the saves
+
+use crate::*;
+
+// The game is saved next to the executable, in one of the slots. The first one is the save from before the slots
+pub const SAVE_FILES: [&str; 3] = ["save.ron", "save_2.ron", "save_3.ron"];
+// Next to the executable, the options are kept from one game to the next
+pub const ACCESSIBILITY_FILE: &str = "accessibility.ron";
+// The settings too, they are saved when they change
+pub const SETTINGS_FILE: &str = "settings.ron";
+// The lifetime stats, next to the executable too. They are saved when they change,
+// and regularly for the play time
+pub const STATS_FILE: &str = "stats.ron";
+pub const STATS_SAVE_INTERVAL: f32 = 30.0;
+// A world unit per pixel, the characters are about two meters tall
+pub const UNITS_PER_METER: f32 = 32.0;
+pub const WALKER_DISTANCE: f32 = 10_000.0;
+// A day lasts ten minutes of play, there is no day/night cycle to count them yet
+pub const DAY_LENGTH: f64 = 600.0;
+pub const SURVIVOR_DAY: u32 = 3;
+
+// What the player does with the keys that can be bound to other keys
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum Action {
+    MoveUp,
+    MoveLeft,
+    MoveDown,
+    MoveRight,
+    Attack,
+    Dash,
+    Sprint,
+    Grab,
+    Interact,
+    UseItem,
+    Throw,
+}
+
+impl Action {
+    pub fn all() -> [Action; 11] {
+	[Action::MoveUp, Action::MoveLeft, Action::MoveDown, Action::MoveRight, Action::Attack, Action::Dash,
+	 Action::Sprint, Action::Grab, Action::Interact, Action::UseItem, Action::Throw]
+    }
+
+    pub fn name(&self) -> &'static str {
+	match self {
+	    Action::MoveUp => "Move up",
+	    Action::MoveLeft => "Move left",
+	    Action::MoveDown => "Move down",
+	    Action::MoveRight => "Move right",
+	    Action::Attack => "Attack",
+	    Action::Dash => "Dash",
+	    Action::Sprint => "Sprint",
+	    Action::Grab => "Grab",
+	    Action::Interact => "Interact",
+	    Action::UseItem => "Use item",
+	    Action::Throw => "Throw",
+	}
+    }
+
+    pub fn default_key(&self) -> KeyCode {
+	match self {
+	    Action::MoveUp => KeyCode::W,
+	    Action::MoveLeft => KeyCode::A,
+	    Action::MoveDown => KeyCode::S,
+	    Action::MoveRight => KeyCode::D,
+	    Action::Attack => ATTACK_KEY,
+	    Action::Dash => DASH_KEY,
+	    Action::Sprint => SPRINT_KEY,
+	    Action::Grab => GRAB_KEY,
+	    Action::Interact => INTERACT_KEY,
+	    Action::UseItem => USE_ITEM_KEY,
+	    Action::Throw => THROW_KEY,
+	}
+    }
+}
+
+// The keys bound to the actions. An action missing from older settings keeps its default key
+#[derive(Resource, Clone, Default, Serialize, Deserialize, Debug)]
+pub struct KeyBindings {
+    pub keys: HashMap<Action, KeyCode>,
+}
+
+impl KeyBindings {
+    pub fn key(&self, action: Action) -> KeyCode {
+	self.keys.get(&action).copied().unwrap_or_else(|| action.default_key())
+    }
+
+    pub fn pressed(&self, keyboard_input: &Input<KeyCode>, action: Action) -> bool {
+	keyboard_input.pressed(self.key(action))
+    }
+
+    pub fn just_pressed(&self, keyboard_input: &Input<KeyCode>, action: Action) -> bool {
+	keyboard_input.just_pressed(self.key(action))
+    }
+
+    // The action the key is bound to already, other than the given one
+    pub fn conflict(&self, action: Action, key: KeyCode) -> Option<Action> {
+	Action::all().into_iter().find(|other| *other != action && self.key(*other) == key)
+    }
+
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+	self.keys.insert(action, key);
+    }
+}
+
+// Player preferences
+#[derive(Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    // Silences everything without stopping the sounds
+    pub muted: bool,
+    // Crisp pixel art: nearest neighbor sampling, and a camera on whole pixels
+    pub pixel_perfect: bool,
+    // Whether the game pauses when the window loses the focus
+    pub pause_on_focus_loss: bool,
+    // Width over height of the game view, the whole window is used without it
+    pub aspect_ratio: Option<f32>,
+    // How far the camera looks ahead of the moving player, 0 keeps it centered
+    pub look_ahead: f32,
+    // The tutorial is only shown until it is done once
+    pub tutorial_done: bool,
+    // Index of the color of the player in the palette
+    pub player_tint: usize,
+    // The save slot played in
+    pub save_slot: usize,
+    // The keys of the gameplay actions, changed from the controls screen
+    pub key_bindings: KeyBindings,
+    // The settings of a headless run or a test, they are never written
+    #[serde(skip)]
+    pub in_memory: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+	Settings {
+	    master_volume: 1.0,
+	    music_volume: 0.6,
+	    sfx_volume: 0.8,
+	    muted: false,
+	    pixel_perfect: true,
+	    pause_on_focus_loss: true,
+	    aspect_ratio: ASPECT_RATIOS[0],
+	    look_ahead: CAMERA_LOOK_AHEAD,
+	    tutorial_done: false,
+	    player_tint: 0,
+	    save_slot: 0,
+	    key_bindings: KeyBindings::default(),
+	    in_memory: false,
+	}
+    }
+}
+
+impl Settings {
+    // Reads the settings, or the defaults when they were never saved
+    pub fn load() -> Settings {
+	let full_path = FileAssetIo::get_base_path().join(SETTINGS_FILE);
+	let Ok(contents) = fs::read_to_string(&full_path) else {
+	    return Settings::default();
+	};
+	match ron::from_str(&contents) {
+	    Ok(settings) => settings,
+	    Err(error) => {
+		warn!("Could not parse the settings {}: {error}", full_path.display());
+		Settings::default()
+	    },
+	}
+    }
+
+    pub fn save(&self) {
+	if self.in_memory {
+	    return;
+	}
+	let full_path = FileAssetIo::get_base_path().join(SETTINGS_FILE);
+	let contents = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not serialize the settings: {error}");
+		return;
+	    },
+	};
+	if let Err(error) = fs::write(&full_path, contents) {
+	    warn!("Could not write the settings {}: {error}", full_path.display());
+	}
+    }
+
+    // A tint out of the palette, from older settings, leaves the player as drawn
+    pub fn player_tint(&self) -> Color {
+	PLAYER_TINTS.get(self.player_tint).unwrap_or(&PLAYER_TINTS[0]).1
+    }
+
+    pub fn volume(&self, channel: AudioChannel) -> f32 {
+	if self.muted {
+	    return 0.0;
+	}
+	let channel_volume = match channel {
+	    AudioChannel::Music => self.music_volume,
+	    AudioChannel::Sfx => self.sfx_volume,
+	};
+	self.master_volume * channel_volume
+    }
+
+    // Steps a volume up or down, it stays between silent and full
+    pub fn change_volume(volume: &mut f32, steps: f32) {
+	*volume = ((*volume / VOLUME_STEP).round() + steps).clamp(0.0, 1.0 / VOLUME_STEP) * VOLUME_STEP;
+    }
+}
+
+// Accessibility options, they do not change the difficulty
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Accessibility {
+    // No pulsing screen effects
+    pub reduced_motion: bool,
+    // The sprint key switches sprinting on and off instead of being held
+    pub toggle_sprint: bool,
+    // Colors that stay apart for the common kinds of colorblindness
+    pub colorblind: bool,
+    // Movement keys going the other way
+    pub invert_x: bool,
+    pub invert_y: bool,
+    // The attacks turn to the closest boar
+    pub aim_assist: bool,
+    // The options of a headless run or a test, they are never written
+    #[serde(skip)]
+    pub in_memory: bool,
+}
+
+impl Accessibility {
+    // Reads the options, or the defaults when they were never saved
+    pub fn load() -> Accessibility {
+	let full_path = FileAssetIo::get_base_path().join(ACCESSIBILITY_FILE);
+	let Ok(contents) = fs::read_to_string(&full_path) else {
+	    return Accessibility::default();
+	};
+	match ron::from_str(&contents) {
+	    Ok(accessibility) => accessibility,
+	    Err(error) => {
+		warn!("Could not parse the accessibility options {}: {error}", full_path.display());
+		Accessibility::default()
+	    },
+	}
+    }
+
+    pub fn save(&self) {
+	if self.in_memory {
+	    return;
+	}
+	let full_path = FileAssetIo::get_base_path().join(ACCESSIBILITY_FILE);
+	let contents = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not serialize the accessibility options: {error}");
+		return;
+	    },
+	};
+	if let Err(error) = fs::write(&full_path, contents) {
+	    warn!("Could not write the accessibility options {}: {error}", full_path.display());
+	}
+    }
+
+    // The colors showing health, from the colorblind palette when it is on
+    pub fn low_health_color(&self) -> Color {
+	if self.colorblind {
+	    VIGNETTE_COLORBLIND_COLOR
+	} else {
+	    VIGNETTE_COLOR
+	}
+    }
+
+    pub fn boss_bar_color(&self) -> Color {
+	if self.colorblind {
+	    BOSS_BAR_COLORBLIND_COLOR
+	} else {
+	    BOSS_BAR_COLOR
+	}
+    }
+}
+
+// Totals over all the games played
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub boars_killed: u32,
+    pub deaths: u32,
+    // In seconds, only while playing
+    pub play_time: f64,
+    // In seconds of play since the last death
+    pub alive_time: f64,
+    // In meters
+    pub distance_walked: f32,
+    pub achievements: HashSet<Achievement>,
+    // The stats of a headless run, they are never written
+    #[serde(skip)]
+    pub in_memory: bool,
+}
+
+// The time spent in the current game, only while playing. It starts again with every new game
+#[derive(Resource, Default)]
+pub struct GameClock {
+    // In seconds
+    pub elapsed: f64,
+}
+
+// What happened in the current game, shown at the end
+#[derive(Resource, Default)]
+pub struct RunStats {
+    pub damage_taken: f32,
+}
+
+// Milestones of the player, unlocked once for good
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Achievement {
+    FirstBoar,
+    TamedBoar,
+    Walker,
+    Survivor,
+    FirstDeath,
+    GoodNeighbor,
+}
+
+impl Achievement {
+    // In the order of the achievements screen
+    pub fn all() -> [Achievement; 6] {
+	[
+	    Achievement::FirstBoar,
+	    Achievement::TamedBoar,
+	    Achievement::GoodNeighbor,
+	    Achievement::Walker,
+	    Achievement::Survivor,
+	    Achievement::FirstDeath,
+	]
+    }
+
+    pub fn name(&self) -> &'static str {
+	match self {
+	    Achievement::FirstBoar => "First blood",
+	    Achievement::TamedBoar => "Boar whisperer",
+	    Achievement::Walker => "Long walk",
+	    Achievement::Survivor => "Survivor",
+	    Achievement::FirstDeath => "Back to the earth",
+	    Achievement::GoodNeighbor => "Good neighbor",
+	}
+    }
+
+    pub fn description(&self) -> String {
+	match self {
+	    Achievement::FirstBoar => "Defeat a boar".to_string(),
+	    Achievement::TamedBoar => "Tame a boar".to_string(),
+	    Achievement::Walker => format!("Walk {} km", WALKER_DISTANCE / 1000.0),
+	    Achievement::Survivor => format!("Survive to day {SURVIVOR_DAY}"),
+	    Achievement::FirstDeath => "Die for the first time".to_string(),
+	    Achievement::GoodNeighbor => "Complete a quest".to_string(),
+	}
+    }
+}
+
+impl Stats {
+    // Reads the stats, or starts from zero when there are none yet
+    pub fn load() -> Stats {
+	let full_path = FileAssetIo::get_base_path().join(STATS_FILE);
+	let Ok(contents) = fs::read_to_string(&full_path) else {
+	    return Stats::default();
+	};
+	match ron::from_str(&contents) {
+	    Ok(stats) => stats,
+	    Err(error) => {
+		warn!("Could not parse the stats {}: {error}", full_path.display());
+		Stats::default()
+	    },
+	}
+    }
+
+    pub fn save(&self) {
+	if self.in_memory {
+	    return;
+	}
+	let full_path = FileAssetIo::get_base_path().join(STATS_FILE);
+	let contents = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not serialize the stats: {error}");
+		return;
+	    },
+	};
+	if let Err(error) = fs::write(&full_path, contents) {
+	    warn!("Could not write the stats {}: {error}", full_path.display());
+	}
+    }
+}
+
+// What is kept from a game to the next
+#[derive(Resource, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveGame {
+    // The contents of the chests, by chest id
+    pub chests: HashMap<String, Vec<Option<ItemStack>>>,
+    // Which stages of the quests are done
+    pub quests: HashMap<QuestId, Vec<bool>>,
+    // The level of the player, and the experience toward the next one
+    pub experience: Experience,
+    pub abilities: HashSet<Ability>,
+    // The tamed boars, by spawn id
+    pub tamed: HashSet<String>,
+    // Whether the ending was reached, the game goes on in free play after it
+    pub won: bool,
+    // The days of free play, counted from the first boar defeated in it. 0 until then
+    pub day: u32,
+    // How long the spawners have been waiting for their next spawn, in seconds by spawn id
+    pub spawners: HashMap<String, f32>,
+    // When it was last written, in seconds since the Unix epoch. 0 for the saves from before it was kept
+    pub saved_at: u64,
+    // The slot it is written to
+    #[serde(skip)]
+    pub slot: usize,
+    // The save of a headless run: it starts empty and is never written, the runs do not depend on each other
+    #[serde(skip)]
+    pub in_memory: bool,
+}
+
+impl SaveGame {
+    // The file of a slot, a slot past the last one is the last one
+    pub fn path(slot: usize) -> PathBuf {
+	FileAssetIo::get_base_path().join(SAVE_FILES[slot.min(SAVE_FILES.len() - 1)])
+    }
+
+    // Reads the save of a slot: None when the slot is empty, an error when the file cannot be read or parsed
+    pub fn read(slot: usize) -> Result<Option<SaveGame>, String> {
+	let full_path = SaveGame::path(slot);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+	    Err(error) => return Err(format!("Could not read the save {}: {error}", full_path.display())),
+	};
+	match ron::from_str::<SaveGame>(&contents) {
+	    Ok(save) => Ok(Some(SaveGame { slot, ..save })),
+	    Err(error) => Err(format!("Could not parse the save {}: {error}", full_path.display())),
+	}
+    }
+
+    // Reads the save of a slot, or starts a new one in it when there is none or it cannot be read
+    pub fn load(slot: usize) -> SaveGame {
+	match SaveGame::read(slot) {
+	    Ok(Some(save)) => save,
+	    Ok(None) => SaveGame { slot, ..default() },
+	    Err(error) => {
+		warn!("{error}");
+		SaveGame { slot, ..default() }
+	    },
+	}
+    }
+
+    // Removes the file of a slot, an empty slot stays empty
+    pub fn delete(slot: usize) {
+	let full_path = SaveGame::path(slot);
+	match fs::remove_file(&full_path) {
+	    Err(error) if error.kind() != ErrorKind::NotFound => warn!("Could not delete the save {}: {error}", full_path.display()),
+	    _ => (),
+	}
+    }
+
+    // What the saves screen shows of a slot
+    pub fn summary(slot: usize) -> String {
+	match SaveGame::read(slot) {
+	    Ok(None) => "Empty".to_string(),
+	    Ok(Some(save)) => {
+		let progress = if save.won { format!("free play day {}", save.day) } else { "story".to_string() };
+		let saved = match save.saved_at {
+		    0 => "saved before the slots".to_string(),
+		    saved_at => format!("saved {}", time_ago(saved_at)),
+		};
+		format!("Level {}, {progress}, {saved}", save.experience.level)
+	    },
+	    Err(_) => "Unreadable, it starts over when played".to_string(),
+	}
+    }
+
+    pub fn save(&mut self) {
+	if self.in_memory {
+	    return;
+	}
+	self.saved_at = unix_time();
+	let full_path = SaveGame::path(self.slot);
+	let contents = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not serialize the save: {error}");
+		return;
+	    },
+	};
+	if let Err(error) = fs::write(&full_path, contents) {
+	    warn!("Could not write the save {}: {error}", full_path.display());
+	}
+    }
+}
+
+// System that writes the save when leaving the game, for what is only kept in memory while playing (the spawners)
+pub fn save_on_exit(mut save: ResMut<SaveGame>) {
+    save.save();
+}
+
+// Seconds since the Unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+// How long ago a time from unix_time was, roughly
+pub fn time_ago(time: u64) -> String {
+    let seconds = unix_time().saturating_sub(time);
+    match seconds {
+	0..=59 => "just now".to_string(),
+	60..=3599 => format!("{} minutes ago", seconds / 60),
+	3600..=86399 => format!("{} hours ago", seconds / 3600),
+	_ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+// System that unlocks the achievements, each one once. They are met by events, and by the stats when they change
+pub fn unlock_achievements(
+    mut stats: ResMut<Stats>,
+    quests: Res<QuestLog>,
+    mut toasts: EventWriter<Toast>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut deaths: EventReader<DeathEvent>,
+    mut tamings: EventReader<BoarTamed>,
+) {
+    let mut met = Vec::new();
+    if deaths.iter().count() > 0 {
+	met.push(Achievement::FirstBoar);
+    }
+    if tamings.iter().count() > 0 {
+	met.push(Achievement::TamedBoar);
+    }
+    if quests.is_changed() && quests.quests.iter().any(Quest::is_complete) {
+	met.push(Achievement::GoodNeighbor);
+    }
+    if stats.is_changed() {
+	if stats.deaths > 0 {
+	    met.push(Achievement::FirstDeath);
+	}
+	if stats.distance_walked >= WALKER_DISTANCE {
+	    met.push(Achievement::Walker);
+	}
+	if stats.alive_time >= f64::from(SURVIVOR_DAY - 1) * DAY_LENGTH {
+	    met.push(Achievement::Survivor);
+	}
+    }
+
+    let mut unlocked = false;
+    for achievement in met {
+	if stats.achievements.contains(&achievement) {
+	    continue;
+	}
+	stats.achievements.insert(achievement);
+	toasts.send(Toast(format!("Achievement unlocked: {}", achievement.name())));
+	unlocked = true;
+    }
+    if unlocked {
+	sfx_events.send(PlaySfx(Sfx::Achievement));
+	stats.save();
+    }
+}
+
+// System that adds up the time spent playing, it is saved from time to time
+pub fn count_play_time(time: Res<Time>, mut stats: ResMut<Stats>, mut autosave: Local<Timer>) {
+    stats.play_time += time.delta_seconds_f64();
+    stats.alive_time += time.delta_seconds_f64();
+    if autosave.duration().is_zero() {
+	*autosave = Timer::from_seconds(STATS_SAVE_INTERVAL, TimerMode::Repeating);
+    }
+    if autosave.tick(time.delta()).just_finished() {
+	stats.save();
+    }
+}
+
+// System that adds up the distance covered by the player, saved with the play time
+pub fn count_distance_walked(
+    mut stats: ResMut<Stats>,
+    query_player: Query<(Entity, &Transform), With<Player>>,
+    // The player of a new game starts elsewhere, nothing is walked to get there
+    mut last_position: Local<Option<(Entity, Vec2)>>,
+) {
+    let (player, transform) = query_player.single();
+    let position = transform.translation.truncate();
+    if let Some((last_player, last_position)) = *last_position {
+	if last_player == player {
+	    stats.distance_walked += position.distance(last_position) / UNITS_PER_METER;
+	}
+    }
+    *last_position = Some((player, position));
+}
+
+pub fn tick_game_clock(time: Res<Time>, mut clock: ResMut<GameClock>) {
+    clock.elapsed += time.delta_seconds_f64();
+}
+
+// The stats are saved when the game stops behind a menu
+pub fn save_stats(stats: Res<Stats>) {
+    stats.save();
+}
@@ -0,0 +1,115 @@
+// The app of the tests: no window, no rendering and no audio. The tests press the keys themselves,
+// add the systems they check to the fixed stage, and step it one tick at a time
+
+use crate::*;
+use bevy::input::gamepad::{gamepad_connection_system, gamepad_event_system, GamepadEvent, GamepadEventType, GamepadInfo, GamepadSettings};
+
+pub struct TestApp {
+    pub app: App,
+}
+
+impl TestApp {
+    // The resources and events of the gameplay, with their defaults instead of the files of the assets folder
+    pub fn new() -> TestApp {
+	let mut app = App::new();
+	app.add_plugins(MinimalPlugins)
+	    // The assets are not loaded, their handles are only made
+	    .add_plugin(AssetPlugin::default())
+	    .add_state(GameState::Playing)
+	    .init_resource::<Input<KeyCode>>()
+	    // The gamepads of the input plugin, without the keyboard systems that would clear the keys pressed by the tests
+	    .add_event::<GamepadEventRaw>()
+	    .add_event::<GamepadEvent>()
+	    .init_resource::<GamepadSettings>()
+	    .init_resource::<Gamepads>()
+	    .init_resource::<Input<GamepadButton>>()
+	    .init_resource::<Axis<GamepadAxis>>()
+	    .init_resource::<Axis<GamepadButton>>()
+	    .add_system_to_stage(CoreStage::PreUpdate, gamepad_event_system.label(InputSystem))
+	    .add_system_to_stage(CoreStage::PreUpdate, gamepad_connection_system.after(InputSystem))
+	    .init_resource::<KeyBindings>()
+	    .insert_resource(Settings { in_memory: true, ..default() })
+	    .init_resource::<Simulation>()
+	    .insert_resource(Accessibility { in_memory: true, ..default() })
+	    // Nothing is written next to the executable
+	    .insert_resource(Stats { in_memory: true, ..default() })
+	    .init_resource::<LootTables>()
+	    .init_resource::<Particles>()
+	    .init_resource::<Upgrades>()
+	    .init_resource::<Abilities>()
+	    .init_resource::<RunStats>()
+	    .init_resource::<HitStop>()
+	    .init_resource::<PlayArea>()
+	    .insert_resource(GameRng::new(0))
+	    .add_event::<DamageEvent>()
+	    .add_event::<DeathEvent>()
+	    .add_event::<ExperienceGained>()
+	    .add_event::<CollisionEvent>()
+	    .add_event::<BoarStateChanged>()
+	    .add_event::<PlayAreaChanged>()
+	    .add_event::<PlaySfx>()
+	    .add_event::<PlaySpatialSfx>()
+	    // Every update is a tick, there is no time to wait for
+	    .add_stage_after(CoreStage::Update, FixedUpdateStage, SystemStage::parallel());
+	TestApp { app }
+    }
+
+    pub fn add_fixed_systems(&mut self, systems: SystemSet) -> &mut TestApp {
+	self.app.add_system_set_to_stage(FixedUpdateStage, systems);
+	self
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+	self.app.world.resource_mut::<Input<KeyCode>>().press(key);
+    }
+
+    pub fn release(&mut self, key: KeyCode) {
+	self.app.world.resource_mut::<Input<KeyCode>>().release(key);
+    }
+
+    // Connects a gamepad, the first time, and holds its left stick in the given direction from the next tick on
+    pub fn tilt_left_stick(&mut self, stick: Vec2) {
+	let gamepad = Gamepad::new(0);
+	let mut raw_events = self.app.world.resource_mut::<Events<GamepadEventRaw>>();
+	raw_events.send(GamepadEventRaw::new(gamepad, GamepadEventType::Connected(GamepadInfo { name: "Test gamepad".to_string() })));
+	raw_events.send(GamepadEventRaw::new(gamepad, GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, stick.x)));
+	raw_events.send(GamepadEventRaw::new(gamepad, GamepadEventType::AxisChanged(GamepadAxisType::LeftStickY, stick.y)));
+    }
+
+    // Runs the given number of fixed ticks. A key pressed is only just pressed in the first one, as with the input plugin
+    pub fn step_fixed(&mut self, ticks: u32) {
+	for _ in 0..ticks {
+	    self.app.update();
+	    self.app.world.resource_mut::<Input<KeyCode>>().clear();
+	}
+    }
+
+    // How many ticks the given time lasts
+    pub fn ticks(&self, seconds: f32) -> u32 {
+	(seconds * self.app.world.resource::<Simulation>().tick_rate).ceil() as u32
+    }
+
+    // The player with what the gameplay systems look for, as spawned by the level but without the prefab
+    pub fn spawn_player(&mut self, position: Vec2) -> Entity {
+	self.app.world.spawn((
+	    SpriteBundle {
+		transform: Transform::from_translation(position.extend(0.2)),
+		..default()
+	    },
+	    Player,
+	    PlayerState::Walking,
+	    Dash::new(),
+	    Sprinting::default(),
+	    Facing::Right,
+	    Velocity::default(),
+	    PlayerSize(PLAYER_SIZE),
+	    Speed(PLAYER_SPEED),
+	    HealthPoints::new(PLAYER_HEALTH),
+	    Collider,
+	)).id()
+    }
+
+    pub fn position(&self, entity: Entity) -> Vec2 {
+	self.app.world.get::<Transform>(entity).expect("the entity has no transform").translation.truncate()
+    }
+}
@@ -0,0 +1,2217 @@
+// The HUD and the screens shown over the game
+
+use crate::*;
+
+// Low health settings: below the ratio, red edges pulse on the screen with a heartbeat,
+// stronger as the health gets lower
+pub const LOW_HEALTH_RATIO: f32 = 0.25;
+pub const VIGNETTE_COLOR: Color = Color::rgb(0.8, 0.0, 0.0);
+// With the colorblind palette, a blue that stays apart from the greens and browns of the map
+pub const VIGNETTE_COLORBLIND_COLOR: Color = Color::rgb(0.0, 0.35, 0.9);
+// With reduced motion, the edges stay at this strength instead of pulsing
+pub const VIGNETTE_STEADY_PULSE: f32 = 0.7;
+pub const VIGNETTE_MAX_ALPHA: f32 = 0.9;
+pub const HEARTBEAT_PERIOD: f32 = 1.0;
+// A hit on the player shows a bar at the edge of the game view, on the side it came from, in the low health color.
+// The bar fades out
+pub const DAMAGE_INDICATOR_SIZE: Vec2 = Vec2::new(120.0, 10.0);
+pub const DAMAGE_INDICATOR_MARGIN: f32 = 12.0;
+pub const DAMAGE_INDICATOR_ALPHA: f32 = 0.8;
+pub const DAMAGE_INDICATOR_TIME: f32 = 1.0;
+
+// UI settings
+pub const FONT: &str = "fonts/DejaVuSans.ttf";
+pub const MENU_BACKGROUND_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+// In the pause menu
+pub const PIXEL_PERFECT_KEY: KeyCode = KeyCode::X;
+pub const FOCUS_PAUSE_KEY: KeyCode = KeyCode::U;
+pub const ASPECT_RATIO_KEY: KeyCode = KeyCode::V;
+pub const PLAYER_TINT_KEY: KeyCode = KeyCode::N;
+// Each pair of keys lowers and raises a volume: the master one, the music and the sound effects
+pub const VOLUME_KEYS: [[KeyCode; 2]; 3] = [[KeyCode::Key1, KeyCode::Key2], [KeyCode::Key3, KeyCode::Key4], [KeyCode::Key5, KeyCode::Key6]];
+pub const VOLUME_STEP: f32 = 0.1;
+// The controls screen binds the actions to other keys, a key bound already is refused with a warning
+pub const CONTROLS_KEY: KeyCode = KeyCode::B;
+pub const CONTROLS_WARNING_COLOR: Color = Color::rgb(1.0, 0.5, 0.3);
+// The quest tracker stays in the top left corner, the quest log screen shows everything about the quests
+pub const QUEST_LOG_KEY: KeyCode = KeyCode::J;
+pub const QUEST_LOG_DONE_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
+pub const QUEST_LOG_ACTIVE_COLOR: Color = Color::rgb(1.0, 0.8, 0.3);
+pub const QUEST_DETAILS_SIZE: Vec2 = Vec2::new(520.0, 360.0);
+pub const QUEST_SCROLL_STEP: f32 = 40.0;
+pub const HUD_ICON_SIZE: f32 = 32.0;
+pub const TOAST_TIME: f32 = 2.0;
+// Tutorial settings, the prompts are only shown in the first game
+pub const TUTORIAL_TOP: f32 = 60.0;
+pub const TUTORIAL_FONT_SIZE: f32 = 28.0;
+pub const TUTORIAL_COLOR: Color = Color::rgb(1.0, 0.95, 0.6);
+// The inventory screen shows all the slots in a grid, Tab opens it too outside of the level editor
+pub const INVENTORY_KEY: KeyCode = KeyCode::I;
+pub const INVENTORY_ALT_KEY: KeyCode = KeyCode::Tab;
+pub const INVENTORY_COLUMNS: usize = 4;
+pub const INVENTORY_SLOT_SIZE: f32 = 64.0;
+pub const INVENTORY_CURSOR_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.25);
+pub const INVENTORY_HELD_COLOR: Color = Color::rgba(1.0, 0.8, 0.3, 0.5);
+// Distance from the mouse to the tooltip and the dragged item
+pub const INVENTORY_MOUSE_OFFSET: f32 = 16.0;
+// Talking to the house, the answers are picked with the arrows or the mouse
+pub const DIALOGUE_NAME_COLOR: Color = Color::rgb(1.0, 0.8, 0.3);
+// The dash indicator fills up as the dash recharges, and flashes once ready
+pub const DASH_INDICATOR_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+pub const DASH_CHARGING_COLOR: Color = Color::rgb(0.4, 0.5, 0.7);
+pub const DASH_READY_COLOR: Color = Color::rgb(0.5, 0.8, 1.0);
+pub const DASH_READY_FLASH_COLOR: Color = Color::WHITE;
+pub const DASH_READY_FLASH_TIME: f32 = 0.15;
+// Accessibility options, in the pause menu too
+pub const REDUCED_MOTION_KEY: KeyCode = KeyCode::R;
+pub const SPRINT_MODE_KEY: KeyCode = KeyCode::T;
+pub const COLORBLIND_KEY: KeyCode = KeyCode::C;
+pub const INVERT_X_KEY: KeyCode = KeyCode::I;
+pub const INVERT_Y_KEY: KeyCode = KeyCode::O;
+pub const AIM_ASSIST_KEY: KeyCode = KeyCode::K;
+// In the main menu
+pub const STATS_KEY: KeyCode = KeyCode::S;
+// The achievements are kept with the stats, and listed from the main menu too
+pub const ACHIEVEMENTS_KEY: KeyCode = KeyCode::A;
+// The saves screen picks the slot played in, and empties the slots
+pub const SAVES_KEY: KeyCode = KeyCode::L;
+pub const DELETE_SAVE_KEY: KeyCode = KeyCode::Delete;
+
+// The health bar of the boss, and its fill
+#[derive(Component)]
+pub struct BossHealthBar;
+
+#[derive(Component)]
+pub struct BossHealthFill;
+
+// An answer of the dialogue box, with its index. It is on the background and on the text
+#[derive(Component)]
+pub struct DialogueChoice(pub usize);
+
+// A slot of the chest screen, on the slot background, on its icon and on its count
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContainerSlot {
+    pub container: Container,
+    pub index: usize,
+}
+
+// The chest open on the chest screen, and the slot being dragged.
+// The items stay in their slot until they are let go, closing the screen in the middle of a drag moves nothing
+#[derive(Resource, Default)]
+pub struct ChestScreen {
+    pub chest: Option<Entity>,
+    pub dragged: Option<ContainerSlot>,
+}
+
+// Where the keyboard is on the inventory screen, and the slot being moved with the mouse or the keyboard
+#[derive(Resource, Default)]
+pub struct InventoryScreen {
+    pub cursor: usize,
+    pub held: Option<usize>,
+    // Whether the held slot follows the mouse
+    pub dragging: bool,
+}
+
+// Where the keyboard is on the saves screen, and what the slots hold
+#[derive(Resource, Default)]
+pub struct SavesScreen {
+    pub cursor: usize,
+    pub summaries: Vec<String>,
+}
+
+impl SavesScreen {
+    // Reads the slots again, each one on its own: a slot that cannot be read does not hide the others
+    pub fn refresh(&mut self) {
+	self.summaries = (0..SAVE_FILES.len()).map(SaveGame::summary).collect();
+    }
+}
+
+// A line of the saves screen, with its slot. It is on the background and on the text
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SaveSlotRow(pub usize);
+
+// Where the keyboard is on the controls screen, whether the next key pressed is bound to the selected action,
+// and why the last key was refused
+#[derive(Resource, Default)]
+pub struct ControlsScreen {
+    pub cursor: usize,
+    pub capturing: bool,
+    pub warning: Option<String>,
+}
+
+// A line of the controls screen, with the index of its action. It is on the background and on the text
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ControlRow(pub usize);
+
+#[derive(Component)]
+pub struct ControlsWarning;
+
+// A line of the shop screen, with the offer it trades (an upgrade for the upgrades).
+// It is on the background and on the text
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ShopRow {
+    pub trade: Trade,
+    pub offer: usize,
+}
+
+// The line selected on the shop screen
+#[derive(Resource, Default)]
+pub struct ShopScreen {
+    pub cursor: usize,
+}
+
+// Marker for the red edges shown when the health of the player is low
+#[derive(Component)]
+pub struct LowHealthVignette;
+
+// Marker for the icon shown while the sound is muted
+#[derive(Component)]
+pub struct MuteIcon;
+
+// An icon of the lives on the HUD, with its index. It is hidden once that life is lost
+#[derive(Component)]
+pub struct LifeIcon(pub u32);
+
+// A short message at the bottom of the screen
+pub struct Toast(pub String);
+
+// A slot of the hotbar, with the index of the inventory slot it shows.
+// It is on the slot background, on its icon and on its count
+#[derive(Component)]
+pub struct HotbarSlot(pub usize);
+
+#[derive(Component)]
+pub struct ToastText;
+
+// The steps of the tutorial, each one is done by doing what it asks
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TutorialStep {
+    Move,
+    Attack,
+    VisitHouse,
+}
+
+impl TutorialStep {
+    // The prompts name the keys bound to the actions
+    pub fn prompt(&self, bindings: &KeyBindings) -> String {
+	match self {
+	    TutorialStep::Move => {
+		let keys: Vec<String> = [Action::MoveUp, Action::MoveLeft, Action::MoveDown, Action::MoveRight].iter()
+		    .map(|action| key_name(bindings.key(*action)))
+		    .collect();
+		format!("{} to move", keys.concat())
+	    },
+	    TutorialStep::Attack => format!("{} to attack", key_name(bindings.key(Action::Attack))),
+	    TutorialStep::VisitHouse => "Walk to the house".to_string(),
+	}
+    }
+
+    pub fn next(&self) -> Option<TutorialStep> {
+	match self {
+	    TutorialStep::Move => Some(TutorialStep::Attack),
+	    TutorialStep::Attack => Some(TutorialStep::VisitHouse),
+	    TutorialStep::VisitHouse => None,
+	}
+    }
+}
+
+// How far the tutorial went in this game, it starts over in the next one until it is done
+#[derive(Resource)]
+pub struct Tutorial {
+    pub step: Option<TutorialStep>,
+}
+
+impl Default for Tutorial {
+    fn default() -> Tutorial {
+	Tutorial {
+	    step: Some(TutorialStep::Move),
+	}
+    }
+}
+
+#[derive(Component)]
+pub struct TutorialText;
+
+// A slot of the inventory screen, with the index of the inventory slot it shows.
+// It is on the slot background, on its icon and on its count
+#[derive(Component)]
+pub struct InventorySlot(pub usize);
+
+// The window of the inventory screen, the items dragged out of it are dropped
+#[derive(Component)]
+pub struct InventoryWindow;
+
+// The name and description of the item under the mouse, on the frame and on its text
+#[derive(Component)]
+pub struct InventoryTooltip;
+
+// The icon of the item being dragged, it follows the mouse
+#[derive(Component)]
+pub struct DraggedItem;
+
+// Fill of the dash indicator, its height follows the dash cooldown
+#[derive(Component)]
+pub struct DashIndicator;
+
+// A part of the HUD hidden until the player has the ability
+#[derive(Component)]
+pub struct AbilityIndicator(pub Ability);
+
+// The number of coins of the player, on the HUD
+#[derive(Component)]
+pub struct CoinCounter;
+
+// A bar at the edge of the game view, toward where a hit on the player came from
+#[derive(Component)]
+pub struct DamageIndicator;
+
+// The number of boars left to defeat, on the HUD
+#[derive(Component)]
+pub struct BoarCounter;
+
+// The level of the player and the fill of the experience bar, on the HUD
+#[derive(Component)]
+pub struct LevelText;
+
+#[derive(Component)]
+pub struct ExperienceFill;
+
+// The number of coins of the player, on the shop screen
+#[derive(Component)]
+pub struct ShopCoins;
+
+// The node holding the lines of the quest tracker
+#[derive(Component)]
+pub struct QuestLogList;
+
+// The quest selected on the quest log screen, as an index in the order of the list, and how far its details are scrolled
+#[derive(Resource, Default)]
+pub struct QuestLogScreen {
+    pub selected: usize,
+    pub scroll: f32,
+}
+
+// The nodes of the quest log screen: the list of the quests, the frame of the details, and the details moved inside it to scroll
+#[derive(Component)]
+pub struct QuestLogEntries;
+
+#[derive(Component)]
+pub struct QuestDetailsFrame;
+
+#[derive(Component)]
+pub struct QuestDetails;
+
+pub fn spawn_stats_screen(mut commands: Commands, asset_server: Res<AssetServer>, stats: Res<Stats>) {
+    let seconds = stats.play_time as u64;
+    spawn_menu(&mut commands, &asset_server, "Stats", &format!(
+	"Boars killed: {}\nDeaths: {}\nPlay time: {}:{:02}:{:02}\n\nPress S to go back",
+	stats.boars_killed,
+	stats.deaths,
+	seconds / 3600,
+	seconds / 60 % 60,
+	seconds % 60,
+    ));
+}
+
+// The stats screen replaces the main menu, which is spawned again when coming back
+pub fn show_stats(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    transition: Res<Transition>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(STATS_KEY) && transition.target.is_none() && state.set(GameState::Stats).is_ok() {
+	keyboard_input.reset(STATS_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+pub fn hide_stats(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(STATS_KEY) && state.set(GameState::MainMenu).is_ok() {
+	// The main menu is updated again in this frame, the key must not come back here right away
+	keyboard_input.reset(STATS_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+pub fn spawn_achievements_screen(mut commands: Commands, asset_server: Res<AssetServer>, stats: Res<Stats>) {
+    let lines: Vec<String> = Achievement::all().iter().map(|achievement| {
+	let state = if stats.achievements.contains(achievement) { "Unlocked" } else { "Locked" };
+	format!("{}: {} ({state})", achievement.name(), achievement.description())
+    }).collect();
+    spawn_menu(&mut commands, &asset_server, "Achievements", &format!("{}\n\nPress A to go back", lines.join("\n")));
+}
+
+// The achievements screen replaces the main menu, like the stats screen
+pub fn show_achievements(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    transition: Res<Transition>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(ACHIEVEMENTS_KEY) && transition.target.is_none() && state.set(GameState::Achievements).is_ok() {
+	keyboard_input.reset(ACHIEVEMENTS_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+pub fn hide_achievements(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(ACHIEVEMENTS_KEY) && state.set(GameState::MainMenu).is_ok() {
+	keyboard_input.reset(ACHIEVEMENTS_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+pub fn spawn_saves_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    mut screen: ResMut<SavesScreen>,
+) {
+    screen.cursor = settings.save_slot;
+    screen.refresh();
+    let font = asset_server.load(FONT);
+    let text = |text: &str, font_size| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size,
+	color: Color::WHITE,
+    });
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(text("Saves", 60.0));
+	for slot in 0..SAVE_FILES.len() {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			padding: UiRect::all(Val::Px(8.0)),
+			margin: UiRect::top(Val::Px(8.0)),
+			..default()
+		    },
+		    background_color: HOTBAR_SLOT_COLOR.into(),
+		    ..default()
+		},
+		SaveSlotRow(slot),
+	    )).with_children(|parent| {
+		parent.spawn((text("", 24.0), SaveSlotRow(slot)));
+	    });
+	}
+	parent.spawn(text("\nUse the arrows to choose, Enter to play in the slot and Delete to empty it\nPress L to go back", 24.0));
+    });
+}
+
+// The saves screen replaces the main menu, like the stats screen
+pub fn show_saves(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    transition: Res<Transition>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(SAVES_KEY) && transition.target.is_none() && state.set(GameState::Saves).is_ok() {
+	keyboard_input.reset(SAVES_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System of the saves screen that moves between the slots, empties the selected one, or plays in it.
+// The level is built again from the save when going back to the main menu
+pub fn choose_save_slot(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut screen: ResMut<SavesScreen>,
+    mut settings: ResMut<Settings>,
+    mut save: ResMut<SaveGame>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let slots = SAVE_FILES.len();
+    if keyboard_input.just_pressed(KeyCode::Up) {
+	screen.cursor = (screen.cursor + slots - 1) % slots;
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+	screen.cursor = (screen.cursor + 1) % slots;
+    }
+    if keyboard_input.just_pressed(DELETE_SAVE_KEY) {
+	SaveGame::delete(screen.cursor);
+	// The save played in starts over, or it would be written back
+	if save.slot == screen.cursor {
+	    *save = SaveGame::load(screen.cursor);
+	}
+	screen.refresh();
+	sfx_events.send(PlaySfx(Sfx::Click));
+    }
+    if keyboard_input.just_pressed(INTERACT_KEY) {
+	settings.save_slot = screen.cursor;
+	settings.save();
+	*save = SaveGame::load(screen.cursor);
+	// The main menu is updated again in this frame, the key must not start the game right away
+	keyboard_input.reset(INTERACT_KEY);
+	let _ = state.set(GameState::MainMenu);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    } else if keyboard_input.just_pressed(SAVES_KEY) && state.set(GameState::MainMenu).is_ok() {
+	keyboard_input.reset(SAVES_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System that shows what the slots hold, and the selected one
+pub fn update_saves_screen(
+    screen: Res<SavesScreen>,
+    settings: Res<Settings>,
+    mut query_row: Query<(&SaveSlotRow, &mut BackgroundColor)>,
+    mut query_text: Query<(&SaveSlotRow, &mut Text)>,
+) {
+    for (row, mut color) in &mut query_row {
+	*color = if row.0 == screen.cursor { INVENTORY_CURSOR_COLOR } else { HOTBAR_SLOT_COLOR }.into();
+    }
+    for (row, mut text) in &mut query_text {
+	let Some(summary) = screen.summaries.get(row.0) else {
+	    continue;
+	};
+	let in_use = if row.0 == settings.save_slot { " (in use)" } else { "" };
+	text.sections[0].value = format!("{}. {summary}{in_use}", row.0 + 1);
+    }
+}
+
+// The name of a key on the screens
+pub fn key_name(key: KeyCode) -> String {
+    format!("{key:?}")
+}
+
+// The controls screen replaces the pause menu, like the stats screen replaces the main menu
+pub fn show_controls(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(CONTROLS_KEY) && state.set(GameState::Controls).is_ok() {
+	keyboard_input.reset(CONTROLS_KEY);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+pub fn spawn_controls_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut screen: ResMut<ControlsScreen>,
+) {
+    *screen = ControlsScreen::default();
+    let font = asset_server.load(FONT);
+    let text = |text: &str, font_size, color| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size,
+	color,
+    });
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(text("Controls", 60.0, Color::WHITE));
+	for index in 0..Action::all().len() {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			padding: UiRect::all(Val::Px(4.0)),
+			margin: UiRect::top(Val::Px(4.0)),
+			..default()
+		    },
+		    background_color: HOTBAR_SLOT_COLOR.into(),
+		    ..default()
+		},
+		ControlRow(index),
+	    )).with_children(|parent| {
+		parent.spawn((text("", 22.0, Color::WHITE), ControlRow(index)));
+	    });
+	}
+	parent.spawn((text("", 22.0, CONTROLS_WARNING_COLOR), ControlsWarning));
+	parent.spawn(text("Use the arrows to choose, Enter to change the key and Escape to cancel\nPress B to go back", 24.0, Color::WHITE));
+    });
+}
+
+// System of the controls screen that moves between the actions, and binds the next key pressed to the selected one.
+// The key comes from the keyboard events of the frame, so that the key starting the change is not taken for it
+pub fn rebind_keys(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut key_events: EventReader<KeyboardInput>,
+    mut screen: ResMut<ControlsScreen>,
+    mut bindings: ResMut<KeyBindings>,
+    mut settings: ResMut<Settings>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let pressed = key_events.iter()
+	.filter(|event| event.state == ButtonState::Pressed)
+	.find_map(|event| event.key_code);
+    let actions = Action::all();
+    if screen.capturing {
+	let Some(key) = pressed else {
+	    return;
+	};
+	// Escape cancels, and does not close the game
+	keyboard_input.reset(key);
+	if key == KeyCode::Escape {
+	    screen.capturing = false;
+	    screen.warning = None;
+	    sfx_events.send(PlaySfx(Sfx::Blip));
+	    return;
+	}
+	let action = actions[screen.cursor];
+	if let Some(other) = bindings.conflict(action, key) {
+	    screen.warning = Some(format!("{} is already bound to {}, press another key", key_name(key), other.name()));
+	    sfx_events.send(PlaySfx(Sfx::Thud));
+	    return;
+	}
+	bindings.bind(action, key);
+	settings.key_bindings = bindings.clone();
+	settings.save();
+	screen.capturing = false;
+	screen.warning = None;
+	sfx_events.send(PlaySfx(Sfx::Click));
+	return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Up) {
+	screen.cursor = (screen.cursor + actions.len() - 1) % actions.len();
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) {
+	screen.cursor = (screen.cursor + 1) % actions.len();
+    }
+    if keyboard_input.just_pressed(KeyCode::Return) {
+	screen.capturing = true;
+	screen.warning = None;
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    } else if let Some(&key) = [CONTROLS_KEY, KeyCode::Escape].iter().find(|key| keyboard_input.just_pressed(**key)) {
+	if state.set(GameState::Paused).is_ok() {
+	    keyboard_input.reset(key);
+	    sfx_events.send(PlaySfx(Sfx::Blip));
+	}
+    }
+}
+
+// System that shows the key of each action, the selected one, and the warning
+pub fn update_controls_screen(
+    screen: Res<ControlsScreen>,
+    bindings: Res<KeyBindings>,
+    mut query_row: Query<(&ControlRow, &mut BackgroundColor)>,
+    mut query_text: Query<(&ControlRow, &mut Text)>,
+    mut query_warning: Query<&mut Text, (With<ControlsWarning>, Without<ControlRow>)>,
+) {
+    for (row, mut color) in &mut query_row {
+	*color = if row.0 == screen.cursor { INVENTORY_CURSOR_COLOR } else { HOTBAR_SLOT_COLOR }.into();
+    }
+    for (row, mut text) in &mut query_text {
+	let action = Action::all()[row.0];
+	text.sections[0].value = if screen.capturing && row.0 == screen.cursor {
+	    format!("{}: press a key", action.name())
+	} else {
+	    format!("{}: {}", action.name(), key_name(bindings.key(action)))
+	};
+    }
+    for mut text in &mut query_warning {
+	text.sections[0].value = screen.warning.clone().unwrap_or_default();
+    }
+}
+
+// Goes to the next shape of the game view
+pub fn cycle_aspect_ratio(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(ASPECT_RATIO_KEY) {
+	let index = ASPECT_RATIOS.iter().position(|ratio| *ratio == settings.aspect_ratio).unwrap_or(0);
+	settings.aspect_ratio = ASPECT_RATIOS[(index + 1) % ASPECT_RATIOS.len()];
+	settings.save();
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System of the pause menu that goes to the next color of the player, the player in the game is tinted right away
+pub fn cycle_player_tint(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut query_player: Query<&mut PlayerTint>,
+) {
+    if keyboard_input.just_pressed(PLAYER_TINT_KEY) {
+	settings.player_tint = (settings.player_tint + 1) % PLAYER_TINTS.len();
+	settings.save();
+	for mut tint in &mut query_player {
+	    tint.0 = settings.player_tint();
+	}
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System of the pause menu that changes the volumes, the sounds being played follow right away
+pub fn change_volumes(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let Some((index, steps)) = VOLUME_KEYS.iter().enumerate().find_map(|(index, [lower, raise])| {
+	if keyboard_input.just_pressed(*lower) {
+	    Some((index, -1.0))
+	} else if keyboard_input.just_pressed(*raise) {
+	    Some((index, 1.0))
+	} else {
+	    None
+	}
+    }) else {
+	return;
+    };
+    let volume = match index {
+	0 => &mut settings.master_volume,
+	1 => &mut settings.music_volume,
+	_ => &mut settings.sfx_volume,
+    };
+    Settings::change_volume(volume, steps);
+    settings.save();
+    // The blip is heard at the new volume
+    sfx_events.send(PlaySfx(Sfx::Blip));
+}
+
+pub fn toggle_focus_pause(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(FOCUS_PAUSE_KEY) {
+	settings.pause_on_focus_loss = !settings.pause_on_focus_loss;
+	settings.save();
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System of the pause menu that switches the accessibility options, they are saved right away
+pub fn toggle_accessibility(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut accessibility: ResMut<Accessibility>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(REDUCED_MOTION_KEY) {
+	accessibility.reduced_motion = !accessibility.reduced_motion;
+    } else if keyboard_input.just_pressed(SPRINT_MODE_KEY) {
+	accessibility.toggle_sprint = !accessibility.toggle_sprint;
+    } else if keyboard_input.just_pressed(COLORBLIND_KEY) {
+	accessibility.colorblind = !accessibility.colorblind;
+    } else if keyboard_input.just_pressed(INVERT_X_KEY) {
+	accessibility.invert_x = !accessibility.invert_x;
+    } else if keyboard_input.just_pressed(INVERT_Y_KEY) {
+	accessibility.invert_y = !accessibility.invert_y;
+    } else if keyboard_input.just_pressed(AIM_ASSIST_KEY) {
+	accessibility.aim_assist = !accessibility.aim_assist;
+    } else {
+	return;
+    }
+    accessibility.save();
+    sfx_events.send(PlaySfx(Sfx::Blip));
+}
+
+pub fn toggle_pixel_perfect(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<Settings>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(PIXEL_PERFECT_KEY) {
+	settings.pixel_perfect = !settings.pixel_perfect;
+	settings.save();
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System that fills the dash indicator as the dash recharges, with a flash and a blip once ready
+pub fn update_dash_indicator(
+    time: Res<Time>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_player: Query<&Dash, With<Player>>,
+    mut query_indicator: Query<(&mut Style, &mut BackgroundColor), With<DashIndicator>>,
+    mut was_ready: Local<Option<bool>>,
+    mut flash: Local<Timer>,
+) {
+    let dash = query_player.single();
+    let (mut style, mut color) = query_indicator.single_mut();
+    let ready = dash.cooldown.finished();
+    // Nothing when the game starts with the dash ready
+    if *was_ready == Some(false) && ready {
+	sfx_events.send(PlaySfx(Sfx::Blip));
+	*flash = Timer::from_seconds(DASH_READY_FLASH_TIME, TimerMode::Once);
+    }
+    *was_ready = Some(ready);
+    flash.tick(time.delta());
+
+    style.size.height = Val::Percent(dash.cooldown.percent() * 100.0);
+    *color = if !ready {
+	DASH_CHARGING_COLOR
+    } else if !flash.finished() {
+	DASH_READY_FLASH_COLOR
+    } else {
+	DASH_READY_COLOR
+    }.into();
+}
+
+// System that pulses the red edges of the screen while the player is low on health,
+// with a heartbeat at every pulse
+pub fn low_health_warning(
+    time: Res<Time>,
+    accessibility: Res<Accessibility>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_player: Query<&HealthPoints, With<Player>>,
+    mut query_vignette: Query<(&mut Visibility, &mut BackgroundColor), With<LowHealthVignette>>,
+    mut heartbeat: Local<Option<Timer>>,
+) {
+    let health = query_player.single();
+    let (mut visibility, mut color) = query_vignette.single_mut();
+    let ratio = health.ratio();
+    if ratio >= LOW_HEALTH_RATIO {
+	visibility.is_visible = false;
+	*heartbeat = None;
+	return;
+    }
+
+    // The first heartbeat comes right away
+    let heartbeat = heartbeat.get_or_insert_with(|| {
+	let mut timer = Timer::from_seconds(HEARTBEAT_PERIOD, TimerMode::Repeating);
+	timer.set_elapsed(Duration::from_secs_f32(HEARTBEAT_PERIOD));
+	timer
+    });
+    if heartbeat.tick(time.delta()).just_finished() {
+	sfx_events.send(PlaySfx(Sfx::Heartbeat));
+    }
+
+    // From half to full strength as the health goes down, and a pulse in time with the heartbeat
+    let severity = 1.0 - ratio / LOW_HEALTH_RATIO;
+    let pulse = if accessibility.reduced_motion {
+	VIGNETTE_STEADY_PULSE
+    } else {
+	0.5 + 0.5 * (heartbeat.percent() * 2.0 * PI).cos()
+    };
+    let alpha = VIGNETTE_MAX_ALPHA * (0.5 + 0.5 * severity) * (0.4 + 0.6 * pulse);
+    let mut tint = accessibility.low_health_color();
+    tint.set_a(alpha);
+    visibility.is_visible = true;
+    *color = tint.into();
+}
+
+// System that opens the quest log screen, the game waits behind it
+pub fn open_quest_log(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut screen: ResMut<QuestLogScreen>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(QUEST_LOG_KEY) && state.push(GameState::QuestLog).is_ok() {
+	// The quest log screen is updated again in this frame, the key must not close it right away
+	keyboard_input.reset(QUEST_LOG_KEY);
+	*screen = QuestLogScreen::default();
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// System that closes the quest log screen. Escape closes the screen and not the game
+pub fn close_quest_log(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let Some(&key) = [QUEST_LOG_KEY, KeyCode::Escape].iter().find(|key| keyboard_input.just_pressed(**key)) else {
+	return;
+    };
+    let _ = state.pop();
+    keyboard_input.reset(key);
+    sfx_events.send(PlaySfx(Sfx::Blip));
+}
+
+// The list of the quests on the left, the details of the selected one on the right. Both are filled when updating
+pub fn spawn_quest_log_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load(FONT);
+    let text = |text: &str, font_size| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size,
+	color: Color::WHITE,
+    });
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(text("Quest Log", 60.0));
+	parent.spawn(NodeBundle::default()).with_children(|parent| {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			flex_direction: FlexDirection::Column,
+			margin: UiRect::all(Val::Px(16.0)),
+			..default()
+		    },
+		    ..default()
+		},
+		QuestLogEntries,
+	    ));
+	    // The details are cut at the frame, they scroll inside it
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			size: Size::new(Val::Px(QUEST_DETAILS_SIZE.x), Val::Px(QUEST_DETAILS_SIZE.y)),
+			margin: UiRect::all(Val::Px(16.0)),
+			overflow: Overflow::Hidden,
+			..default()
+		    },
+		    background_color: HOTBAR_SLOT_COLOR.into(),
+		    ..default()
+		},
+		QuestDetailsFrame,
+	    )).with_children(|parent| {
+		parent.spawn((
+		    NodeBundle {
+			style: Style {
+			    flex_direction: FlexDirection::Column,
+			    flex_shrink: 0.0,
+			    padding: UiRect::all(Val::Px(12.0)),
+			    align_self: AlignSelf::FlexStart,
+			    ..default()
+			},
+			..default()
+		    },
+		    QuestDetails,
+		));
+	    });
+	});
+	parent.spawn(text("Use Up and Down to choose a quest, Page Up and Page Down or the mouse wheel to scroll\
+			   \nPress J or Escape to close", 24.0));
+    });
+}
+
+// System that selects a quest with the arrows and scrolls its details with the page keys or the mouse wheel
+pub fn navigate_quest_log(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    quests: Res<QuestLog>,
+    mut screen: ResMut<QuestLogScreen>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let count = quests.quests.len();
+    if count == 0 {
+	return;
+    }
+    let selected = if keyboard_input.just_pressed(KeyCode::Up) {
+	(screen.selected + count - 1) % count
+    } else if keyboard_input.just_pressed(KeyCode::Down) {
+	(screen.selected + 1) % count
+    } else {
+	screen.selected
+    };
+    if selected != screen.selected {
+	*screen = QuestLogScreen { selected, scroll: 0.0 };
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+
+    let mut scroll = mouse_wheel.iter().map(|event| match event.unit {
+	MouseScrollUnit::Line => -event.y * QUEST_SCROLL_STEP,
+	MouseScrollUnit::Pixel => -event.y,
+    }).sum::<f32>();
+    if keyboard_input.just_pressed(KeyCode::PageUp) {
+	scroll -= QUEST_DETAILS_SIZE.y / 2.0;
+    }
+    if keyboard_input.just_pressed(KeyCode::PageDown) {
+	scroll += QUEST_DETAILS_SIZE.y / 2.0;
+    }
+    if scroll != 0.0 {
+	// Kept within the details in the update
+	screen.scroll += scroll;
+    }
+}
+
+// System that fills the quest log screen: the quests listed with the selected one highlighted,
+// and the stages of the selected one as a checklist. The done stages are struck through, the current one is highlighted
+pub fn update_quest_log_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    quests: Res<QuestLog>,
+    mut screen: ResMut<QuestLogScreen>,
+    query_entries: Query<Entity, With<QuestLogEntries>>,
+    query_frame: Query<&Node, With<QuestDetailsFrame>>,
+    mut query_details: Query<(Entity, &Node, &mut Style, ChangeTrackers<QuestDetails>)>,
+    // The quest shown in the details, they are filled again only when another one is selected
+    mut shown: Local<usize>,
+) {
+    let (details, details_node, mut details_style, details_tracker) = query_details.single_mut();
+    // The details are as tall as their text once laid out
+    let max_scroll = (details_node.size().y - query_frame.single().size().y).max(0.0);
+    let scroll = screen.scroll.clamp(0.0, max_scroll);
+    if scroll != screen.scroll {
+	screen.scroll = scroll;
+    }
+    if details_style.position.top != Val::Px(-scroll) {
+	details_style.position.top = Val::Px(-scroll);
+    }
+
+    if !details_tracker.is_added() && !quests.is_changed() && *shown == screen.selected {
+	return;
+    }
+    *shown = screen.selected;
+    let listed = quests.listed();
+    let Some(selected) = listed.get(screen.selected) else {
+	return;
+    };
+    let font = asset_server.load(FONT);
+    let text = |text: String, font_size, color| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size,
+	color,
+    }).with_style(Style {
+	max_size: Size::new(Val::Px(QUEST_DETAILS_SIZE.x - 24.0), Val::Undefined),
+	..default()
+    });
+
+    let entries = query_entries.single();
+    commands.entity(entries).despawn_descendants();
+    commands.entity(entries).with_children(|parent| {
+	for (index, quest) in listed.iter().enumerate() {
+	    let (mark, color) = match (index == screen.selected, quest.is_complete()) {
+		(true, _) => ('▶', QUEST_LOG_ACTIVE_COLOR),
+		(false, true) => ('✓', QUEST_LOG_DONE_COLOR),
+		(false, false) => ('☐', Color::WHITE),
+	    };
+	    parent.spawn(text(format!("{mark} {}", quest.title), 24.0, color));
+	}
+    });
+
+    commands.entity(details).despawn_descendants();
+    commands.entity(details).with_children(|parent| {
+	parent.spawn(text(selected.title.to_string(), 32.0, Color::WHITE));
+	parent.spawn(text(selected.description.to_string(), 20.0, Color::WHITE));
+	for (index, stage) in selected.stages.iter().enumerate() {
+	    let line = if stage.done {
+		// A combining long stroke after each character strikes the line through
+		format!("✓ {}", stage.description.chars().flat_map(|c| [c, '\u{336}']).collect::<String>())
+	    } else {
+		format!("☐ {}", stage.description)
+	    };
+	    let color = match (stage.done, index == selected.current) {
+		(true, _) => QUEST_LOG_DONE_COLOR,
+		(false, true) => QUEST_LOG_ACTIVE_COLOR,
+		(false, false) => Color::WHITE,
+	    };
+	    parent.spawn(text(line, 20.0, color).with_style(Style {
+		margin: UiRect::top(Val::Px(8.0)),
+		max_size: Size::new(Val::Px(QUEST_DETAILS_SIZE.x - 24.0), Val::Undefined),
+		..default()
+	    }));
+	}
+    });
+}
+
+// System that shows the last toast for a moment
+pub fn show_toasts(
+    time: Res<Time>,
+    mut toasts: EventReader<Toast>,
+    mut query_text: Query<(&mut Text, &mut Visibility), With<ToastText>>,
+    mut timer: Local<Timer>,
+) {
+    let (mut text, mut visibility) = query_text.single_mut();
+    if let Some(Toast(message)) = toasts.iter().last() {
+	text.sections[0].value = message.clone();
+	visibility.is_visible = true;
+	*timer = Timer::from_seconds(TOAST_TIME, TimerMode::Once);
+    }
+    if timer.tick(time.delta()).just_finished() {
+	visibility.is_visible = false;
+    }
+}
+
+// System that shows the prompt of the tutorial step, and goes to the next step once the player did what it asks.
+// The tutorial is over for good after the last step
+pub fn run_tutorial(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    location: Res<Location>,
+    mut settings: ResMut<Settings>,
+    mut tutorial: ResMut<Tutorial>,
+    mut query_text: Query<(&mut Text, &mut Visibility), With<TutorialText>>,
+) {
+    let (mut text, mut visibility) = query_text.single_mut();
+    let step = tutorial.step.filter(|_| !settings.tutorial_done);
+    visibility.is_visible = step.is_some();
+    let Some(step) = step else {
+	return;
+    };
+    let prompt = step.prompt(&bindings);
+    if text.sections[0].value != prompt {
+	text.sections[0].value = prompt;
+    }
+
+    let done = match step {
+	TutorialStep::Move => [Action::MoveUp, Action::MoveLeft, Action::MoveDown, Action::MoveRight].iter()
+	    .any(|action| bindings.pressed(&keyboard_input, *action)),
+	TutorialStep::Attack => bindings.just_pressed(&keyboard_input, Action::Attack),
+	TutorialStep::VisitHouse => *location == Location::House,
+    };
+    if !done {
+	return;
+    }
+    tutorial.step = step.next();
+    if tutorial.step.is_none() {
+	settings.tutorial_done = true;
+	settings.save();
+    }
+}
+
+// System that shows the first slots of the inventory in the hotbar, when the inventory changes
+pub fn update_hotbar(
+    asset_server: Res<AssetServer>,
+    inventory: Res<Inventory>,
+    mut query_slot: Query<(&HotbarSlot, &mut BackgroundColor), (Without<UiImage>, Without<Text>)>,
+    mut query_icon: Query<(&HotbarSlot, &mut UiImage, &mut Visibility), Without<Text>>,
+    mut query_count: Query<(&HotbarSlot, &mut Text)>,
+) {
+    if !inventory.is_changed() {
+	return;
+    }
+    for (slot, mut color) in &mut query_slot {
+	*color = if slot.0 == inventory.selected { HOTBAR_SELECTED_COLOR } else { HOTBAR_SLOT_COLOR }.into();
+    }
+    for (slot, mut image, mut visibility) in &mut query_icon {
+	visibility.is_visible = inventory.slots[slot.0].is_some();
+	if let Some(stack) = inventory.slots[slot.0] {
+	    *image = asset_server.load(stack.item.sprite()).into();
+	}
+    }
+    for (slot, mut text) in &mut query_count {
+	text.sections[0].value = match inventory.slots[slot.0] {
+	    Some(stack) if stack.count > 1 => stack.count.to_string(),
+	    _ => String::new(),
+	};
+    }
+}
+
+// System that opens the inventory screen, the game waits behind it
+pub fn open_inventory(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    editor: Option<Res<LevelEditor>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    // Tab rotates the walls in the level editor
+    let keys = if editor.is_some() { &[INVENTORY_KEY][..] } else { &[INVENTORY_KEY, INVENTORY_ALT_KEY][..] };
+    let Some(&key) = keys.iter().find(|key| keyboard_input.just_pressed(**key)) else {
+	return;
+    };
+    let _ = state.push(GameState::Inventory);
+    // The screen is updated again in this frame, the key must not close it right away
+    keyboard_input.reset(key);
+    sfx_events.send(PlaySfx(Sfx::Blip));
+}
+
+// System that closes the inventory screen. Escape closes the screen and not the game
+pub fn close_inventory(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let keys = [INVENTORY_KEY, INVENTORY_ALT_KEY, KeyCode::Escape];
+    let Some(&key) = keys.iter().find(|key| keyboard_input.just_pressed(**key)) else {
+	return;
+    };
+    let _ = state.pop();
+    keyboard_input.reset(key);
+    sfx_events.send(PlaySfx(Sfx::Blip));
+}
+
+// Spawns a window with all the slots of an inventory in rows, the mouse goes through the slots to the window.
+// The slot background, its icon and its count are marked with the slot
+pub fn spawn_item_grid<M: Component>(parent: &mut ChildBuilder, font: &Handle<Font>, window: impl Bundle, slot: impl Fn(usize) -> M) {
+    parent.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Px(INVENTORY_COLUMNS as f32 * (INVENTORY_SLOT_SIZE + 4.0) + 16.0), Val::Auto),
+		flex_wrap: FlexWrap::Wrap,
+		padding: UiRect::all(Val::Px(8.0)),
+		margin: UiRect::all(Val::Px(16.0)),
+		..default()
+	    },
+	    background_color: HOTBAR_SLOT_COLOR.into(),
+	    ..default()
+	},
+	Interaction::default(),
+	window,
+    )).with_children(|parent| {
+	for index in 0..INVENTORY_SLOTS {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			size: Size::new(Val::Px(INVENTORY_SLOT_SIZE), Val::Px(INVENTORY_SLOT_SIZE)),
+			margin: UiRect::all(Val::Px(2.0)),
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			..default()
+		    },
+		    background_color: HOTBAR_SLOT_COLOR.into(),
+		    focus_policy: FocusPolicy::Pass,
+		    ..default()
+		},
+		Interaction::default(),
+		slot(index),
+	    )).with_children(|parent| {
+		parent.spawn((
+		    ImageBundle {
+			style: Style {
+			    size: Size::new(Val::Px(INVENTORY_SLOT_SIZE / 2.0), Val::Px(INVENTORY_SLOT_SIZE / 2.0)),
+			    ..default()
+			},
+			focus_policy: FocusPolicy::Pass,
+			visibility: Visibility { is_visible: false },
+			..default()
+		    },
+		    slot(index),
+		));
+		parent.spawn((
+		    TextBundle {
+			text: Text::from_section("", TextStyle {
+			    font: font.clone(),
+			    font_size: 16.0,
+			    color: Color::WHITE,
+			}),
+			style: Style {
+			    position_type: PositionType::Absolute,
+			    position: UiRect {
+				bottom: Val::Px(2.0),
+				right: Val::Px(4.0),
+				..default()
+			    },
+			    ..default()
+			},
+			focus_policy: FocusPolicy::Pass,
+			..default()
+		    },
+		    slot(index),
+		));
+	    });
+	}
+    });
+
+}
+
+// Spawns the icon of the item being dragged, above the whole screen
+pub fn spawn_dragged_item(commands: &mut Commands) {
+    commands.spawn((
+	ImageBundle {
+	    style: Style {
+		size: Size::new(Val::Px(INVENTORY_SLOT_SIZE / 2.0), Val::Px(INVENTORY_SLOT_SIZE / 2.0)),
+		position_type: PositionType::Absolute,
+		..default()
+	    },
+	    focus_policy: FocusPolicy::Pass,
+	    visibility: Visibility { is_visible: false },
+	    z_index: ZIndex::Global(1),
+	    ..default()
+	},
+	DraggedItem,
+	MenuUi,
+    ));
+}
+
+// Where to put something next to the mouse, in the UI which counts from the top of the window
+pub fn next_to_mouse(windows: &Windows) -> Option<UiRect> {
+    let window = windows.get_primary()?;
+    let cursor = window.cursor_position()?;
+    Some(UiRect {
+	left: Val::Px(cursor.x + INVENTORY_MOUSE_OFFSET),
+	top: Val::Px(window.height() - cursor.y + INVENTORY_MOUSE_OFFSET),
+	..default()
+    })
+}
+
+pub fn spawn_inventory_screen(mut commands: Commands, asset_server: Res<AssetServer>, mut screen: ResMut<InventoryScreen>) {
+    *screen = InventoryScreen::default();
+    let font = asset_server.load(FONT);
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(TextBundle::from_section("Inventory", TextStyle {
+	    font: font.clone(),
+	    font_size: 60.0,
+	    color: Color::WHITE,
+	}));
+	spawn_item_grid(parent, &font, InventoryWindow, InventorySlot);
+	parent.spawn(TextBundle::from_section(
+	    "Drag the items to move them, or use the arrows and Enter\
+	     \nDrop them out of the window to leave them on the ground\nPress I to close",
+	    TextStyle {
+		font: font.clone(),
+		font_size: 24.0,
+		color: Color::WHITE,
+	    },
+	));
+    });
+
+    // The tooltip and the dragged item are above the whole screen, where the mouse is
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		position_type: PositionType::Absolute,
+		padding: UiRect::all(Val::Px(6.0)),
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    focus_policy: FocusPolicy::Pass,
+	    visibility: Visibility { is_visible: false },
+	    z_index: ZIndex::Global(1),
+	    ..default()
+	},
+	InventoryTooltip,
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn((
+	    TextBundle {
+		text: Text::from_section("", TextStyle {
+		    font,
+		    font_size: 18.0,
+		    color: Color::WHITE,
+		}),
+		focus_policy: FocusPolicy::Pass,
+		..default()
+	    },
+	    InventoryTooltip,
+	));
+    });
+    spawn_dragged_item(&mut commands);
+}
+
+// System that moves the items of the inventory screen with the mouse.
+// An item let go out of the window is dropped at the feet of the player
+pub fn drag_inventory_items(
+    mut commands: Commands,
+    mouse_input: Res<Input<MouseButton>>,
+    mut inventory: ResMut<Inventory>,
+    mut screen: ResMut<InventoryScreen>,
+    mut game_rng: ResMut<GameRng>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut toasts: EventWriter<Toast>,
+    query_player: Query<&Transform, With<Player>>,
+    query_slot: Query<(&InventorySlot, &Interaction, ChangeTrackers<Interaction>)>,
+    query_window: Query<&Interaction, With<InventoryWindow>>,
+) {
+    for (slot, interaction, _) in query_slot.iter().filter(|(_, _, tracker)| tracker.is_changed()) {
+	match interaction {
+	    Interaction::Clicked if inventory.slots[slot.0].is_some() => {
+		screen.held = Some(slot.0);
+		screen.dragging = true;
+		screen.cursor = slot.0;
+	    }
+	    Interaction::Hovered if screen.held.is_none() || screen.dragging => screen.cursor = slot.0,
+	    _ => (),
+	}
+    }
+    if !screen.dragging || !mouse_input.just_released(MouseButton::Left) {
+	return;
+    }
+    let Some(from) = screen.held.take() else {
+	return;
+    };
+    screen.dragging = false;
+
+    if *query_window.single() != Interaction::None {
+	// Between two slots, the item goes back where it was
+	let target = query_slot.iter().find(|(_, interaction, _)| **interaction == Interaction::Hovered);
+	if let Some((&InventorySlot(to), _, _)) = target.filter(|(slot, _, _)| slot.0 != from) {
+	    inventory.move_stack(from, to);
+	    sfx_events.send(PlaySfx(Sfx::Click));
+	}
+	return;
+    }
+    let Some(stack) = inventory.slots[from].take() else {
+	return;
+    };
+    let feet = query_player.single().translation.truncate() + Vec2::Y * SHADOW_OFFSET;
+    for pickup in spawn_pickups(&mut commands, &mut game_rng.0, &[(stack.item, stack.count)], feet) {
+	commands.entity(pickup).insert(Dropped);
+    }
+    sfx_events.send(PlaySfx(Sfx::Thud));
+    toasts.send(Toast(format!("Dropped {} ({})", stack.item.name(), stack.count)));
+}
+
+// System that moves the items of the inventory screen with the keyboard:
+// the arrows move the cursor, Enter picks up the item under it and puts it down in another slot
+pub fn navigate_inventory(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut inventory: ResMut<Inventory>,
+    mut screen: ResMut<InventoryScreen>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if screen.dragging {
+	return;
+    }
+    let (column, row) = (screen.cursor % INVENTORY_COLUMNS, screen.cursor / INVENTORY_COLUMNS);
+    let rows = INVENTORY_SLOTS / INVENTORY_COLUMNS;
+    let moved = if keyboard_input.just_pressed(KeyCode::Left) && column > 0 {
+	Some(screen.cursor - 1)
+    } else if keyboard_input.just_pressed(KeyCode::Right) && column < INVENTORY_COLUMNS - 1 {
+	Some(screen.cursor + 1)
+    } else if keyboard_input.just_pressed(KeyCode::Up) && row > 0 {
+	Some(screen.cursor - INVENTORY_COLUMNS)
+    } else if keyboard_input.just_pressed(KeyCode::Down) && row < rows - 1 {
+	Some(screen.cursor + INVENTORY_COLUMNS)
+    } else {
+	None
+    };
+    if let Some(cursor) = moved {
+	screen.cursor = cursor;
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+
+    if !keyboard_input.just_pressed(KeyCode::Return) {
+	return;
+    }
+    if let Some(from) = screen.held.take() {
+	inventory.move_stack(from, screen.cursor);
+	sfx_events.send(PlaySfx(Sfx::Click));
+    } else if inventory.slots[screen.cursor].is_some() {
+	screen.held = Some(screen.cursor);
+	sfx_events.send(PlaySfx(Sfx::Click));
+    }
+}
+
+// System that shows the inventory in the slots of the screen, with the tooltip and the dragged item at the mouse
+pub fn update_inventory_screen(
+    asset_server: Res<AssetServer>,
+    windows: Res<Windows>,
+    inventory: Res<Inventory>,
+    screen: Res<InventoryScreen>,
+    mut query_slot: Query<(&InventorySlot, &Interaction, &mut BackgroundColor)>,
+    mut query_icon: Query<(&InventorySlot, &mut UiImage, &mut Visibility), Without<Text>>,
+    mut query_count: Query<(&InventorySlot, &mut Text)>,
+    mut query_tooltip: Query<(&mut Style, &mut Visibility), (With<InventoryTooltip>, Without<InventorySlot>, Without<Text>)>,
+    mut query_tooltip_text: Query<&mut Text, (With<InventoryTooltip>, Without<InventorySlot>)>,
+    mut query_dragged: Query<(&mut UiImage, &mut Style, &mut Visibility), (With<DraggedItem>, Without<InventorySlot>, Without<InventoryTooltip>, Without<Text>)>,
+) {
+    let mut hovered = None;
+    for (slot, interaction, mut color) in &mut query_slot {
+	if *interaction == Interaction::Hovered {
+	    hovered = Some(slot.0);
+	}
+	*color = if screen.held == Some(slot.0) {
+	    INVENTORY_HELD_COLOR
+	} else if screen.cursor == slot.0 {
+	    INVENTORY_CURSOR_COLOR
+	} else {
+	    HOTBAR_SLOT_COLOR
+	}.into();
+    }
+    for (slot, mut image, mut visibility) in &mut query_icon {
+	// The dragged item leaves its slot empty
+	let dragged = screen.dragging && screen.held == Some(slot.0);
+	visibility.is_visible = inventory.slots[slot.0].is_some() && !dragged;
+	if let Some(stack) = inventory.slots[slot.0] {
+	    *image = asset_server.load(stack.item.sprite()).into();
+	}
+    }
+    for (slot, mut text) in &mut query_count {
+	text.sections[0].value = match inventory.slots[slot.0] {
+	    Some(stack) if stack.count > 1 => stack.count.to_string(),
+	    _ => String::new(),
+	};
+    }
+
+    let mouse = next_to_mouse(&windows);
+
+    let (mut style, mut visibility) = query_tooltip.single_mut();
+    let stack = hovered.and_then(|slot| inventory.slots[slot]);
+    visibility.is_visible = false;
+    if let (Some(stack), Some(mouse), false) = (stack, mouse, screen.dragging) {
+	query_tooltip_text.single_mut().sections[0].value = format!("{}\n{}", stack.item.name(), stack.item.description());
+	style.position = mouse;
+	visibility.is_visible = true;
+    }
+
+    let (mut image, mut style, mut visibility) = query_dragged.single_mut();
+    let stack = screen.held.filter(|_| screen.dragging).and_then(|slot| inventory.slots[slot]);
+    visibility.is_visible = false;
+    if let (Some(stack), Some(mouse)) = (stack, mouse) {
+	*image = asset_server.load(stack.item.sprite()).into();
+	style.position = mouse;
+	visibility.is_visible = true;
+    }
+}
+
+// System that opens the chest screen on the chest used
+pub fn open_chest(
+    mut interactions: EventReader<Interacted>,
+    mut screen: ResMut<ChestScreen>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_chest: Query<(), With<Chest>>,
+) {
+    for Interacted(entity) in interactions.iter() {
+	if query_chest.contains(*entity) && state.push(GameState::Chest).is_ok() {
+	    *screen = ChestScreen { chest: Some(*entity), dragged: None };
+	    sfx_events.send(PlaySfx(Sfx::Click));
+	}
+    }
+}
+
+// System that closes the chest screen. Escape closes the screen and not the game
+pub fn close_chest(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    let Some(&key) = [INTERACT_KEY, KeyCode::Escape].iter().find(|key| keyboard_input.just_pressed(**key)) else {
+	return;
+    };
+    let _ = state.pop();
+    keyboard_input.reset(key);
+    sfx_events.send(PlaySfx(Sfx::Click));
+}
+
+// The inventory of the player on the left, the chest on the right
+pub fn spawn_chest_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load(FONT);
+    let label = |text| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size: 30.0,
+	color: Color::WHITE,
+    });
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(NodeBundle::default()).with_children(|parent| {
+	    for (title, container) in [("Inventory", Container::Player), ("Chest", Container::Chest)] {
+		parent.spawn(NodeBundle {
+		    style: Style {
+			flex_direction: FlexDirection::Column,
+			align_items: AlignItems::Center,
+			..default()
+		    },
+		    ..default()
+		}).with_children(|parent| {
+		    parent.spawn(label(title));
+		    spawn_item_grid(parent, &font, (), |index| ContainerSlot { container, index });
+		});
+	    }
+	});
+	parent.spawn(TextBundle::from_section(
+	    "Click an item to move one to the other side, right click to move them all\
+	     \nDrag the items to put them in another slot\nPress Enter to close",
+	    TextStyle {
+		font: font.clone(),
+		font_size: 24.0,
+		color: Color::WHITE,
+	    },
+	));
+    });
+    spawn_dragged_item(&mut commands);
+}
+
+// System that moves the items between the inventory and the chest: one at a time with a click,
+// all of the stack with a right click, or the stack to a given slot by dragging it
+pub fn move_chest_items(
+    mouse_input: Res<Input<MouseButton>>,
+    mut inventory: ResMut<Inventory>,
+    mut screen: ResMut<ChestScreen>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut toasts: EventWriter<Toast>,
+    mut query_chest: Query<&mut Inventory, With<Chest>>,
+    query_slot: Query<(&ContainerSlot, &Interaction)>,
+) {
+    let Some(Ok(mut chest)) = screen.chest.map(|chest| query_chest.get_mut(chest)) else {
+	return;
+    };
+    let find = |wanted| query_slot.iter().find(|(_, interaction)| **interaction == wanted).map(|(slot, _)| *slot);
+    let hovered = find(Interaction::Hovered);
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+	screen.dragged = find(Interaction::Clicked).filter(|slot| {
+	    slot.container.sides(&mut inventory, &mut chest).0.slots[slot.index].is_some()
+	});
+    }
+    let (moved, amount) = if mouse_input.just_pressed(MouseButton::Right) {
+	(hovered, MAX_STACK)
+    } else if mouse_input.just_released(MouseButton::Left) {
+	let Some(from) = screen.dragged.take() else {
+	    return;
+	};
+	match hovered {
+	    // Let go where it was taken, one item goes to the other side
+	    Some(to) if to == from => (Some(from), 1),
+	    Some(to) => {
+		if from.container == to.container {
+		    from.container.sides(&mut inventory, &mut chest).0.move_stack(from.index, to.index);
+		} else {
+		    let (source, target) = from.container.sides(&mut inventory, &mut chest);
+		    (source.slots[from.index], target.slots[to.index]) = stack_onto(source.slots[from.index], target.slots[to.index]);
+		}
+		sfx_events.send(PlaySfx(Sfx::Click));
+		return;
+	    },
+	    // Let go out of the slots, nothing moves
+	    None => return,
+	}
+    } else {
+	return;
+    };
+
+    let Some(slot) = moved else {
+	return;
+    };
+    let (source, target) = slot.container.sides(&mut inventory, &mut chest);
+    if source.slots[slot.index].is_none() {
+	return;
+    }
+    if source.transfer(slot.index, amount, target) == 0 {
+	toasts.send(Toast(slot.container.full_message().to_string()));
+    } else {
+	sfx_events.send(PlaySfx(Sfx::Click));
+    }
+}
+
+// System that shows the inventory and the chest in the slots of the chest screen, with the dragged item at the mouse
+pub fn update_chest_screen(
+    asset_server: Res<AssetServer>,
+    windows: Res<Windows>,
+    inventory: Res<Inventory>,
+    screen: Res<ChestScreen>,
+    query_chest: Query<&Inventory, With<Chest>>,
+    mut query_slot: Query<(&ContainerSlot, &mut BackgroundColor)>,
+    mut query_icon: Query<(&ContainerSlot, &mut UiImage, &mut Visibility), Without<Text>>,
+    mut query_count: Query<(&ContainerSlot, &mut Text)>,
+    mut query_dragged: Query<(&mut UiImage, &mut Style, &mut Visibility), (With<DraggedItem>, Without<ContainerSlot>)>,
+) {
+    let Some(Ok(chest)) = screen.chest.map(|chest| query_chest.get(chest)) else {
+	return;
+    };
+    let stack = |slot: &ContainerSlot| match slot.container {
+	Container::Player => inventory.slots[slot.index],
+	Container::Chest => chest.slots[slot.index],
+    };
+
+    for (slot, mut color) in &mut query_slot {
+	*color = if screen.dragged == Some(*slot) { INVENTORY_HELD_COLOR } else { HOTBAR_SLOT_COLOR }.into();
+    }
+    for (slot, mut image, mut visibility) in &mut query_icon {
+	visibility.is_visible = stack(slot).is_some() && screen.dragged != Some(*slot);
+	if let Some(stack) = stack(slot) {
+	    *image = asset_server.load(stack.item.sprite()).into();
+	}
+    }
+    for (slot, mut text) in &mut query_count {
+	text.sections[0].value = match stack(slot) {
+	    Some(stack) if stack.count > 1 => stack.count.to_string(),
+	    _ => String::new(),
+	};
+    }
+
+    let (mut image, mut style, mut visibility) = query_dragged.single_mut();
+    visibility.is_visible = false;
+    if let (Some(stack), Some(mouse)) = (screen.dragged.as_ref().and_then(stack), next_to_mouse(&windows)) {
+	*image = asset_server.load(stack.item.sprite()).into();
+	style.position = mouse;
+	visibility.is_visible = true;
+    }
+}
+
+// The contents of the chest are saved when its screen closes
+pub fn save_chest(
+    mut screen: ResMut<ChestScreen>,
+    mut save: ResMut<SaveGame>,
+    query_chest: Query<(&Chest, &Inventory)>,
+) {
+    let open = screen.chest.take();
+    screen.dragged = None;
+    let Some(Ok((chest, inventory))) = open.map(|chest| query_chest.get(chest)) else {
+	return;
+    };
+    save.chests.insert(chest.id.clone(), inventory.slots.to_vec());
+    save.save();
+}
+
+pub fn spawn_victory_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    clock: Res<GameClock>,
+    run_stats: Res<RunStats>,
+    save: Res<SaveGame>,
+) {
+    let seconds = clock.elapsed as u64;
+    let path = if save.tamed.contains(FRANK_ID) { "Frank was tamed" } else { "Frank was driven off" };
+    spawn_menu(&mut commands, &asset_server, "Victory", &format!(
+	"{path}\nTime: {}:{:02}\nDamage taken: {}\n\nPress Enter to keep playing\nPress Q to return to the main menu",
+	seconds / 60,
+	seconds % 60,
+	run_stats.damage_taken.round(),
+    ));
+}
+
+pub fn spawn_game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    clock: Res<GameClock>,
+    run_stats: Res<RunStats>,
+) {
+    let seconds = clock.elapsed as u64;
+    spawn_menu(&mut commands, &asset_server, "Game over", &format!(
+	"No life left\nTime: {}:{:02}\nDamage taken: {}\n\nPress Q to return to the main menu",
+	seconds / 60,
+	seconds % 60,
+	run_stats.damage_taken.round(),
+    ));
+}
+
+// The game goes on where it was, with the quest complete
+pub fn continue_free_play(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    transition: Res<Transition>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Return) && transition.target.is_none() && state.pop().is_ok() {
+	// Enter also talks to the house, the game is updated again in this frame
+	keyboard_input.reset(KeyCode::Return);
+	sfx_events.send(PlaySfx(Sfx::Blip));
+    }
+}
+
+// The dialogue box, at the bottom of the screen with the game still visible above
+pub fn spawn_dialogue_box(mut commands: Commands, asset_server: Res<AssetServer>, dialogue: Res<Dialogue>) {
+    let font = asset_server.load(FONT);
+    let text = |text: &str, color| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size: 24.0,
+	color,
+    });
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Auto),
+		position_type: PositionType::Absolute,
+		position: UiRect {
+		    bottom: Val::Px(0.0),
+		    ..default()
+		},
+		flex_direction: FlexDirection::Column,
+		padding: UiRect::all(Val::Px(16.0)),
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(text(&dialogue.speaker, DIALOGUE_NAME_COLOR));
+	parent.spawn(text(&dialogue.text, Color::WHITE));
+	for (index, (choice, _)) in dialogue.choices.iter().enumerate() {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			margin: UiRect::top(Val::Px(4.0)),
+			padding: UiRect::horizontal(Val::Px(8.0)),
+			..default()
+		    },
+		    ..default()
+		},
+		Interaction::default(),
+		DialogueChoice(index),
+	    )).with_children(|parent| {
+		parent.spawn(text(&format!("{}. {choice}", index + 1), Color::WHITE));
+	    });
+	}
+    });
+}
+
+// System that picks an answer of the dialogue, with the arrows and the interact key or with the mouse.
+// Escape leaves the dialogue and does not close the game
+pub fn choose_dialogue(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    mut gamepad_input: ResMut<Input<GamepadButton>>,
+    mut dialogue: ResMut<Dialogue>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut dialogue_actions: EventWriter<DialogueAction>,
+    query_choice: Query<(&DialogueChoice, &Interaction, ChangeTrackers<Interaction>)>,
+) {
+    if dialogue.choices.is_empty() {
+	let _ = state.pop();
+	return;
+    }
+    let mut chosen = None;
+    for (choice, interaction, _) in query_choice.iter().filter(|(_, _, tracker)| tracker.is_changed()) {
+	match interaction {
+	    Interaction::Clicked => chosen = Some(choice.0),
+	    Interaction::Hovered => dialogue.cursor = choice.0,
+	    Interaction::None => (),
+	}
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) || consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::DPadUp) {
+	dialogue.cursor = (dialogue.cursor + dialogue.choices.len() - 1) % dialogue.choices.len();
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) || consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::DPadDown) {
+	dialogue.cursor = (dialogue.cursor + 1) % dialogue.choices.len();
+    }
+    if keyboard_input.just_pressed(INTERACT_KEY) {
+	keyboard_input.reset(INTERACT_KEY);
+	chosen = Some(dialogue.cursor);
+    } else if consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::South) {
+	chosen = Some(dialogue.cursor);
+    }
+    let action = match chosen {
+	Some(index) => dialogue.choices[index].1,
+	None if keyboard_input.just_pressed(KeyCode::Escape) => {
+	    keyboard_input.reset(KeyCode::Escape);
+	    DialogueAction::Leave
+	},
+	None if consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::East) => DialogueAction::Leave,
+	None => return,
+    };
+    match action {
+	DialogueAction::OpenShop => {
+	    let _ = state.set(GameState::Shop);
+	},
+	DialogueAction::AcceptQuest(_) | DialogueAction::GrantAbility(_) | DialogueAction::Leave => {
+	    let _ = state.pop();
+	},
+    }
+    dialogue_actions.send(action);
+    sfx_events.send(PlaySfx(Sfx::Click));
+}
+
+// System that highlights the selected answer of the dialogue
+pub fn update_dialogue_box(dialogue: Res<Dialogue>, mut query_choice: Query<(&DialogueChoice, &mut BackgroundColor)>) {
+    for (choice, mut color) in &mut query_choice {
+	*color = if choice.0 == dialogue.cursor { INVENTORY_CURSOR_COLOR } else { Color::NONE }.into();
+    }
+}
+
+// The coins of the player, then what the house sells on the left and what it buys on the right
+pub fn spawn_shop_screen(mut commands: Commands, asset_server: Res<AssetServer>, shop: Res<Shop>, mut screen: ResMut<ShopScreen>) {
+    screen.cursor = 0;
+    let font = asset_server.load(FONT);
+    let text = |text: &str, font_size| TextBundle::from_section(text, TextStyle {
+	font: font.clone(),
+	font_size,
+	color: Color::WHITE,
+    });
+    let rows = shop.rows();
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		flex_direction: FlexDirection::Column,
+		justify_content: JustifyContent::Center,
+		align_items: AlignItems::Center,
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+	MenuUi,
+    )).with_children(|parent| {
+	parent.spawn(text("Shop", 60.0));
+	parent.spawn((text("", 30.0), ShopCoins));
+	parent.spawn(NodeBundle::default()).with_children(|parent| {
+	    for (title, trade) in [("Buy", Trade::Buy), ("Sell", Trade::Sell), ("Upgrades", Trade::Upgrade)] {
+		parent.spawn(NodeBundle {
+		    style: Style {
+			flex_direction: FlexDirection::Column,
+			margin: UiRect::all(Val::Px(16.0)),
+			..default()
+		    },
+		    ..default()
+		}).with_children(|parent| {
+		    parent.spawn(text(title, 30.0));
+		    for &row in rows.iter().filter(|row| row.trade == trade) {
+			parent.spawn((
+			    NodeBundle {
+				style: Style {
+				    align_items: AlignItems::Center,
+				    padding: UiRect::all(Val::Px(4.0)),
+				    margin: UiRect::top(Val::Px(4.0)),
+				    ..default()
+				},
+				background_color: HOTBAR_SLOT_COLOR.into(),
+				..default()
+			    },
+			    Interaction::default(),
+			    row,
+			)).with_children(|parent| {
+			    parent.spawn(ImageBundle {
+				style: Style {
+				    size: Size::new(Val::Px(SHOP_ICON_SIZE), Val::Px(SHOP_ICON_SIZE)),
+				    margin: UiRect::right(Val::Px(8.0)),
+				    ..default()
+				},
+				image: asset_server.load(shop.sprite(row)).into(),
+				focus_policy: FocusPolicy::Pass,
+				..default()
+			    });
+			    parent.spawn((text("", 24.0), row));
+			});
+		    }
+		});
+	    }
+	});
+	parent.spawn(text("Use the arrows to choose and Enter to trade, or click\nPress Escape to leave\nWith a gamepad: the D-pad, A to trade and B to leave", 24.0));
+    });
+}
+
+// System that closes the shop. Escape closes the screen and not the game
+pub fn close_shop(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    mut gamepad_input: ResMut<Input<GamepadButton>>,
+    mut state: ResMut<State<GameState>>,
+    mut sfx_events: EventWriter<PlaySfx>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+	keyboard_input.reset(KeyCode::Escape);
+    } else if !consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::East) {
+	return;
+    }
+    let _ = state.pop();
+    sfx_events.send(PlaySfx(Sfx::Click));
+}
+
+// System that buys or sells the selected item, or buys the selected upgrade,
+// with the interact key, the A button of a gamepad or a click
+pub fn trade_in_shop(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    mut gamepad_input: ResMut<Input<GamepadButton>>,
+    mut shop: ResMut<Shop>,
+    mut screen: ResMut<ShopScreen>,
+    mut currency: ResMut<Currency>,
+    mut inventory: ResMut<Inventory>,
+    mut upgrades: ResMut<Upgrades>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    mut toasts: EventWriter<Toast>,
+    query_row: Query<(&ShopRow, &Interaction, ChangeTrackers<Interaction>)>,
+) {
+    let rows = shop.rows();
+    if rows.is_empty() {
+	return;
+    }
+    let mut traded = None;
+    for (row, interaction, _) in query_row.iter().filter(|(_, _, tracker)| tracker.is_changed()) {
+	let Some(index) = rows.iter().position(|other| other == row) else {
+	    continue;
+	};
+	match interaction {
+	    Interaction::Clicked => {
+		screen.cursor = index;
+		traded = Some(*row);
+	    },
+	    Interaction::Hovered => screen.cursor = index,
+	    Interaction::None => (),
+	}
+    }
+    if keyboard_input.just_pressed(KeyCode::Up) || consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::DPadUp) {
+	screen.cursor = (screen.cursor + rows.len() - 1) % rows.len();
+    }
+    if keyboard_input.just_pressed(KeyCode::Down) || consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::DPadDown) {
+	screen.cursor = (screen.cursor + 1) % rows.len();
+    }
+    if keyboard_input.just_pressed(INTERACT_KEY) || consume_gamepad_press(&gamepads, &mut gamepad_input, GamepadButtonType::South) {
+	traded = rows.get(screen.cursor).copied();
+    }
+    let Some(row) = traded else {
+	return;
+    };
+    match shop.trade(row, &mut currency, &mut inventory, &mut upgrades) {
+	Ok(()) => sfx_events.send(PlaySfx(Sfx::Blip)),
+	Err(message) => toasts.send(Toast(message.to_string())),
+    }
+}
+
+// System that shows the prices, the stock and the coins on the shop screen
+pub fn update_shop_screen(
+    shop: Res<Shop>,
+    screen: Res<ShopScreen>,
+    currency: Res<Currency>,
+    inventory: Res<Inventory>,
+    mut query_row: Query<(&ShopRow, &mut BackgroundColor)>,
+    mut query_text: Query<(&ShopRow, &mut Text)>,
+    mut query_coins: Query<&mut Text, (With<ShopCoins>, Without<ShopRow>)>,
+) {
+    let selected = shop.rows().get(screen.cursor).copied();
+    for (row, mut color) in &mut query_row {
+	*color = if Some(*row) == selected { INVENTORY_CURSOR_COLOR } else { HOTBAR_SLOT_COLOR }.into();
+    }
+    for (row, mut text) in &mut query_text {
+	text.sections[0].value = shop.describe(*row, &inventory);
+    }
+    query_coins.single_mut().sections[0].value = format!("Coins: {}", currency.coins);
+}
+
+// System that shows the parts of the HUD of the abilities once they are unlocked
+pub fn show_ability_indicators(abilities: Res<Abilities>, mut query_indicator: Query<(&AbilityIndicator, &mut Visibility)>) {
+    if !abilities.is_changed() {
+	return;
+    }
+    for (AbilityIndicator(ability), mut visibility) in &mut query_indicator {
+	visibility.is_visible = abilities.has(*ability);
+    }
+}
+
+// System that shows the level of the player and its experience toward the next one on the HUD
+pub fn update_experience_bar(
+    progression: Res<Progression>,
+    query_player: Query<&Experience, (With<Player>, Changed<Experience>)>,
+    mut query_text: Query<&mut Text, With<LevelText>>,
+    mut query_fill: Query<&mut Style, With<ExperienceFill>>,
+) {
+    let Ok(experience) = query_player.get_single() else {
+	return;
+    };
+    query_text.single_mut().sections[0].value = format!("Level {}", experience.level);
+    let ratio = experience.current as f32 / progression.needed(experience.level) as f32;
+    query_fill.single_mut().size.width = Val::Percent(ratio * 100.0);
+}
+
+// System that shows the coins of the player on the HUD
+pub fn update_coin_counter(currency: Res<Currency>, mut query_counter: Query<&mut Text, With<CoinCounter>>) {
+    if currency.is_changed() {
+	query_counter.single_mut().sections[0].value = currency.coins.to_string();
+    }
+}
+
+// System that shows how many boars are left to defeat, the tamed ones are dealt with and the boss is one of them
+pub fn update_boar_counter(
+    query_boar: Query<(), (With<Boar>, With<Hostile>)>,
+    mut query_counter: Query<&mut Text, With<BoarCounter>>,
+) {
+    let count = query_boar.iter().count().to_string();
+    for mut text in &mut query_counter {
+	if text.sections[0].value != count {
+	    text.sections[0].value = count.clone();
+	}
+    }
+}
+
+// Where the middle of the indicator of a hit from the given direction goes, in a view of the given size counted
+// from its top left corner, and whether the bar stands upright on a side of the view
+pub fn damage_indicator_position(direction: Vec2, view_size: Vec2) -> (Vec2, bool) {
+    // The UI counts down from the top
+    let direction = Vec2::new(direction.x, -direction.y);
+    let half_size = view_size / 2.0 - DAMAGE_INDICATOR_MARGIN;
+    let on_side = direction.x.abs() * half_size.y > direction.y.abs() * half_size.x;
+    let reach = if on_side { half_size.x / direction.x.abs() } else { half_size.y / direction.y.abs() };
+    (view_size / 2.0 + direction * reach, on_side)
+}
+
+// System that shows where the hits on the player came from, at the edge of the game view
+pub fn show_damage_direction(
+    mut commands: Commands,
+    accessibility: Res<Accessibility>,
+    mut damage_events: EventReader<DamageEvent>,
+    query_player: Query<(Entity, &Transform), With<Player>>,
+    query_camera: Query<&Camera, With<MapCamera>>,
+) {
+    let Ok((player, player_transform)) = query_player.get_single() else {
+	return;
+    };
+    let Some((view_min, view_max)) = query_camera.get_single().ok().and_then(|camera| camera.logical_viewport_rect()) else {
+	return;
+    };
+    for event in damage_events.iter().filter(|event| event.target == player) {
+	let direction = event.source - player_transform.translation.truncate();
+	if direction == Vec2::ZERO {
+	    continue;
+	}
+	let (center, upright) = damage_indicator_position(direction, view_max - view_min);
+	let size = if upright { Vec2::new(DAMAGE_INDICATOR_SIZE.y, DAMAGE_INDICATOR_SIZE.x) } else { DAMAGE_INDICATOR_SIZE };
+	let corner = view_min + center - size / 2.0;
+	commands.spawn((
+	    NodeBundle {
+		style: Style {
+		    size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+		    position_type: PositionType::Absolute,
+		    position: UiRect {
+			left: Val::Px(corner.x),
+			top: Val::Px(corner.y),
+			..default()
+		    },
+		    ..default()
+		},
+		background_color: (*accessibility.low_health_color().set_a(DAMAGE_INDICATOR_ALPHA)).into(),
+		..default()
+	    },
+	    Tween::alpha(DAMAGE_INDICATOR_ALPHA, 0.0).over(DAMAGE_INDICATOR_TIME).despawn_on_complete(),
+	    DamageIndicator,
+	));
+    }
+}
+
+// System that shows as many hearts as lives left
+pub fn update_life_icons(lives: Res<Lives>, mut query_icon: Query<(&LifeIcon, &mut Visibility)>) {
+    for (icon, mut visibility) in &mut query_icon {
+	visibility.is_visible = icon.0 < lives.0;
+    }
+}
+
+// System that shows the health of the boss while it is there, in the color of the palette
+pub fn update_boss_health_bar(
+    accessibility: Res<Accessibility>,
+    query_boss: Query<&HealthPoints, With<Boss>>,
+    mut query_bar: Query<&mut Visibility, With<BossHealthBar>>,
+    mut query_fill: Query<(&mut Style, &mut BackgroundColor), With<BossHealthFill>>,
+) {
+    let mut visibility = query_bar.single_mut();
+    let Ok(health) = query_boss.get_single() else {
+	visibility.is_visible = false;
+	return;
+    };
+    visibility.is_visible = true;
+    let (mut style, mut color) = query_fill.single_mut();
+    style.size.width = Val::Percent(health.ratio() * 100.0);
+    *color = accessibility.boss_bar_color().into();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    // A hit shows on the side of the view it came from, the view counts down from its top left corner
+    #[test]
+    fn damage_indicator_is_on_the_side_of_the_hit() {
+	let view_size = Vec2::new(800.0, 600.0);
+	let margin = DAMAGE_INDICATOR_MARGIN;
+	assert_eq!(damage_indicator_position(Vec2::new(-5.0, 0.0), view_size), (Vec2::new(margin, 300.0), true));
+	assert_eq!(damage_indicator_position(Vec2::new(0.0, 3.0), view_size), (Vec2::new(400.0, margin), false));
+	let (corner, upright) = damage_indicator_position(Vec2::new(1.0, -1.0), view_size);
+	assert_eq!(corner, Vec2::new(400.0 + 300.0 - margin, 600.0 - margin));
+	assert!(!upright);
+    }
+
+    // Each key of the pause menu switches its own option, and pressing it again switches it back
+    #[test]
+    fn accessibility_keys_toggle_their_option() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(toggle_accessibility));
+	let options = |test: &TestApp| {
+	    let accessibility = test.app.world.resource::<Accessibility>();
+	    [accessibility.reduced_motion, accessibility.toggle_sprint, accessibility.colorblind,
+	     accessibility.invert_x, accessibility.invert_y, accessibility.aim_assist]
+	};
+	let keys = [REDUCED_MOTION_KEY, SPRINT_MODE_KEY, COLORBLIND_KEY, INVERT_X_KEY, INVERT_Y_KEY, AIM_ASSIST_KEY];
+	for (index, key) in keys.into_iter().enumerate() {
+	    let mut expected = [false; 6];
+	    expected[index] = true;
+	    for on in [true, false] {
+		test.press(key);
+		test.step_fixed(2);
+		test.release(key);
+		assert_eq!(options(&test), if on { expected } else { [false; 6] }, "{:?}", key);
+	    }
+	}
+    }
+
+    // The volumes go by steps, from silent to full, and the settings give the volume of each channel
+    #[test]
+    fn volume_keys_change_their_volume() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(change_volumes));
+	let tap = |test: &mut TestApp, key: KeyCode, times: usize| {
+	    for _ in 0..times {
+		test.press(key);
+		test.step_fixed(1);
+		test.release(key);
+	    }
+	};
+	let defaults = Settings::default();
+
+	tap(&mut test, VOLUME_KEYS[1][0], 1);
+	tap(&mut test, VOLUME_KEYS[2][1], 5);
+	tap(&mut test, VOLUME_KEYS[0][0], 3);
+	let settings = test.app.world.resource::<Settings>();
+	assert!((settings.music_volume - (defaults.music_volume - VOLUME_STEP)).abs() < 1e-5);
+	assert_eq!(settings.sfx_volume, 1.0);
+	assert!((settings.master_volume - 0.7).abs() < 1e-5);
+	assert!((settings.volume(AudioChannel::Music) - 0.7 * settings.music_volume).abs() < 1e-5);
+
+	tap(&mut test, VOLUME_KEYS[0][0], 20);
+	let settings = test.app.world.resource::<Settings>();
+	assert_eq!(settings.master_volume, 0.0);
+	assert_eq!(settings.volume(AudioChannel::Sfx), 0.0);
+    }
+
+    // The colorblind palette changes every color showing health, the boss bar as well
+    #[test]
+    fn colorblind_palette_changes_the_health_colors() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(update_boss_health_bar));
+	test.app.world.spawn((Boss::new(), HealthPoints::new(BOSS_HEALTH)));
+	test.app.world.spawn((NodeBundle::default(), BossHealthBar));
+	let fill = test.app.world.spawn((NodeBundle { background_color: BOSS_BAR_COLOR.into(), ..default() }, BossHealthFill)).id();
+	let fill_color = |test: &TestApp| test.app.world.get::<BackgroundColor>(fill).unwrap().0;
+
+	test.step_fixed(1);
+	assert_eq!(fill_color(&test), BOSS_BAR_COLOR);
+
+	test.app.world.resource_mut::<Accessibility>().colorblind = true;
+	test.step_fixed(1);
+	let accessibility = test.app.world.resource::<Accessibility>();
+	assert_eq!(fill_color(&test), BOSS_BAR_COLORBLIND_COLOR);
+	assert_eq!(accessibility.boss_bar_color(), BOSS_BAR_COLORBLIND_COLOR);
+	assert_eq!(accessibility.low_health_color(), VIGNETTE_COLORBLIND_COLOR);
+    }
+}
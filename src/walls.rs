@@ -0,0 +1,424 @@
+// The walls around the map and inside it, with the level editor
+
+use crate::*;
+
+// Level editor settings, the editor is enabled with --editor.
+// Walls are placed one grid cell at a time, with the mouse
+pub const EDITOR_GRID: f32 = 32.0;
+pub const EDITOR_ROTATE_KEY: KeyCode = KeyCode::Tab;
+pub const EDITOR_SAVE_KEY: KeyCode = KeyCode::F5;
+pub const EDITOR_RELOAD_KEY: KeyCode = KeyCode::F9;
+
+// A wall inside the play area
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct WallSegment {
+    pub position: Vec2,
+    pub size: Vec2,
+}
+
+// Walls are a bundle consisting of a sprite and a collider
+#[derive(Bundle)]
+pub struct WallBundle {
+    pub sprite_bundle: SpriteBundle,
+    pub collider: Collider,
+    pub texture: WallTexture,
+}
+
+// Marker for the walls of the map, the ones the level editor can change
+#[derive(Component)]
+pub struct InsideWall;
+
+// Marker for the four walls around the map, they are built again when the play area changes
+#[derive(Component)]
+pub struct OuterWall;
+
+// Sent when the walls change, with the new play area
+pub struct PlayAreaChanged(pub PlayArea);
+
+// The level editor is only there when enabled
+#[derive(Resource)]
+pub struct LevelEditor {
+    // Where the map is saved
+    pub map_path: String,
+    // Orientation of the walls placed next
+    pub vertical: bool,
+}
+
+// Texture repeated along a wall, the wall is a plain WALL_COLOR rectangle without one
+#[derive(Component)]
+pub struct WallTexture {
+    pub texture: Option<Handle<Image>>,
+    // The texture is mirrored on the bottom and right walls, so that every wall faces the play area
+    pub flip: bool,
+}
+
+// To better manipulate the walls, we will consider the four separately
+pub enum WallLocation {
+    Top,
+    Left,
+    Bottom,
+    Right,
+    // The walls of the map, inside the play area
+    Inside(WallSegment),
+}
+
+impl WallLocation {
+    // Outputs the location of the (middle) of a wall
+    pub fn position(&self, play_area: &PlayArea) -> Vec2 {
+	let walls = play_area.walls;
+	match self {
+	    | WallLocation::Top => Vec2::new(walls.center().x, walls.max.y),
+	    | WallLocation::Left => Vec2::new(walls.min.x, walls.center().y),
+	    | WallLocation::Bottom => Vec2::new(walls.center().x, walls.min.y),
+	    | WallLocation::Right => Vec2::new(walls.max.x, walls.center().y),
+	    | WallLocation::Inside(segment) => segment.position,
+	}
+    }
+
+    // Outputs the length of the wall
+    pub fn size(&self, play_area: &PlayArea) -> Vec2 {
+	let walls = play_area.walls;
+	match self {
+	    WallLocation::Left | WallLocation::Right => Vec2::new(WALL_THICKNESS, walls.height() - WALL_THICKNESS),
+	    WallLocation::Top | WallLocation::Bottom => Vec2::new(walls.width() - WALL_THICKNESS, WALL_THICKNESS),
+	    WallLocation::Inside(segment) => segment.size,
+	}
+    }
+
+    // Outputs the texture matching the direction of the wall
+    pub fn texture(&self, assets: &GameAssets) -> Handle<Image> {
+	match self {
+	    WallLocation::Left | WallLocation::Right => assets.wall_vertical.clone(),
+	    WallLocation::Top | WallLocation::Bottom => assets.wall_horizontal.clone(),
+	    WallLocation::Inside(segment) if segment.size.y > segment.size.x => assets.wall_vertical.clone(),
+	    WallLocation::Inside(_) => assets.wall_horizontal.clone(),
+	}
+    }
+}
+
+impl WallBundle {
+    pub fn new(location: WallLocation, texture: Option<Handle<Image>>, play_area: &PlayArea) -> WallBundle {
+	WallBundle {
+	    sprite_bundle: SpriteBundle{
+		transform: Transform{
+		    // Not sure why we need to transform into Vec3 ??
+		    translation: location.position(play_area).extend(0.0),
+		    scale: location.size(play_area).extend(1.0),
+		    ..default()
+		},
+		sprite: Sprite {
+		    color: WALL_COLOR,
+		    // The size of the wall is given by the scale
+		    custom_size: Some(Vec2::ONE),
+		    ..default()
+		},
+		..default()
+	    },
+
+	    collider: Collider,
+	    texture: WallTexture {
+		texture,
+		flip: matches!(location, WallLocation::Bottom | WallLocation::Right),
+	    },
+	}
+    }
+}
+
+// Cell of the editor grid under a point of the world, as the wall placed there:
+// horizontal walls lie on the horizontal grid lines, and vertical walls on the vertical ones
+pub fn editor_segment(point: Vec2, vertical: bool) -> WallSegment {
+    let cell = (point / EDITOR_GRID).floor();
+    let line = (point / EDITOR_GRID).round();
+    if vertical {
+	WallSegment {
+	    position: Vec2::new(line.x, cell.y + 0.5) * EDITOR_GRID,
+	    size: Vec2::new(WALL_THICKNESS, EDITOR_GRID),
+	}
+    } else {
+	WallSegment {
+	    position: Vec2::new(cell.x + 0.5, line.y) * EDITOR_GRID,
+	    size: Vec2::new(EDITOR_GRID, WALL_THICKNESS),
+	}
+    }
+}
+
+// System of the level editor that adds and removes walls where the mouse clicks
+pub fn edit_walls(
+    mut commands: Commands,
+    editor: Option<ResMut<LevelEditor>>,
+    assets: Res<GameAssets>,
+    play_area: Res<PlayArea>,
+    windows: Res<Windows>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    query_camera: Query<(&Camera, &GlobalTransform), With<MapCamera>>,
+    query_wall: Query<(Entity, &Transform), With<InsideWall>>,
+) {
+    let Some(mut editor) = editor else {
+	return;
+    };
+    if keyboard_input.just_pressed(EDITOR_ROTATE_KEY) {
+	editor.vertical = !editor.vertical;
+    }
+
+    // The cursor, from the window to the world
+    let Ok((camera, camera_transform)) = query_camera.get_single() else {
+	return;
+    };
+    let Some(cursor) = windows.get_primary().and_then(|window| cursor_in_view(window, camera)) else {
+	return;
+    };
+    let Some(point) = camera.viewport_to_world(camera_transform, cursor).map(|ray| ray.origin.truncate()) else {
+	return;
+    };
+
+    if mouse_input.just_pressed(MouseButton::Left) {
+	let segment = editor_segment(point, editor.vertical);
+	let taken = query_wall.iter().any(|(_, wall_transform)| {
+	    wall_transform.translation.truncate() == segment.position && wall_transform.scale.truncate() == segment.size
+	});
+	if !taken {
+	    let location = WallLocation::Inside(segment);
+	    let texture = location.texture(&assets);
+	    commands.spawn((WallBundle::new(location, Some(texture), &play_area), InsideWall));
+	}
+    }
+    if mouse_input.just_pressed(MouseButton::Right) {
+	for (wall, wall_transform) in &query_wall {
+	    let half_size = wall_transform.scale.truncate() / 2.0;
+	    let offset = (point - wall_transform.translation.truncate()).abs();
+	    if offset.x <= half_size.x && offset.y <= half_size.y {
+		commands.entity(wall).despawn_recursive();
+	    }
+	}
+    }
+}
+
+// System of the level editor that writes the walls in the map file
+pub fn save_walls(
+    editor: Option<Res<LevelEditor>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut map: ResMut<MapData>,
+    query_wall: Query<&Transform, With<InsideWall>>,
+) {
+    let Some(editor) = editor else {
+	return;
+    };
+    if !keyboard_input.just_pressed(EDITOR_SAVE_KEY) {
+	return;
+    }
+    map.walls = query_wall.iter()
+	.map(|wall_transform| WallSegment {
+	    position: wall_transform.translation.truncate(),
+	    size: wall_transform.scale.truncate(),
+	})
+	.collect();
+    map.save_walls(&editor.map_path);
+}
+
+// System of the level editor that reads the walls again from the map file, to undo the changes since the last save
+// or to see the changes made to the file
+pub fn reload_walls(
+    mut commands: Commands,
+    editor: Option<Res<LevelEditor>>,
+    assets: Res<GameAssets>,
+    play_area: Res<PlayArea>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut map: ResMut<MapData>,
+    mut play_area_events: EventWriter<PlayAreaChanged>,
+    query_wall: Query<Entity, With<InsideWall>>,
+) {
+    let Some(editor) = editor else {
+	return;
+    };
+    if !keyboard_input.just_pressed(EDITOR_RELOAD_KEY) {
+	return;
+    }
+    map.walls = MapData::load(&editor.map_path).walls;
+    for wall in &query_wall {
+	commands.entity(wall).despawn_recursive();
+    }
+    spawn_inside_walls(&mut commands, &assets, &play_area, &map.walls);
+    play_area_events.send(PlayAreaChanged(*play_area));
+}
+
+pub fn spawn_inside_walls(commands: &mut Commands, assets: &GameAssets, play_area: &PlayArea, segments: &[WallSegment]) {
+    for segment in segments {
+	let location = WallLocation::Inside(*segment);
+	let texture = location.texture(assets);
+	commands.spawn((WallBundle::new(location, Some(texture), play_area), InsideWall));
+    }
+}
+
+pub fn spawn_outer_walls(commands: &mut Commands, assets: &GameAssets, play_area: &PlayArea) {
+    for location in [WallLocation::Top, WallLocation::Left, WallLocation::Bottom, WallLocation::Right] {
+	let texture = location.texture(assets);
+	commands.spawn((WallBundle::new(location, Some(texture), play_area), OuterWall));
+    }
+}
+
+// System that moves the walls around the map to the edges of its ground fills, which can go far past the background,
+// or else to the edges of the background, centered on it, once its size is known
+pub fn fit_walls_to_map(
+    mut commands: Commands,
+    images: Res<Assets<Image>>,
+    assets: Res<GameAssets>,
+    map: Res<MapData>,
+    mut play_area: ResMut<PlayArea>,
+    mut play_area_events: EventWriter<PlayAreaChanged>,
+    query_background: Query<&Handle<Image>, With<Background>>,
+    query_wall: Query<Entity, With<OuterWall>>,
+) {
+    let walls = match map.ground_area() {
+	Some(area) => area,
+	None => {
+	    let Some(image) = query_background.get_single().ok().and_then(|handle| images.get(handle)) else {
+		return;
+	    };
+	    Rect::from_center_size(Vec2::ZERO, image.size())
+	},
+    };
+    let fitted = PlayArea { walls };
+    if *play_area == fitted {
+	return;
+    }
+    *play_area = fitted;
+    for wall in &query_wall {
+	commands.entity(wall).despawn_recursive();
+    }
+    spawn_outer_walls(&mut commands, &assets, &play_area);
+    play_area_events.send(PlayAreaChanged(*play_area));
+}
+
+// System that brings the characters left outside back inside the walls, when they move
+pub fn keep_inside_play_area(
+    mut play_area_events: EventReader<PlayAreaChanged>,
+    mut query_character: Query<(&mut Transform, Option<&PlayerSize>), With<HealthPoints>>,
+) {
+    let Some(PlayAreaChanged(play_area)) = play_area_events.iter().last() else {
+	return;
+    };
+    for (mut transform, player_size) in &mut query_character {
+	let bounds = play_area.bounds(player_size.map_or(CHARACTER_SIZE, |size| size.0));
+	transform.translation.x = transform.translation.x.clamp(bounds.min.x, bounds.max.x);
+	transform.translation.y = transform.translation.y.clamp(bounds.min.y, bounds.max.y);
+    }
+}
+
+// System that covers the new textured walls with a row of tiles
+pub fn tile_wall_textures(
+    mut commands: Commands,
+    mut query_wall: Query<(Entity, &Transform, &WallTexture, &mut Sprite), Added<WallTexture>>,
+) {
+    for (wall, wall_transform, wall_texture, mut wall_sprite) in &mut query_wall {
+	let Some(texture) = &wall_texture.texture else {
+	    continue;
+	};
+	// The wall sprite is hidden but it keeps its size in the scale, for the collisions
+	wall_sprite.color = Color::NONE;
+	let size = wall_transform.scale.truncate();
+	let vertical = size.y > size.x;
+	let (length, thickness) = if vertical { (size.y, size.x) } else { (size.x, size.y) };
+
+	// The tiles are squares as wide as the wall, slightly stretched so that they cover it exactly
+	let count = (length / thickness).ceil().max(1.0);
+	let tile_length = length / count;
+	commands.entity(wall).with_children(|parent| {
+	    for i in 0..count as usize {
+		let offset = -length / 2.0 + (i as f32 + 0.5) * tile_length;
+		let (position, tile_size) = if vertical {
+		    (Vec2::new(0.0, offset), Vec2::new(thickness, tile_length))
+		} else {
+		    (Vec2::new(offset, 0.0), Vec2::new(tile_length, thickness))
+		};
+		parent.spawn(SpriteBundle {
+		    texture: texture.clone(),
+		    sprite: Sprite {
+			custom_size: Some(tile_size),
+			flip_x: wall_texture.flip && vertical,
+			flip_y: wall_texture.flip && !vertical,
+			..default()
+		    },
+		    // The children inherit the scale of the wall, so it is undone here
+		    transform: Transform {
+			translation: (position / size).extend(0.0),
+			scale: (1.0 / size).extend(1.0),
+			..default()
+		    },
+		    ..default()
+		});
+	    }
+	});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    // The walls closing in around the player push them back inside
+    #[test]
+    fn player_is_kept_inside_a_smaller_play_area() {
+	let mut test = TestApp::new();
+	test.add_fixed_systems(SystemSet::new().with_system(keep_inside_play_area));
+	let player = test.spawn_player(Vec2::new(RIGHT_WALL, TOP_WALL) / 2.0);
+	let play_area = PlayArea {
+	    walls: Rect::from_center_size(Vec2::ZERO, Vec2::splat(200.0)),
+	};
+	*test.app.world.resource_mut::<PlayArea>() = play_area;
+	test.app.world.send_event(PlayAreaChanged(play_area));
+
+	test.step_fixed(1);
+	assert_eq!(test.position(player), play_area.bounds(PLAYER_SIZE).max);
+    }
+
+    // Walking diagonally into a wall stops the player at its side, the other direction keeps its part of the speed along it:
+    // up-right into a wall on the right still goes up, and into a wall above still goes right
+    #[test]
+    fn player_slides_along_a_wall() {
+	let length = 1000.0;
+	let walls = [
+	    WallSegment { position: Vec2::new(PLAYER_SPEED / 2.0, 0.0), size: Vec2::new(WALL_THICKNESS, length) },
+	    WallSegment { position: Vec2::new(0.0, PLAYER_SPEED / 2.0), size: Vec2::new(length, WALL_THICKNESS) },
+	];
+	for (axis, segment) in walls.into_iter().enumerate() {
+	    let mut test = TestApp::new();
+	    test.add_fixed_systems(SystemSet::new()
+				   .with_system(move_player)
+				   .with_system(check_for_collisions.after(move_player)));
+	    let player = test.spawn_player(Vec2::ZERO);
+	    test.app.world.spawn((WallBundle::new(WallLocation::Inside(segment), None, &PlayArea::default()), InsideWall));
+
+	    test.press(KeyCode::D);
+	    test.press(KeyCode::W);
+	    test.step_fixed(test.ticks(1.0));
+	    let position = test.position(player);
+	    let other = 1 - axis;
+	    assert_eq!(position[axis], segment.position[axis] - (PLAYER_SIZE[axis] + segment.size[axis]) / 2.0);
+	    assert!((position[other] - PLAYER_SPEED * std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01, "{position}");
+	}
+    }
+
+    // The walls go around the whole ground of the stress map, far past the background, and the player can go there
+    #[test]
+    fn play_area_fits_the_ground_of_the_map() {
+	let mut test = TestApp::new();
+	test.app.add_asset::<Image>()
+	    .insert_resource(GameAssets { wall_horizontal: Handle::default(), wall_vertical: Handle::default() })
+	    .insert_resource(MapData::load("maps/stress.ron"));
+	test.add_fixed_systems(SystemSet::new()
+			       .with_system(fit_walls_to_map)
+			       .with_system(keep_inside_play_area.after(fit_walls_to_map)));
+	let far = Vec2::splat(10000.0);
+	let player = test.spawn_player(far);
+
+	test.step_fixed(2);
+	let ground = test.app.world.resource::<MapData>().ground_area().unwrap();
+	assert_eq!(ground, Rect::new(-16384.0, -16384.0, 16384.0, 16384.0));
+	assert_eq!(test.app.world.resource::<PlayArea>().walls, ground);
+	assert_eq!(test.position(player), far);
+	let mut query_wall = test.app.world.query_filtered::<(), With<OuterWall>>();
+	assert_eq!(query_wall.iter(&test.app.world).count(), 4);
+    }
+}
@@ -0,0 +1,1699 @@
+// The map: its spawn points, tiles and props, the prefabs, and the level spawned from them
+
+use crate::*;
+
+// Safe zone settings: around the houses the player slowly heals, in health per second, and the hostile animals
+// stay out. They give up the chase instead. The zone is a faint ring on the ground
+pub const SAFE_ZONE_RADIUS: f32 = 160.0;
+pub const SAFE_ZONE_REGEN: f32 = 2.0;
+pub const SAFE_ZONE_COLOR: Color = Color::rgba(0.6, 1.0, 0.6, 0.15);
+// Just above the ground, relative to the house
+pub const SAFE_ZONE_Z: f32 = -0.09;
+
+// Map settings, the spawn positions are used when the map does not define them
+pub const MAP_FILE: &str = "maps/meadow.ron";
+pub const PLAYER_SPAWN: Vec2 = Vec2::new(350.0, 350.0);
+pub const HOUSE_SPAWN: Vec2 = Vec2::new(150.0, -200.0);
+pub const BOAR_SPAWN: Vec2 = Vec2::new(-360.0, 270.0);
+
+// Crate settings, the crates are only where the map puts them
+pub const CRATE_SIZE: Vec2 = Vec2::new(32.0, 32.0);
+pub const CRATE_HEALTH: f32 = 20.0;
+pub const CRATE_SPLINTER_COUNT: usize = 16;
+
+// Tiles and props are spawned by chunks, only around the camera
+pub const CHUNK_SIZE: f32 = 512.0;
+// The tilemap is under the ground fills
+pub const TILEMAP_Z: f32 = 0.005;
+pub const GROUND_Z: f32 = 0.01;
+pub const PROP_Z: f32 = 0.05;
+
+// Shadow settings: an oval at the feet of the characters, drawn over the ground and the props
+pub const SHADOW_Z: f32 = 0.07;
+pub const SHADOW_OFFSET: f32 = -26.0;
+pub const SHADOW_SIZE: Vec2 = Vec2::new(40.0, 14.0);
+pub const SHADOW_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.35);
+// The shadow shrinks when the character is raised above the ground (half the size at this height)
+pub const SHADOW_FALLOFF_HEIGHT: f32 = 40.0;
+
+// In free play, a boar comes out of a den every day. How they grow with the days is in the free play file
+// (in the assets folder). They never appear this close to the player
+pub const FREE_PLAY_FILE: &str = "config/free_play.ron";
+pub const DEN_MIN_DISTANCE: f32 = 400.0;
+
+// All the character sprites are 64x64
+pub const CHARACTER_SIZE: Vec2 = Vec2::new(64.0, 64.0);
+
+// Foreground settings (roofs, tree tops)
+pub const OVERLAY_Z: f32 = 0.4;
+// What the characters and the houses are made of, a file per prefab in the assets folder
+pub const PREFAB_FOLDER: &str = "prefabs";
+pub const OVERLAY_FADED_ALPHA: f32 = 0.5;
+pub const OVERLAY_FADE_TIME: f32 = 0.2;
+
+// Walls settings, the walls follow the edges of the background once it is loaded. These are used until then
+pub const WALL_THICKNESS: f32 = 10.0;
+pub const TOP_WALL: f32 = 540.0;
+pub const LEFT_WALL: f32 = -960.0;
+pub const BOTTOM_WALL: f32 = -540.0;
+pub const RIGHT_WALL: f32 = 960.0;
+pub const WALL_COLOR: Color = Color::rgb(0.0, 0.0, 0.0);
+
+// A circle around its entity where the player heals, and the hostile animals do not come in
+#[derive(Component)]
+pub struct SafeZone {
+    pub radius: f32,
+}
+
+impl SafeZone {
+    pub fn contains(&self, center: Vec2, position: Vec2) -> bool {
+	center.distance(position) < self.radius
+    }
+}
+
+// The name of a character, the dialogues show it
+#[derive(Component)]
+pub struct DisplayName(pub String);
+
+// Where the walls around the map are: the rectangle goes through the middle of the four walls
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct PlayArea {
+    pub walls: Rect,
+}
+
+impl Default for PlayArea {
+    fn default() -> PlayArea {
+	PlayArea {
+	    walls: Rect::new(LEFT_WALL, BOTTOM_WALL, RIGHT_WALL, TOP_WALL),
+	}
+    }
+}
+
+impl PlayArea {
+    // Where the center of a box of the given size can be, inside the walls around the map
+    pub fn bounds(&self, size: Vec2) -> Rect {
+	let margin = (WALL_THICKNESS + size) / 2.0;
+	Rect {
+	    min: self.walls.min + margin,
+	    max: self.walls.max - margin,
+	}
+    }
+}
+
+// The shadow of a character, it is a child of the character
+#[derive(Component)]
+pub struct Shadow;
+
+// Sprite of a shadow, for a character at the given depth
+pub fn shadow_bundle(texture: Handle<Image>, parent_z: f32) -> (SpriteBundle, Shadow) {
+    (
+	SpriteBundle {
+	    texture,
+	    sprite: Sprite {
+		color: SHADOW_COLOR,
+		custom_size: Some(SHADOW_SIZE),
+		..default()
+	    },
+	    // The translation is relative to the character
+	    transform: Transform::from_xyz(0.0, SHADOW_OFFSET, SHADOW_Z - parent_z),
+	    ..default()
+	},
+	Shadow,
+    )
+}
+
+// The name of the spawn point of a character, to find what is saved about it
+#[derive(Component)]
+pub struct SpawnId(pub String);
+
+// Sprites drawn above the characters, which become see-through when the player is underneath
+#[derive(Component)]
+pub struct Overlay {
+    pub size: Vec2,
+}
+
+// How the boars of free play grow with the days
+#[derive(Resource, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct FreePlay {
+    // Added to the multipliers of the health and the speed of the new boars each day, they are 1 on the first day
+    pub health_per_day: f32,
+    pub speed_per_day: f32,
+    // No boar comes out of the dens while there are this many around
+    pub max_boars: usize,
+}
+
+impl Default for FreePlay {
+    fn default() -> FreePlay {
+	FreePlay {
+	    health_per_day: 0.25,
+	    speed_per_day: 0.05,
+	    max_boars: 6,
+	}
+    }
+}
+
+impl FreePlay {
+    // Reads the free play settings from the assets folder, the default ones are used if they cannot be read
+    pub fn load() -> FreePlay {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(FREE_PLAY_FILE);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the free play settings {}: {error}", full_path.display());
+		return FreePlay::default();
+	    },
+	};
+	match ron::from_str(&contents) {
+	    Ok(free_play) => free_play,
+	    Err(error) => {
+		warn!("Could not parse the free play settings {}: {error}", full_path.display());
+		FreePlay::default()
+	    },
+	}
+    }
+
+    pub fn health_scale(&self, day: u32) -> f32 {
+	1.0 + day.saturating_sub(1) as f32 * self.health_per_day
+    }
+
+    pub fn speed_scale(&self, day: u32) -> f32 {
+	1.0 + day.saturating_sub(1) as f32 * self.speed_per_day
+    }
+}
+
+// The different things that can be placed from the map data
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpawnKind {
+    Player,
+    House,
+    Boar,
+    Crate,
+    Pickup,
+    Boss,
+    Chest,
+    // Where the boars of free play come from
+    Den,
+    // Anything from the prefabs folder, named by the spawn point
+    Prefab,
+    // A character from the prefabs folder, with the behavior of its kind
+    Npc,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SpawnPoint {
+    pub kind: SpawnKind,
+    pub position: Vec2,
+    // Name of the patrol path followed by this character when wandering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patrol: Option<String>,
+    // Item lying there, for a pickup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item: Option<ItemKind>,
+    // Name that does not change from a game to the next, to find what is saved about it (chests)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // Name of the prefab placed there, for a prefab or an NPC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<String>,
+}
+
+// A loop of points walked in order, instead of wandering randomly
+#[derive(Component, Deserialize, Serialize, Clone, Debug)]
+pub struct PatrolPath {
+    pub points: Vec<Vec2>,
+    // Index of the point currently walked to
+    #[serde(skip)]
+    pub next: usize,
+}
+
+// The kind of ground, it decides the sound of the footsteps
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Terrain {
+    #[default]
+    Grass,
+    Dirt,
+}
+
+// A texture tiled over a whole area of the map (grass, ...)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct GroundFill {
+    pub texture: String,
+    // Part of the texture used for one tile
+    pub rect: Rect,
+    pub min: Vec2,
+    pub max: Vec2,
+    #[serde(default)]
+    pub terrain: Terrain,
+}
+
+impl GroundFill {
+    // Center of every tile of the fill whose center is inside the area
+    pub fn tiles_in(&self, area: Rect) -> Vec<Vec2> {
+	let tile_size = self.rect.size();
+	let columns = ((self.max.x - self.min.x) / tile_size.x).floor().max(0.0);
+	let rows = ((self.max.y - self.min.y) / tile_size.y).floor().max(0.0);
+	// Tile i has its center at min + (i + 0.5) * tile_size
+	let first = ((area.min - self.min) / tile_size - 0.5).ceil().max(Vec2::ZERO);
+	let last = ((area.max - self.min) / tile_size - 0.5).ceil().min(Vec2::new(columns, rows));
+
+	let mut tiles = Vec::new();
+	for i in first.x as i32..last.x as i32 {
+	    for j in first.y as i32..last.y as i32 {
+		tiles.push(self.min + (Vec2::new(i as f32, j as f32) + 0.5) * tile_size);
+	    }
+	}
+	tiles
+    }
+}
+
+// A grid of tiles from a tileset, it replaces the background image. The rows go from the top of the map down,
+// each number is a tile of the tileset, counted row by row from its top left corner
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Tilemap {
+    pub tileset: String,
+    pub tile_size: Vec2,
+    // Tiles in a row of the tileset
+    pub columns: usize,
+    // Bottom left corner of the grid
+    pub origin: Vec2,
+    pub tiles: Vec<Vec<usize>>,
+    // The tiles that are not grass
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub terrains: HashMap<usize, Terrain>,
+}
+
+impl Tilemap {
+    // The area covered by the tiles, the longest row gives the width
+    pub fn area(&self) -> Rect {
+	let columns = self.tiles.iter().map(Vec::len).max().unwrap_or(0);
+	let size = Vec2::new(columns as f32, self.tiles.len() as f32) * self.tile_size;
+	Rect::from_corners(self.origin, self.origin + size)
+    }
+
+    // Part of the tileset used for a tile
+    pub fn tile_rect(&self, tile: usize) -> Rect {
+	let columns = self.columns.max(1);
+	let min = Vec2::new((tile % columns) as f32, (tile / columns) as f32) * self.tile_size;
+	Rect::from_corners(min, min + self.tile_size)
+    }
+
+    // The tile of the grid at (column, row), row 0 being at the bottom of the map
+    pub fn tile(&self, column: usize, row: usize) -> Option<usize> {
+	let line = self.tiles.len().checked_sub(row + 1)?;
+	self.tiles[line].get(column).copied()
+    }
+
+    pub fn terrain_at(&self, position: Vec2) -> Option<Terrain> {
+	let cell = ((position - self.origin) / self.tile_size).floor();
+	if cell.x < 0.0 || cell.y < 0.0 {
+	    return None;
+	}
+	let tile = self.tile(cell.x as usize, cell.y as usize)?;
+	Some(self.terrains.get(&tile).copied().unwrap_or_default())
+    }
+
+    // Center and tile of every cell whose center is inside the area, like the ground fills
+    pub fn tiles_in(&self, area: Rect) -> Vec<(Vec2, usize)> {
+	let grid = self.area().size() / self.tile_size;
+	let first = ((area.min - self.origin) / self.tile_size - 0.5).ceil().max(Vec2::ZERO);
+	let last = ((area.max - self.origin) / self.tile_size - 0.5).ceil().min(grid);
+
+	let mut tiles = Vec::new();
+	for i in first.x as usize..last.x as usize {
+	    for j in first.y as usize..last.y as usize {
+		if let Some(tile) = self.tile(i, j) {
+		    tiles.push((self.origin + (Vec2::new(i as f32, j as f32) + 0.5) * self.tile_size, tile));
+		}
+	    }
+	}
+	tiles
+    }
+}
+
+// A decoration placed on the map
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Prop {
+    pub texture: String,
+    pub position: Vec2,
+}
+
+#[derive(Resource, Deserialize, Serialize, Default, Debug)]
+pub struct MapData {
+    pub spawn_points: Vec<SpawnPoint>,
+    #[serde(default)]
+    pub patrol_paths: HashMap<String, PatrolPath>,
+    #[serde(default)]
+    pub ground: Vec<GroundFill>,
+    #[serde(default)]
+    pub props: Vec<Prop>,
+    #[serde(default)]
+    pub walls: Vec<WallSegment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tilemap: Option<Tilemap>,
+}
+
+impl MapData {
+    // Reads a map from the assets folder, an empty map is returned if it cannot be read
+    pub fn load(path: &str) -> MapData {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(path);
+	let contents = match fs::read_to_string(&full_path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		warn!("Could not read the map {}: {error}", full_path.display());
+		return MapData::default();
+	    },
+	};
+	match ron::from_str(&contents) {
+	    Ok(map) => map,
+	    Err(error) => {
+		warn!("Could not parse the map {}: {error}", full_path.display());
+		MapData::default()
+	    },
+	}
+    }
+
+    // The area covered by the ground fills, none without them
+    pub fn ground_area(&self) -> Option<Rect> {
+	self.ground.iter()
+	    .map(|fill| Rect::from_corners(fill.min, fill.max))
+	    .reduce(|area, fill_area| area.union(fill_area))
+    }
+
+    // Writes the walls in the map file of the assets folder. The rest of the file is kept as written by hand,
+    // with its comments
+    pub fn save_walls(&self, path: &str) {
+	let full_path = FileAssetIo::get_base_path().join("assets").join(path);
+	let Some(contents) = self.with_walls(fs::read_to_string(&full_path).ok().as_deref()) else {
+	    warn!("Could not write the walls in the map {}", full_path.display());
+	    return;
+	};
+	match fs::write(&full_path, contents) {
+	    Ok(()) => info!("Saved the walls to {}", full_path.display()),
+	    Err(error) => warn!("Could not write the map {}: {error}", full_path.display()),
+	}
+    }
+
+    // The text of a map file with these walls, they replace the ones of the file or are added at its end
+    pub fn with_walls(&self, contents: Option<&str>) -> Option<String> {
+	// One wall per line
+	let config = ron::ser::PrettyConfig::default().depth_limit(1).indentor("\t".to_string());
+	let walls = match ron::ser::to_string_pretty(&self.walls, config) {
+	    Ok(walls) => walls.replace('\n', "\n    "),
+	    Err(error) => {
+		warn!("Could not serialize the walls: {error}");
+		return None;
+	    },
+	};
+	match contents {
+	    Some(contents) => replace_ron_field(contents, "walls", &walls),
+	    None => Some(format!("(\n    walls: {walls},\n)\n")),
+	}
+    }
+
+    // All the spawn points of a kind, or the fallback position if the map has none
+    pub fn spawn_points(&self, kind: SpawnKind, fallback: Vec2) -> Vec<SpawnPoint> {
+	let points: Vec<SpawnPoint> = self.spawn_points.iter()
+	    .filter(|point| point.kind == kind)
+	    .cloned()
+	    .collect();
+	if points.is_empty() {
+	    warn!("The map has no spawn point for {kind:?}, using {fallback}");
+	    return vec![SpawnPoint { kind, position: fallback, patrol: None, item: None, id: None, prefab: None }];
+	}
+	points
+    }
+
+    pub fn patrol_path(&self, name: &str) -> Option<PatrolPath> {
+	let path = self.patrol_paths.get(name).cloned();
+	if path.is_none() {
+	    warn!("The map has no patrol path named {name}");
+	}
+	path
+    }
+}
+
+// Gives a field of the top level struct of a RON text a new value, the rest of the text is kept as it is.
+// A missing field is added at the end of the struct. None if the text is not a struct
+pub fn replace_ron_field(contents: &str, field: &str, value: &str) -> Option<String> {
+    let bytes = contents.as_bytes();
+    let is_identifier = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let mut depth = 0;
+    // Right after the colon of the field, once found
+    let mut value_start = None;
+    // Right after the last character that is not a space or in a comment
+    let mut last_token = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+	// Only the comments and the strings may have other characters than ASCII, they are skipped whole
+	let Some(rest) = contents.get(index..) else {
+	    index += 1;
+	    continue;
+	};
+	if rest.starts_with("//") {
+	    index += rest.find('\n').unwrap_or(rest.len());
+	    continue;
+	}
+	if rest.starts_with("/*") {
+	    index += rest.find("*/").map_or(rest.len(), |end| end + 2);
+	    continue;
+	}
+	match bytes[index] {
+	    b'"' => {
+		index += 1;
+		while index < bytes.len() && bytes[index] != b'"' {
+		    index += if bytes[index] == b'\\' { 2 } else { 1 };
+		}
+	    },
+	    b'[' | b'{' if depth == 0 => return None,
+	    b'(' | b'[' | b'{' => depth += 1,
+	    b')' | b']' | b'}' if depth == 1 => {
+		let (start, end) = match value_start {
+		    Some(start) => (start, last_token),
+		    // Added after the last field, with a comma between them
+		    None => {
+			let comma = if matches!(bytes[last_token - 1], b',' | b'(') { "" } else { "," };
+			return Some(format!("{}{comma}\n    {field}: {value},{}", &contents[..last_token], &contents[last_token..]));
+		    },
+		};
+		return Some(format!("{} {value}{}", &contents[..start], &contents[end..]));
+	    },
+	    b')' | b']' | b'}' => depth -= 1,
+	    b',' if depth == 1 => if let Some(start) = value_start {
+		return Some(format!("{} {value}{}", &contents[..start], &contents[last_token..]));
+	    },
+	    _ if depth == 1 && value_start.is_none() && rest.starts_with(field) && !is_identifier(bytes[index - 1]) => {
+		let after = &rest[field.len()..];
+		if !after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') && after.trim_start().starts_with(':') {
+		    index = contents.len() - after.trim_start().len() + 1;
+		    value_start = Some(index);
+		    continue;
+		}
+	    },
+	    _ => {},
+	}
+	if !bytes[index].is_ascii_whitespace() {
+	    last_token = index + 1;
+	}
+	index += 1;
+    }
+    None
+}
+
+// Where the tiles and props of each chunk are, and which chunks are currently spawned.
+// The characters and the houses do not belong to chunks and always stay in the world
+#[derive(Resource, Default)]
+pub struct ChunkIndex {
+    pub tilemap: Option<(Tilemap, Handle<Image>)>,
+    pub ground: Vec<(GroundFill, Handle<Image>)>,
+    pub props: HashMap<IVec2, Vec<(Handle<Image>, Vec2)>>,
+    // Each spawned chunk is a parent entity of its sprites
+    pub loaded: HashMap<IVec2, Entity>,
+}
+
+impl ChunkIndex {
+    pub fn chunk_of(position: Vec2) -> IVec2 {
+	(position / CHUNK_SIZE).floor().as_ivec2()
+    }
+
+    pub fn chunk_area(chunk: IVec2) -> Rect {
+	let min = chunk.as_vec2() * CHUNK_SIZE;
+	Rect::from_corners(min, min + CHUNK_SIZE)
+    }
+
+    // The fills are drawn in order, so the last one containing the position is the visible one.
+    // The tilemap is under all of them
+    pub fn terrain_at(&self, position: Vec2) -> Terrain {
+	self.ground.iter().rev()
+	    .map(|(fill, _)| fill)
+	    .find(|fill| Rect::from_corners(fill.min, fill.max).contains(position))
+	    .map(|fill| fill.terrain)
+	    .or_else(|| self.tilemap.as_ref().and_then(|(tilemap, _)| tilemap.terrain_at(position)))
+	    .unwrap_or_default()
+    }
+}
+
+// Where the player is, it changes the ambience
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Location {
+    #[default]
+    Outdoors,
+    House,
+}
+
+// Marker for the background image, the play area is fitted to its size on a map without ground fills
+#[derive(Component)]
+pub struct Background;
+
+// An entity described in the prefabs folder: its look and the components it starts with.
+// The behaviors (the controls of the player, the AI of the boars) are added by the code spawning it.
+// The keys are checked, a key that is not one of these makes the prefab fail to load
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prefab {
+    // Shown in the dialogues
+    pub name: Option<String>,
+    // The still sprite
+    pub sprite: Option<String>,
+    // The animation sheet, it replaces the sprite when the file is there
+    pub sheet: Option<PrefabSheet>,
+    pub hitbox: Option<Hitbox>,
+    pub health: Option<f32>,
+    pub speed: Option<f32>,
+    // Hurts the player on touch
+    pub contact_damage: Option<f32>,
+    // The table of the loot file rolled on death
+    pub loot: Option<String>,
+    pub npc: Option<NpcKind>,
+    pub interactable: bool,
+    // Solid for the player
+    pub collider: bool,
+    // A shadow at the feet
+    pub shadow: bool,
+    // A part of the sprite from its top left corner, drawn again in the foreground at the offset
+    pub overlay: Option<PrefabOverlay>,
+    pub spawner: Option<Spawner>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PrefabSheet {
+    pub path: String,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PrefabOverlay {
+    pub size: Vec2,
+    pub offset: Vec2,
+}
+
+// A prefab with its textures loaded
+#[derive(Clone)]
+pub struct LoadedPrefab {
+    pub prefab: Prefab,
+    pub texture: Handle<Image>,
+    pub sprite: Sprite,
+    pub sheet: Option<SpriteSheet>,
+}
+
+// The prefabs by name, the name of a prefab is the one of its file
+#[derive(Resource, Default)]
+pub struct PrefabLibrary {
+    pub prefabs: HashMap<String, LoadedPrefab>,
+}
+
+// Reads the prefabs before anything is spawned. A prefab that cannot be read is left out, with an error
+pub fn load_prefabs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    let mut library = PrefabLibrary::default();
+    let folder = FileAssetIo::get_base_path().join("assets").join(PREFAB_FOLDER);
+    let entries = match fs::read_dir(&folder) {
+	Ok(entries) => entries,
+	Err(error) => {
+	    error!("Could not read the prefabs in {}: {error}", folder.display());
+	    commands.insert_resource(library);
+	    return;
+	},
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+	let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+	    continue;
+	};
+	if path.extension().and_then(|extension| extension.to_str()) != Some("ron") {
+	    continue;
+	}
+	let contents = match fs::read_to_string(&path) {
+	    Ok(contents) => contents,
+	    Err(error) => {
+		error!("Could not read the prefab {}: {error}", path.display());
+		continue;
+	    },
+	};
+	// The error names the unknown keys
+	let prefab: Prefab = match ron::from_str(&contents) {
+	    Ok(prefab) => prefab,
+	    Err(error) => {
+		error!("Could not parse the prefab {}: {error}", path.display());
+		continue;
+	    },
+	};
+
+	let sheet = prefab.sheet.as_ref()
+	    .filter(|sheet| FileAssetIo::get_base_path().join("assets").join(&sheet.path).exists());
+	let loaded = match sheet {
+	    Some(sheet) => {
+		let texture = asset_server.load(sheet.path.as_str());
+		let atlas = TextureAtlas::from_grid(texture.clone(), CHARACTER_SIZE, sheet.columns, sheet.rows, None, None);
+		LoadedPrefab {
+		    sprite: Sprite {
+			rect: Some(atlas.textures[0]),
+			..default()
+		    },
+		    sheet: Some(SpriteSheet {
+			atlas: atlases.add(atlas),
+			columns: sheet.columns,
+		    }),
+		    texture,
+		    prefab: prefab.clone(),
+		}
+	    },
+	    None => LoadedPrefab {
+		texture: prefab.sprite.as_ref().map(|sprite| asset_server.load(sprite.as_str())).unwrap_or_default(),
+		sprite: Sprite::default(),
+		sheet: None,
+		prefab: prefab.clone(),
+	    },
+	};
+	library.prefabs.insert(name.to_string(), loaded);
+    }
+    commands.insert_resource(library);
+}
+
+// Spawns an entity from a prefab, the other components are inserted after the ones of the prefab
+pub trait PrefabCommands<'w, 's> {
+    fn spawn_prefab<'a>(&'a mut self, name: &str, position: Vec3) -> EntityCommands<'w, 's, 'a>;
+}
+
+impl<'w, 's> PrefabCommands<'w, 's> for Commands<'w, 's> {
+    fn spawn_prefab<'a>(&'a mut self, name: &str, position: Vec3) -> EntityCommands<'w, 's, 'a> {
+	let entity = self.spawn_empty().id();
+	self.add(InsertPrefab { entity, name: name.to_string(), position });
+	self.entity(entity)
+    }
+}
+
+// Gives an entity the components of a prefab, once the commands are applied
+pub struct InsertPrefab {
+    pub entity: Entity,
+    pub name: String,
+    pub position: Vec3,
+}
+
+impl Command for InsertPrefab {
+    fn write(self, world: &mut World) {
+	let Some(loaded) = world.resource::<PrefabLibrary>().prefabs.get(&self.name).cloned() else {
+	    error!("There is no prefab named {}", self.name);
+	    return;
+	};
+	let shadow = loaded.prefab.shadow.then(|| world.resource::<AssetServer>().load("sprites/shadow.png"));
+	let prefab = loaded.prefab;
+	let mut entity = world.entity_mut(self.entity);
+	entity.insert(SpriteBundle {
+	    texture: loaded.texture.clone(),
+	    sprite: loaded.sprite,
+	    transform: Transform::from_translation(self.position),
+	    ..default()
+	});
+	if let Some(sheet) = loaded.sheet {
+	    entity.insert((AnimationState::new(), sheet));
+	}
+	if let Some(name) = prefab.name {
+	    entity.insert(DisplayName(name));
+	}
+	if let Some(hitbox) = prefab.hitbox {
+	    entity.insert(hitbox);
+	}
+	if let Some(health) = prefab.health {
+	    entity.insert(HealthPoints::new(health));
+	}
+	if let Some(speed) = prefab.speed {
+	    entity.insert(Speed(speed));
+	}
+	if let Some(amount) = prefab.contact_damage {
+	    entity.insert(ContactDamage::new(amount));
+	}
+	if let Some(table) = prefab.loot {
+	    entity.insert(Loot(table));
+	}
+	match prefab.npc {
+	    Some(NpcKind::House) => {
+		entity.insert(House);
+	    },
+	    Some(NpcKind::Boar) => {
+		entity.insert((Boar, Hostile));
+	    },
+	    Some(NpcKind::Wolf) => {
+		entity.insert((Wolf, Hostile));
+	    },
+	    Some(NpcKind::Villager) => {
+		entity.insert(Villager);
+	    },
+	    None => (),
+	}
+	if prefab.interactable {
+	    entity.insert(Interactable);
+	}
+	if prefab.collider {
+	    entity.insert(Collider);
+	}
+	if let Some(spawner) = prefab.spawner {
+	    entity.insert(spawner);
+	}
+	entity.with_children(|parent| {
+	    if let Some(shadow) = shadow {
+		parent.spawn(shadow_bundle(shadow, self.position.z));
+	    }
+	    if let Some(overlay) = prefab.overlay {
+		parent.spawn((
+		    SpriteBundle {
+			texture: loaded.texture,
+			sprite: Sprite {
+			    rect: Some(Rect::from_corners(Vec2::ZERO, overlay.size)),
+			    ..default()
+			},
+			// The translation is relative to the parent
+			transform: Transform::from_translation(overlay.offset.extend(OVERLAY_Z - self.position.z)),
+			..default()
+		    },
+		    Overlay { size: overlay.size },
+		));
+	    }
+	});
+    }
+}
+
+// Loads the map data before anything is spawned, another map can be played with --map <file>
+pub fn load_map(mut commands: Commands) {
+    let path = env::args()
+	.skip_while(|arg| arg != "--map")
+	.nth(1)
+	.unwrap_or_else(|| MAP_FILE.to_string());
+    commands.insert_resource(MapData::load(&path));
+    if env::args().any(|arg| arg == "--editor") {
+	info!("Level editor enabled: click to add a wall, right click to remove one, \
+	       {EDITOR_ROTATE_KEY:?} to rotate, {EDITOR_SAVE_KEY:?} to save, {EDITOR_RELOAD_KEY:?} to reload");
+	commands.insert_resource(LevelEditor { map_path: path, vertical: false });
+    }
+}
+
+// Sorts the tiles and props of the map by chunk, they are spawned later by stream_chunks
+pub fn index_chunks(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    map: Res<MapData>,
+) {
+    let mut index = ChunkIndex::default();
+    if let Some(tilemap) = &map.tilemap {
+	index.tilemap = Some((tilemap.clone(), asset_server.load(tilemap.tileset.as_str())));
+    }
+    for fill in &map.ground {
+	index.ground.push((fill.clone(), asset_server.load(fill.texture.as_str())));
+    }
+    for prop in &map.props {
+	index.props.entry(ChunkIndex::chunk_of(prop.position))
+	    .or_default()
+	    .push((asset_server.load(prop.texture.as_str()), prop.position));
+    }
+    commands.insert_resource(index);
+}
+
+// Places the scenery and the HUD, before the first game and again for every new game
+pub fn spawn_level(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    assets: Res<GameAssets>,
+    map: Res<MapData>,
+    play_area: Res<PlayArea>,
+) {
+    // Background, the tilemap replaces it
+    if map.tilemap.is_none() {
+	commands.spawn((
+	    SpriteBundle {
+		texture: asset_server.load("sprites/background.png"),
+		..default()
+	    },
+	    Background,
+	));
+    }
+
+    // Spawn the walls
+    spawn_outer_walls(&mut commands, &assets, &play_area);
+    spawn_inside_walls(&mut commands, &assets, &play_area, &map.walls);
+
+    // Red edges of the screen when the health is low, below the rest of the HUD
+    commands.spawn((
+	ImageBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		position_type: PositionType::Absolute,
+		..default()
+	    },
+	    image: asset_server.load("sprites/vignette.png").into(),
+	    background_color: Color::NONE.into(),
+	    visibility: Visibility { is_visible: false },
+	    ..default()
+	},
+	LowHealthVignette,
+    ));
+
+    // The dash indicator is in the bottom left corner
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Px(HUD_ICON_SIZE), Val::Px(HUD_ICON_SIZE)),
+		position_type: PositionType::Absolute,
+		position: UiRect {
+		    bottom: Val::Px(8.0),
+		    left: Val::Px(8.0),
+		    ..default()
+		},
+		..default()
+	    },
+	    background_color: DASH_INDICATOR_BACKGROUND.into(),
+	    visibility: Visibility { is_visible: false },
+	    ..default()
+	},
+	AbilityIndicator(Ability::Dash),
+    )).with_children(|parent| {
+	parent.spawn((
+	    NodeBundle {
+		style: Style {
+		    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		    position_type: PositionType::Absolute,
+		    position: UiRect {
+			bottom: Val::Px(0.0),
+			..default()
+		    },
+		    ..default()
+		},
+		background_color: DASH_READY_COLOR.into(),
+		..default()
+	    },
+	    DashIndicator,
+	));
+    });
+    // The coins are in the bottom right corner
+    commands.spawn(NodeBundle {
+	style: Style {
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		bottom: Val::Px(8.0),
+		right: Val::Px(8.0),
+		..default()
+	    },
+	    align_items: AlignItems::Center,
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	parent.spawn(ImageBundle {
+	    style: Style {
+		size: Size::new(Val::Px(HUD_ICON_SIZE), Val::Px(HUD_ICON_SIZE)),
+		..default()
+	    },
+	    image: asset_server.load(ItemKind::Coin.sprite()).into(),
+	    ..default()
+	});
+	parent.spawn((
+	    TextBundle::from_section("0", TextStyle {
+		font: asset_server.load(FONT),
+		font_size: 24.0,
+		color: Color::WHITE,
+	    }),
+	    CoinCounter,
+	));
+    });
+
+    // The lives are in the top right corner, left of the mute icon
+    commands.spawn(NodeBundle {
+	style: Style {
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		top: Val::Px(12.0),
+		right: Val::Px(16.0 + HUD_ICON_SIZE),
+		..default()
+	    },
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	for index in 0..PLAYER_LIVES {
+	    parent.spawn((
+		ImageBundle {
+		    style: Style {
+			size: Size::new(Val::Px(LIFE_ICON_SIZE), Val::Px(LIFE_ICON_SIZE)),
+			margin: UiRect::left(Val::Px(4.0)),
+			..default()
+		    },
+		    image: asset_server.load("sprites/heart.png").into(),
+		    ..default()
+		},
+		LifeIcon(index),
+	    ));
+	}
+    });
+
+    // The boars left are under the lives
+    commands.spawn(NodeBundle {
+	style: Style {
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		top: Val::Px(20.0 + LIFE_ICON_SIZE),
+		right: Val::Px(16.0 + HUD_ICON_SIZE),
+		..default()
+	    },
+	    align_items: AlignItems::Center,
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	parent.spawn(ImageBundle {
+	    style: Style {
+		size: Size::new(Val::Px(LIFE_ICON_SIZE), Val::Px(LIFE_ICON_SIZE)),
+		margin: UiRect::right(Val::Px(4.0)),
+		..default()
+	    },
+	    image: asset_server.load(ItemKind::Tusk.sprite()).into(),
+	    ..default()
+	});
+	parent.spawn((
+	    TextBundle::from_section("0", TextStyle {
+		font: asset_server.load(FONT),
+		font_size: 24.0,
+		color: Color::WHITE,
+	    }),
+	    BoarCounter,
+	));
+    });
+
+    // Health bar of the boss, at the top of the screen, hidden until the boss comes
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Percent(50.0), Val::Px(16.0)),
+		position_type: PositionType::Absolute,
+		position: UiRect {
+		    top: Val::Px(8.0),
+		    left: Val::Percent(25.0),
+		    ..default()
+		},
+		..default()
+	    },
+	    background_color: BOSS_BAR_BACKGROUND.into(),
+	    visibility: Visibility { is_visible: false },
+	    ..default()
+	},
+	BossHealthBar,
+    )).with_children(|parent| {
+	parent.spawn((
+	    NodeBundle {
+		style: Style {
+		    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+		    ..default()
+		},
+		// The color comes from the palette once the boss is there
+		background_color: BOSS_BAR_COLOR.into(),
+		..default()
+	    },
+	    BossHealthFill,
+	));
+    });
+
+    // Hotbar, at the bottom of the screen
+    commands.spawn(NodeBundle {
+	style: Style {
+	    size: Size::new(Val::Percent(100.0), Val::Auto),
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		bottom: Val::Px(8.0),
+		..default()
+	    },
+	    justify_content: JustifyContent::Center,
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	for index in 0..HOTBAR_KEYS.len() {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			size: Size::new(Val::Px(HOTBAR_SLOT_SIZE), Val::Px(HOTBAR_SLOT_SIZE)),
+			margin: UiRect::all(Val::Px(2.0)),
+			justify_content: JustifyContent::Center,
+			align_items: AlignItems::Center,
+			..default()
+		    },
+		    background_color: HOTBAR_SLOT_COLOR.into(),
+		    ..default()
+		},
+		HotbarSlot(index),
+	    )).with_children(|parent| {
+		parent.spawn((
+		    ImageBundle {
+			style: Style {
+			    size: Size::new(Val::Px(HOTBAR_SLOT_SIZE / 2.0), Val::Px(HOTBAR_SLOT_SIZE / 2.0)),
+			    ..default()
+			},
+			visibility: Visibility { is_visible: false },
+			..default()
+		    },
+		    HotbarSlot(index),
+		));
+		// The count is in the bottom right corner of the slot
+		parent.spawn((
+		    TextBundle {
+			text: Text::from_section("", TextStyle {
+			    font: asset_server.load(FONT),
+			    font_size: 14.0,
+			    color: Color::WHITE,
+			}),
+			style: Style {
+			    position_type: PositionType::Absolute,
+			    position: UiRect {
+				bottom: Val::Px(2.0),
+				right: Val::Px(4.0),
+				..default()
+			    },
+			    ..default()
+			},
+			..default()
+		    },
+		    HotbarSlot(index),
+		));
+	    });
+	}
+    });
+
+    // Level and experience bar, right above the hotbar
+    commands.spawn(NodeBundle {
+	style: Style {
+	    size: Size::new(Val::Percent(100.0), Val::Auto),
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		bottom: Val::Px(62.0),
+		..default()
+	    },
+	    justify_content: JustifyContent::Center,
+	    align_items: AlignItems::Center,
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	parent.spawn((
+	    TextBundle {
+		text: Text::from_section("", TextStyle {
+		    font: asset_server.load(FONT),
+		    font_size: 14.0,
+		    color: Color::WHITE,
+		}),
+		style: Style {
+		    margin: UiRect {
+			right: Val::Px(6.0),
+			..default()
+		    },
+		    ..default()
+		},
+		..default()
+	    },
+	    LevelText,
+	));
+	parent.spawn(NodeBundle {
+	    style: Style {
+		size: Size::new(Val::Px(XP_BAR_SIZE.x), Val::Px(XP_BAR_SIZE.y)),
+		..default()
+	    },
+	    background_color: XP_BAR_BACKGROUND.into(),
+	    ..default()
+	}).with_children(|parent| {
+	    parent.spawn((
+		NodeBundle {
+		    style: Style {
+			size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+			..default()
+		    },
+		    background_color: XP_BAR_COLOR.into(),
+		    ..default()
+		},
+		ExperienceFill,
+	    ));
+	});
+    });
+
+    // Toasts, above the hotbar, hidden until there is a message
+    commands.spawn(NodeBundle {
+	style: Style {
+	    size: Size::new(Val::Percent(100.0), Val::Auto),
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		bottom: Val::Px(80.0),
+		..default()
+	    },
+	    justify_content: JustifyContent::Center,
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	parent.spawn((
+	    TextBundle {
+		text: Text::from_section("", TextStyle {
+		    font: asset_server.load(FONT),
+		    font_size: 24.0,
+		    color: Color::WHITE,
+		}),
+		visibility: Visibility { is_visible: false },
+		..default()
+	    },
+	    ToastText,
+	));
+    });
+
+    // Tutorial prompts, at the top of the screen
+    commands.spawn(NodeBundle {
+	style: Style {
+	    size: Size::new(Val::Percent(100.0), Val::Auto),
+	    position_type: PositionType::Absolute,
+	    position: UiRect {
+		top: Val::Px(TUTORIAL_TOP),
+		..default()
+	    },
+	    justify_content: JustifyContent::Center,
+	    ..default()
+	},
+	..default()
+    }).with_children(|parent| {
+	parent.spawn((
+	    TextBundle {
+		text: Text::from_section("", TextStyle {
+		    font: asset_server.load(FONT),
+		    font_size: TUTORIAL_FONT_SIZE,
+		    color: TUTORIAL_COLOR,
+		}),
+		visibility: Visibility { is_visible: false },
+		..default()
+	    },
+	    TutorialText,
+	));
+    });
+
+    // Quest tracker, in the top left corner
+    commands.spawn((
+	NodeBundle {
+	    style: Style {
+		position_type: PositionType::Absolute,
+		position: UiRect {
+		    top: Val::Px(8.0),
+		    left: Val::Px(8.0),
+		    ..default()
+		},
+		flex_direction: FlexDirection::Column,
+		padding: UiRect::all(Val::Px(12.0)),
+		..default()
+	    },
+	    background_color: MENU_BACKGROUND_COLOR.into(),
+	    ..default()
+	},
+    )).with_children(|parent| {
+	parent.spawn(TextBundle::from_section("Quests", TextStyle {
+	    font: asset_server.load(FONT),
+	    font_size: 32.0,
+	    color: Color::WHITE,
+	}));
+	parent.spawn((
+	    NodeBundle {
+		style: Style {
+		    flex_direction: FlexDirection::Column,
+		    ..default()
+		},
+		..default()
+	    },
+	    QuestLogList,
+	));
+    });
+}
+
+// Places the characters at the spawn points of the map
+pub fn spawn_world(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    map: Res<MapData>,
+    save: Res<SaveGame>,
+    loot: Res<LootTables>,
+    mut game_rng: ResMut<GameRng>,
+    progression: Res<Progression>,
+    loadout: Res<PlayerLoadout>,
+    settings: Res<Settings>,
+    mut inventory: ResMut<Inventory>,
+    mut currency: ResMut<Currency>,
+) {
+    // Player character, there is only one
+    let player_position = map.spawn_points(SpawnKind::Player, PLAYER_SPAWN)[0].position;
+    let mut player = commands.spawn_prefab("player", player_position.extend(0.2));
+    player.insert((
+	Player,
+	PlayerState::Walking,
+	Dash::new(),
+	Sprinting::default(),
+	// Dust at the feet while sprinting, just behind the player
+	ParticleEmitter::new(ParticleKind::Dust, DUST_RATE, Vec3::new(0.0, SHADOW_OFFSET, -0.05)),
+	Footsteps::new(player_position),
+	Attack::new(),
+	Facing::Right,
+	Velocity::default(),
+	PlayerSize(PLAYER_SIZE),
+	PlayerTint(settings.player_tint()),
+	Collider,
+    ));
+    // The levels of the save are given back
+    let level = save.experience.level;
+    player.insert((
+	save.experience,
+	HealthPoints::new(loadout.health + progression.health_bonus(level)),
+	Speed(PLAYER_SPEED + progression.speed_bonus(level)),
+    ));
+    // What the player carries from the start
+    for (item, amount) in &loadout.items {
+	if *item == ItemKind::Coin {
+	    currency.coins += amount;
+	} else if inventory.add(*item, *amount).is_err() {
+	    warn!("No room in the inventory for the {} {} of the loadout", amount, item.name());
+	}
+    }
+
+    // Houses, the roof is drawn again in the foreground
+    for spawn_point in map.spawn_points(SpawnKind::House, HOUSE_SPAWN) {
+	commands.spawn_prefab("house", spawn_point.position.extend(0.1))
+	    .insert(SafeZone { radius: SAFE_ZONE_RADIUS })
+	    .with_children(|parent| {
+		parent.spawn(SpriteBundle {
+		    texture: asset_server.load("sprites/ring.png"),
+		    sprite: Sprite {
+			color: SAFE_ZONE_COLOR,
+			custom_size: Some(Vec2::splat(2.0 * SAFE_ZONE_RADIUS)),
+			..default()
+		    },
+		    transform: Transform::from_xyz(0.0, 0.0, SAFE_ZONE_Z),
+		    ..default()
+		});
+	    });
+    }
+
+    // Items lying around from the start
+    for spawn_point in map.spawn_points.iter().filter(|point| point.kind == SpawnKind::Pickup) {
+	let Some(item) = spawn_point.item else {
+	    warn!("The pickup at {} has no item", spawn_point.position);
+	    continue;
+	};
+	spawn_pickups(&mut commands, &mut game_rng.0, &[(item, 1)], spawn_point.position);
+    }
+
+    // Chests, with what they held at the last save
+    for spawn_point in map.spawn_points.iter().filter(|point| point.kind == SpawnKind::Chest) {
+	let id = spawn_point.id.clone().unwrap_or_else(|| format!("chest at {}", spawn_point.position));
+	let mut inventory = Inventory::default();
+	match save.chests.get(&id) {
+	    Some(slots) => {
+		for (slot, stack) in inventory.slots.iter_mut().zip(slots) {
+		    *slot = *stack;
+		}
+	    },
+	    None => {
+		for (item, amount) in loot.roll(CHEST_LOOT, &mut game_rng.0) {
+		    let _ = inventory.add(item, amount);
+		}
+	    },
+	}
+	commands.spawn((
+	    SpriteBundle {
+		texture: asset_server.load("sprites/chest.png"),
+		sprite: Sprite {
+		    custom_size: Some(CHEST_SIZE),
+		    ..default()
+		},
+		transform: Transform::from_translation(spawn_point.position.extend(0.15)),
+		..default()
+	    },
+	    Chest { id },
+	    inventory,
+	    Interactable,
+	));
+    }
+
+    // Crates, they block the way until broken
+    for spawn_point in map.spawn_points.iter().filter(|point| point.kind == SpawnKind::Crate) {
+	commands.spawn((
+	    SpriteBundle {
+		texture: asset_server.load("sprites/crate.png"),
+		sprite: Sprite {
+		    custom_size: Some(CRATE_SIZE),
+		    ..default()
+		},
+		transform: Transform::from_translation(spawn_point.position.extend(0.1)),
+		..default()
+	    },
+	    Destructible,
+	    HealthPoints::new(CRATE_HEALTH),
+	    Collider,
+	));
+    }
+
+
+    // Boars
+    for spawn_point in map.spawn_points(SpawnKind::Boar, BOAR_SPAWN) {
+	let boar = spawn_boar(&mut commands, spawn_point.position);
+	if let Some(path) = spawn_point.patrol.and_then(|name| map.patrol_path(&name)) {
+	    commands.entity(boar).insert(path);
+	}
+	if spawn_point.id.as_deref() == Some(FRANK_ID) {
+	    commands.entity(boar).insert(Frank);
+	}
+	if let Some(id) = spawn_point.id {
+	    if save.tamed.contains(&id) {
+		commands.entity(boar).insert(Tamed).remove::<(Interactable, Hostile)>();
+	    }
+	    commands.entity(boar).insert(SpawnId(id));
+	}
+    }
+
+    // The rest of the prefabs placed by the map (the dens), on the ground under the characters
+    for spawn_point in map.spawn_points.iter().filter(|point| point.kind == SpawnKind::Prefab) {
+	let Some(prefab) = &spawn_point.prefab else {
+	    warn!("The prefab spawn point at {} has no prefab", spawn_point.position);
+	    continue;
+	};
+	let mut entity = commands.spawn_prefab(prefab, spawn_point.position.extend(0.05));
+	if let Some(id) = &spawn_point.id {
+	    entity.insert(SpawnId(id.clone()));
+	}
+    }
+
+    // The other characters
+    for spawn_point in map.spawn_points.iter().filter(|point| point.kind == SpawnKind::Npc) {
+	let Some(prefab) = &spawn_point.prefab else {
+	    warn!("The NPC spawn point at {} has no prefab", spawn_point.position);
+	    continue;
+	};
+	let npc = commands.spawn_npc(prefab, spawn_point.position);
+	if let Some(path) = spawn_point.patrol.as_deref().and_then(|name| map.patrol_path(name)) {
+	    commands.entity(npc).insert(path);
+	}
+	if let Some(id) = &spawn_point.id {
+	    commands.entity(npc).insert(SpawnId(id.clone()));
+	}
+    }
+}
+
+// System that counts the days of free play, from the first boar defeated after the ending. Every day a boar
+// comes out of a den away from the player, stronger with each day. It waits when there are enough boars around,
+// and when no den is free
+pub fn spawn_daily_boars(
+    mut commands: Commands,
+    time: Res<Time>,
+    map: Res<MapData>,
+    free_play: Res<FreePlay>,
+    mut save: ResMut<SaveGame>,
+    mut game_rng: ResMut<GameRng>,
+    mut toasts: EventWriter<Toast>,
+    mut deaths: EventReader<DeathEvent>,
+    query_player: Query<&Transform, With<Player>>,
+    query_boar: Query<(), (With<Boar>, With<Hostile>)>,
+    query_collider: Query<(&Transform, &Sprite), With<Collider>>,
+    mut day_timer: Local<Timer>,
+    // A boar of the day is still to come out
+    mut pending: Local<bool>,
+) {
+    if !save.won {
+	return;
+    }
+    if save.day == 0 {
+	if deaths.iter().count() == 0 {
+	    return;
+	}
+	save.day = 1;
+	save.save();
+    }
+    if day_timer.duration().is_zero() {
+	*day_timer = Timer::from_seconds(DAY_LENGTH as f32, TimerMode::Repeating);
+    }
+    if day_timer.tick(time.delta()).just_finished() {
+	save.day += 1;
+	save.save();
+	toasts.send(Toast(format!("Day {}", save.day)));
+	*pending = true;
+    }
+    if !*pending || query_boar.iter().count() >= free_play.max_boars {
+	return;
+    }
+
+    // The dens in a random order, the first one far enough from the player and not blocked is used
+    let player_position = query_player.single().translation.truncate();
+    let mut dens: Vec<Vec2> = map.spawn_points.iter()
+	.filter(|point| matches!(point.kind, SpawnKind::Den | SpawnKind::Boar))
+	.map(|point| point.position)
+	.collect();
+    dens.shuffle(&mut game_rng.0);
+    let den = dens.into_iter().find(|position| {
+	position.distance(player_position) >= DEN_MIN_DISTANCE
+	    && !query_collider.iter().any(|(transform, sprite)| {
+		collide(position.extend(0.0), CHARACTER_SIZE, transform.translation, collider_size(transform, sprite)).is_some()
+	    })
+    });
+    let Some(position) = den else {
+	return;
+    };
+    let boar = spawn_boar(&mut commands, position);
+    // The boar keeps its own variation on top of the scaling of the day
+    let (health_scale, speed_scale) = (free_play.health_scale(save.day), free_play.speed_scale(save.day));
+    commands.add(move |world: &mut World| {
+	let mut boar = world.entity_mut(boar);
+	if let Some(mut health) = boar.get_mut::<HealthPoints>() {
+	    *health = HealthPoints::new(health.max * health_scale);
+	}
+	if let Some(mut speed) = boar.get_mut::<Speed>() {
+	    speed.0 *= speed_scale;
+	}
+    });
+    *pending = false;
+}
+
+// System that keeps the shadows on the ground: a carried boar is raised above the head of
+// the player, so its shadow stays at the feet of the player, smaller
+pub fn update_shadows(
+    mut shadow_query: Query<(&Parent, &mut Transform), With<Shadow>>,
+    character_query: Query<(&GlobalTransform, Option<&Carried>)>,
+) {
+    for (parent, mut transform) in &mut shadow_query {
+	let Ok((character_transform, carried)) = character_query.get(parent.get()) else {
+	    continue;
+	};
+	let height = if carried.is_some() { CARRY_OFFSET.y } else { 0.0 };
+	transform.translation.y = SHADOW_OFFSET - height;
+	transform.translation.z = SHADOW_Z - character_transform.translation().z;
+	transform.scale = Vec3::splat(1.0 / (1.0 + height / SHADOW_FALLOFF_HEIGHT));
+    }
+}
+
+// System that finds out whether the player is in a house, the door is heard when going in
+pub fn update_location(
+    mut location: ResMut<Location>,
+    mut sfx_events: EventWriter<PlaySfx>,
+    query_player: Query<&Transform, With<Player>>,
+    query_house: Query<&Transform, With<House>>,
+) {
+    let player_position = query_player.single().translation;
+    let in_house = query_house.iter().any(|house_transform| {
+	collide(player_position, Vec2::ZERO, house_transform.translation, CHARACTER_SIZE).is_some()
+    });
+    let new_location = if in_house { Location::House } else { Location::Outdoors };
+    if new_location == Location::House && *location != Location::House {
+	sfx_events.send(PlaySfx(Sfx::Door));
+    }
+    *location = new_location;
+}
+
+// System that heals the player standing in a safe zone
+pub fn regenerate_in_safe_zones(
+    simulation: Res<Simulation>,
+    query_zone: Query<(&Transform, &SafeZone)>,
+    mut query_player: Query<(&Transform, &mut HealthPoints), With<Player>>,
+) {
+    let (player_transform, mut health) = query_player.single_mut();
+    let player_position = player_transform.translation.truncate();
+    // The dead stay dead
+    if health.current == 0.0 || !query_zone.iter().any(|(zone_transform, zone)| zone.contains(zone_transform.translation.truncate(), player_position)) {
+	return;
+    }
+    health.heal(SAFE_ZONE_REGEN * simulation.dt());
+}
+
+// System that spawns the chunks seen by the camera (with a margin of one chunk) and despawns the others
+pub fn stream_chunks(
+    mut commands: Commands,
+    mut index: ResMut<ChunkIndex>,
+    query_camera: Query<(&Transform, &OrthographicProjection), With<MapCamera>>,
+) {
+    // Nothing is seen without a camera
+    let Ok((camera_transform, camera_proj)) = query_camera.get_single() else {
+	return;
+    };
+    let camera_position = camera_transform.translation.truncate();
+    let view_min = camera_position + Vec2::new(camera_proj.left, camera_proj.bottom) * camera_proj.scale;
+    let view_max = camera_position + Vec2::new(camera_proj.right, camera_proj.top) * camera_proj.scale;
+    let first = ChunkIndex::chunk_of(view_min) - IVec2::ONE;
+    let last = ChunkIndex::chunk_of(view_max) + IVec2::ONE;
+    let in_view = |chunk: &IVec2| chunk.cmpge(first).all() && chunk.cmple(last).all();
+
+    // Chunks that went out of view
+    index.loaded.retain(|chunk, entity| {
+	if !in_view(chunk) {
+	    commands.entity(*entity).despawn_recursive();
+	}
+	in_view(chunk)
+    });
+
+    // Chunks that came into view
+    for x in first.x..=last.x {
+	for y in first.y..=last.y {
+	    let chunk = IVec2::new(x, y);
+	    if index.loaded.contains_key(&chunk) {
+		continue;
+	    }
+	    let area = ChunkIndex::chunk_area(chunk);
+
+	    let entity = commands.spawn(SpatialBundle::default()).with_children(|parent| {
+		if let Some((tilemap, texture)) = &index.tilemap {
+		    for (center, tile) in tilemap.tiles_in(area) {
+			parent.spawn(SpriteBundle {
+			    texture: texture.clone(),
+			    sprite: Sprite {
+				rect: Some(tilemap.tile_rect(tile)),
+				..default()
+			    },
+			    transform: Transform::from_translation(center.extend(TILEMAP_Z)),
+			    ..default()
+			});
+		    }
+		}
+		for (fill, texture) in &index.ground {
+		    for tile in fill.tiles_in(area) {
+			parent.spawn(SpriteBundle {
+			    texture: texture.clone(),
+			    sprite: Sprite {
+				rect: Some(fill.rect),
+				..default()
+			    },
+			    transform: Transform::from_translation(tile.extend(GROUND_Z)),
+			    ..default()
+			});
+		    }
+		}
+		for (texture, position) in index.props.get(&chunk).into_iter().flatten() {
+		    parent.spawn(SpriteBundle {
+			texture: texture.clone(),
+			transform: Transform::from_translation(position.extend(PROP_Z)),
+			..default()
+		    });
+		}
+	    }).id();
+	    index.loaded.insert(chunk, entity);
+	}
+    }
+}
+
+// System that fades the overlays out when the player walks under them, and back in when it leaves
+pub fn fade_overlays(
+    time: Res<Time>,
+    monitor: Res<PerformanceMonitor>,
+    query_player: Query<&Transform, With<Player>>,
+    mut query_overlay: Query<(&GlobalTransform, &Overlay, &mut Sprite)>,
+) {
+    let player_transform = query_player.single();
+    // Going from opaque to faded takes OVERLAY_FADE_TIME seconds
+    let step = (1.0 - OVERLAY_FADED_ALPHA) / OVERLAY_FADE_TIME * time.delta_seconds();
+
+    for (overlay_transform, overlay, mut sprite) in &mut query_overlay {
+	let target = if collide(player_transform.translation, CHARACTER_SIZE,
+				overlay_transform.translation(), overlay.size).is_some() {
+	    OVERLAY_FADED_ALPHA
+	} else {
+	    1.0
+	};
+
+	// The fade is skipped when effects are reduced
+	let alpha = sprite.color.a();
+	if monitor.quality == EffectsQuality::Low {
+	    sprite.color.set_a(target);
+	} else if alpha < target {
+	    sprite.color.set_a((alpha + step).min(target));
+	} else if alpha > target {
+	    sprite.color.set_a((alpha - step).max(target));
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::testing::TestApp;
+
+    fn walls() -> Vec<WallSegment> {
+	vec![
+	    WallSegment { position: Vec2::new(32.0, 0.0), size: Vec2::new(WALL_THICKNESS, EDITOR_GRID) },
+	    WallSegment { position: Vec2::new(16.0, -64.0), size: Vec2::new(EDITOR_GRID, WALL_THICKNESS) },
+	]
+    }
+
+    // The walls saved by the editor are added to the map written by hand, its comments stay
+    #[test]
+    fn saved_walls_keep_the_comments_of_the_map() {
+	let path = FileAssetIo::get_base_path().join("assets").join(MAP_FILE);
+	let original = fs::read_to_string(path).unwrap();
+	let map = MapData { walls: walls(), ..ron::from_str(&original).unwrap() };
+
+	let saved = map.with_walls(Some(&original)).unwrap();
+	let comments: Vec<&str> = original.lines().filter(|line| line.trim_start().starts_with("//")).collect();
+	assert!(!comments.is_empty());
+	assert!(comments.iter().all(|comment| saved.contains(comment)));
+	let loaded: MapData = ron::from_str(&saved).unwrap();
+	assert_eq!(loaded.walls, walls());
+	assert_eq!(loaded.spawn_points.len(), map.spawn_points.len());
+
+	// Saved again, the walls are replaced and the rest does not move
+	let moved = MapData { walls: walls()[1..].to_vec(), ..loaded };
+	let saved_again = moved.with_walls(Some(&saved)).unwrap();
+	assert_eq!(ron::from_str::<MapData>(&saved_again).unwrap().walls, walls()[1..]);
+	assert_eq!(saved_again.replace(&saved_again[saved_again.find("walls:").unwrap()..], ""),
+		   saved.replace(&saved[saved.find("walls:").unwrap()..], ""));
+    }
+
+    // Only the field of the top level struct is replaced, not a field of the same name in a value
+    #[test]
+    fn ron_field_is_found_at_the_top_level() {
+	let contents = "(\n    a: (walls: 1), // walls: 2\n    walls: [3],\n    b: \"walls: 4\",\n)";
+	assert_eq!(replace_ron_field(contents, "walls", "[5]").unwrap(),
+		   "(\n    a: (walls: 1), // walls: 2\n    walls: [5],\n    b: \"walls: 4\",\n)");
+	assert_eq!(replace_ron_field("(a: 1)", "walls", "[5]").unwrap(), "(a: 1,\n    walls: [5],)");
+	assert_eq!(replace_ron_field("[1, 2]", "walls", "[5]"), None);
+    }
+
+    // The door is heard once when the player goes in the house, not while staying in or when leaving
+    #[test]
+    fn door_is_heard_when_entering_the_house() {
+	let mut test = TestApp::new();
+	test.app.init_resource::<Location>();
+	test.add_fixed_systems(SystemSet::new().with_system(update_location));
+	let player = test.spawn_player(Vec2::ZERO);
+	let house_position = Vec2::new(200.0, 0.0);
+	test.app.world.spawn((TransformBundle::from(Transform::from_translation(house_position.extend(0.0))), House));
+	let mut reader = test.app.world.resource::<Events<PlaySfx>>().get_reader();
+	let mut doors = |test: &mut TestApp, position: Vec2| {
+	    test.app.world.get_mut::<Transform>(player).unwrap().translation = position.extend(0.0);
+	    (0..3).map(|_| {
+		test.step_fixed(1);
+		reader.iter(test.app.world.resource::<Events<PlaySfx>>()).filter(|event| event.0 == Sfx::Door).count()
+	    }).sum::<usize>()
+	};
+
+	assert_eq!(doors(&mut test, Vec2::ZERO), 0);
+	assert_eq!(doors(&mut test, house_position), 1);
+	assert_eq!(*test.app.world.resource::<Location>(), Location::House);
+	assert_eq!(doors(&mut test, house_position + Vec2::X), 0);
+	assert_eq!(doors(&mut test, Vec2::ZERO), 0);
+	assert_eq!(doors(&mut test, house_position), 1);
+    }
+}